@@ -0,0 +1,9 @@
+class BitWriter:
+    def __init__(self):
+        self.bits = []
+    def put(self, v, n):
+        for i in range(n - 1, -1, -1):
+            self.bits.append((v >> i) & 1)
+    def data(self):
+        b = self.bits + [0] * (-len(self.bits) % 8)
+        return bytes(int(''.join(map(str, b[i:i+8])), 2) for i in range(0, len(b), 8))
@@ -0,0 +1,36 @@
+import heapq
+
+def code_lengths(freqs, limit=15):
+    """Huffman code lengths for the frequencies, limited to `limit` bits"""
+    freqs = list(freqs)
+    while True:
+        used = [(f, i) for i, f in enumerate(freqs) if f > 0]
+        lengths = [0] * len(freqs)
+        if len(used) == 1:
+            lengths[used[0][1]] = 1
+            return lengths
+        heap = [(f, n, [i]) for n, (f, i) in enumerate(used)]
+        heapq.heapify(heap)
+        n = len(heap)
+        while len(heap) > 1:
+            f1, _, s1 = heapq.heappop(heap)
+            f2, _, s2 = heapq.heappop(heap)
+            for i in s1 + s2:
+                lengths[i] += 1
+            heapq.heappush(heap, (f1 + f2, n, s1 + s2))
+            n += 1
+        if max(lengths) <= limit:
+            return lengths
+        freqs = [(f >> 1) | 1 if f else 0 for f in freqs]
+
+def canonical_codes(lengths):
+    """Canonical codes, shorter codes and lower symbols first"""
+    codes = [None] * len(lengths)
+    code = 0
+    for l in range(1, 16):
+        for i, li in enumerate(lengths):
+            if li == l:
+                codes[i] = (code, l)
+                code += 1
+        code <<= 1
+    return codes
@@ -0,0 +1,39 @@
+def find_matches(data, window, min_len=2, max_len=255, max_dist=None):
+    """Greedy LZ77 parse with hash chains, yields ('lit', c) or ('match', length, distance)"""
+    max_dist = max_dist or window
+    heads = {}
+    prev = [-1] * len(data)
+    def insert(p):
+        if p + 3 <= len(data):
+            k = data[p:p+3]
+            prev[p] = heads.get(k, -1)
+            heads[k] = p
+    pos = 0
+    while pos < len(data):
+        best_len, best_dist = 0, 0
+        if pos + 3 <= len(data):
+            cand = heads.get(data[pos:pos+3], -1)
+            tries = 0
+            while cand >= 0 and pos - cand <= max_dist and tries < 256:
+                l = 0
+                while l < max_len and pos + l < len(data) and data[cand + l] == data[pos + l]:
+                    l += 1
+                if l > best_len:
+                    best_len, best_dist = l, pos - cand
+                cand = prev[cand]
+                tries += 1
+        if best_len < 3 and min_len <= 2 and pos + 2 <= len(data):
+            # short matches close by
+            for d in range(1, min(pos, 256) + 1):
+                if data[pos - d:pos - d + 2] == data[pos:pos + 2]:
+                    best_len, best_dist = 2, d
+                    break
+        if best_len >= min_len:
+            yield ('match', best_len, best_dist)
+            for p in range(pos, pos + best_len):
+                insert(p)
+            pos += best_len
+        else:
+            yield ('lit', data[pos])
+            insert(pos)
+            pos += 1
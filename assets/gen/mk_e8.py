@@ -0,0 +1,203 @@
+"""Build rar4-e8-128kb-exe.rar: x86 like code packed as RAR 2.9 LZ with the E8 filter.
+
+The data is generated in the filtered form with absolute call addresses, the
+file itself is the result of the E8 translation of the decoder. Only literals
+are written, so the packed stream is simple enough to check by hand.
+bsdtar -xf (libarchive 3.8) extracts the same file out of the archive.
+"""
+import struct, zlib, random
+from bitw import BitWriter
+from huff import code_lengths, canonical_codes
+from rar4 import asset, make_rar4
+
+NC, DC, LDC, RC, BC = 299, 60, 17, 28, 20
+
+# ---- a 53 byte program with the fingerprint of the standard E8 filter ----
+# The real WinRAR byte code isn't available here. Decoders only look at the
+# length and the CRC32 (and unrar at the XOR check byte), so bytes 48..52 and
+# byte 0 are solved over GF(2) to give exactly this fingerprint.
+def forge(length=53, crc=0xAD576887, free=(0, 48, 49, 50, 51, 52)):
+    base = bytearray(length)
+    text = b'E8 fingerprint, not the WinRAR byte code ' * 2
+    for i in range(1, 48):
+        base[i] = text[i - 1]
+    # constraint vector: 32 bits of crc, 8 bits of xor(code[1:]) ^ code[0]
+    def cons(b):
+        x = 0
+        for v in b[1:]:
+            x ^= v
+        x ^= b[0]
+        return zlib.crc32(bytes(b)) | (x << 32)
+    zero = cons(bytearray(length))
+    c0 = cons(base)
+    want = c0 ^ crc  # the XOR part must become 0
+    cols = []
+    for p in free:
+        for bit in range(8):
+            d = bytearray(length)
+            d[p] = 1 << bit
+            cols.append((cons(d) ^ zero, (p, bit)))
+    # gaussian elimination: find subset of cols xoring to want
+    basis = {}
+    for v, pb in cols:
+        comb = {pb}
+        for piv in sorted(basis, reverse=True):
+            if v >> piv & 1:
+                bv, bc = basis[piv]
+                v ^= bv
+                comb ^= bc
+        if v:
+            basis[v.bit_length() - 1] = (v, comb)
+    v, comb = want, set()
+    for piv in sorted(basis, reverse=True):
+        if v >> piv & 1:
+            bv, bc = basis[piv]
+            v ^= bv
+            comb ^= bc
+    assert v == 0
+    for p, bit in comb:
+        base[p] ^= 1 << bit
+    code = bytes(base)
+    assert zlib.crc32(code) == crc
+    x = 0
+    for b in code[1:]:
+        x ^= b
+    assert x == code[0]
+    return code
+
+def put_data(w, v):
+    """Number in the variable length format of the RarVM"""
+    if v < 16:
+        w.put(0, 2); w.put(v, 4)
+    elif v < 256:
+        w.put(1, 2); w.put(v, 8)
+    elif v < 0x10000:
+        w.put(2, 2); w.put(v, 16)
+    else:
+        w.put(3, 2); w.put(v, 32)
+
+def filter_record(block_start, block_length, program):
+    w = BitWriter()
+    put_data(w, block_start)
+    put_data(w, block_length)
+    put_data(w, len(program))
+    for b in program:
+        w.put(b, 8)
+    return w.data()
+
+# ---- the E8 translation of the decoder, absolute to relative ----
+def e8_inverse(data, file_offset=0):
+    data = bytearray(data)
+    FILE_SIZE = 0x1000000
+    pos = 0
+    while pos + 4 < len(data):
+        b = data[pos]
+        pos += 1
+        if b != 0xE8:
+            continue
+        offset = (pos + file_offset) & 0xffffffff
+        addr = struct.unpack_from('<I', data, pos)[0]
+        if addr & 0x80000000:
+            if (addr + offset) & 0x80000000 == 0:
+                struct.pack_into('<I', data, pos, (addr + FILE_SIZE) & 0xffffffff)
+        elif (addr - FILE_SIZE) & 0x80000000:
+            struct.pack_into('<I', data, pos, (addr - offset) & 0xffffffff)
+        pos += 4
+    return bytes(data)
+
+# ---- x86 like code with calls to a few functions, as stored after the filter ----
+def make_filtered(n=16384, seed=29):
+    rnd = random.Random(seed)
+    funcs = [rnd.randrange(0, n) & ~15 for _ in range(24)]
+    out = bytearray()
+    while len(out) < n:
+        r = rnd.random()
+        if r < 0.25:
+            # call with the absolute target, which the filter made of the relative one
+            out += b'\xE8' + struct.pack('<I', rnd.choice(funcs))
+        elif r < 0.35:
+            out += b'\x55\x89\xE5'
+        elif r < 0.45:
+            out += b'\x8B\x45' + bytes([rnd.randrange(0, 64) * 4 & 0xff])
+        elif r < 0.5:
+            out += b'\xC9\xC3'
+        else:
+            out += bytes([rnd.choice([0x50, 0x51, 0x52, 0x53, 0x58, 0x59, 0x5A, 0x5B, 0x90, 0x01, 0x31, 0xC0])])
+    return bytes(out[:n])
+
+def lz29_literals(filtered, record):
+    """LZ block with new tables: the filter, the literals and the end marker"""
+    first_byte = 0x20 | 6  # block length given, length of the record in the next byte
+    assert 7 <= len(record) < 7 + 256
+    symbols = [257] + list(filtered) + [256]
+    freq = [0] * NC
+    for s in symbols:
+        freq[s] += 1
+    ld = code_lengths(freq, 15)
+    table = ld + [0] * (DC + LDC + RC)
+
+    # bit length table with the lengths 0..15 and the zero runs 18/19
+    bfreq = [0] * BC
+    items = []
+    i = 0
+    while i < len(table):
+        if table[i] == 0:
+            j = i
+            while j < len(table) and table[j] == 0:
+                j += 1
+            run = j - i
+            while run >= 11:
+                n = min(run, 138)
+                items.append((19, n - 11, 7)); run -= n; i += n
+            while run >= 3:
+                n = min(run, 10)
+                items.append((18, n - 3, 3)); run -= n; i += n
+            while run > 0:
+                items.append((0, None, 0)); run -= 1; i += 1
+        else:
+            items.append((table[i], None, 0)); i += 1
+    for s, _, _ in items:
+        bfreq[s] += 1
+    bl = code_lengths(bfreq, 15)
+    bcodes = canonical_codes(bl)
+    codes = canonical_codes(ld)
+
+    w = BitWriter()
+    w.put(0, 2)  # LZ block, new tables
+    for l in bl:
+        if l == 15:
+            w.put(15, 4); w.put(0, 4)
+        else:
+            w.put(l, 4)
+    for s, extra, nbits in items:
+        c, l = bcodes[s]
+        w.put(c, l)
+        if nbits:
+            w.put(extra, nbits)
+
+    c, l = codes[257]
+    w.put(c, l)
+    w.put(first_byte, 8)
+    w.put(len(record) - 7, 8)
+    for b in record:
+        w.put(b, 8)
+    for b in filtered:
+        c, l = codes[b]
+        w.put(c, l)
+    c, l = codes[256]
+    w.put(c, l)
+    w.put(0, 2)  # new file, no new table
+    return w.data()
+
+if __name__ == '__main__':
+    program = forge()
+    filtered = make_filtered()
+    original = e8_inverse(filtered)
+    record = filter_record(0, len(filtered), program)
+    packed = lz29_literals(filtered, record)
+    arc = make_rar4(packed, original, 29, dict_bits=1, name=b'code.exe')
+    open(asset('rar4-e8-128kb-exe.rar'), 'wb').write(arc)
+    print('program', program.hex())
+    print('record', len(record), record.hex())
+    print('packed', len(packed), 'crc', hex(zlib.crc32(original)))
+    print('e8 count', filtered.count(b'\xE8'), 'changed', sum(a != b for a, b in zip(original, filtered)))
@@ -0,0 +1,43 @@
+"""Build rar4-save-4096kb-txt-pw15-test.rar: the stored text with RAR 1.5 encryption"""
+import struct, zlib
+from rar4 import asset, blocks, fix_crc
+
+T = []
+for n in range(256):
+    c = n
+    for _ in range(8):
+        c = (c >> 1) ^ 0xEDB88320 if c & 1 else c >> 1
+    T.append(c)
+def key(p):
+    b = p.encode(); crc = (~zlib.crc32(b)) & 0xffffffff
+    k = [crc & 0xffff, crc >> 16, 0, 0]
+    for ch in b:
+        k[2] ^= (ch ^ T[ch]) & 0xffff
+        k[3] = (k[3] + ch + (T[ch] >> 16)) & 0xffff
+    return k
+def ror(x): return ((x >> 1) | (x << 15)) & 0xffff
+def crypt(k, data):
+    k = list(k); out = bytearray()
+    for d in data:
+        k[0] = (k[0] + 0x1234) & 0xffff
+        t = T[(k[0] & 0x1fe) >> 1]
+        k[1] ^= t & 0xffff
+        k[2] = (k[2] - (t >> 16)) & 0xffff
+        k[0] ^= k[2]
+        k[3] = ror(k[3]) ^ k[1]
+        k[3] = ror(k[3])
+        k[0] ^= k[3]
+        out.append(d ^ (k[0] >> 8))
+    return bytes(out)
+
+src = open(asset('rar4-save-4096kb-txt.rar'), 'rb').read()
+out = src[:7]
+for h, d in blocks(src):
+    if h[2] == 0x74:
+        h = bytearray(h)
+        h[24] = 15
+        struct.pack_into('<H', h, 3, struct.unpack_from('<H', h, 3)[0] | 0x04)
+        h = fix_crc(bytes(h))
+        d = crypt(key('test'), d)
+    out += h + d
+open(asset('rar4-save-4096kb-txt-pw15-test.rar'), 'wb').write(out)
@@ -0,0 +1,19 @@
+"""Build the RAR 1.5 and RAR 2.0 fixtures out of text.txt.
+
+rar15-normal-64kb-txt.rar   RAR 1.5 format, 64 KB dictionary
+rar20-normal-1024kb-txt.rar RAR 2.0 LZ, new tables in front of byte 1000
+rar20-audio-1024kb-txt.rar  RAR 2.0 audio block with 2 channels
+"""
+import rar15, rar20
+from rar4 import asset, make_rar4
+
+text = open(asset('text.txt'), 'rb').read()
+
+packed, _ = rar15.compress(text)
+open(asset('rar15-normal-64kb-txt.rar'), 'wb').write(make_rar4(packed, text, 15, dict_bits=0))
+
+packed = rar20.compress_lz(text, split=1000)
+open(asset('rar20-normal-1024kb-txt.rar'), 'wb').write(make_rar4(packed, text, 20, dict_bits=4))
+
+packed = rar20.compress_audio(text, 2)
+open(asset('rar20-audio-1024kb-txt.rar'), 'wb').write(make_rar4(packed, text, 20, dict_bits=4))
@@ -0,0 +1,53 @@
+"""Build the RAR 3.x encrypted fixtures out of rar4-save-4096kb-txt.rar.
+
+rar4-save-4096kb-txt-pw-test.rar  encrypted file data
+rar4-save-4096kb-txt-pwf-test.rar encrypted headers and file data
+"""
+import struct
+from rar3 import kdf, enc
+from rar4 import asset, blocks, fix_crc
+
+src = open(asset('rar4-save-4096kb-txt.rar'), 'rb').read()
+sig, main = src[:7], src[7:7 + struct.unpack_from('<H', src, 12)[0]]
+PWD = 'test'
+
+def enc_file(h, data, salt):
+    h = bytearray(h)
+    fl = struct.unpack_from('<H', h, 3)[0]
+    assert not fl & 0x100
+    name_len = struct.unpack_from('<H', h, 26)[0]
+    pos = 32 + name_len
+    key, iv = kdf(PWD, salt)
+    edata = enc(key, iv, data)
+    h = h[:pos] + salt + h[pos:]
+    h[24] = 29
+    struct.pack_into('<H', h, 3, fl | 0x04 | 0x400)
+    struct.pack_into('<H', h, 5, len(h))
+    struct.pack_into('<I', h, 7, len(edata))
+    return fix_crc(bytes(h)), edata
+
+fsalt = bytes([0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88])
+
+# encrypted file data
+out = sig + main
+for h, d in blocks(src):
+    if h[2] == 0x74:
+        h, d = enc_file(h, d, fsalt)
+    if h[2] == 0x73:
+        continue
+    out += h + d
+open(asset('rar4-save-4096kb-txt-pw-test.rar'), 'wb').write(out)
+
+# encrypted headers and file data
+m = bytearray(main)
+struct.pack_into('<H', m, 3, struct.unpack_from('<H', m, 3)[0] | 0x80)
+out = sig + fix_crc(bytes(m))
+hsalt = bytes([0xA1, 0xB2, 0xC3, 0xD4, 0xE5, 0xF6, 0x07, 0x18])
+hkey, hiv = kdf(PWD, hsalt)
+for h, d in blocks(src):
+    if h[2] == 0x73:
+        continue
+    if h[2] == 0x74:
+        h, d = enc_file(h, d, fsalt)
+    out += hsalt + enc(hkey, hiv, h) + d
+open(asset('rar4-save-4096kb-txt-pwf-test.rar'), 'wb').write(out)
@@ -0,0 +1,369 @@
+"""Encoder for the RAR 1.5 compression format.
+
+The encoder runs the adaptive state of the unrar reference decoder
+(unpack15.cpp) and emits the codes, which make it decode the tokens.
+"""
+from lz import find_matches
+
+L1 = (2, [0x8000, 0xa000, 0xc000, 0xd000, 0xe000, 0xea00, 0xee00, 0xf000, 0xf200, 0xf200, 0xffff],
+      [0, 0, 0, 2, 3, 5, 7, 11, 16, 20, 24, 32, 32])
+L2 = (3, [0xa000, 0xc000, 0xd000, 0xe000, 0xea00, 0xee00, 0xf000, 0xf200, 0xf240, 0xffff],
+      [0, 0, 0, 0, 5, 7, 9, 13, 18, 22, 26, 34, 36])
+HF0 = (4, [0x8000, 0xc000, 0xe000, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xffff],
+       [0, 0, 0, 0, 0, 8, 16, 24, 33, 33, 33, 33, 33])
+HF1 = (5, [0x2000, 0xc000, 0xe000, 0xf000, 0xf200, 0xf200, 0xf7e0, 0xffff],
+       [0, 0, 0, 0, 0, 0, 4, 44, 60, 76, 80, 80, 127])
+HF2 = (5, [0x1000, 0x2400, 0x8000, 0xc000, 0xfa00, 0xffff, 0xffff, 0xffff],
+       [0, 0, 0, 0, 0, 0, 2, 7, 53, 117, 233, 0, 0])
+HF3 = (6, [0x800, 0x2400, 0xee00, 0xfe80, 0xffff, 0xffff, 0xffff],
+       [0, 0, 0, 0, 0, 0, 0, 2, 16, 218, 251, 0, 0])
+HF4 = (8, [0xff00, 0xffff, 0xffff, 0xffff, 0xffff, 0xffff],
+       [0, 0, 0, 0, 0, 0, 0, 0, 0, 255, 0, 0, 0])
+
+SHORT_LEN1 = [1, 3, 4, 4, 5, 6, 7, 8, 8, 4, 4, 5, 6, 6, 4, 0]
+SHORT_XOR1 = [0, 0xa0, 0xd0, 0xe0, 0xf0, 0xf8, 0xfc, 0xfe, 0xff, 0xc0, 0x80, 0x90, 0x98, 0x9c, 0xb0]
+SHORT_LEN2 = [2, 3, 3, 3, 4, 4, 5, 6, 6, 4, 4, 5, 6, 6, 4, 0]
+SHORT_XOR2 = [0, 0x40, 0x60, 0xa0, 0xd0, 0xe0, 0xf0, 0xf8, 0xfc, 0xc0, 0x80, 0x90, 0x98, 0x9c, 0xb0]
+
+
+def decode_num(num, table):
+    start_pos, dec_tab, pos_tab = table
+    num &= 0xfff0
+    i = 0
+    while dec_tab[i] <= num:
+        start_pos += 1
+        i += 1
+    base = dec_tab[i - 1] if i > 0 else 0
+    return ((num - base) >> (16 - start_pos)) + pos_tab[start_pos], start_pos
+
+
+def encode_map(table):
+    """The shortest code for every number of a fixed table"""
+    m = {}
+    for num in range(0, 0x10000, 16):
+        v, bits = decode_num(num, table)
+        code = num >> (16 - bits)
+        if v not in m or m[v][1] > bits:
+            m[v] = (code, bits)
+    return m
+
+MAPS = {id(t): encode_map(t) for t in (L1, L2, HF0, HF1, HF2, HF3, HF4)}
+
+
+class CharSet:
+    def __init__(self, f):
+        self.set = [f(i) for i in range(256)]
+        self.ntp = [0] * 256
+
+    def correct(self):
+        for i in range(256):
+            self.set[i] = (self.set[i] & ~0xff) | (7 - i // 32)
+        self.ntp = [0] * 256
+        for i in range(7):
+            self.ntp[i] = (7 - i) * 32
+
+    def update(self, place, limit):
+        while True:
+            value = self.set[place]
+            cls = value & 0xff
+            new_place = self.ntp[cls]
+            self.ntp[cls] = (self.ntp[cls] + 1) & 0xff
+            value += 1
+            if value & 0xff > limit or value & 0xff == 0:
+                self.correct()
+                continue
+            self.set[place] = self.set[new_place]
+            self.set[new_place] = value
+            return value - 1
+
+    def place(self, sym):
+        for i, v in enumerate(self.set):
+            if v >> 8 == sym:
+                return i
+        raise ValueError(sym)
+
+
+class Encoder:
+    def __init__(self):
+        self.items = []
+        self.ch_set = CharSet(lambda i: i << 8)
+        self.ch_set_b = CharSet(lambda i: i << 8)
+        self.ch_set_c = CharSet(lambda i: ((-i) & 0xff) << 8)
+        self.ch_set_a = list(range(256))
+        self.ch_set_b.correct()
+        self.old_dist = [0] * 4
+        self.old_dist_ptr = 0
+        self.last_dist = 0
+        self.last_length = 0
+        self.avr_plc_b = 0
+        self.avr_ln1 = 0
+        self.avr_ln2 = 0
+        self.avr_ln3 = 0
+        self.num_huf = 0
+        self.buf60 = 0
+        self.avr_plc = 0x3500
+        self.max_dist3 = 0x2001
+        self.nhfb = 0x80
+        self.nlzb = 0x80
+        self.flags_cnt = 0
+        self.st_mode = False
+        self.l_count = 0
+        self.flags = None
+        self.stats = {}
+
+    def count(self, what):
+        self.stats[what] = self.stats.get(what, 0) + 1
+
+    def put(self, value, n):
+        self.items.append((value, n))
+
+    def put_num(self, value, table):
+        code, bits = MAPS[id(table)][value]
+        self.put(code, bits)
+
+    # flags
+
+    def finish_flags(self):
+        if self.flags is None:
+            return
+        index, bits = self.flags
+        byte = 0
+        for i, b in enumerate(bits):
+            byte |= b << (7 - i)
+        place = self.ch_set_c.place(byte)
+        code, n = MAPS[id(HF2)][place]
+        self.items[index] = (code, n)
+        assert self.ch_set_c.update(place, 0xff) >> 8 == byte
+        self.flags = None
+
+    def read_flags(self):
+        self.finish_flags()
+        self.flags = (len(self.items), [])
+        self.items.append(None)
+
+    def next_flag(self, flag):
+        self.flags_cnt -= 1
+        if self.flags_cnt < 0:
+            self.read_flags()
+            self.flags_cnt = 7
+        self.flags[1].append(flag)
+
+    # symbols
+
+    def literal_place_table(self):
+        if self.avr_plc > 0x75ff:
+            return HF4
+        if self.avr_plc > 0x5dff:
+            return HF3
+        if self.avr_plc > 0x35ff:
+            return HF2
+        if self.avr_plc > 0x0dff:
+            return HF1
+        return HF0
+
+    def huff(self, c):
+        """A literal, the flags are already written in the normal mode"""
+        place = self.ch_set.place(c)
+        table = self.literal_place_table()
+        if self.st_mode:
+            self.put_num(place + 1, table)
+        else:
+            self.put_num(place, table)
+            if self.num_huf >= 16 and self.flags_cnt == 0:
+                self.st_mode = True
+                self.count('st_mode')
+            self.num_huf += 1
+        self.avr_plc += place
+        self.avr_plc -= self.avr_plc >> 8
+        self.nhfb += 16
+        if self.nhfb > 0xff:
+            self.nhfb = 0x90
+            self.nlzb >>= 1
+        assert self.ch_set.update(place, 0xa1) >> 8 == c
+        self.count('literal')
+
+    def st_escape(self):
+        self.put_num(0, self.literal_place_table())
+
+    def st_exit(self):
+        self.st_escape()
+        self.put(1, 1)
+        self.num_huf = 0
+        self.st_mode = False
+        self.count('st_exit')
+
+    def st_match(self, length, dist):
+        self.st_escape()
+        self.put(0, 1)
+        self.put(1 if length == 4 else 0, 1)
+        self.put_num(dist >> 5, HF2)
+        self.put(dist & 31, 5)
+        self.count('st_match')
+
+    def insert_old_dist(self, d):
+        self.old_dist[self.old_dist_ptr & 3] = d
+        self.old_dist_ptr = (self.old_dist_ptr + 1) & 3
+
+    def short_code(self, idx):
+        if self.avr_ln1 < 37:
+            lens, xors, var = SHORT_LEN1, SHORT_XOR1, 1
+        else:
+            lens, xors, var = SHORT_LEN2, SHORT_XOR2, 3
+        n = self.buf60 + 3 if idx == var else lens[idx]
+        self.put(xors[idx] >> (8 - n), n)
+
+    def short(self, length, dist, kind):
+        """A short match: 'last' repeats the last match, 'old' uses an
+        old distance and 'near' a distance of the list A"""
+        self.num_huf = 0
+        if self.l_count == 2:
+            if kind == 'last':
+                self.put(1, 1)
+                self.count('short_last2')
+                return
+            self.put(0, 1)
+            self.l_count = 0
+
+        if kind == 'last':
+            self.short_code(9)
+            self.l_count += 1
+            self.count('short_last')
+            return
+
+        if kind == 'old':
+            n = [self.old_dist[(self.old_dist_ptr - k) & 3] for k in range(1, 5)].index(dist) + 1
+            self.short_code(9 + n)
+            self.l_count = 0
+            code = length - 2 - (dist > 256) - (dist >= self.max_dist3)
+            assert 0 <= code < 255
+            self.put_num(code, L1)
+            self.insert_old_dist(dist)
+            self.last_length, self.last_dist = length, dist
+            self.count('short_old')
+            return
+
+        idx = length - 2
+        self.short_code(idx)
+        self.l_count = 0
+        self.avr_ln1 += idx
+        self.avr_ln1 -= self.avr_ln1 >> 4
+        place = self.ch_set_a.index(dist - 1)
+        self.put_num(place, HF2)
+        if place > 0:
+            self.ch_set_a[place], self.ch_set_a[place - 1] = \
+                self.ch_set_a[place - 1], self.ch_set_a[place]
+        self.insert_old_dist(dist)
+        self.last_length, self.last_dist = length, dist
+        self.count('short')
+
+    def long(self, length, dist):
+        self.num_huf = 0
+        self.nlzb += 16
+        if self.nlzb > 0xff:
+            self.nlzb = 0x90
+            self.nhfb >>= 1
+        old_avr2 = self.avr_ln2
+
+        code = length - 3 - (dist >= self.max_dist3) - 8 * (dist <= 256)
+        assert 0 <= code <= 255, (length, dist)
+        if self.avr_ln2 >= 122:
+            self.put_num(code, L2)
+        elif self.avr_ln2 >= 64:
+            self.put_num(code, L1)
+        elif code < 8 and code > 0 or code == 0:
+            self.put(1, code + 1)
+        else:
+            self.put(code, 16)
+        self.avr_ln2 += code
+        self.avr_ln2 -= self.avr_ln2 >> 5
+
+        if self.avr_plc_b > 0x28ff:
+            table = HF2
+        elif self.avr_plc_b > 0x6ff:
+            table = HF1
+        else:
+            table = HF0
+        place = self.ch_set_b.place(dist >> 7)
+        self.put_num(place, table)
+        self.avr_plc_b += place
+        self.avr_plc_b -= self.avr_plc_b >> 8
+        assert self.ch_set_b.update(place, 0xff) >> 8 == dist >> 7
+        self.put(dist & 0x7f, 7)
+
+        old_avr3 = self.avr_ln3
+        if code != 1 and code != 4:
+            if code == 0 and dist <= self.max_dist3:
+                self.avr_ln3 += 1
+                self.avr_ln3 -= self.avr_ln3 >> 8
+            elif self.avr_ln3 > 0:
+                self.avr_ln3 -= 1
+        if old_avr3 > 0xb0 or (self.avr_plc >= 0x2a00 and old_avr2 < 0x40):
+            self.max_dist3 = 0x7f00
+        else:
+            self.max_dist3 = 0x2001
+        self.insert_old_dist(dist)
+        self.last_length, self.last_dist = length, dist
+        self.count('long')
+
+    # token selection
+
+    def token(self, tok):
+        if self.st_mode:
+            if tok[0] == 'lit' and self.ch_set.place(tok[1]) + 1 < 256:
+                self.huff(tok[1])
+                return
+            if tok[0] == 'match' and tok[1] in (3, 4) and 1 <= tok[2] < 256 * 32:
+                self.st_match(tok[1], tok[2])
+                return
+            self.st_exit()
+
+        lz_first = self.nlzb > self.nhfb
+        if tok[0] == 'lit':
+            if lz_first:
+                self.next_flag(0)
+                self.next_flag(1)
+            else:
+                self.next_flag(1)
+            self.huff(tok[1])
+            return
+
+        _, length, dist = tok
+        olds = [self.old_dist[(self.old_dist_ptr - k) & 3] for k in range(1, 5)]
+        if (length, dist) == (self.last_length, self.last_dist):
+            kind = 'last'
+        elif dist in olds and 0 <= length - 2 - (dist > 256) - (dist >= self.max_dist3) < 255:
+            kind = 'old'
+        elif length <= 10 and dist <= 256:
+            kind = 'near'
+        else:
+            kind = None
+        if kind:
+            self.next_flag(0)
+            self.next_flag(0)
+            self.short(length, dist, kind)
+        else:
+            if lz_first:
+                self.next_flag(1)
+            else:
+                self.next_flag(0)
+                self.next_flag(1)
+            self.long(length, dist)
+
+    def data(self):
+        self.finish_flags()
+        bits = []
+        for value, n in self.items:
+            for i in range(n - 1, -1, -1):
+                bits.append((value >> i) & 1)
+        bits += [0] * (-len(bits) % 8)
+        return bytes(int(''.join(map(str, bits[i:i + 8])), 2) for i in range(0, len(bits), 8))
+
+
+def compress(data):
+    enc = Encoder()
+    if data:
+        enc.read_flags()
+        enc.flags_cnt = 8
+    for tok in find_matches(data, 0x8000, max_len=200, max_dist=0x7fff):
+        enc.token(tok)
+    return enc.data(), enc.stats
@@ -0,0 +1,262 @@
+"""Encoder for the RAR 2.0 compression format (LZ and audio blocks).
+
+Follows the bit stream, which the unrar reference decoder (unpack20.cpp) reads.
+"""
+from bitw import BitWriter
+from huff import code_lengths, canonical_codes
+from lz import find_matches
+
+NC, DC, RC, BC, MC = 298, 48, 28, 19, 257
+LDECODE = [0,1,2,3,4,5,6,7,8,10,12,14,16,20,24,28,32,40,48,56,64,80,96,112,128,160,192,224]
+LBITS = [0,0,0,0,0,0,0,0,1,1,1,1,2,2,2,2,3,3,3,3,4,4,4,4,5,5,5,5]
+DDECODE = [0,1,2,3,4,6,8,12,16,24,32,48,64,96,128,192,256,384,512,768,1024,1536,2048,3072,
+           4096,6144,8192,12288,16384,24576,32768,49152,65536,98304,131072,196608,262144,
+           327680,393216,458752,524288,589824,655360,720896,786432,851968,917504,983040]
+DBITS = [0,0,0,0,1,1,2,2,3,3,4,4,5,5,6,6,7,7,8,8,9,9,10,10,11,11,12,12,13,13,14,14,15,15,
+         16,16,16,16,16,16,16,16,16,16,16,16,16,16]
+SDDECODE = [0, 4, 8, 16, 32, 64, 128, 192]
+SDBITS = [2, 2, 3, 4, 5, 6, 6, 6]
+
+
+def slot(value, bases, bits):
+    """Returns the slot and the extra bits of a value"""
+    for i in range(len(bases) - 1, -1, -1):
+        if bases[i] <= value:
+            assert value - bases[i] < (1 << bits[i])
+            return i, value - bases[i], bits[i]
+    raise ValueError(value)
+
+
+def write_lengths(w, lengths, old):
+    """Write the bit lengths of a block with the bit length table"""
+    syms = []
+    i = 0
+    while i < len(lengths):
+        l = lengths[i]
+        run = 1
+        while i + run < len(lengths) and lengths[i + run] == l:
+            run += 1
+        if l == 0 and run >= 11:
+            n = min(run, 138)
+            syms.append((18, n - 11, 7))
+            i += n
+        elif l == 0 and run >= 3:
+            n = min(run, 10)
+            syms.append((17, n - 3, 3))
+            i += n
+        elif i > 0 and lengths[i - 1] == l and run >= 3:
+            n = min(run, 6)
+            syms.append((16, n - 3, 2))
+            i += n
+        else:
+            syms.append(((l - old[i]) & 0xf, 0, 0))
+            i += 1
+    freqs = [0] * BC
+    for s, _, _ in syms:
+        freqs[s] += 1
+    bl = code_lengths(freqs)
+    for l in bl:
+        w.put(l, 4)
+    codes = canonical_codes(bl)
+    for s, extra, n in syms:
+        c, l = codes[s]
+        w.put(c, l)
+        if n:
+            w.put(extra, n)
+
+
+class Encoder:
+    def __init__(self):
+        self.w = BitWriter()
+        self.old_dist = [0] * 4
+        self.old_dist_ptr = 0
+        self.last_dist = 0
+        self.last_length = 0
+        self.old_table = [0] * (MC * 4)
+        # audio state
+        self.channels = 1
+        self.cur_channel = 0
+        self.channel_delta = 0
+        self.aud = [dict(k=[0]*5, d=[0]*4, last_delta=0, dif=[0]*11, byte_count=0, last_char=0)
+                    for _ in range(4)]
+
+    def copy_string(self, length, distance):
+        self.old_dist[self.old_dist_ptr & 3] = distance
+        self.old_dist_ptr += 1
+        self.last_dist = distance
+        self.last_length = length
+
+    # LZ blocks
+
+    def lz_block(self, tokens, keep_old, new_tables_symbol=False):
+        """Encode the tokens as a LZ block"""
+        syms = []  # (table, symbol, extra, nbits) entries
+        for tok in tokens:
+            if tok[0] == 'lit':
+                syms.append([('ld', tok[1], 0, 0)])
+                continue
+            _, length, dist = tok
+            if (length, dist) == (self.last_length, self.last_dist):
+                syms.append([('ld', 256, 0, 0)])
+                self.copy_string(length, dist)
+                continue
+            olds = [self.old_dist[(self.old_dist_ptr - n) & 3] for n in range(1, 5)]
+            if dist in olds:
+                n = olds.index(dist) + 1
+                adj = (dist >= 0x101) + (dist >= 0x2000) + (dist >= 0x40000)
+                code = length - 2 - adj
+                if 0 <= code <= 255:
+                    s, e, b = slot(code, LDECODE, LBITS)
+                    syms.append([('ld', 256 + n, 0, 0), ('rd', s, e, b)])
+                    self.copy_string(length, dist)
+                    continue
+            if length == 2 and dist <= 256:
+                s, e, b = slot(dist - 1, SDDECODE, SDBITS)
+                syms.append([('ld', 261 + s, e, b)])
+                self.copy_string(length, dist)
+                continue
+            adj = (dist >= 0x2000) + (dist >= 0x40000)
+            code = length - 3 - adj
+            assert 0 <= code <= 255, tok
+            s, e, b = slot(code, LDECODE, LBITS)
+            ds, de, db = slot(dist - 1, DDECODE, DBITS)
+            syms.append([('ld', 270 + s, e, b), ('dd', ds, de, db)])
+            self.copy_string(length, dist)
+
+        sizes = {'ld': NC, 'dd': DC, 'rd': RC}
+        freqs = {t: [0] * n for t, n in sizes.items()}
+        for group in syms:
+            for t, s, _, _ in group:
+                freqs[t][s] += 1
+        if new_tables_symbol:
+            freqs['ld'][269] += 1
+        lengths = {t: code_lengths(f) for t, f in freqs.items()}
+        table = lengths['ld'] + lengths['dd'] + lengths['rd']
+
+        w = self.w
+        w.put(0, 1)
+        w.put(1 if keep_old else 0, 1)
+        old = self.old_table if keep_old else [0] * len(self.old_table)
+        write_lengths(w, table, old)
+        self.old_table[:len(table)] = table
+
+        codes = {t: canonical_codes(l) for t, l in lengths.items()}
+        self.codes = codes
+        for group in syms:
+            for t, s, e, b in group:
+                c, l = codes[t][s]
+                w.put(c, l)
+                if b:
+                    w.put(e, b)
+
+    def new_tables(self):
+        c, l = self.codes['ld'][269]
+        self.w.put(c, l)
+
+    # audio blocks
+
+    def decode_audio(self, delta):
+        """The predictor of the decoder, returns the byte for the delta"""
+        v = self.aud[self.cur_channel]
+        v['byte_count'] += 1
+        d = v['d']
+        d[3] = d[2]
+        d[2] = d[1]
+        d[1] = v['last_delta'] - d[0]
+        d[0] = v['last_delta']
+        k = v['k']
+        pch = 8 * v['last_char'] + k[0] * d[0] + k[1] * d[1] + k[2] * d[2] + k[3] * d[3] \
+            + k[4] * self.channel_delta
+        pch = (pch >> 3) & 0xff
+        ch = (pch - delta) & 0xffffffff
+        dd = (delta - 256 if delta >= 128 else delta) * 8
+        dif = v['dif']
+        diffs = [0, d[0], -d[0], d[1], -d[1], d[2], -d[2], d[3], -d[3],
+                 self.channel_delta, -self.channel_delta]
+        for i in range(11):
+            dif[i] += abs(dd - diffs[i])
+        x = (ch - v['last_char']) & 0xff
+        v['last_delta'] = x - 256 if x >= 128 else x
+        self.channel_delta = v['last_delta']
+        # the C code keeps the unsigned value, only its low bits matter
+        v['last_char'] = ch if ch < 0x80000000 else ch - 0x100000000
+        if v['byte_count'] & 0x1f == 0:
+            min_dif = dif[0]
+            num = 0
+            dif[0] = 0
+            for i in range(1, 11):
+                if dif[i] < min_dif:
+                    min_dif = dif[i]
+                    num = i
+                dif[i] = 0
+            if num:
+                j = (num - 1) // 2
+                if num % 2 == 1:
+                    if k[j] >= -16:
+                        k[j] -= 1
+                elif k[j] < 16:
+                    k[j] += 1
+        return ch & 0xff
+
+    def predict(self):
+        """The byte, which the current channel predicts"""
+        v = self.aud[self.cur_channel]
+        d0 = v['last_delta']
+        d1 = v['last_delta'] - v['d'][0]
+        d2, d3 = v['d'][1], v['d'][2]
+        k = v['k']
+        pch = 8 * v['last_char'] + k[0] * d0 + k[1] * d1 + k[2] * d2 + k[3] * d3 \
+            + k[4] * self.channel_delta
+        return (pch >> 3) & 0xff
+
+    def audio_block(self, data, channels, keep_old):
+        deltas = []
+        for c in data:
+            delta = (self.predict() - c) & 0xff
+            assert self.decode_audio(delta) == c
+            deltas.append((self.cur_channel, delta))
+            self.cur_channel = (self.cur_channel + 1) % channels
+
+        freqs = [[0] * MC for _ in range(channels)]
+        for ch, delta in deltas:
+            freqs[ch][delta] += 1
+        lengths = [code_lengths(f) for f in freqs]
+        table = sum(lengths, [])
+
+        w = self.w
+        w.put(1, 1)
+        w.put(1 if keep_old else 0, 1)
+        w.put(channels - 1, 2)
+        old = self.old_table if keep_old else [0] * len(self.old_table)
+        write_lengths(w, table, old)
+        self.old_table[:len(table)] = table
+
+        codes = [canonical_codes(l) for l in lengths]
+        for ch, delta in deltas:
+            c, l = codes[ch][delta]
+            w.put(c, l)
+
+
+def compress_lz(data, split=None):
+    """Compress the data in LZ blocks, a new block starts at `split`"""
+    tokens = list(find_matches(data, 0x100000, max_len=257))
+    enc = Encoder()
+    if split is None:
+        enc.lz_block(tokens, False)
+    else:
+        # find the token index at which the output reaches the split
+        pos, i = 0, 0
+        while pos < split:
+            pos += 1 if tokens[i][0] == 'lit' else tokens[i][1]
+            i += 1
+        enc.lz_block(tokens[:i], False, new_tables_symbol=True)
+        enc.new_tables()
+        enc.lz_block(tokens[i:], True)
+    return enc.w.data()
+
+
+def compress_audio(data, channels):
+    enc = Encoder()
+    enc.channels = channels
+    enc.audio_block(data, channels, False)
+    return enc.w.data()
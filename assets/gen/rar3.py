@@ -0,0 +1,23 @@
+"""Key derivation and AES-128 encryption of RAR 3.x, needs the cryptography package"""
+import hashlib
+from cryptography.hazmat.primitives.ciphers import Cipher, algorithms, modes
+
+
+def kdf(pwd, salt):
+    """Key and IV of the password, SHA1 over 0x40000 rounds"""
+    raw = pwd.encode('utf-16-le') + (salt or b'')
+    assert len(raw) < 64
+    h = hashlib.sha1(); iv = bytearray(16)
+    for i in range(0x40000):
+        h.update(raw); h.update(bytes([i & 0xff, (i >> 8) & 0xff, (i >> 16) & 0xff]))
+        if i % (0x40000 // 16) == 0:
+            iv[i // (0x40000 // 16)] = h.copy().digest()[19]
+    d = h.digest()
+    key = b''.join(d[i*4:i*4+4][::-1] for i in range(4))
+    return key, bytes(iv)
+
+
+def enc(key, iv, data):
+    data = data + b'\0' * (-len(data) % 16)
+    e = Cipher(algorithms.AES(key), modes.CBC(iv)).encryptor()
+    return e.update(data) + e.finalize()
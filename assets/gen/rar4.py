@@ -0,0 +1,44 @@
+"""Helpers to build RAR 4.x archives out of the blocks of an existing one"""
+import os, struct, zlib
+
+ASSETS = os.path.join(os.path.dirname(os.path.abspath(__file__)), '..')
+
+
+def asset(name):
+    return os.path.join(ASSETS, name)
+
+
+def blocks(d):
+    """Yields the header and the data area of every block behind the signature"""
+    p = 7
+    while p < len(d):
+        crc, typ, fl, size = struct.unpack_from('<HBHH', d, p)
+        add = struct.unpack_from('<I', d, p + 7)[0] if fl & 0x8000 else 0
+        yield d[p:p+size], d[p+size:p+size+add]
+        p += size + add
+
+
+def fix_crc(h):
+    """Store the CRC16 of the header in its first two bytes"""
+    return struct.pack('<H', zlib.crc32(h[2:]) & 0xffff) + h[2:]
+
+
+def make_rar4(packed, unpacked, version, method=0x33, dict_bits=1, name=b'text.txt'):
+    """Archive with one packed file, the headers are taken from rar4-normal-4096kb-txt.rar"""
+    d = open(asset('rar4-normal-4096kb-txt.rar'), 'rb').read()
+    bl = list(blocks(d))
+    fh = bytearray(bl[1][0])
+    flags = struct.unpack_from('<H', fh, 3)[0]
+    flags = (flags & ~0xe0) | (dict_bits << 5)
+    struct.pack_into('<H', fh, 3, flags)
+    struct.pack_into('<I', fh, 7, len(packed))
+    struct.pack_into('<I', fh, 11, len(unpacked))
+    struct.pack_into('<I', fh, 16, zlib.crc32(unpacked))
+    fh[24] = version
+    fh[25] = method
+    old = struct.unpack_from('<H', fh, 26)[0]
+    fh[32:32 + old] = name
+    struct.pack_into('<H', fh, 26, len(name))
+    struct.pack_into('<H', fh, 5, len(fh))
+    fh = fix_crc(bytes(fh))
+    return d[:7] + bl[0][0] + fh + packed + bl[2][0]
@@ -1,160 +1,88 @@
-use crypto::aessafe::AesSafe256Decryptor;
-use crypto::blockmodes::{CbcDecryptor, DecPadding, NoPadding};
-use crypto::buffer::{BufferResult, ReadBuffer, RefReadBuffer, RefWriteBuffer, WriteBuffer};
-use crypto::hmac::Hmac;
-use crypto::pbkdf2::pbkdf2;
+use crypto::aessafe::{AesSafe128Decryptor, AesSafe256Decryptor};
+use crypto::digest::Digest;
 use crypto::sha2::Sha256;
-use crypto::symmetriccipher::{BlockDecryptor, Decryptor};
+use crypto::symmetriccipher::BlockDecryptor;
+use std::borrow::Borrow;
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::{Hash, Hasher};
 use std::io::{Error, ErrorKind, Read, Result, Seek, SeekFrom};
+use zeroize::{Zeroize, Zeroizing};
 
-use extra_block::FileEncryptionBlock;
+use encryption_block::EncryptionBlock;
+use error::RarError;
+use extra_block::FileEncryptionVersion;
 use file_block::FileBlock;
+use head_block::MAX_HEADER_SIZE;
+use password::{aborted, Keyring, PasswordResponse};
+use rar15_reader::key_rar15;
+use sha1::Sha1;
+use util::wipe;
+use vint::vint;
 
 /// RAR Decryption reader to decrypt .rar archive files
 ///
-/// Original source-code: https://docs.rs/aes-stream/0.2.1/aesstream/
+/// The data is decrypted block by block in the CBC mode. The round
+/// keys of the decryptor are wiped, when the reader is dropped.
 pub struct RarAesReader<R: Read> {
     /// Reader to read encrypted data from
     reader: R,
-    /// Decryptor to decrypt data with
-    dec: CbcDecryptor<AesSafe256Decryptor, DecPadding<NoPadding>>,
-    /// Define if the encryption is active
-    active: bool,
-    /// Block size of BlockDecryptor, needed when seeking to correctly seek to the nearest block
-    block_size: usize,
-    /// Buffer used to store blob needed to find out if we reached eof
+    /// Decryptor to decrypt data with, without one the data is passed through
+    aes: Option<AesKey>,
+    /// IV of the first block
+    init: [u8; 16],
+    /// IV of the next block
+    iv: [u8; 16],
+    /// Decrypted data, which was not returned yet
     buffer: Vec<u8>,
-    /// Indicates wheather eof of the underlying buffer was reached
-    eof: bool,
+    /// Position of the data in the buffer, which is returned next
+    pos: usize,
 }
 
 impl<R: Read> RarAesReader<R> {
-    /// Create a new decryption reader
-    pub fn new(reader: R, file: FileBlock, pwd: &str) -> RarAesReader<R> {
-        let mut key = [0u8; 32];
-        let mut active = false;
-        let mut feb = FileEncryptionBlock::default();
-        if let Some(f) = file.extra.file_encryption {
-            key = generate_key(&f, pwd);
-            active = true;
-            feb = f;
-        }
+    /// Create a new decryption reader with the key of `request_key`.
+    /// Without a key the data is passed through unchanged.
+    pub fn new(reader: R, file: FileBlock, key: Option<FileKey>) -> RarAesReader<R> {
+        let feb = file.extra.file_encryption.unwrap_or_default();
 
         // define decryptor
-        let aes = AesSafe256Decryptor::new(&key);
-        let block_size = aes.block_size();
-        let dec = CbcDecryptor::new(aes, NoPadding, feb.init.to_vec());
+        let (aes, iv) = match key {
+            Some(ref key) => {
+                let (aes, iv) = AesKey::new(key, &feb.init);
+                (Some(aes), iv)
+            }
+            None => (None, [0u8; 16]),
+        };
 
         RarAesReader {
             reader,
-            dec,
-            active,
-            block_size,
+            aes,
+            init: iv,
+            iv,
             buffer: Vec::new(),
-            eof: false,
-        }
-    }
-
-    /// Reads at max BUFFER_SIZE bytes, handles potential eof and returns the buffer as Vec<u8>
-    fn fill_buf(&mut self) -> Result<Vec<u8>> {
-        let mut eof_buffer = vec![0u8; ::BUFFER_SIZE];
-        let read = self.reader.read(&mut eof_buffer)?;
-        self.eof = read == 0;
-        eof_buffer.truncate(read);
-        Ok(eof_buffer)
-    }
-
-    /// Reads and decrypts data from the underlying stream and writes it into the passed buffer.
-    ///
-    /// The CbcDecryptor has an internal output buffer, but not an input buffer.
-    /// Therefore, we need to take care of letfover input.
-    /// Additionally, we need to handle eof correctly, as CbcDecryptor needs to correctly interpret
-    /// padding.
-    /// Thus, we need to read 2 buffers. The first one is read as input for decryption and the second
-    /// one to determine if eof is reached.
-    /// The next time this function is called, the second buffer is passed as input into decryption
-    /// and the first buffer is filled to find out if we reached eof.
-    ///
-    /// # Parameters
-    ///
-    /// * **buf**: Buffer to write decrypted data into.
-    fn read_decrypt(&mut self, buf: &mut [u8]) -> Result<usize> {
-        // if this is the first iteration, fill internal buffer
-        if self.buffer.is_empty() && !self.eof {
-            self.buffer = self.fill_buf()?;
-        }
-
-        let buf_len = buf.len();
-        let mut write_buf = RefWriteBuffer::new(buf);
-        let res;
-        let remaining;
-        {
-            let mut read_buf = RefReadBuffer::new(&self.buffer);
-
-            // test if CbcDecryptor still has enough decrypted data or we have enough buffered
-            res = self
-                .dec
-                .decrypt(&mut read_buf, &mut write_buf, self.eof)
-                .map_err(|e| Error::new(ErrorKind::Other, format!("decryption error: {:?}", e)))?;
-            remaining = read_buf.remaining();
-        }
-        // keep remaining bytes
-        let len = self.buffer.len();
-        self.buffer.drain(..(len - remaining));
-        // if we were able to decrypt, return early
-        match res {
-            BufferResult::BufferOverflow => return Ok(buf_len),
-            BufferResult::BufferUnderflow if self.eof => return Ok(write_buf.position()),
-            _ => {}
-        }
-
-        // else read new buffer
-        let mut dec_len = 0;
-        // We must return something, if we have something.
-        // If the reader doesn't return enough so that we can decrypt a block, we need to continue
-        // reading until we have enough data to return one decrypted block, or until we reach eof.
-        // If we reach eof, we will be able to decrypt the final block because of padding.
-        while dec_len == 0 && !self.eof {
-            let eof_buffer = self.fill_buf()?;
-            let remaining;
-            {
-                let mut read_buf = RefReadBuffer::new(&self.buffer);
-                self.dec
-                    .decrypt(&mut read_buf, &mut write_buf, self.eof)
-                    .map_err(|e| {
-                        Error::new(ErrorKind::Other, format!("decryption error: {:?}", e))
-                    })?;
-                let mut dec = write_buf.take_read_buffer();
-                let dec = dec.take_remaining();
-                dec_len = dec.len();
-                remaining = read_buf.remaining();
-            }
-            // keep remaining bytes
-            let len = self.buffer.len();
-            self.buffer.drain(..(len - remaining));
-            // append newly read bytes
-            self.buffer.extend(eof_buffer);
+            pos: 0,
         }
-        Ok(dec_len)
     }
 }
+
 impl<R: Read + Seek> RarAesReader<R> {
-    /// Seeks to *offset* from the start of the file
+    /// Seeks to *offset* from the start of the decrypted data
     fn seek_from_start(&mut self, offset: u64) -> Result<u64> {
-        let block_num = offset / self.block_size as u64;
-        let block_offset = offset % self.block_size as u64;
-        // reset CbcDecryptor
-        self.reader
-            .seek(SeekFrom::Start((block_num - 1) * self.block_size as u64))?;
-        let mut iv = vec![0u8; self.block_size];
-        self.reader.read_exact(&mut iv)?;
-        self.dec.reset(&iv);
-        self.buffer = Vec::new();
-        self.eof = false;
-        let mut skip = vec![0u8; block_offset as usize];
-        self.read_exact(&mut skip)?;
-        // subtract IV
-        Ok(offset - 16)
+        let block_num = offset / 16;
+        let block_offset = offset % 16;
+        // the encrypted block in front is the IV of the block
+        if block_num == 0 {
+            self.reader.seek(SeekFrom::Start(0))?;
+            self.iv = self.init;
+        } else {
+            self.reader.seek(SeekFrom::Start((block_num - 1) * 16))?;
+            self.reader.read_exact(&mut self.iv)?;
+        }
+        self.buffer.clear();
+        self.pos = 0;
+        let mut skip = [0u8; 16];
+        self.read_exact(&mut skip[..block_offset as usize])?;
+        Ok(offset)
     }
 }
 
@@ -162,12 +90,38 @@ impl<R: Read> Read for RarAesReader<R> {
     /// Reads encrypted data from the underlying reader, decrypts it and writes the result into the
     /// passed buffer.
     fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
-        if !self.active {
-            return self.reader.read(buf);
+        let aes = match self.aes {
+            Some(ref aes) => aes,
+            None => return self.reader.read(buf),
+        };
+
+        // decrypt the next whole blocks, when everything was returned
+        if self.pos == self.buffer.len() {
+            self.buffer.resize(::BUFFER_SIZE, 0);
+            let mut len = 0;
+            while len < self.buffer.len() {
+                match self.reader.read(&mut self.buffer[len..])? {
+                    0 => break,
+                    n => len += n,
+                }
+            }
+
+            if len % 16 != 0 {
+                return Err(Error::new(
+                    ErrorKind::UnexpectedEof,
+                    "Encrypted data ends within a block",
+                ));
+            }
+
+            self.buffer.truncate(len);
+            cbc_decrypt(aes.decryptor(), &mut self.iv, &mut self.buffer);
+            self.pos = 0;
         }
 
-        let read = self.read_decrypt(buf)?;
-        Ok(read)
+        let len = buf.len().min(self.buffer.len() - self.pos);
+        buf[..len].copy_from_slice(&self.buffer[self.pos..self.pos + len]);
+        self.pos += len;
+        Ok(len)
     }
 }
 
@@ -175,37 +129,923 @@ impl<R: Read + Seek> Seek for RarAesReader<R> {
     /// Seek to an offset, in bytes, in a stream.
     /// [Read more](https://doc.rust-lang.org/nightly/std/io/trait.Seek.html#tymethod.seek)
     ///
-    /// When seeking, this reader takes care of reinitializing the CbcDecryptor with the correct IV.
+    /// When seeking, this reader takes care of reinitializing the decryption with the correct IV.
     /// The passed position does *not* need to be aligned to the blocksize.
+    /// The end of the stream is the end of the encrypted data, including the padding.
     fn seek(&mut self, pos: SeekFrom) -> Result<u64> {
-        match pos {
-            SeekFrom::Start(offset) => {
-                // +16 because first block is the iv
-                self.seek_from_start(offset + 16)
+        if self.aes.is_none() {
+            return self.reader.seek(pos);
+        }
+
+        let offset = match pos {
+            SeekFrom::Start(offset) => offset,
+            SeekFrom::Current(0) => {
+                // the decrypted data in the buffer was not returned yet
+                let pos = self.reader.stream_position()?;
+                return Ok(pos - (self.buffer.len() - self.pos) as u64);
+            }
+            SeekFrom::Current(amt) => {
+                let pos = self.stream_position()?;
+                checked_offset(pos, amt)?
+            }
+            SeekFrom::End(_) => self.reader.seek(pos)?,
+        };
+
+        self.seek_from_start(offset)
+    }
+}
+
+/// Add a signed amount to a position, which must not get negative
+pub fn checked_offset(pos: u64, amt: i64) -> Result<u64> {
+    if amt < 0 {
+        pos.checked_sub(amt.unsigned_abs())
+    } else {
+        pos.checked_add(amt as u64)
+    }
+    .ok_or_else(|| {
+        Error::new(
+            ErrorKind::InvalidInput,
+            "Invalid seek to a negative or overflowing position",
+        )
+    })
+}
+
+/// The AES decryptors of the different key sizes.
+///
+/// Their round keys are wiped, when they are dropped.
+enum AesKey {
+    Aes256(AesSafe256Decryptor),
+    Aes128(AesSafe128Decryptor),
+}
+
+impl AesKey {
+    /// Create the decryptor for the key of a file and return it with the IV.
+    /// RAR 5.0 stores the IV in the file, RAR 3.x derives it from the password.
+    fn new(key: &FileKey, init: &[u8; 16]) -> (AesKey, [u8; 16]) {
+        match *key {
+            FileKey::Aes256 { ref key, .. } => {
+                (AesKey::Aes256(AesSafe256Decryptor::new(key)), *init)
             }
-            SeekFrom::End(_) | SeekFrom::Current(_) => {
-                let pos = self.reader.seek(pos)?;
-                self.seek_from_start(pos)
+            FileKey::Aes128 { ref key, ref iv } => {
+                (AesKey::Aes128(AesSafe128Decryptor::new(key)), *iv)
             }
+            FileKey::Rar15(_) => unreachable!("RAR 1.5 files are decrypted by the Rar15Reader"),
+        }
+    }
+
+    /// Returns the inner decryptor
+    fn decryptor(&self) -> &dyn BlockDecryptor {
+        match *self {
+            AesKey::Aes256(ref aes) => aes,
+            AesKey::Aes128(ref aes) => aes,
         }
     }
 }
 
-/// Generate the decryption key from the encryption block infos
-fn generate_key(feb: &FileEncryptionBlock, pwd: &str) -> [u8; 32] {
-    // calculate the hashing iterations
-    let iter_number = 2u32.pow(feb.kdf_count.into());
+impl Drop for AesKey {
+    fn drop(&mut self) {
+        // SAFETY: the decryptors of rust-crypto 0.2 only hold their round
+        // keys as arrays of `u16` or `u32`, for which zeros are valid values.
+        // They own no memory and have no `Drop` of their own, so the zeroed
+        // values are dropped like any other. `test_wipe_layouts` notices,
+        // when a new version adds anything else.
+        unsafe {
+            match *self {
+                AesKey::Aes256(ref mut aes) => wipe(aes),
+                AesKey::Aes128(ref mut aes) => wipe(aes),
+            }
+        }
+    }
+}
+
+/// The keys which are derived from the password of an encrypted file.
+///
+/// The keys are wiped, when they are dropped.
+#[derive(PartialEq, Clone)]
+pub enum FileKey {
+    /// RAR 5.0 AES-256 key with the key of the HMAC,
+    /// which converts the checksums of the file
+    Aes256 { key: [u8; 32], hash_key: [u8; 32] },
+    /// RAR 3.x AES-128 key with its IV
+    Aes128 { key: [u8; 16], iv: [u8; 16] },
+    /// RAR 1.5 key, which is used by the `Rar15Reader`
+    Rar15([u16; 4]),
+}
+
+impl FileKey {
+    /// Convert a CRC32 into the tweaked one, which is stored for encrypted files
+    pub fn tweak_crc(&self, crc: u32) -> u32 {
+        let hash_key = match *self {
+            FileKey::Aes256 { ref hash_key, .. } => hash_key,
+            FileKey::Aes128 { .. } | FileKey::Rar15(_) => return crc,
+        };
+        let digest = HmacSha256::new(hash_key).mac(&crc.to_le_bytes());
+
+        let mut tweaked = 0;
+        for (i, d) in digest.iter().enumerate() {
+            tweaked ^= (*d as u32) << ((i & 3) * 8);
+        }
+        tweaked
+    }
+
+    /// Convert a BLAKE2sp hash into the tweaked one, which is stored for encrypted files
+    pub fn tweak_hash(&self, hash: &[u8; 32]) -> [u8; 32] {
+        match *self {
+            FileKey::Aes256 { ref hash_key, .. } => HmacSha256::new(hash_key).mac(hash),
+            FileKey::Aes128 { .. } | FileKey::Rar15(_) => *hash,
+        }
+    }
+}
+
+impl Drop for FileKey {
+    fn drop(&mut self) {
+        match *self {
+            FileKey::Aes256 {
+                ref mut key,
+                ref mut hash_key,
+            } => {
+                key.zeroize();
+                hash_key.zeroize();
+            }
+            FileKey::Aes128 {
+                ref mut key,
+                ref mut iv,
+            } => {
+                key.zeroize();
+                iv.zeroize();
+            }
+            FileKey::Rar15(ref mut key) => key.zeroize(),
+        }
+    }
+}
+
+/// The keys are never printed
+impl fmt::Debug for FileKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            FileKey::Aes256 { .. } => "Aes256",
+            FileKey::Aes128 { .. } => "Aes128",
+            FileKey::Rar15(_) => "Rar15",
+        };
+        write!(f, "FileKey::{}(<redacted>)", name)
+    }
+}
+
+/// HMAC-SHA256 with the hash states of the padded key.
+///
+/// The key is hashed only once for all messages
+/// and the hash states are wiped, when they are dropped.
+struct HmacSha256 {
+    inner: Sha256,
+    outer: Sha256,
+}
+
+impl HmacSha256 {
+    /// Create the HMAC with the key
+    fn new(key: &[u8]) -> HmacSha256 {
+        // long keys are replaced by their hash
+        let mut block = Zeroizing::new([0u8; 64]);
+        if key.len() > 64 {
+            let mut sha = Sha256::new();
+            sha.input(key);
+            sha.result(&mut block[..32]);
+            // SAFETY: see the `Drop` of `HmacSha256`
+            unsafe { wipe(&mut sha) };
+        } else {
+            block[..key.len()].copy_from_slice(key);
+        }
+
+        let mut hmac = HmacSha256 {
+            inner: Sha256::new(),
+            outer: Sha256::new(),
+        };
+        let mut pad = Zeroizing::new([0u8; 64]);
+        for (p, b) in pad.iter_mut().zip(block.iter()) {
+            *p = b ^ 0x36;
+        }
+        hmac.inner.input(&pad[..]);
+        for (p, b) in pad.iter_mut().zip(block.iter()) {
+            *p = b ^ 0x5C;
+        }
+        hmac.outer.input(&pad[..]);
+
+        hmac
+    }
+
+    /// Returns the HMAC of the data
+    fn mac(&self, data: &[u8]) -> [u8; 32] {
+        let mut digest = [0u8; 32];
+
+        let mut sha = self.inner;
+        sha.input(data);
+        sha.result(&mut digest);
+
+        let mut sha2 = self.outer;
+        sha2.input(&digest);
+        sha2.result(&mut digest);
+
+        // SAFETY: see the `Drop` of `HmacSha256`
+        unsafe {
+            wipe(&mut sha);
+            wipe(&mut sha2);
+        }
+        digest
+    }
+}
+
+impl Drop for HmacSha256 {
+    fn drop(&mut self) {
+        // SAFETY: the `Sha256` of rust-crypto 0.2 consists of integer
+        // arrays, a length, a buffer index and a `bool`, for which zeros
+        // are valid values (`false` for the `bool`). It owns no memory and
+        // has no `Drop` of its own, so the zeroed values are dropped like
+        // any other. `test_wipe_layouts` notices, when a new version adds
+        // anything else.
+        unsafe {
+            wipe(&mut self.inner);
+            wipe(&mut self.outer);
+        }
+    }
+}
+
+/// Derive the keys of an encrypted file from the password.
+///
+/// The password is verified with the check value of the file, when the
+/// archive provides one. Files which are not encrypted don't need a key.
+pub fn derive_key(
+    file: &FileBlock,
+    pwd: &str,
+    cache: &mut KeyCache,
+) -> ::std::result::Result<Option<FileKey>, ::failure::Error> {
+    let feb = match file.extra.file_encryption {
+        Some(ref feb) => feb,
+        None => return Ok(None),
+    };
+
+    if pwd.is_empty() {
+        return Err(RarError::PasswordRequired {
+            name: file.name.clone(),
+        }
+        .into());
+    }
+
+    match feb.version {
+        FileEncryptionVersion::Aes256 => {}
+        FileEncryptionVersion::Rar30(ref salt) => {
+            let keys = cache.generate_key_rar3(pwd, salt.as_ref());
+            return Ok(Some(FileKey::Aes128 {
+                key: keys.0,
+                iv: keys.1,
+            }));
+        }
+        FileEncryptionVersion::Rar15 => return Ok(Some(FileKey::Rar15(key_rar15(pwd)))),
+        // the block cipher of the unpack versions 20 to 28 isn't implemented yet
+        FileEncryptionVersion::Rar20 => {
+            return Err(format_err!(
+                "RAR 2.0 encryption of {} is not supported",
+                file.name
+            ))
+        }
+        ref v => return Err(format_err!("Encryption {:?} is not supported", v)),
+    }
+
+    let keys = cache.generate_key(feb.kdf_count, &feb.salt, pwd);
+
+    if feb.flags.pw_check_data && !check_password(&feb.pw_check, &keys.2) {
+        return Err(RarError::WrongPassword {
+            name: file.name.clone(),
+        }
+        .into());
+    }
+
+    Ok(Some(FileKey::Aes256 {
+        key: keys.0,
+        hash_key: keys.1,
+    }))
+}
+
+/// Decision about the data of a file, after the password provider was asked
+#[derive(PartialEq, Debug)]
+pub enum KeyRequest {
+    /// Extract the data with the key, files which are not encrypted have none
+    Key(Option<FileKey>),
+    /// Skip the data of the file
+    Skip,
+}
+
+/// Ask the password provider of the keyring for the key of a file.
+///
+/// A password which doesn't match the check value of the file is
+/// asked again with a higher retry count. The provider is not asked
+/// for files which are not encrypted.
+pub fn request_key(
+    file: &FileBlock,
+    keyring: &mut Keyring,
+) -> ::std::result::Result<KeyRequest, ::failure::Error> {
+    if file.extra.file_encryption.is_none() {
+        return Ok(KeyRequest::Key(None));
+    }
+
+    let mut retry = 0;
+    loop {
+        let pwd = match keyring.passwords.password(file, retry) {
+            PasswordResponse::Password(pwd) => pwd,
+            PasswordResponse::Skip => return Ok(KeyRequest::Skip),
+            PasswordResponse::Abort => return Err(aborted(&file.name, retry).into()),
+        };
+
+        match derive_key(file, &pwd, &mut keyring.keys) {
+            Ok(key) => return Ok(KeyRequest::Key(key)),
+            Err(e) => match e.downcast::<RarError>() {
+                Ok(RarError::WrongPassword { .. }) => retry += 1,
+                Ok(e) => return Err(e.into()),
+                Err(e) => return Err(e),
+            },
+        }
+    }
+}
+
+/// Key, hash key and password check value of a RAR5 password
+type Pbkdf2Keys = ([u8; 32], [u8; 32], [u8; 8]);
+/// Key and IV of a RAR 3.x password
+type Rar3Keys = ([u8; 16], [u8; 16]);
+
+/// RAR5 keys of a password by KDF count and salt
+type Pbkdf2Cache = HashMap<(u8, [u8; 16]), Zeroizing<Pbkdf2Keys>>;
+/// RAR 3.x keys of a password by salt
+type Rar3Cache = HashMap<Option<[u8; 8]>, Zeroizing<Rar3Keys>>;
+
+/// Password, by which the keys of the `KeyCache` are looked up.
+///
+/// It hashes and compares like a `str`, so a borrowed password finds
+/// its keys without a copy. The copy is wiped, when it is dropped.
+#[derive(PartialEq, Eq)]
+struct CachedPassword(Zeroizing<String>);
+
+impl Hash for CachedPassword {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.as_str().hash(state)
+    }
+}
+
+impl Borrow<str> for CachedPassword {
+    fn borrow(&self) -> &str {
+        &self.0
+    }
+}
+
+/// Cache of the keys, which were derived from the passwords of an archive.
+///
+/// WinRAR uses the same salt for all files of an archive, so the
+/// expensive key derivation only needs to be done once per password.
+/// The passwords and keys are wiped, when the cache is dropped.
+#[derive(Default)]
+pub struct KeyCache {
+    /// RAR5 keys by password, then by KDF count and salt
+    pbkdf2: HashMap<CachedPassword, Pbkdf2Cache>,
+    /// RAR 3.x keys by password, then by salt
+    rar3: HashMap<CachedPassword, Rar3Cache>,
+}
+
+/// Returns the entry of the password, the password is copied only,
+/// when it isn't in the map yet.
+fn password_entry<'a, V: Default>(map: &'a mut HashMap<CachedPassword, V>, pwd: &str) -> &'a mut V {
+    if !map.contains_key(pwd) {
+        map.insert(CachedPassword(Zeroizing::new(pwd.into())), V::default());
+    }
+    map.get_mut(pwd).unwrap()
+}
+
+impl KeyCache {
+    /// Returns the keys of `generate_key`, which are derived only once
+    pub fn generate_key(
+        &mut self,
+        kdf_count: u8,
+        salt: &[u8; 16],
+        pwd: &str,
+    ) -> Zeroizing<Pbkdf2Keys> {
+        password_entry(&mut self.pbkdf2, pwd)
+            .entry((kdf_count, *salt))
+            .or_insert_with(|| generate_key(kdf_count, salt, pwd))
+            .clone()
+    }
+
+    /// Returns the key and IV of `generate_key_rar3`, which are derived only once
+    pub fn generate_key_rar3(&mut self, pwd: &str, salt: Option<&[u8; 8]>) -> Zeroizing<Rar3Keys> {
+        password_entry(&mut self.rar3, pwd)
+            .entry(salt.cloned())
+            .or_insert_with(|| generate_key_rar3(pwd, salt))
+            .clone()
+    }
+}
 
-    // key store
-    let mut key = [0u8; 32];
+/// Generate the decryption key, the hash key and the password
+/// check value from the encryption block infos.
+///
+/// All are taken from the same PBKDF2 run, the hash key 16
+/// and the check value 32 iterations after the key.
+fn generate_key(kdf_count: u8, salt: &[u8; 16], pwd: &str) -> Zeroizing<Pbkdf2Keys> {
+    // calculate the hashing iterations
+    let iter_number = 2u32.pow(kdf_count.into());
 
     // define the hashing type and pwd
-    let mut mac = Hmac::new(Sha256::new(), pwd.as_bytes());
+    let mac = HmacSha256::new(pwd.as_bytes());
+
+    // the first round hashes the salt with the block number
+    let mut block = [0u8; 20];
+    block[..16].copy_from_slice(salt);
+    block[16..].copy_from_slice(&[0, 0, 0, 1]);
+    let mut u = Zeroizing::new(mac.mac(&block));
+    let mut t = u.clone();
+
+    let mut keys = Zeroizing::new(([0u8; 32], [0u8; 32], [0u8; 8]));
+    for i in 1..iter_number + 32 {
+        if i == iter_number {
+            keys.0 = *t;
+        } else if i == iter_number + 16 {
+            keys.1 = *t;
+        }
+
+        *u = mac.mac(&u[..]);
+        for (t, u) in t.iter_mut().zip(u.iter()) {
+            *t ^= u;
+        }
+    }
+
+    // fold the last value into the 8 byte check value
+    for (i, t) in t.iter().enumerate() {
+        keys.2[i % 8] ^= t;
+    }
+
+    keys
+}
+
+/// Compare the stored check value with the one of the password.
+///
+/// The stored check value is protected by the first 4 bytes of its
+/// SHA-256 hash against damage. A damaged one can't reject the password.
+fn check_password(stored: &[u8; 12], pw_check: &[u8; 8]) -> bool {
+    // the stored value is no secret, it can be checked by everybody
+    let mut hash = [0u8; 32];
+    let mut sha = Sha256::new();
+    sha.input(&stored[..8]);
+    sha.result(&mut hash);
+
+    hash[..4] != stored[8..] || stored[..8] == pw_check[..]
+}
+
+/// Derive the key of the encrypted headers from the password.
+///
+/// The `name` of the archive is used for the errors.
+pub fn derive_header_key(
+    eb: &EncryptionBlock,
+    pwd: &str,
+    name: &str,
+    cache: &mut KeyCache,
+) -> ::std::result::Result<Zeroizing<[u8; 32]>, ::failure::Error> {
+    if eb.version != FileEncryptionVersion::Aes256 {
+        return Err(format_err!("Encryption {:?} is not supported", eb.version));
+    }
+
+    if pwd.is_empty() {
+        return Err(RarError::PasswordRequired { name: name.into() }.into());
+    }
+
+    let keys = cache.generate_key(eb.kdf_count, &eb.salt, pwd);
+
+    if eb.pw_check_data && !check_password(&eb.pw_check, &keys.2) {
+        return Err(RarError::WrongPassword { name: name.into() }.into());
+    }
+
+    Ok(Zeroizing::new(keys.0))
+}
+
+/// Generate the RAR 3.x AES-128 key and IV from the password and the salt.
+///
+/// The password is hashed as UTF-16 together with the salt and a round
+/// counter with SHA-1. The IV is collected from the digests in between.
+fn generate_key_rar3(pwd: &str, salt: Option<&[u8; 8]>) -> Zeroizing<Rar3Keys> {
+    const ROUNDS: u32 = 0x40000;
+
+    // reserve the room for the salt, a reallocation would leave a copy behind
+    let pwd_len = pwd.encode_utf16().count();
+    let mut raw = Zeroizing::new(Vec::with_capacity(pwd_len * 2 + 8));
+    for c in pwd.encode_utf16() {
+        raw.extend_from_slice(&c.to_le_bytes());
+    }
+    if let Some(salt) = salt {
+        raw.extend_from_slice(salt);
+    }
+
+    let mut sha = Sha1::new();
+    let mut keys = Zeroizing::new(([0u8; 16], [0u8; 16]));
+    for i in 0..ROUNDS {
+        sha.update_rar29(&mut raw);
+        sha.update(&[i as u8, (i >> 8) as u8, (i >> 16) as u8]);
 
-    // hash the key
-    pbkdf2(&mut mac, &feb.salt, iter_number, &mut key);
+        if i % (ROUNDS / 16) == 0 {
+            keys.1[(i / (ROUNDS / 16)) as usize] = sha.digest()[19];
+        }
+    }
+
+    // the words of the digest are stored in little endian
+    let digest = Zeroizing::new(sha.digest());
+    for (k, d) in keys.0.chunks_mut(4).zip(digest.chunks(4)) {
+        k.copy_from_slice(d);
+        k.reverse();
+    }
+
+    keys
+}
+
+/// Read the buffer completly, but return false when the reader was
+/// already at the end. This marks the end of the encrypted headers.
+pub fn read_or_eof<R: Read>(reader: &mut R, buf: &mut [u8]) -> Result<bool> {
+    let mut read = 0;
+    while read < buf.len() {
+        match reader.read(&mut buf[read..])? {
+            0 if read == 0 => return Ok(false),
+            0 => return Err(Error::new(ErrorKind::UnexpectedEof, "Truncated header")),
+            n => read += n,
+        }
+    }
+
+    Ok(true)
+}
+
+/// Read and decrypt the next RAR5 header of an archive with encrypted headers.
+///
+/// Each header is stored behind its own IV and padded to the AES block size.
+/// The plain header is returned without the padding, at the end of the data
+/// nothing is returned.
+pub fn decrypt_header<R: Read>(reader: &mut R, key: &[u8; 32]) -> Result<Vec<u8>> {
+    let mut iv = [0u8; 16];
+    if !read_or_eof(reader, &mut iv)? {
+        return Ok(vec![]);
+    }
+
+    // the first block contains the size of the header
+    decrypt_blocks(
+        reader,
+        AesKey::Aes256(AesSafe256Decryptor::new(key)).decryptor(),
+        iv,
+        |header| match vint(&header[4..]) {
+            Ok((rest, size)) if size <= MAX_HEADER_SIZE => Some(16 - rest.len() + size as usize),
+            _ => None,
+        },
+    )
+}
 
-    key
+/// Read and decrypt the next RAR 1.5 - 4.x header of an archive with encrypted headers.
+///
+/// The key and IV are derived from the salt in front of the header,
+/// which was read already.
+pub fn decrypt_header_rar3<R: Read>(
+    reader: &mut R,
+    key: &[u8; 16],
+    iv: &[u8; 16],
+) -> Result<Vec<u8>> {
+    // the first block contains the size of the header
+    decrypt_blocks(
+        reader,
+        AesKey::Aes128(AesSafe128Decryptor::new(key)).decryptor(),
+        *iv,
+        |header| match u16::from_le_bytes([header[5], header[6]]) as usize {
+            size if size >= 7 => Some(size),
+            _ => None,
+        },
+    )
+}
+
+/// Decrypt a header, which is padded to the AES block size.
+///
+/// The size of the header is taken from its first decrypted block.
+fn decrypt_blocks<R: Read, F>(
+    reader: &mut R,
+    aes: &dyn BlockDecryptor,
+    mut iv: [u8; 16],
+    header_size: F,
+) -> Result<Vec<u8>>
+where
+    F: Fn(&[u8]) -> Option<usize>,
+{
+    let mut header = vec![0u8; 16];
+    reader.read_exact(&mut header)?;
+    cbc_decrypt(aes, &mut iv, &mut header);
+
+    let size = match header_size(&header) {
+        Some(size) => size,
+        None => return Err(Error::other("Invalid size of an encrypted header")),
+    };
+
+    // read the rest of the header including the padding
+    let padded = size.div_ceil(16) * 16;
+    let mut rest = vec![0u8; padded - 16];
+    reader.read_exact(&mut rest)?;
+    cbc_decrypt(aes, &mut iv, &mut rest);
+    header.extend(rest);
+
+    header.truncate(size);
+    Ok(header)
+}
+
+/// Decrypt whole AES blocks in the CBC mode and update the IV
+fn cbc_decrypt(aes: &dyn BlockDecryptor, iv: &mut [u8; 16], data: &mut [u8]) {
+    let mut plain = [0u8; 16];
+    for block in data.chunks_mut(16) {
+        aes.decrypt_block(block, &mut plain);
+        for (p, v) in plain.iter_mut().zip(iv.iter()) {
+            *p ^= v;
+        }
+        iv.copy_from_slice(block);
+        block.copy_from_slice(&plain);
+    }
+}
+
+#[test]
+fn test_derive_key() {
+    let mut feb = ::extra_block::FileEncryptionBlock::default();
+    feb.flags.pw_check_data = true;
+    feb.kdf_count = 4;
+    feb.salt = [
+        145, 54, 92, 222, 142, 142, 13, 19, 255, 186, 128, 233, 43, 95, 8, 74,
+    ];
+
+    // store the check value of the password with its checksum
+    let (key, hash_key, pw_check) = *generate_key(feb.kdf_count, &feb.salt, "test");
+    feb.pw_check[..8].copy_from_slice(&pw_check);
+    let mut hash = [0u8; 32];
+    let mut sha = Sha256::new();
+    sha.input(&pw_check);
+    sha.result(&mut hash);
+    feb.pw_check[8..].copy_from_slice(&hash[..4]);
+
+    let mut cache = KeyCache::default();
+    let mut file = FileBlock {
+        name: "text.txt".into(),
+        ..FileBlock::default()
+    };
+    assert_eq!(derive_key(&file, "", &mut cache).unwrap(), None);
+
+    file.extra.file_encryption = Some(feb);
+    assert_eq!(
+        derive_key(&file, "test", &mut cache).unwrap(),
+        Some(FileKey::Aes256 { key, hash_key })
+    );
+    assert_eq!(
+        derive_key(&file, "wrong", &mut cache)
+            .unwrap_err()
+            .downcast::<RarError>()
+            .unwrap(),
+        RarError::WrongPassword {
+            name: "text.txt".into()
+        }
+    );
+    assert_eq!(
+        derive_key(&file, "", &mut cache)
+            .unwrap_err()
+            .downcast::<RarError>()
+            .unwrap(),
+        RarError::PasswordRequired {
+            name: "text.txt".into()
+        }
+    );
+
+    // files of the unpack versions 20 to 28 are reported by name
+    file.extra.file_encryption = Some(::extra_block::FileEncryptionBlock {
+        version: FileEncryptionVersion::Rar20,
+        ..Default::default()
+    });
+    assert_eq!(
+        derive_key(&file, "test", &mut cache)
+            .unwrap_err()
+            .to_string(),
+        "RAR 2.0 encryption of text.txt is not supported"
+    );
+}
+#[test]
+fn test_request_key() {
+    /// Gives the passwords in order and records the retries
+    struct Passwords(Vec<PasswordResponse>, Vec<u32>);
+    impl ::password::PasswordProvider for Passwords {
+        fn password(&mut self, _: &FileBlock, retry: u32) -> PasswordResponse {
+            self.1.push(retry);
+            self.0.remove(0)
+        }
+    }
+
+    let mut feb = ::extra_block::FileEncryptionBlock::default();
+    feb.flags.pw_check_data = true;
+    let (key, hash_key, pw_check) = *generate_key(feb.kdf_count, &feb.salt, "test");
+    feb.pw_check[..8].copy_from_slice(&pw_check);
+    let mut hash = [0u8; 32];
+    let mut sha = Sha256::new();
+    sha.input(&pw_check);
+    sha.result(&mut hash);
+    feb.pw_check[8..].copy_from_slice(&hash[..4]);
+
+    // the provider is not asked for files without encryption
+    let mut file = FileBlock::default();
+    let mut passwords = Passwords(vec![], vec![]);
+    assert_eq!(
+        request_key(&file, &mut Keyring::new(&mut passwords)).unwrap(),
+        KeyRequest::Key(None)
+    );
+    assert!(passwords.1.is_empty());
+
+    // wrong passwords are asked again
+    file.extra.file_encryption = Some(feb);
+    let mut passwords = Passwords(
+        vec![
+            PasswordResponse::Password(Zeroizing::new("wrong".into())),
+            PasswordResponse::Password(Zeroizing::new("test".into())),
+        ],
+        vec![],
+    );
+    assert_eq!(
+        request_key(&file, &mut Keyring::new(&mut passwords)).unwrap(),
+        KeyRequest::Key(Some(FileKey::Aes256 { key, hash_key }))
+    );
+    assert_eq!(passwords.1, [0, 1]);
+
+    let mut passwords = Passwords(vec![PasswordResponse::Skip], vec![]);
+    assert_eq!(
+        request_key(&file, &mut Keyring::new(&mut passwords)).unwrap(),
+        KeyRequest::Skip
+    );
+
+    let mut passwords = Passwords(
+        vec![
+            PasswordResponse::Password(Zeroizing::new("wrong".into())),
+            PasswordResponse::Abort,
+        ],
+        vec![],
+    );
+    assert_eq!(
+        request_key(&file, &mut Keyring::new(&mut passwords))
+            .unwrap_err()
+            .downcast::<RarError>()
+            .unwrap(),
+        RarError::WrongPassword { name: "".into() }
+    );
+}
+
+#[test]
+fn test_generate_key() {
+    use crypto::hmac::Hmac;
+
+    // the key is the plain PBKDF2-HMAC-SHA256 of the password
+    let feb = ::extra_block::FileEncryptionBlock {
+        kdf_count: 4,
+        salt: [
+            145, 54, 92, 222, 142, 142, 13, 19, 255, 186, 128, 233, 43, 95, 8, 74,
+        ],
+        ..::extra_block::FileEncryptionBlock::default()
+    };
+
+    let mut expected = [0u8; 32];
+    let mut mac = Hmac::new(Sha256::new(), b"test");
+    ::crypto::pbkdf2::pbkdf2(&mut mac, &feb.salt, 16, &mut expected);
+
+    assert_eq!(generate_key(feb.kdf_count, &feb.salt, "test").0, expected);
+
+    // the hash key is the same PBKDF2 run with 16 more iterations
+    let mut mac = Hmac::new(Sha256::new(), b"test");
+    ::crypto::pbkdf2::pbkdf2(&mut mac, &feb.salt, 32, &mut expected);
+
+    assert_eq!(generate_key(feb.kdf_count, &feb.salt, "test").1, expected);
+}
+
+#[test]
+fn test_wipe_layouts() {
+    use std::mem::size_of;
+
+    // `wipe` is only sound for the known layouts of the rust-crypto types:
+    // 11 or 15 round keys of 8 `u16` and the SHA-256 engine, which has a
+    // `u64` length, a 64 byte buffer with a `usize` index, 8 `u32` and a `bool`
+    assert_eq!(size_of::<AesSafe128Decryptor>(), 11 * 8 * 2);
+    assert_eq!(size_of::<AesSafe256Decryptor>(), 15 * 8 * 2);
+    let sha256 = 8 + 64 + size_of::<usize>() + 8 * 4 + 1;
+    let align = ::std::mem::align_of::<Sha256>();
+    assert_eq!(size_of::<Sha256>(), sha256.div_ceil(align) * align);
+}
+
+#[test]
+fn test_key_cache() {
+    let salt = [7u8; 16];
+    let mut cache = KeyCache::default();
+
+    // every derivation is done only once
+    let keys = cache.generate_key(4, &salt, "test");
+    assert_eq!(keys, generate_key(4, &salt, "test"));
+    assert_eq!(cache.generate_key(4, &salt, "test"), keys);
+    assert_eq!(cache.pbkdf2.len(), 1);
+    assert_eq!(cache.pbkdf2["test"].len(), 1);
+
+    // a different password, salt or KDF count needs its own keys
+    assert_ne!(cache.generate_key(4, &salt, "other").0, keys.0);
+    assert_ne!(cache.generate_key(4, &[8; 16], "test").0, keys.0);
+    assert_ne!(cache.generate_key(5, &salt, "test").0, keys.0);
+    assert_eq!(cache.pbkdf2.len(), 2);
+    assert_eq!(cache.pbkdf2["test"].len(), 3);
+
+    let keys = cache.generate_key_rar3("test", None);
+    assert_eq!(cache.generate_key_rar3("test", None), keys);
+    assert_ne!(cache.generate_key_rar3("test", Some(&[0; 8])), keys);
+    assert_eq!(cache.rar3.len(), 1);
+    assert_eq!(cache.rar3["test"].len(), 2);
+}
+
+#[test]
+fn test_tweak_crc() {
+    use crypto::hmac::Hmac;
+    use crypto::mac::Mac;
+
+    let key = FileKey::Aes256 {
+        key: [0; 32],
+        hash_key: [0x0B; 32],
+    };
+
+    // fold the HMAC of the little endian CRC into 32 bits
+    let mut digest = [0u8; 32];
+    let mut mac = Hmac::new(Sha256::new(), &[0x0B; 32]);
+    mac.input(&[0x26, 0x39, 0xF4, 0xCB]);
+    mac.raw_result(&mut digest);
+    let mut expected = 0;
+    for d in digest.chunks(4) {
+        expected ^= u32::from_le_bytes([d[0], d[1], d[2], d[3]]);
+    }
+
+    assert_eq!(key.tweak_crc(0xCBF4_3926), expected);
+    let mut mac = Hmac::new(Sha256::new(), &[0x0B; 32]);
+    mac.input(&[0x26; 32]);
+    mac.raw_result(&mut digest);
+    assert_eq!(key.tweak_hash(&[0x26; 32]), digest);
+
+    // RAR 3.x doesn't tweak the checksums
+    let key = FileKey::Aes128 {
+        key: [0; 16],
+        iv: [0; 16],
+    };
+    assert_eq!(key.tweak_crc(0xCBF4_3926), 0xCBF4_3926);
+}
+
+#[test]
+fn test_hmac_sha256() {
+    use crypto::hmac::Hmac;
+    use crypto::mac::Mac;
+
+    // short and long keys match the generic HMAC
+    for key in [&[0x0B; 20][..], &[0xAA; 131][..]].iter() {
+        let mut expected = [0u8; 32];
+        let mut mac = Hmac::new(Sha256::new(), key);
+        mac.input(b"Hi There");
+        mac.raw_result(&mut expected);
+
+        let hmac = HmacSha256::new(key);
+        assert_eq!(hmac.mac(b"Hi There"), expected);
+        // the prekeyed states are reused
+        assert_eq!(hmac.mac(b"Hi There"), expected);
+    }
+}
+
+#[test]
+fn test_file_key_debug() {
+    let key = FileKey::Aes256 {
+        key: [0x11; 32],
+        hash_key: [0x22; 32],
+    };
+    assert_eq!(format!("{:?}", key), "FileKey::Aes256(<redacted>)");
+}
+
+#[test]
+fn test_generate_key_rar3() {
+    assert_eq!(
+        *generate_key_rar3("test", Some(&[0, 1, 2, 3, 4, 5, 6, 7])),
+        (
+            [
+                0x82, 0x7F, 0x67, 0xC3, 0x51, 0xDF, 0xAB, 0x37, 0x7D, 0x3A, 0x65, 0x0B, 0x99, 0x90,
+                0x12, 0x3E
+            ],
+            [
+                0x9A, 0x58, 0x4B, 0xF3, 0x69, 0x8B, 0x17, 0xFD, 0x5F, 0xFC, 0x2A, 0x28, 0xB9, 0x5C,
+                0x23, 0xCE
+            ]
+        )
+    );
+    assert_eq!(
+        *generate_key_rar3("test", None),
+        (
+            [
+                0x03, 0xD2, 0x58, 0xB7, 0xE2, 0x06, 0xD1, 0x38, 0x3B, 0xA5, 0x90, 0x8E, 0xAC, 0x46,
+                0xE2, 0xB4
+            ],
+            [
+                0x8D, 0xBD, 0x35, 0x6C, 0x9B, 0x09, 0xD8, 0x17, 0x78, 0x30, 0xB9, 0x44, 0x64, 0x5F,
+                0x62, 0x6B
+            ]
+        )
+    );
 }
 
 #[test]
@@ -215,9 +1055,55 @@ fn test_aes_stream_disabled() {
         0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x10,
     ];
 
-    let mut reader = RarAesReader::new(&data[..], f, "");
+    let mut reader = RarAesReader::new(&data[..], f, None);
     let mut buffer = vec![];
 
     reader.read_to_end(&mut buffer).unwrap();
     assert_eq!(buffer, data);
 }
+#[test]
+fn test_aes_seek() {
+    use crypto::aessafe::AesSafe128Encryptor;
+    use crypto::symmetriccipher::BlockEncryptor;
+    use std::io::Cursor;
+
+    let key = FileKey::Aes128 {
+        key: [0x42; 16],
+        iv: [0x24; 16],
+    };
+    let plain: Vec<u8> = (0..20_000u32).map(|i| (i * 7) as u8).collect();
+
+    // encrypt the data in the CBC mode
+    let aes = AesSafe128Encryptor::new(&[0x42; 16]);
+    let mut iv = [0x24u8; 16];
+    let mut data = vec![];
+    for block in plain.chunks(16) {
+        let mut input = [0u8; 16];
+        for (i, (b, v)) in block.iter().zip(iv.iter()).enumerate() {
+            input[i] = b ^ v;
+        }
+        aes.encrypt_block(&input, &mut iv);
+        data.extend_from_slice(&iv);
+    }
+
+    let mut reader = RarAesReader::new(Cursor::new(data), FileBlock::default(), Some(key));
+    let mut buf = [0u8; 100];
+
+    // unaligned positions within the first block and behind the buffer
+    for &pos in [3u64, 0, 16, 8200, 19_900, 31].iter() {
+        assert_eq!(reader.seek(SeekFrom::Start(pos)).unwrap(), pos);
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf[..], &plain[pos as usize..pos as usize + 100]);
+        assert_eq!(reader.stream_position().unwrap(), pos + 100);
+    }
+
+    // relative to the current position and the end of the padded data
+    assert_eq!(reader.seek(SeekFrom::Current(-50)).unwrap(), 81);
+    reader.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf[..], &plain[81..181]);
+    assert_eq!(reader.seek(SeekFrom::End(-200)).unwrap(), 20_000 - 200);
+    reader.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf[..], &plain[19_800..19_900]);
+
+    assert!(reader.seek(SeekFrom::Current(-100_000)).is_err());
+}
@@ -1,5 +1,6 @@
-use head_block::HeadBlock;
+use head_block::{rest_rar4, HeadBlock};
 use nom;
+use sig_block::SignatureBlock;
 use util::get_bit_at;
 use vint::vint;
 
@@ -51,6 +52,36 @@ impl ArchiveBlock {
 
         Ok((input, archive))
     }
+
+    /// Parse a RAR 1.5 - 4.x archive header from a byte slice.
+    pub fn parse_rar4(inp: &[u8]) -> nom::IResult<&[u8], ArchiveBlock> {
+        // get the base header
+        let (input, (head, flags)) = HeadBlock::parse_rar4(inp)?;
+
+        // check if the defined type is archive header
+        if head.typ != ::head_block::Typ::MainArchive {
+            return Err(nom::Err::Error(error_position!(inp, nom::ErrorKind::IsNot)));
+        }
+
+        // the av position and encryption version are not processed right now
+        let (input, _) = rest_rar4(inp, input, &head)?;
+
+        let archive = ArchiveBlock {
+            head,
+            flags: ArchiveFlags::from_rar4(flags),
+            volume_number: 0,
+        };
+
+        Ok((input, archive))
+    }
+
+    /// Returns the archive header parser for the given archive version
+    pub fn parser(version: &SignatureBlock) -> fn(&[u8]) -> nom::IResult<&[u8], ArchiveBlock> {
+        match version {
+            SignatureBlock::RAR5 => ArchiveBlock::parse,
+            SignatureBlock::RAR4 => ArchiveBlock::parse_rar4,
+        }
+    }
 }
 
 #[test]
@@ -61,15 +92,18 @@ fn test_archive() {
         0x00, 0x8C, 0x0D, 0x88, 0xE2,
     ];
 
-    let mut flags = ::head_block::Flags::default();
-    flags.extra_area = true;
-    flags.skip = true;
+    let flags = ::head_block::Flags {
+        extra_area: true,
+        skip: true,
+        ..Default::default()
+    };
     let mut arc = ArchiveBlock {
-        head: HeadBlock::new(4091642603, 11, ::head_block::Typ::MainArchive, flags),
+        head: HeadBlock::new(0xEB82_E1F3, 11, ::head_block::Typ::MainArchive, flags),
         flags: ArchiveFlags::default(),
         volume_number: 0,
     };
     arc.head.extra_area_size = 7;
+    arc.flags.new_numbering = true;
     assert_eq!(
         ArchiveBlock::parse(&data),
         Ok((&[0x8C, 0x0D, 0x88, 0xE2][..], arc))
@@ -89,15 +123,49 @@ fn test_archive() {
     );
 }
 
+#[test]
+fn test_archive_rar4() {
+    // solid archive with a recovery record
+    let data = [
+        0xCF, 0x90, 0x73, 0x48, 0x00, 0x0D, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x74,
+    ];
+
+    let mut arc = ArchiveBlock {
+        head: HeadBlock::new(
+            0x90CF,
+            13,
+            ::head_block::Typ::MainArchive,
+            Default::default(),
+        ),
+        flags: ArchiveFlags::default(),
+        volume_number: 0,
+    };
+    arc.flags.solid = true;
+    arc.flags.recovery = true;
+    assert_eq!(ArchiveBlock::parse_rar4(&data), Ok((&[0x74][..], arc)));
+
+    // an end of archive header is no archive header
+    let data = [0xC4, 0x3D, 0x7B, 0x00, 0x40, 0x07, 0x00];
+    assert_eq!(
+        ArchiveBlock::parse_rar4(&data),
+        Err(nom::Err::Error(error_position!(
+            &data[..],
+            nom::ErrorKind::IsNot
+        )))
+    );
+}
+
 /// Archive header flags which define main
 /// flags for the archive header
 #[derive(PartialEq, Debug, Default)]
 pub struct ArchiveFlags {
-    pub multivolume: bool,   // Volume. Archive is a part of multivolume set.
+    pub multivolume: bool,       // Volume. Archive is a part of multivolume set.
     pub volume_number: bool, // Volume number field is present. This flag is present in all volumes except first.
     pub solid: bool,         // Solid archive.
     pub recovery: bool,      // 0x0008   Recovery record is present.
     pub locked: bool,        // Locked archive.
+    pub encrypted_headers: bool, // All headers behind the archive header are encrypted (RAR 1.5 - 4.x).
+    pub new_numbering: bool, // Volumes are named name.partN.rar instead of name.rar, name.r00 (always set for RAR5).
 }
 
 impl From<u64> for ArchiveFlags {
@@ -119,7 +187,25 @@ impl From<u64> for ArchiveFlags {
         if get_bit_at(i, 4) {
             f.locked = true;
         }
+        // RAR5 doesn't store the naming scheme, the name of the first volume tells it
+        f.new_numbering = true;
 
         f
     }
 }
+
+impl ArchiveFlags {
+    /// Get the archive flags from the flags of a RAR 1.5 - 4.x archive header
+    pub fn from_rar4(i: u16) -> Self {
+        let i = i as u64;
+        ArchiveFlags {
+            multivolume: get_bit_at(i, 0),
+            volume_number: false,
+            solid: get_bit_at(i, 3),
+            recovery: get_bit_at(i, 6),
+            locked: get_bit_at(i, 2),
+            encrypted_headers: get_bit_at(i, 7),
+            new_numbering: get_bit_at(i, 4),
+        }
+    }
+}
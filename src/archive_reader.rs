@@ -0,0 +1,180 @@
+use archive_block::ArchiveBlock;
+use encryption_block::EncryptionBlock;
+use end_block::EndBlock;
+use extractor;
+use failure::Error;
+use file_block::FileBlock;
+use head_block::{CrcMode, HeadBlock, HeaderReader, Typ};
+use password::PasswordProvider;
+use rar_reader::RarReader;
+use sig_block::SignatureBlock;
+use volume::VolumeProvider;
+use Archive;
+
+/// The ArchiveReader walks over the blocks of an archive
+/// and follows the files which continue in the next volumes.
+pub struct ArchiveReader<'a> {
+    /// Reader which is placed at the next block or the data of the last file
+    pub reader: RarReader<'a>,
+    pub version: SignatureBlock,
+    pub details: ArchiveBlock,
+    pub encryption: Option<EncryptionBlock>,
+    /// Reader of the block headers, which decrypts them when needed
+    pub headers: HeaderReader<'a>,
+    pub quick_open: Option<FileBlock>,
+    /// Provider of the following volumes
+    volumes: &'a mut dyn VolumeProvider,
+    /// Number of the .rar file which is read right now, starting at 1
+    file_number: usize,
+}
+
+impl<'a> ArchiveReader<'a> {
+    /// Open the first volume and read its signature and archive block
+    pub fn open(
+        volumes: &'a mut dyn VolumeProvider,
+        passwords: &'a mut dyn PasswordProvider,
+        mode: CrcMode,
+    ) -> Result<ArchiveReader<'a>, Error> {
+        let reader = RarReader::new(volumes.volume(0)?);
+        ArchiveReader::new(reader, volumes, passwords, mode)
+    }
+
+    /// Read the signature and the archive block of the first volume out of the reader
+    pub fn new(
+        mut reader: RarReader<'a>,
+        volumes: &'a mut dyn VolumeProvider,
+        passwords: &'a mut dyn PasswordProvider,
+        mode: CrcMode,
+    ) -> Result<ArchiveReader<'a>, Error> {
+        let file_name = volumes.name(0);
+        // try to parse the signature
+        let version = reader
+            .exec_nom_parser(SignatureBlock::parse)
+            .map_err(|_| format_err!("Can't read RAR signature"))?;
+        // every block header is decrypted when needed and
+        // checked against its CRC before parsing it
+        let mut headers = HeaderReader::new(mode, passwords);
+        let encryption = headers.begin_volume(&mut reader, &version, &file_name)?;
+        // try to parse the archive information
+        let offset = reader.position();
+        let mut details = reader
+            .exec_nom_parser(ArchiveBlock::parser(&version))
+            .map_err(|_| format_err!("Can't read RAR archive block"))?;
+        headers.archive_block(&details)?;
+        details.head.offset = offset;
+        // e.g. the names of the following volumes depend on the flags
+        volumes.archive_flags(&details.flags);
+
+        Ok(ArchiveReader {
+            reader,
+            version,
+            details,
+            encryption,
+            headers,
+            quick_open: None,
+            volumes,
+            file_number: 1,
+        })
+    }
+
+    /// Returns the next file with the size of its data area, or nothing
+    /// when the end of the archive is reached.
+    ///
+    /// The reader is placed at the data of the file afterwards, which
+    /// must be read or skipped before the next file. The data areas of a
+    /// file which continues in the next volumes are queued behind each other,
+    /// the offset of the file is the one of its header in the first volume.
+    pub fn next_file(&mut self) -> Result<Option<(FileBlock, u64)>, Error> {
+        loop {
+            let offset = self.reader.position();
+            self.headers.next(&mut self.reader, &self.version)?;
+
+            // skip all blocks we don't know
+            if let Ok(head) = self
+                .reader
+                .exec_nom_parser(HeadBlock::unknown_parser(&self.version))
+            {
+                self.reader.r_seek(head.data_area_size)?;
+                continue;
+            }
+
+            let mut f = match self
+                .reader
+                .exec_nom_parser(FileBlock::parser(&self.version))
+            {
+                Ok(f) => f,
+                Err(_) => return Ok(None),
+            };
+            f.head.offset = offset;
+
+            // service blocks like the quick open data or the recovery record
+            if f.head.typ == Typ::Service {
+                self.reader.r_seek(f.head.data_area_size)?;
+                if f.name == "QO" {
+                    self.quick_open = Some(f);
+                }
+                continue;
+            }
+
+            let first_volume = self.file_number - 1;
+
+            // limit the reader, because the rest of the file is not important,
+            // when we have multiple files
+            if f.head.flags.data_next {
+                self.reader.limit(f.head.data_area_size);
+            }
+
+            // queue the data areas of the following .rar files
+            // behind each other to extract the right one
+            let mut data_area_size = f.head.data_area_size;
+            while f.head.flags.data_next {
+                extractor::continue_data_next_file(
+                    &mut self.reader,
+                    &mut f,
+                    &self.version,
+                    self.volumes,
+                    &mut self.file_number,
+                    &mut data_area_size,
+                    &mut self.headers,
+                )?;
+            }
+
+            f.volumes = first_volume..self.file_number;
+            f.head.offset = offset;
+
+            return Ok(Some((f, data_area_size)));
+        }
+    }
+
+    /// Read the end block of the archive and return the archive with the files
+    pub fn finish(mut self, files: Vec<FileBlock>) -> Result<Archive, Error> {
+        // Get the end block
+        let end = self
+            .reader
+            .exec_nom_parser(EndBlock::parser(&self.version))
+            .map_err(|_| format_err!("Can't read RAR end"))?;
+
+        Ok(self.into_archive(files, Some(end)))
+    }
+
+    /// Return the archive with the files, which were read so far.
+    ///
+    /// The rest of the archive is not read, so it has no end block
+    /// and the files behind the last one are missing.
+    pub fn stop(self, files: Vec<FileBlock>) -> Archive {
+        self.into_archive(files, None)
+    }
+
+    /// Return the archive information
+    fn into_archive(self, files: Vec<FileBlock>, end: Option<EndBlock>) -> Archive {
+        Archive {
+            version: self.version,
+            details: self.details,
+            files,
+            quick_open: self.quick_open,
+            end,
+            encryption: self.encryption,
+            warnings: self.headers.warnings,
+        }
+    }
+}
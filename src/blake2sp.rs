@@ -0,0 +1,242 @@
+/// Initialization vector of BLAKE2s
+const IV: [u32; 8] = [
+    0x6A09_E667,
+    0xBB67_AE85,
+    0x3C6E_F372,
+    0xA54F_F53A,
+    0x510E_527F,
+    0x9B05_688C,
+    0x1F83_D9AB,
+    0x5BE0_CD19,
+];
+
+/// Message word permutations of the BLAKE2s rounds
+const SIGMA: [[usize; 16]; 10] = [
+    [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15],
+    [14, 10, 4, 8, 9, 15, 13, 6, 1, 12, 0, 2, 11, 7, 5, 3],
+    [11, 8, 12, 0, 5, 2, 15, 13, 10, 14, 3, 6, 7, 1, 9, 4],
+    [7, 9, 3, 1, 13, 12, 11, 14, 2, 6, 5, 10, 4, 0, 15, 8],
+    [9, 0, 5, 7, 2, 4, 10, 15, 14, 1, 11, 12, 6, 8, 3, 13],
+    [2, 12, 6, 10, 0, 11, 8, 3, 4, 13, 7, 5, 15, 14, 1, 9],
+    [12, 5, 1, 15, 14, 13, 4, 10, 0, 7, 6, 3, 9, 2, 8, 11],
+    [13, 11, 7, 14, 12, 1, 3, 9, 5, 0, 15, 4, 8, 6, 2, 10],
+    [6, 15, 14, 9, 11, 3, 0, 8, 12, 2, 13, 7, 1, 4, 10, 5],
+    [10, 2, 8, 4, 7, 6, 1, 5, 15, 11, 9, 14, 3, 12, 13, 0],
+];
+
+/// Number of leaves BLAKE2sp spreads the data on
+const LEAVES: usize = 8;
+/// Block size of BLAKE2s
+const BLOCK: usize = 64;
+
+/// A single BLAKE2s node with the tree parameters of BLAKE2sp
+#[derive(Clone)]
+struct Blake2s {
+    h: [u32; 8],
+    t: u64,
+    buf: [u8; BLOCK],
+    buf_len: usize,
+    last_node: bool,
+}
+
+impl Blake2s {
+    /// Create a node at the given offset and depth of the tree
+    fn new(node_offset: u32, node_depth: u32, last_node: bool) -> Blake2s {
+        // digest length 32, fanout 8, depth 2 and inner length 32
+        let param = [
+            32 | (LEAVES as u32) << 16 | 2 << 24,
+            0,
+            node_offset,
+            node_depth << 16 | 32 << 24,
+            0,
+            0,
+            0,
+            0,
+        ];
+
+        let mut h = IV;
+        for (h, p) in h.iter_mut().zip(param.iter()) {
+            *h ^= p;
+        }
+
+        Blake2s {
+            h,
+            t: 0,
+            buf: [0; BLOCK],
+            buf_len: 0,
+            last_node,
+        }
+    }
+
+    /// Hash more data. The last block is kept back,
+    /// because it needs to be compressed with the final flag.
+    fn update(&mut self, mut data: &[u8]) {
+        while !data.is_empty() {
+            if self.buf_len == BLOCK {
+                self.t += BLOCK as u64;
+                let block = self.buf;
+                self.compress(&block, false);
+                self.buf_len = 0;
+            }
+
+            let len = (BLOCK - self.buf_len).min(data.len());
+            self.buf[self.buf_len..self.buf_len + len].copy_from_slice(&data[..len]);
+            self.buf_len += len;
+            data = &data[len..];
+        }
+    }
+
+    /// Compress the last block and return the digest
+    fn finalize(mut self) -> [u8; 32] {
+        self.t += self.buf_len as u64;
+        for b in self.buf[self.buf_len..].iter_mut() {
+            *b = 0;
+        }
+        let block = self.buf;
+        self.compress(&block, true);
+
+        let mut out = [0u8; 32];
+        for (o, h) in out.chunks_mut(4).zip(self.h.iter()) {
+            o.copy_from_slice(&h.to_le_bytes());
+        }
+        out
+    }
+
+    /// The BLAKE2s compression function
+    fn compress(&mut self, block: &[u8; BLOCK], last: bool) {
+        let mut m = [0u32; 16];
+        for (m, b) in m.iter_mut().zip(block.chunks(4)) {
+            *m = u32::from_le_bytes([b[0], b[1], b[2], b[3]]);
+        }
+
+        let mut v = [0u32; 16];
+        v[..8].copy_from_slice(&self.h);
+        v[8..].copy_from_slice(&IV);
+        v[12] ^= self.t as u32;
+        v[13] ^= (self.t >> 32) as u32;
+        if last {
+            v[14] = !v[14];
+            if self.last_node {
+                v[15] = !v[15];
+            }
+        }
+
+        for s in SIGMA.iter() {
+            g(&mut v, 0, 4, 8, 12, m[s[0]], m[s[1]]);
+            g(&mut v, 1, 5, 9, 13, m[s[2]], m[s[3]]);
+            g(&mut v, 2, 6, 10, 14, m[s[4]], m[s[5]]);
+            g(&mut v, 3, 7, 11, 15, m[s[6]], m[s[7]]);
+            g(&mut v, 0, 5, 10, 15, m[s[8]], m[s[9]]);
+            g(&mut v, 1, 6, 11, 12, m[s[10]], m[s[11]]);
+            g(&mut v, 2, 7, 8, 13, m[s[12]], m[s[13]]);
+            g(&mut v, 3, 4, 9, 14, m[s[14]], m[s[15]]);
+        }
+
+        for i in 0..8 {
+            self.h[i] ^= v[i] ^ v[i + 8];
+        }
+    }
+}
+
+/// The BLAKE2s mixing function
+fn g(v: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize, x: u32, y: u32) {
+    v[a] = v[a].wrapping_add(v[b]).wrapping_add(x);
+    v[d] = (v[d] ^ v[a]).rotate_right(16);
+    v[c] = v[c].wrapping_add(v[d]);
+    v[b] = (v[b] ^ v[c]).rotate_right(12);
+    v[a] = v[a].wrapping_add(v[b]).wrapping_add(y);
+    v[d] = (v[d] ^ v[a]).rotate_right(8);
+    v[c] = v[c].wrapping_add(v[d]);
+    v[b] = (v[b] ^ v[c]).rotate_right(7);
+}
+
+/// BLAKE2sp hasher, which is used by RAR5 for the file hashes.
+///
+/// The data is spread block by block over 8 BLAKE2s leaves,
+/// whose digests are hashed again by the root node.
+#[derive(Clone)]
+pub struct Blake2sp {
+    leaves: Vec<Blake2s>,
+    pos: u64,
+}
+
+impl Blake2sp {
+    /// Create a new BLAKE2sp hasher
+    pub fn new() -> Blake2sp {
+        Blake2sp {
+            leaves: (0..LEAVES)
+                .map(|i| Blake2s::new(i as u32, 0, i == LEAVES - 1))
+                .collect(),
+            pos: 0,
+        }
+    }
+
+    /// Hash more data
+    pub fn update(&mut self, mut data: &[u8]) {
+        while !data.is_empty() {
+            let leaf = (self.pos / BLOCK as u64) as usize % LEAVES;
+            let len = (BLOCK - (self.pos % BLOCK as u64) as usize).min(data.len());
+
+            self.leaves[leaf].update(&data[..len]);
+            self.pos += len as u64;
+            data = &data[len..];
+        }
+    }
+
+    /// Returns the digest of all hashed data
+    pub fn finalize(self) -> [u8; 32] {
+        let mut root = Blake2s::new(0, 1, true);
+        for leaf in self.leaves {
+            root.update(&leaf.finalize());
+        }
+        root.finalize()
+    }
+}
+
+impl Default for Blake2sp {
+    fn default() -> Self {
+        Blake2sp::new()
+    }
+}
+
+#[test]
+fn test_blake2sp() {
+    let hash = |data: &[u8]| {
+        let mut b = Blake2sp::new();
+        b.update(data);
+        b.finalize()
+    };
+
+    assert_eq!(
+        hash(b""),
+        [
+            0xDD, 0x0E, 0x89, 0x17, 0x76, 0x93, 0x3F, 0x43, 0xC7, 0xD0, 0x32, 0xB0, 0x8A, 0x91,
+            0x7E, 0x25, 0x74, 0x1F, 0x8A, 0xA9, 0xA1, 0x2C, 0x12, 0xE1, 0xCA, 0xC8, 0x80, 0x15,
+            0x00, 0xF2, 0xCA, 0x4F
+        ]
+    );
+    assert_eq!(
+        hash(b"abc"),
+        [
+            0x70, 0xF7, 0x5B, 0x58, 0xF1, 0xFE, 0xCA, 0xB8, 0x21, 0xDB, 0x43, 0xC8, 0x8A, 0xD8,
+            0x4E, 0xDD, 0xE5, 0xA5, 0x26, 0x00, 0x61, 0x6C, 0xD2, 0x25, 0x17, 0xB7, 0xBB, 0x14,
+            0xD4, 0x40, 0xA7, 0xD5
+        ]
+    );
+
+    // the data is spread over all leaves and more than one block per leaf
+    let data: Vec<u8> = (0..1280).map(|i| i as u8).collect();
+    let expected = [
+        0xF8, 0x64, 0x3D, 0xBE, 0xAC, 0xFC, 0xC9, 0x8C, 0x1D, 0xE6, 0x62, 0xE3, 0x5A, 0x80, 0x0F,
+        0xD8, 0xDF, 0xC8, 0xA3, 0x69, 0xCE, 0x62, 0xED, 0x51, 0x4E, 0x81, 0x3E, 0x65, 0x72, 0x8E,
+        0xD3, 0x79,
+    ];
+    assert_eq!(hash(&data), expected);
+
+    // hashing in uneven parts gives the same result
+    let mut b = Blake2sp::new();
+    for part in data.chunks(100) {
+        b.update(part);
+    }
+    assert_eq!(b.finalize(), expected);
+}
@@ -0,0 +1,124 @@
+use extra_block::{FileEncryptionVersion, MAX_KDF_COUNT};
+use head_block::HeadBlock;
+use nom;
+use std::fmt;
+use util::get_bit_at;
+use vint::vint;
+
+/// EncryptionBlock which is placed in front of the other headers
+/// of an archive with encrypted headers.
+///
+/// All following headers are encrypted with the key derived from
+/// the password and the salt of this block.
+#[derive(PartialEq, Clone, Default)]
+pub struct EncryptionBlock {
+    pub head: HeadBlock,
+    pub version: FileEncryptionVersion,
+    pub pw_check_data: bool,
+    pub kdf_count: u8,
+    pub salt: [u8; 16],
+    pub pw_check: [u8; 12],
+}
+
+/// The password check value is derived from the password,
+/// so it is never printed.
+impl fmt::Debug for EncryptionBlock {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("EncryptionBlock")
+            .field("head", &self.head)
+            .field("version", &self.version)
+            .field("pw_check_data", &self.pw_check_data)
+            .field("kdf_count", &self.kdf_count)
+            .field("salt", &self.salt)
+            .field("pw_check", &"<redacted>")
+            .finish()
+    }
+}
+
+impl EncryptionBlock {
+    /// Parse the encryption block information from a byte slice
+    pub fn parse(inp: &[u8]) -> nom::IResult<&[u8], EncryptionBlock> {
+        // get the base header
+        let (input, head) = HeadBlock::parse(inp)?;
+
+        // check if the defined type is an encryption header
+        if head.typ != ::head_block::Typ::Encryption {
+            return Err(nom::Err::Error(error_position!(inp, nom::ErrorKind::IsNot)));
+        }
+
+        let (input, version) = FileEncryptionVersion::parse(input)?;
+        let (input, flags) = vint(input)?;
+        let (input, kdf_count) = take!(input, 1)?;
+        if kdf_count[0] > MAX_KDF_COUNT {
+            return Err(nom::Err::Error(error_position!(
+                inp,
+                nom::ErrorKind::TooLarge
+            )));
+        }
+        let (mut input, salt) = take!(input, 16)?;
+
+        let mut eb = EncryptionBlock {
+            head,
+            version,
+            pw_check_data: get_bit_at(flags, 0),
+            kdf_count: kdf_count[0],
+            salt: [0; 16],
+            pw_check: [0; 12],
+        };
+        eb.salt.copy_from_slice(salt);
+
+        if eb.pw_check_data {
+            let (i, p) = take!(input, 12)?;
+            eb.pw_check.copy_from_slice(p);
+            input = i;
+        }
+
+        Ok((input, eb))
+    }
+}
+
+#[test]
+fn test_encryption_block() {
+    let data = [
+        0xEF, 0xA6, 0xA3, 0xE3, 0x21, 0x04, 0x00, 0x00, 0x01, 0x0F, 0xC7, 0x6B, 0xD0, 0xD1, 0x9F,
+        0x80, 0x56, 0x48, 0xBA, 0x8B, 0xD7, 0x11, 0x93, 0x0D, 0x37, 0x95, 0x67, 0x09, 0x55, 0xF3,
+        0x81, 0x59, 0x18, 0x4B, 0x0D, 0x5C, 0xCF, 0x0B, 0x2E, 0x79, 0xCC, 0xFF,
+    ];
+
+    let eb = EncryptionBlock {
+        head: HeadBlock::new(
+            0xE3A3_A6EF,
+            33,
+            ::head_block::Typ::Encryption,
+            ::head_block::Flags::new(),
+        ),
+        version: FileEncryptionVersion::Aes256,
+        pw_check_data: true,
+        kdf_count: 15,
+        salt: [
+            0xC7, 0x6B, 0xD0, 0xD1, 0x9F, 0x80, 0x56, 0x48, 0xBA, 0x8B, 0xD7, 0x11, 0x93, 0x0D,
+            0x37, 0x95,
+        ],
+        pw_check: [
+            0x67, 0x09, 0x55, 0xF3, 0x81, 0x59, 0x18, 0x4B, 0x0D, 0x5C, 0xCF, 0x0B,
+        ],
+    };
+    assert_eq!(
+        EncryptionBlock::parse(&data),
+        Ok((&[0x2E, 0x79, 0xCC, 0xFF][..], eb.clone()))
+    );
+
+    // the password check value is not printed
+    let debug = format!("{:?}", eb);
+    assert!(debug.contains("pw_check: \"<redacted>\""));
+    assert!(!debug.contains("103, 9, 85"));
+
+    // too many rounds for the key derivation
+    let mut malformed = data;
+    malformed[9] = MAX_KDF_COUNT + 1;
+    assert!(EncryptionBlock::parse(&malformed).is_err());
+
+    // test a wrong header type
+    let data = [0x1D, 0x77, 0x56, 0x51, 0x03, 0x05, 0x04, 0x00];
+    assert!(EncryptionBlock::parse(&data).is_err());
+}
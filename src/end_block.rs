@@ -1,5 +1,7 @@
-use head_block::HeadBlock;
+use head_block::{rest_rar4, HeadBlock};
 use nom;
+use sig_block::SignatureBlock;
+use util::get_bit_at;
 use util::to_bool;
 use vint::vint;
 
@@ -30,6 +32,34 @@ impl EndBlock {
 
         Ok((input, end))
     }
+
+    /// Parse a RAR 1.5 - 4.x end block from a byte slice
+    pub fn parse_rar4(inp: &[u8]) -> nom::IResult<&[u8], EndBlock> {
+        // get the base header
+        let (input, (head, flags)) = HeadBlock::parse_rar4(inp)?;
+
+        // check if the defined type is end archive header
+        if head.typ != ::head_block::Typ::EndArchive {
+            return Err(nom::Err::Error(error_position!(inp, nom::ErrorKind::IsNot)));
+        }
+
+        // the archive crc and volume number are not processed right now
+        let (input, _) = rest_rar4(inp, input, &head)?;
+
+        // the first flag marks, that a next volume exists
+        let last_volume = !get_bit_at(flags as u64, 0);
+        let end = EndBlock { head, last_volume };
+
+        Ok((input, end))
+    }
+
+    /// Returns the end block parser for the given archive version
+    pub fn parser(version: &SignatureBlock) -> fn(&[u8]) -> nom::IResult<&[u8], EndBlock> {
+        match version {
+            SignatureBlock::RAR5 => EndBlock::parse,
+            SignatureBlock::RAR4 => EndBlock::parse_rar4,
+        }
+    }
 }
 
 #[test]
@@ -40,7 +70,7 @@ fn test_archive() {
     let mut flags = ::head_block::Flags::new();
     flags.skip = true;
     let arc = EndBlock {
-        head: HeadBlock::new(494360145, 3, ::head_block::Typ::EndArchive, flags),
+        head: HeadBlock::new(0x5156_771D, 3, ::head_block::Typ::EndArchive, flags),
         last_volume: true,
     };
     assert_eq!(EndBlock::parse(&data), Ok((&[][..], arc)));
@@ -58,3 +88,27 @@ fn test_archive() {
         )))
     );
 }
+
+#[test]
+fn test_end_rar4() {
+    // end of the last volume
+    let data = [0xC4, 0x3D, 0x7B, 0x00, 0x40, 0x07, 0x00];
+    let mut flags = ::head_block::Flags::new();
+    flags.skip = true;
+    let end = EndBlock {
+        head: HeadBlock::new(0x3DC4, 7, ::head_block::Typ::EndArchive, flags.clone()),
+        last_volume: true,
+    };
+    assert_eq!(EndBlock::parse_rar4(&data), Ok((&[][..], end)));
+
+    // end of a volume with a following volume, crc and volume number
+    let data = [
+        0x4A, 0x87, 0x7B, 0x0F, 0x40, 0x14, 0x00, 0xF8, 0x93, 0x87, 0x7B, 0x01, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00,
+    ];
+    let end = EndBlock {
+        head: HeadBlock::new(0x874A, 20, ::head_block::Typ::EndArchive, flags),
+        last_volume: false,
+    };
+    assert_eq!(EndBlock::parse_rar4(&data), Ok((&[][..], end)));
+}
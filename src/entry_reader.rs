@@ -0,0 +1,187 @@
+use aes_reader::{checked_offset, FileKey, RarAesReader};
+use failure::Error;
+use file_block::{CompressionFlags, FileBlock};
+use std::io;
+use std::io::{ErrorKind, Read, Seek, SeekFrom};
+
+/// Reader with random access to the data of a stored file in a .rar archive.
+///
+/// Encrypted files are decrypted on the fly, the AES blocks in front of
+/// the position are used as IV. Because the data is not read as a whole,
+/// the checksums of the file can't be verified.
+pub struct EntryReader<R: Read + Seek> {
+    /// Decryption reader over the data area of the file
+    reader: RarAesReader<DataArea<R>>,
+    /// Size of the file, without the padding of the encryption
+    size: u64,
+    /// Position in the file, which is read next
+    pos: u64,
+}
+
+impl<R: Read + Seek> EntryReader<R> {
+    /// Create a reader for the file, whose data area starts at the offset
+    /// in the .rar file. The key must be the one of `request_key`.
+    pub fn new(
+        reader: R,
+        file: &FileBlock,
+        offset: u64,
+        key: Option<FileKey>,
+    ) -> Result<EntryReader<R>, Error> {
+        if file.compression.flag != CompressionFlags::Save {
+            return Err(format_err!(
+                "Only stored files can be read with random access, {} is packed",
+                file.name
+            ));
+        }
+        if file.head.flags.data_prev || file.head.flags.data_next {
+            return Err(format_err!(
+                "{} is split over volumes, which is not supported for random access",
+                file.name
+            ));
+        }
+        if let Some(FileKey::Rar15(_)) = key {
+            return Err(format_err!(
+                "Random access to RAR 1.5 encrypted files is not supported"
+            ));
+        }
+
+        let data_area = DataArea::new(reader, offset, file.head.data_area_size)?;
+
+        Ok(EntryReader {
+            reader: RarAesReader::new(data_area, file.clone(), key),
+            size: file.unpacked_size,
+            pos: 0,
+        })
+    }
+
+    /// Returns the size of the file
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+}
+
+impl<R: Read + Seek> Read for EntryReader<R> {
+    /// Reads the decrypted data of the file, the padding is cut off.
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = buf.len().min(self.size.saturating_sub(self.pos) as usize);
+        if len == 0 {
+            return Ok(0);
+        }
+
+        let read = self.reader.read(&mut buf[..len])?;
+        if read == 0 {
+            return Err(io::Error::new(
+                ErrorKind::UnexpectedEof,
+                "Data area is smaller than the file",
+            ));
+        }
+
+        self.pos += read as u64;
+        Ok(read)
+    }
+}
+
+impl<R: Read + Seek> Seek for EntryReader<R> {
+    /// Seek to an offset of the file, the end is the end of the file.
+    ///
+    /// Positions behind the end are allowed, reading from them returns nothing.
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let pos = match pos {
+            SeekFrom::Start(offset) => offset,
+            SeekFrom::End(amt) => checked_offset(self.size, amt)?,
+            SeekFrom::Current(amt) => checked_offset(self.pos, amt)?,
+        };
+
+        // the data area is positioned with the next read in range
+        if pos < self.size {
+            self.reader.seek(SeekFrom::Start(pos))?;
+        }
+
+        self.pos = pos;
+        Ok(pos)
+    }
+}
+
+/// The data area of a file in the .rar file, which can be read
+/// and seeked like a file of its own.
+struct DataArea<R: Read + Seek> {
+    /// Reader of the .rar file
+    reader: R,
+    /// Offset of the data area in the .rar file
+    start: u64,
+    /// Size of the data area
+    size: u64,
+    /// Position in the data area
+    pos: u64,
+}
+
+impl<R: Read + Seek> DataArea<R> {
+    /// Create the data area and move the reader to its start
+    fn new(mut reader: R, start: u64, size: u64) -> io::Result<DataArea<R>> {
+        reader.seek(SeekFrom::Start(start))?;
+
+        Ok(DataArea {
+            reader,
+            start,
+            size,
+            pos: 0,
+        })
+    }
+}
+
+impl<R: Read + Seek> Read for DataArea<R> {
+    /// Reads the data up to the end of the data area
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = buf.len().min(self.size.saturating_sub(self.pos) as usize);
+        let read = self.reader.read(&mut buf[..len])?;
+        self.pos += read as u64;
+        Ok(read)
+    }
+}
+
+impl<R: Read + Seek> Seek for DataArea<R> {
+    /// Seek within the data area, the end is the end of the data area
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let pos = match pos {
+            SeekFrom::Start(offset) => offset,
+            SeekFrom::End(amt) => checked_offset(self.size, amt)?,
+            SeekFrom::Current(amt) => checked_offset(self.pos, amt)?,
+        };
+
+        self.reader.seek(SeekFrom::Start(self.start + pos))?;
+        self.pos = pos;
+        Ok(pos)
+    }
+}
+
+#[test]
+fn test_data_area() {
+    use std::io::Cursor;
+
+    let data: Vec<u8> = (0..100).collect();
+    let mut area = DataArea::new(Cursor::new(data), 10, 20).unwrap();
+
+    let mut buf = vec![];
+    area.read_to_end(&mut buf).unwrap();
+    assert_eq!(buf, (10..30).collect::<Vec<u8>>());
+
+    assert_eq!(area.seek(SeekFrom::End(-5)).unwrap(), 15);
+    let mut buf = [0u8; 3];
+    area.read_exact(&mut buf).unwrap();
+    assert_eq!(buf, [25, 26, 27]);
+    assert_eq!(area.seek(SeekFrom::Current(-18)).unwrap(), 0);
+    area.read_exact(&mut buf).unwrap();
+    assert_eq!(buf, [10, 11, 12]);
+}
+#[test]
+fn test_entry_reader_packed() {
+    use std::io::Cursor;
+
+    let mut file = FileBlock {
+        name: "text.txt".into(),
+        ..FileBlock::default()
+    };
+    file.compression.flag = CompressionFlags::Normal;
+
+    assert!(EntryReader::new(Cursor::new(vec![]), &file, 0, None).is_err());
+}
@@ -1,7 +1,41 @@
+// the derive macro of failure places its impls inside of a constant
+#![allow(non_local_definitions)]
+
+/// Errors which are specific to the content of a RAR archive
 #[derive(Debug, Fail, PartialEq)]
-enum RarError {
-    #[fail(display = "invalid toolchain name: {}", name)]
-    InvalidToolchainName { name: String },
-    #[fail(display = "Unknown error occoured: {}", _0)]
-    Unknown(String),
+pub enum RarError {
+    /// The CRC32 of the extracted data doesn't match the one of the file header
+    #[fail(
+        display = "CRC32 mismatch for {}: expected {:08X}, calculated {:08X}",
+        name, expected, calculated
+    )]
+    CrcMismatch {
+        name: String,
+        expected: u32,
+        calculated: u32,
+    },
+    /// The BLAKE2sp hash of the extracted data doesn't match the one of the file header
+    #[fail(display = "BLAKE2sp hash mismatch for {}", name)]
+    HashMismatch { name: String },
+    /// The password doesn't match the check value of an encrypted file
+    #[fail(display = "Wrong password for {}", name)]
+    WrongPassword { name: String },
+    /// An encrypted file can't be extracted without a password
+    #[fail(display = "A password is required for {}", name)]
+    PasswordRequired { name: String },
+    /// The CRC32 of a block header doesn't match the stored one
+    #[fail(
+        display = "Header CRC32 mismatch: expected {:08X}, calculated {:08X}",
+        expected, calculated
+    )]
+    HeaderCrcMismatch { expected: u32, calculated: u32 },
+    /// A block header is larger than the data which is available for it
+    #[fail(display = "Header size of {} bytes exceeds the available data", size)]
+    TruncatedHeader { size: u64 },
+    /// A block header claims a size, which no valid header can have
+    #[fail(
+        display = "Header size of {} bytes exceeds the maximum header size",
+        size
+    )]
+    HeaderTooLarge { size: u64 },
 }
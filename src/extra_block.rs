@@ -1,8 +1,14 @@
-use chrono::naive::NaiveDateTime;
+use chrono::naive::{NaiveDate, NaiveDateTime};
+use chrono::{DateTime, Duration};
 use nom;
+use std::fmt;
 use util;
 use vint;
 
+/// Highest binary logarithm of the PBKDF2 iteration count, like in unrar.
+/// Higher counts would stall the key derivation or overflow it.
+pub const MAX_KDF_COUNT: u8 = 24;
+
 /// The Extra Area Block which provides optional
 /// information about the file.
 /// This can be about the time, encryption, hash,
@@ -11,6 +17,7 @@ use vint;
 pub struct ExtraAreaBlock {
     pub file_time: Option<FileTimeBlock>,
     pub file_encryption: Option<FileEncryptionBlock>,
+    pub file_hash: Option<FileHashBlock>,
 }
 
 impl ExtraAreaBlock {
@@ -21,10 +28,11 @@ impl ExtraAreaBlock {
         let mut eab = ExtraAreaBlock {
             file_time: None,
             file_encryption: None,
+            file_hash: None,
         };
 
         // parse all the different extra blocks after each other
-        while inp.len() > 0 {
+        while !inp.is_empty() {
             let (i, size) = vint::vint(inp)?;
             let (i, typ) = vint::vint(i)?;
             let (i, data) = take!(i, size - 1)?;
@@ -32,7 +40,14 @@ impl ExtraAreaBlock {
 
             // based upon the block type use the right parser
             match typ {
-                0x01 => eab.file_encryption = FileEncryptionBlock::parse(data).ok().map(|i| i.1),
+                0x01 => {
+                    // a damaged encryption record must not look like an unencrypted file
+                    let (_, feb) = FileEncryptionBlock::parse(data).map_err(|_| {
+                        nom::Err::Error(error_position!(data, nom::ErrorKind::Verify))
+                    })?;
+                    eab.file_encryption = Some(feb);
+                }
+                0x02 => eab.file_hash = FileHashBlock::parse(data).ok().map(|i| i.1),
                 0x03 => eab.file_time = FileTimeBlock::parse(data).ok().map(|i| i.1),
                 _ => {}
             }
@@ -59,6 +74,7 @@ fn test_parse_extra_area_time() {
     let eab = ExtraAreaBlock {
         file_time: Some(ftb),
         file_encryption: None,
+        file_hash: None,
     };
 
     assert_eq!(ExtraAreaBlock::parse(&data), Ok((&[][..], eab)));
@@ -80,24 +96,28 @@ fn test_parse_extra_area_multi() {
         access_time: None,
     };
 
-    let mut febf = FileEncryptionBlockFlags::default();
-    febf.pw_check_data = true;
-    febf.tweaked_crc = true;
+    let febf = FileEncryptionBlockFlags {
+        pw_check_data: true,
+        tweaked_crc: true,
+    };
 
-    let mut feb = FileEncryptionBlock::default();
-    feb.flags = febf;
-    feb.kdf_count = 15;
-    feb.salt = [
-        145, 54, 92, 222, 142, 142, 13, 19, 255, 186, 128, 233, 43, 95, 8, 74,
-    ];
-    feb.init = [
-        141, 80, 55, 232, 205, 190, 86, 123, 202, 195, 252, 119, 133, 39, 123, 186,
-    ];
-    feb.pw_check = [8, 242, 216, 179, 32, 113, 132, 82, 146, 25, 86, 17];
+    let feb = FileEncryptionBlock {
+        flags: febf,
+        kdf_count: 15,
+        salt: [
+            145, 54, 92, 222, 142, 142, 13, 19, 255, 186, 128, 233, 43, 95, 8, 74,
+        ],
+        init: [
+            141, 80, 55, 232, 205, 190, 86, 123, 202, 195, 252, 119, 133, 39, 123, 186,
+        ],
+        pw_check: [8, 242, 216, 179, 32, 113, 132, 82, 146, 25, 86, 17],
+        ..FileEncryptionBlock::default()
+    };
 
     let eab = ExtraAreaBlock {
         file_time: Some(ftb),
         file_encryption: Some(feb),
+        file_hash: None,
     };
 
     assert_eq!(ExtraAreaBlock::parse(&data), Ok((&[][..], eab)));
@@ -150,6 +170,54 @@ impl FileTimeBlock {
         Ok((inp, ftb))
     }
 
+    /// Create the file time block from the RAR 1.5 - 4.x extended time field.
+    ///
+    /// The modification time of the file header is refined by the extended time,
+    /// all other times are stored completly within the extended time field.
+    pub fn parse_rar4(input: &[u8], mtime: u32) -> nom::IResult<&[u8], FileTimeBlock> {
+        let (mut inp, flags) = nom::le_u16(input)?;
+        let mut times = [None; 4];
+
+        // the modification, creation, access and archive time
+        // are each defined by 4 bits, starting with the highest ones
+        for (i, time) in times.iter_mut().enumerate() {
+            let mode = flags >> ((3 - i) * 4);
+            if mode & 0x08 == 0 {
+                continue;
+            }
+
+            let dos = if i == 0 {
+                mtime
+            } else {
+                let (r, t) = nom::le_u32(inp)?;
+                inp = r;
+                t
+            };
+
+            // the remainder in 100ns steps is stored in up to 3 bytes
+            let count = (mode & 0x03) as usize;
+            let (r, bytes) = take!(inp, count)?;
+            inp = r;
+            let mut remainder = 0i64;
+            for (j, b) in bytes.iter().enumerate() {
+                remainder |= (*b as i64) << ((j + 3 - count) * 8);
+            }
+
+            // one second is added to odd times
+            let second = if mode & 0x04 != 0 { 1 } else { 0 };
+            *time = dos_time(dos)
+                .map(|t| t + Duration::seconds(second) + Duration::nanoseconds(remainder * 100));
+        }
+
+        let ftb = FileTimeBlock {
+            modification_time: times[0],
+            creation_time: times[1],
+            access_time: times[2],
+        };
+
+        Ok((inp, ftb))
+    }
+
     /// Parses a timestamp from the byte slice from a unix or windows format
     fn parse_timestamp(
         input: &[u8],
@@ -157,12 +225,12 @@ impl FileTimeBlock {
     ) -> nom::IResult<&[u8], Option<NaiveDateTime>> {
         if unix_time {
             let (i, t) = nom::le_u32(take!(input, 4)?.1)?;
-            let t = NaiveDateTime::from_timestamp_opt(t as i64, 0);
+            let t = DateTime::from_timestamp(t as i64, 0).map(|t| t.naive_utc());
             Ok((i, t))
         } else {
             let (i, t) = nom::le_u64(take!(input, 8)?.1)?;
             let t = (t / 10000000) - 11644473600;
-            let t = NaiveDateTime::from_timestamp_opt(t as i64, 0);
+            let t = DateTime::from_timestamp(t as i64, 0).map(|t| t.naive_utc());
             Ok((i, t))
         }
     }
@@ -182,6 +250,46 @@ fn test_parse_time() {
     assert_eq!(FileTimeBlock::parse(&data), Ok((&[][..], ftb)));
 }
 
+#[test]
+fn test_parse_time_rar4() {
+    // modification time with a remainder of 3 bytes and one second added
+    let data = [0x00, 0xF0, 0xF8, 0x13, 0x5B, 0x46];
+    let ftb = FileTimeBlock {
+        modification_time: Some(
+            NaiveDateTime::parse_from_str("2018-05-23 12:02:11.5968888", "%Y-%m-%d %H:%M:%S%.f")
+                .unwrap(),
+        ),
+        creation_time: None,
+        access_time: None,
+    };
+
+    assert_eq!(
+        FileTimeBlock::parse_rar4(&data, 0x4CB7_6045),
+        Ok((&[0x46][..], ftb))
+    );
+}
+
+/// Convert a MS-DOS date and time into a timestamp.
+///
+/// The DOS time has a resolution of two seconds and is stored in local time.
+pub fn dos_time(t: u32) -> Option<NaiveDateTime> {
+    NaiveDate::from_ymd_opt(
+        ((t >> 25) & 0x7f) as i32 + 1980,
+        (t >> 21) & 0x0f,
+        (t >> 16) & 0x1f,
+    )
+    .and_then(|d| d.and_hms_opt((t >> 11) & 0x1f, (t >> 5) & 0x3f, (t & 0x1f) * 2))
+}
+
+#[test]
+fn test_dos_time() {
+    let t =
+        Some(NaiveDateTime::parse_from_str("2018-05-23 12:02:10", "%Y-%m-%d %H:%M:%S").unwrap());
+
+    assert_eq!(dos_time(0x4CB7_6045), t);
+    assert_eq!(dos_time(0), None);
+}
+
 #[test]
 fn test_convert_time() {
     let data = [0x9D, 0xA1, 0xE3, 0x8C, 0xB5, 0x44, 0xD2, 0x01];
@@ -196,7 +304,7 @@ fn test_convert_time() {
 
 /// File Encryption Block which gives the necessary
 /// Information about the encrypted file.
-#[derive(PartialEq, Debug, Clone, Default)]
+#[derive(PartialEq, Clone, Default)]
 pub struct FileEncryptionBlock {
     pub version: FileEncryptionVersion,
     pub flags: FileEncryptionBlockFlags,
@@ -206,6 +314,21 @@ pub struct FileEncryptionBlock {
     pub pw_check: [u8; 12],
 }
 
+/// The password check value is derived from the password,
+/// so it is never printed.
+impl fmt::Debug for FileEncryptionBlock {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("FileEncryptionBlock")
+            .field("version", &self.version)
+            .field("flags", &self.flags)
+            .field("kdf_count", &self.kdf_count)
+            .field("salt", &self.salt)
+            .field("init", &self.init)
+            .field("pw_check", &"<redacted>")
+            .finish()
+    }
+}
+
 impl FileEncryptionBlock {
     fn parse(input: &[u8]) -> nom::IResult<&[u8], FileEncryptionBlock> {
         // parse version
@@ -214,6 +337,12 @@ impl FileEncryptionBlock {
         let (inp, flags) = FileEncryptionBlockFlags::parse(inp)?;
         // parse kdf count
         let (inp, kdf_count) = take!(inp, 1)?;
+        if kdf_count[0] > MAX_KDF_COUNT {
+            return Err(nom::Err::Error(error_position!(
+                input,
+                nom::ErrorKind::TooLarge
+            )));
+        }
         // parse salt value
         let (inp, salt) = take!(inp, 16)?;
         // parse init value
@@ -222,7 +351,7 @@ impl FileEncryptionBlock {
         let mut pw_check = [0; 12];
         if flags.pw_check_data {
             let (i, p) = take!(inp, 12)?;
-            pw_check.copy_from_slice(&p);
+            pw_check.copy_from_slice(p);
             inp = i;
         }
 
@@ -235,8 +364,8 @@ impl FileEncryptionBlock {
             pw_check,
         };
 
-        feb.salt.copy_from_slice(&salt);
-        feb.init.copy_from_slice(&init);
+        feb.salt.copy_from_slice(salt);
+        feb.init.copy_from_slice(init);
 
         Ok((inp, feb))
     }
@@ -251,34 +380,54 @@ fn test_file_encryption_parse() {
         0x11,
     ];
 
-    let mut febf = FileEncryptionBlockFlags::default();
-    febf.pw_check_data = true;
-    febf.tweaked_crc = true;
+    let febf = FileEncryptionBlockFlags {
+        pw_check_data: true,
+        tweaked_crc: true,
+    };
 
-    let mut feb = FileEncryptionBlock::default();
-    feb.flags = febf;
-    feb.kdf_count = 15;
-    feb.salt = [
-        145, 54, 92, 222, 142, 142, 13, 19, 255, 186, 128, 233, 43, 95, 8, 74,
-    ];
-    feb.init = [
-        141, 80, 55, 232, 205, 190, 86, 123, 202, 195, 252, 119, 133, 39, 123, 186,
-    ];
-    feb.pw_check = [8, 242, 216, 179, 32, 113, 132, 82, 146, 25, 86, 17];
+    let feb = FileEncryptionBlock {
+        flags: febf,
+        kdf_count: 15,
+        salt: [
+            145, 54, 92, 222, 142, 142, 13, 19, 255, 186, 128, 233, 43, 95, 8, 74,
+        ],
+        init: [
+            141, 80, 55, 232, 205, 190, 86, 123, 202, 195, 252, 119, 133, 39, 123, 186,
+        ],
+        pw_check: [8, 242, 216, 179, 32, 113, 132, 82, 146, 25, 86, 17],
+        ..FileEncryptionBlock::default()
+    };
 
     assert_eq!(FileEncryptionBlock::parse(&data), Ok((&[][..], feb)));
+
+    // too many rounds for the key derivation
+    let mut data = data;
+    data[2] = MAX_KDF_COUNT + 1;
+    assert!(FileEncryptionBlock::parse(&data).is_err());
+
+    // the file doesn't lose its encryption
+    let mut extra = vec![data.len() as u8 + 1, 0x01];
+    extra.extend_from_slice(&data);
+    assert!(ExtraAreaBlock::parse(&extra).is_err());
 }
 
 /// File Encryption Block which gives the necessary
 /// Information about the encrypted file.
-#[derive(PartialEq, Debug, Clone)]
+#[derive(PartialEq, Debug, Clone, Default)]
 pub enum FileEncryptionVersion {
+    #[default]
     Aes256,
+    /// RAR 3.x AES-128 encryption with an optional salt
+    Rar30(Option<[u8; 8]>),
+    /// RAR 2.0 block cipher
+    Rar20,
+    /// RAR 1.5 stream cipher
+    Rar15,
     Unknown,
 }
 
 impl FileEncryptionVersion {
-    fn parse(input: &[u8]) -> nom::IResult<&[u8], FileEncryptionVersion> {
+    pub fn parse(input: &[u8]) -> nom::IResult<&[u8], FileEncryptionVersion> {
         let (inp, version) = vint::vint(input)?;
 
         match version {
@@ -288,18 +437,12 @@ impl FileEncryptionVersion {
     }
 }
 
-impl Default for FileEncryptionVersion {
-    fn default() -> FileEncryptionVersion {
-        FileEncryptionVersion::Aes256
-    }
-}
-
 /// File Encryption Block Flags which gives informaton
 /// about how the decrypt the file
 #[derive(PartialEq, Debug, Clone, Default)]
 pub struct FileEncryptionBlockFlags {
-    pw_check_data: bool,
-    tweaked_crc: bool,
+    pub pw_check_data: bool,
+    pub tweaked_crc: bool,
 }
 
 impl FileEncryptionBlockFlags {
@@ -307,11 +450,61 @@ impl FileEncryptionBlockFlags {
         // Parse flags
         let (inp, flags) = vint::vint(input)?;
 
-        let mut febf = FileEncryptionBlockFlags::default();
-
-        febf.pw_check_data = util::get_bit_at(flags, 0);
-        febf.tweaked_crc = util::get_bit_at(flags, 1);
+        let febf = FileEncryptionBlockFlags {
+            pw_check_data: util::get_bit_at(flags, 0),
+            tweaked_crc: util::get_bit_at(flags, 1),
+        };
 
         Ok((inp, febf))
     }
 }
+
+/// File Hash Block which stores a hash of the unpacked file data
+#[derive(PartialEq, Debug, Clone)]
+pub enum FileHashBlock {
+    Blake2sp([u8; 32]),
+}
+
+impl FileHashBlock {
+    fn parse(input: &[u8]) -> nom::IResult<&[u8], FileHashBlock> {
+        let (inp, typ) = vint::vint(input)?;
+
+        match typ {
+            0x00 => {
+                let (inp, hash) = take!(inp, 32)?;
+                let mut blake2sp = [0; 32];
+                blake2sp.copy_from_slice(hash);
+                Ok((inp, FileHashBlock::Blake2sp(blake2sp)))
+            }
+            _ => Err(nom::Err::Error(error_position!(
+                input,
+                nom::ErrorKind::Custom(0)
+            ))),
+        }
+    }
+}
+
+#[test]
+fn test_parse_extra_area_hash() {
+    let mut data = vec![0x22, 0x02, 0x00];
+    data.extend(0..32);
+
+    let mut hash = [0; 32];
+    for (i, h) in hash.iter_mut().enumerate() {
+        *h = i as u8;
+    }
+
+    let eab = ExtraAreaBlock {
+        file_hash: Some(FileHashBlock::Blake2sp(hash)),
+        ..ExtraAreaBlock::default()
+    };
+
+    assert_eq!(ExtraAreaBlock::parse(&data), Ok((&[][..], eab)));
+
+    // unknown hash types are ignored
+    data[2] = 0x01;
+    assert_eq!(
+        ExtraAreaBlock::parse(&data),
+        Ok((&[][..], ExtraAreaBlock::default()))
+    );
+}
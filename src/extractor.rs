@@ -1,97 +1,166 @@
-use aes_reader::RarAesReader;
+use aes_reader::{request_key, FileKey, KeyRequest, RarAesReader};
+use archive_block::ArchiveBlock;
 use failure::Error;
-use file_block::FileBlock;
-use file_writer::FileWriter;
+use file_block::{CompressionFlags, FileBlock};
+use file_writer::CheckedReader;
+use head_block::HeaderReader;
+use password::Keyring;
+use rar15_reader::Rar15Reader;
 use rar_reader::RarReader;
-use std::io::prelude::*;
-use std::io::Read;
-
-/// This function extracts the data from a RarReader and writes it into an file.
-pub fn extract(
+use sig_block::SignatureBlock;
+use std::io::{copy, sink, Read};
+use unpack::{UnpackReader, UnpackState};
+use volume::VolumeProvider;
+
+/// This function extracts the data from a RarReader and passes the
+/// reader of the unpacked data to the visitor.
+///
+/// The reader verifies the checksums of the data at its end, the data
+/// which the visitor didn't read is verified afterwards.
+/// Encrypted files are skipped, when the password provider of the keyring decides so.
+pub fn extract<V>(
     file: &FileBlock,
-    path: &str,
     reader: &mut RarReader,
     data_area_size: u64,
-    password: &str,
-) -> Result<(), Error> {
-    // create file writer to create and fill the file
-    let mut f_writer = FileWriter::new(file.clone(), &path)?;
-
-    // Limit the data to take from the reader
-    let reader = RarReader::new(reader.take(data_area_size));
+    keyring: &mut Keyring,
+    state: &mut UnpackState,
+    visit: V,
+) -> Result<(), Error>
+where
+    V: FnOnce(&FileBlock, &mut dyn Read) -> Result<(), Error>,
+{
+    // verify the password before any file is created
+    let key = match request_key(file, keyring)? {
+        KeyRequest::Key(key) => key,
+        KeyRequest::Skip => {
+            state.skip(file);
+            reader.r_seek(data_area_size)?;
+            return Ok(());
+        }
+    };
 
-    // Initilize the decryption reader
-    let mut reader = RarAesReader::new(reader, file.clone(), password);
+    unpack_with(file, reader, data_area_size, key.clone(), state, |data| {
+        let mut data = CheckedReader::new(data, file.clone(), key);
+        let res = visit(file, &mut data);
 
-    // loop over chunks of the data and write it to the files
-    let mut data_buffer = [0u8; ::BUFFER_SIZE];
-    loop {
-        // read a chunk of data from the buffer
-        let new_byte_count = reader.read(&mut data_buffer)?;
-        let data = &mut data_buffer[..new_byte_count];
+        // make sure the extracted data is not damaged,
+        // which is the cause of the failure as well
+        data.finish()?;
+        res
+    })
+}
 
-        // end loop if nothing is there anymore
-        if new_byte_count <= 0 {
-            break;
+/// This function skips the data of a file.
+///
+/// Packed files of a solid archive are unpacked into a sink, because
+/// the following files depend on the state of the decoder. Without
+/// their password the following files can't be unpacked correctly,
+/// so the state is marked as skipped and unpacking them fails.
+pub fn skip(
+    file: &FileBlock,
+    reader: &mut RarReader,
+    data_area_size: u64,
+    keyring: &mut Keyring,
+    state: &mut UnpackState,
+) -> Result<(), Error> {
+    if state.is_solid() && file.compression.flag != CompressionFlags::Save {
+        if let KeyRequest::Key(key) = request_key(file, keyring)? {
+            unpack_with(file, reader, data_area_size, key, state, |data| {
+                copy(data, &mut sink())?;
+                Ok(())
+            })?;
+            return Ok(());
         }
+        state.skip(file);
+    }
 
-        // unpack if necessary
-        // todo
+    reader.r_seek(data_area_size)?;
+
+    Ok(())
+}
 
-        // write out the data
-        if let Err(e) = f_writer.write_all(&data) {
-            if e.kind() == ::std::io::ErrorKind::WriteZero {
-                // end loop when the file capacity is reached
-                break;
+/// This function decrypts and unpacks the data area of a file
+/// and passes the reader of the unpacked data to the function.
+fn unpack_with<F>(
+    file: &FileBlock,
+    reader: &mut RarReader,
+    data_area_size: u64,
+    key: Option<FileKey>,
+    state: &mut UnpackState,
+    func: F,
+) -> Result<(), Error>
+where
+    F: FnOnce(&mut dyn Read) -> Result<(), Error>,
+{
+    // Limit the data to take from the reader
+    let mut data_area = reader.take(data_area_size);
+
+    {
+        // Initilize the decryption reader, which matches the key
+        let reader: Box<dyn Read> = match key {
+            Some(FileKey::Rar15(key)) => Box::new(Rar15Reader::new(&mut data_area, key)),
+            key => Box::new(RarAesReader::new(&mut data_area, file.clone(), key)),
+        };
+
+        // Initilize the decoder when the data is packed
+        let reader: Box<dyn Read> =
+            if file.compression.flag == CompressionFlags::Save || data_area_size == 0 {
+                Box::new(reader)
             } else {
-                Err(e)?;
-            }
-        }
+                Box::new(UnpackReader::new(reader, state.unpacker(file)?))
+            };
+
+        // the data behind the size of the file is the padding of the encryption
+        func(&mut reader.take(file.unpacked_size))?;
     }
 
-    // flush the data
-    f_writer.flush()?;
+    // skip the rest of the data area, which wasn't needed
+    copy(&mut data_area, &mut sink())?;
 
     Ok(())
 }
 
-/// This function chains a new .rar archive file to the data stream.
-/// This ensures that we can build up a big chained reader which holds the complete
-/// data_area, which then can be extracted.
+/// This function appends a new .rar archive file to the data stream.
+/// This ensures that the reader continues with the next part of the
+/// data_area, which then can be extracted or skipped as a whole.
+///
+/// The next volume is requested from the volume provider.
 pub fn continue_data_next_file<'a>(
-    buffer: RarReader<'a>,
+    buffer: &mut RarReader<'a>,
     file: &mut FileBlock,
-    file_name: &str,
+    version: &SignatureBlock,
+    volumes: &mut dyn VolumeProvider,
     file_number: &mut usize,
     data_area_size: &mut u64,
-) -> Result<RarReader<'a>, Error> {
-    // get the next rar file name
-    let mut new_file_name = file_name.to_string();
-    let len = new_file_name.len();
-    new_file_name.replace_range(len - 5.., &format!("{}.rar", *file_number + 1));
-
-    // open the file
-    let reader = ::std::fs::File::open(&new_file_name)?;
+    headers: &mut HeaderReader,
+) -> Result<(), Error> {
+    // get the next volume, which is counted from 0
+    let new_file_name = volumes.name(*file_number);
+    let reader = volumes.volume(*file_number)?;
 
     // put the reader into our buffer
-    let mut new_buffer = RarReader::new_from_file(reader);
+    let mut new_buffer = RarReader::new(reader);
 
     // try to parse the signature
-    let version = new_buffer
-        .exec_nom_parser(::sig_block::SignatureBlock::parse)
+    let new_version = new_buffer
+        .exec_nom_parser(SignatureBlock::parse)
         .map_err(|_| format_err!("Can't read RAR signature"))?;
     // try to parse the archive information
+    headers.begin_volume(&mut new_buffer, version, &new_file_name)?;
     let details = new_buffer
-        .exec_nom_parser(::archive_block::ArchiveBlock::parse)
+        .exec_nom_parser(ArchiveBlock::parser(version))
         .map_err(|_| format_err!("Can't read RAR archive block"))?;
+    headers.archive_block(&details)?;
     // try to parse the file
+    headers.next(&mut new_buffer, version)?;
     let new_file = new_buffer
-        .exec_nom_parser(FileBlock::parse)
+        .exec_nom_parser(FileBlock::parser(version))
         .map_err(|_| format_err!("Can't read RAR file block"))?;
 
-    // check if the next file info is the same as from prvious .rar
-    if version != ::sig_block::SignatureBlock::RAR5
-        || details.volume_number != *file_number as u64
+    // check if the next file info is the same as from prvious .rar,
+    // only RAR5 stores the volume number in the archive header
+    if new_version != *version
+        || (new_version == SignatureBlock::RAR5 && details.volume_number != *file_number as u64)
         || new_file.name != file.name
     {
         return Err(format_err!(
@@ -102,7 +171,7 @@ pub fn continue_data_next_file<'a>(
     // Limit the data to take from the reader, when this data area
     // continues in another .rar archive file
     if new_file.head.flags.data_next {
-        new_buffer = RarReader::new(new_buffer.take(new_file.head.data_area_size));
+        new_buffer.limit(new_file.head.data_area_size);
     }
 
     // count file number up
@@ -114,6 +183,72 @@ pub fn continue_data_next_file<'a>(
     // change the file with the new file
     *file = new_file;
 
-    // chain the buffer together
-    Ok(RarReader::new(buffer.chain(new_buffer)))
+    // continue with the data area of the new .rar file
+    buffer.append(new_buffer);
+    Ok(())
+}
+
+#[test]
+fn test_skip_encrypted_solid() {
+    use archive_reader::ArchiveReader;
+    use extra_block::FileEncryptionBlock;
+    use password::{PasswordProvider, PasswordResponse};
+    use volume::FileVolumes;
+    use CrcMode;
+
+    struct SkipAll;
+    impl PasswordProvider for SkipAll {
+        fn password(&mut self, _: &FileBlock, _: u32) -> PasswordResponse {
+            PasswordResponse::Skip
+        }
+    }
+
+    // the text is skipped by the extraction and by the filter,
+    // both times the solid photo behind it can't be unpacked
+    for &selected in &[true, false] {
+        let volumes = &mut FileVolumes::new("assets/rar5-normal-32mb-txt-png-solid.rar");
+        let passwords = &mut SkipAll;
+        let mut archive = ArchiveReader::open(volumes, passwords, CrcMode::Strict).unwrap();
+        let mut state = UnpackState::new(archive.details.flags.solid);
+
+        // pretend the text is encrypted, so its password is requested
+        let (mut text, size) = archive.next_file().unwrap().unwrap();
+        assert_eq!(text.name, "text.txt");
+        text.extra.file_encryption = Some(FileEncryptionBlock::default());
+        let keyring = archive.headers.keyring();
+        if selected {
+            extract(
+                &text,
+                &mut archive.reader,
+                size,
+                keyring,
+                &mut state,
+                |_, _| panic!("The skipped file is visited"),
+            )
+            .unwrap();
+        } else {
+            skip(&text, &mut archive.reader, size, keyring, &mut state).unwrap();
+        }
+
+        let (photo, size) = archive.next_file().unwrap().unwrap();
+        assert_eq!(photo.name, "photo.jpg");
+        assert!(photo.compression.solid);
+        let keyring = archive.headers.keyring();
+        let err = extract(
+            &photo,
+            &mut archive.reader,
+            size,
+            keyring,
+            &mut state,
+            |_, data| {
+                copy(data, &mut sink())?;
+                Ok(())
+            },
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Solid file photo.jpg can't be unpacked, because the data of text.txt was skipped"
+        );
+    }
 }
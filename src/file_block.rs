@@ -1,8 +1,12 @@
-use extra_block::ExtraAreaBlock;
-use head_block::HeadBlock;
+use extra_block::{
+    dos_time, ExtraAreaBlock, FileEncryptionBlock, FileEncryptionVersion, FileTimeBlock,
+};
+use head_block::{rest_rar4, HeadBlock};
 use nom;
-use nom::be_u32;
-use util::{get_bit_at, split_u64, to_bool};
+use nom::{be_u32, le_u16, le_u32, le_u8};
+use sig_block::SignatureBlock;
+use std::ops::Range;
+use util::get_bit_at;
 use vint::vint;
 
 /// FileBlock
@@ -19,9 +23,17 @@ pub struct FileBlock {
     pub name_len: u64,
     pub name: String,
     pub extra: ExtraAreaBlock,
+    /// Numbers of the volumes which contain the data of the file,
+    /// starting with 0 for the first .rar file
+    pub volumes: Range<usize>,
 }
 
 impl FileBlock {
+    /// Returns true, when the data of the file is encrypted
+    pub fn is_encrypted(&self) -> bool {
+        self.extra.file_encryption.is_some()
+    }
+
     /// Parse an byte slice an returns a FileBlock.
     pub fn parse(inp: &[u8]) -> nom::IResult<&[u8], FileBlock> {
         // get the base header
@@ -57,11 +69,12 @@ impl FileBlock {
             attributes,
             mtime: 0,
             data_crc: 0,
-            compression: compression,
+            compression,
             creation_os: OsFlags::UNKNOWN,
             name_len: 0,
             name: "".into(),
             extra: eab,
+            volumes: 0..1,
         };
 
         // check for time
@@ -73,7 +86,7 @@ impl FileBlock {
 
         // check for file crc data
         if file.flags.crc {
-            let (i, crc) = be_u32(input)?;
+            let (i, crc) = le_u32(input)?;
             input = i;
             file.data_crc = crc;
         }
@@ -99,6 +112,282 @@ impl FileBlock {
 
         Ok((input, file))
     }
+
+    /// Parse a RAR 1.5 - 4.x file header from a byte slice.
+    pub fn parse_rar4(inp: &[u8]) -> nom::IResult<&[u8], FileBlock> {
+        // get the base header
+        let (input, (mut head, flags)) = HeadBlock::parse_rar4(inp)?;
+
+        // check if the defined type is a file or service header
+        if head.typ != ::head_block::Typ::File && head.typ != ::head_block::Typ::Service {
+            return Err(nom::Err::Error(error_position!(inp, nom::ErrorKind::IsNot)));
+        }
+
+        let bit = |n| get_bit_at(flags as u64, n);
+        head.flags.data_prev = bit(0);
+        head.flags.data_next = bit(1);
+
+        let (input, (unpacked_size, os, data_crc, ftime, version, method, name_len, attr)) =
+            file_header_rar4(input)?;
+
+        let mut file = FileBlock {
+            head,
+            flags: FileFlags::default(),
+            unpacked_size: unpacked_size as u64,
+            attributes: attr as u64,
+            mtime: 0,
+            data_crc,
+            compression: Compression {
+                version,
+                solid: bit(4),
+                flag: method.wrapping_sub(0x30).into(),
+                dictonary: ((flags >> 5) & 0x07) as u8,
+            },
+            creation_os: OsFlags::from_rar4(os),
+            name_len: name_len as u64,
+            name: "".into(),
+            extra: ExtraAreaBlock::default(),
+            volumes: 0..1,
+        };
+        file.flags.crc = true;
+
+        // all bits of the dictonary set define a directory
+        file.flags.directory = file.compression.dictonary == 0x07;
+
+        // check for the high 32 bit of the sizes
+        let mut input = input;
+        if bit(8) {
+            let (i, (high_pack, high_unp)) = tuple!(input, le_u32, le_u32)?;
+            input = i;
+            file.head.data_area_size |= (high_pack as u64) << 32;
+            file.unpacked_size |= (high_unp as u64) << 32;
+        }
+
+        let (mut input, name) = take!(input, name_len)?;
+        file.name = decode_name_rar4(name, bit(9)).replace('\\', "/");
+
+        // check for the encryption and its salt
+        if bit(2) {
+            let version = match version {
+                v if v >= 29 => FileEncryptionVersion::Rar30(None),
+                v if v >= 20 => FileEncryptionVersion::Rar20,
+                _ => FileEncryptionVersion::Rar15,
+            };
+            file.extra.file_encryption = Some(FileEncryptionBlock {
+                version,
+                ..FileEncryptionBlock::default()
+            });
+        }
+        if bit(10) {
+            let (i, s) = take!(input, 8)?;
+            input = i;
+            if let Some(FileEncryptionBlock {
+                version: FileEncryptionVersion::Rar30(ref mut salt),
+                ..
+            }) = file.extra.file_encryption
+            {
+                let mut data = [0; 8];
+                data.copy_from_slice(s);
+                *salt = Some(data);
+            }
+        }
+
+        // check for the extended time, otherwise only the dos time exists
+        if bit(12) {
+            let (i, ftb) = FileTimeBlock::parse_rar4(input, ftime)?;
+            input = i;
+            file.extra.file_time = Some(ftb);
+        } else {
+            file.extra.file_time = Some(FileTimeBlock {
+                modification_time: dos_time(ftime),
+                creation_time: None,
+                access_time: None,
+            });
+        }
+
+        let (input, _) = rest_rar4(inp, input, &file.head)?;
+        Ok((input, file))
+    }
+
+    /// Returns the file header parser for the given archive version
+    pub fn parser(version: &SignatureBlock) -> fn(&[u8]) -> nom::IResult<&[u8], FileBlock> {
+        match version {
+            SignatureBlock::RAR5 => FileBlock::parse,
+            SignatureBlock::RAR4 => FileBlock::parse_rar4,
+        }
+    }
+}
+
+named_attr!(#[doc = "Get the fixed fields of a RAR 1.5 - 4.x file header behind the packed size"],
+    file_header_rar4(&[u8]) -> (u32, u8, u32, u32, u8, u8, u16, u32),
+    tuple!(le_u32, le_u8, le_u32, le_u32, le_u8, le_u8, le_u16, le_u32)
+);
+
+/// Decode a RAR 1.5 - 4.x file name.
+///
+/// Unicode names are stored behind a zero byte after the ASCII name,
+/// compressed with the ASCII name as reference. When no zero byte
+/// exists the name is UTF-8 encoded.
+fn decode_name_rar4(name: &[u8], unicode: bool) -> String {
+    let zero = name.iter().position(|b| *b == 0);
+    let (ascii, enc) = match zero {
+        Some(z) if unicode => (&name[..z], &name[z + 1..]),
+        Some(z) => return String::from_utf8_lossy(&name[..z]).into(),
+        None => return String::from_utf8_lossy(name).into(),
+    };
+
+    let mut out: Vec<u16> = Vec::new();
+    let high_byte = (*enc.first().unwrap_or(&0) as u16) << 8;
+    let mut pos = 1;
+    let mut flags = 0u8;
+    let mut flag_bits = 0;
+
+    while pos < enc.len() {
+        if flag_bits == 0 {
+            flags = enc[pos];
+            pos += 1;
+            flag_bits = 8;
+        }
+
+        match flags >> 6 {
+            0 if pos < enc.len() => {
+                out.push(enc[pos] as u16);
+                pos += 1;
+            }
+            1 if pos < enc.len() => {
+                out.push(enc[pos] as u16 | high_byte);
+                pos += 1;
+            }
+            2 if pos + 1 < enc.len() => {
+                out.push(enc[pos] as u16 | (enc[pos + 1] as u16) << 8);
+                pos += 2;
+            }
+            3 if pos < enc.len() => {
+                let length = enc[pos];
+                pos += 1;
+
+                // the next characters are taken from the ascii name
+                if length & 0x80 != 0 {
+                    if pos >= enc.len() {
+                        break;
+                    }
+                    let correction = enc[pos];
+                    pos += 1;
+                    for _ in 0..(length & 0x7f) + 2 {
+                        match ascii.get(out.len()) {
+                            Some(c) => out.push(c.wrapping_add(correction) as u16 | high_byte),
+                            None => break,
+                        }
+                    }
+                } else {
+                    for _ in 0..length + 2 {
+                        match ascii.get(out.len()) {
+                            Some(c) => out.push(*c as u16),
+                            None => break,
+                        }
+                    }
+                }
+            }
+            _ => break,
+        }
+
+        flags <<= 2;
+        flag_bits -= 2;
+    }
+
+    String::from_utf16_lossy(&out)
+}
+
+#[test]
+fn test_decode_name_rar4() {
+    assert_eq!(decode_name_rar4(b"text.txt", false), "text.txt");
+    assert_eq!(decode_name_rar4("fünf.txt".as_bytes(), true), "fünf.txt");
+
+    // "süß.txt" encoded with the high byte 0x00, the first 3 characters
+    // as single bytes and the rest taken from the ascii name
+    let data = [
+        b's', b'?', b'?', b'.', b't', b'x', b't', 0x00, 0x00, 0x03, b's', 0xFC, 0xDF, 0x02,
+    ];
+    assert_eq!(decode_name_rar4(&data, true), "süß.txt");
+}
+#[test]
+fn test_file_rar4() {
+    use chrono::naive::NaiveDateTime;
+
+    let data = [
+        0x0B, 0x2E, 0x74, 0x20, 0x90, 0x2D, 0x00, 0x46, 0x08, 0x00, 0x00, 0x46, 0x08, 0x00, 0x00,
+        0x02, 0x93, 0xF2, 0x9A, 0xCB, 0x45, 0x60, 0xB7, 0x4C, 0x14, 0x30, 0x08, 0x00, 0x20, 0x00,
+        0x00, 0x00, 0x74, 0x65, 0x78, 0x74, 0x2E, 0x74, 0x78, 0x74, 0x00, 0xF0, 0xF8, 0x13, 0x5B,
+        0x46, 0x61, 0x72, 0x20,
+    ];
+
+    let mut flags = ::head_block::Flags::new();
+    flags.data_area = true;
+
+    let file_flag = FileFlags {
+        crc: true,
+        ..FileFlags::default()
+    };
+
+    let eab = ExtraAreaBlock {
+        file_time: Some(FileTimeBlock {
+            modification_time: Some(
+                NaiveDateTime::parse_from_str(
+                    "2018-05-23 12:02:11.5968888",
+                    "%Y-%m-%d %H:%M:%S%.f",
+                )
+                .unwrap(),
+            ),
+            creation_time: None,
+            access_time: None,
+        }),
+        file_encryption: None,
+        file_hash: None,
+    };
+
+    let mut arc = FileBlock {
+        head: HeadBlock::new(0x2E0B, 45, ::head_block::Typ::File, flags),
+        flags: file_flag,
+        unpacked_size: 2118,
+        attributes: 32,
+        mtime: 0,
+        data_crc: 0xCB9A_F293,
+        compression: Compression {
+            version: 20,
+            solid: false,
+            flag: CompressionFlags::Save,
+            dictonary: 1,
+        },
+        creation_os: OsFlags::WINDOWS,
+        name_len: 8,
+        name: "text.txt".into(),
+        extra: eab,
+        volumes: 0..1,
+    };
+    arc.head.data_area_size = 2118;
+    assert_eq!(FileBlock::parse_rar4(&data), Ok((&data[45..][..], arc)));
+}
+#[test]
+fn test_file_rar4_split() {
+    // the photo continued from the previous volume and continued in the next one
+    let data = [
+        0xF1, 0xD4, 0x74, 0xC3, 0x90, 0x2E, 0x00, 0xAA, 0xFF, 0x0F, 0x00, 0xDB, 0xCA, 0x20, 0x00,
+        0x02, 0x8C, 0x0B, 0xDF, 0x5B, 0x58, 0x65, 0x76, 0x49, 0x1D, 0x33, 0x09, 0x00, 0x20, 0x00,
+        0x00, 0x00, 0x70, 0x68, 0x6F, 0x74, 0x6F, 0x2E, 0x6A, 0x70, 0x67, 0x00, 0xF0, 0x1D, 0x1F,
+        0x0A, 0x66, 0xCE,
+    ];
+
+    let (rest, file) = FileBlock::parse_rar4(&data).unwrap();
+    assert_eq!(rest, &[0x66, 0xCE][..]);
+    assert_eq!(file.name, "photo.jpg");
+    assert!(file.head.flags.data_prev);
+    assert!(file.head.flags.data_next);
+    assert_eq!(file.head.data_area_size, 0x000F_FFAA);
+    assert_eq!(file.unpacked_size, 2_149_083);
+    assert_eq!(file.compression.version, 29);
+    assert_eq!(file.compression.flag, CompressionFlags::Normal);
+    assert_eq!(file.compression.get_directonary(), 4096);
+    assert!(!file.flags.directory);
 }
 
 #[test]
@@ -124,8 +413,10 @@ fn test_archive() {
         dictonary: 0,
     };
 
-    let mut file_flag = FileFlags::default();
-    file_flag.crc = true;
+    let file_flag = FileFlags {
+        crc: true,
+        ..FileFlags::default()
+    };
 
     let eab = ExtraAreaBlock {
         file_time: Some(::extra_block::FileTimeBlock {
@@ -136,20 +427,22 @@ fn test_archive() {
             access_time: None,
         }),
         file_encryption: None,
+        file_hash: None,
     };
 
     let mut arc = FileBlock {
-        head: HeadBlock::new(2349697250, 36, ::head_block::Typ::File, flags),
+        head: HeadBlock::new(0xE288_0D8C, 36, ::head_block::Typ::File, flags),
         flags: file_flag,
         unpacked_size: 2118,
         attributes: 32,
         mtime: 0,
-        data_crc: 2482150091,
+        data_crc: 0xCB9A_F293,
         compression,
         creation_os: OsFlags::WINDOWS,
         name_len: 8,
         name: "text.txt".into(),
         extra: eab,
+        volumes: 0..1,
     };
     arc.head.extra_area_size = 11;
     arc.head.data_area_size = 2118;
@@ -178,8 +471,10 @@ fn test_archive_png() {
         dictonary: 0,
     };
 
-    let mut file_flag = FileFlags::default();
-    file_flag.crc = true;
+    let file_flag = FileFlags {
+        crc: true,
+        ..FileFlags::default()
+    };
 
     let eab = ExtraAreaBlock {
         file_time: Some(::extra_block::FileTimeBlock {
@@ -190,20 +485,22 @@ fn test_archive_png() {
             access_time: None,
         }),
         file_encryption: None,
+        file_hash: None,
     };
 
     let mut arc = FileBlock {
-        head: HeadBlock::new(1002517598, 43, ::head_block::Typ::File, flags),
+        head: HeadBlock::new(0x5E34_C13B, 43, ::head_block::Typ::File, flags),
         flags: file_flag,
         unpacked_size: 2149083,
         attributes: 32,
         mtime: 0,
-        data_crc: 2494669946,
+        data_crc: 0x7AA4_B194,
         compression,
         creation_os: OsFlags::WINDOWS,
         name_len: 9,
         name: "photo.jpg".into(),
         extra: eab,
+        volumes: 0..1,
     };
     arc.head.extra_area_size = 11;
     arc.head.data_area_size = 2149083;
@@ -242,10 +539,12 @@ impl From<u64> for FileFlags {
 }
 
 /// OS flags
-#[derive(PartialEq, Debug, Clone)]
+#[allow(clippy::upper_case_acronyms)]
+#[derive(PartialEq, Debug, Clone, Default)]
 pub enum OsFlags {
     WINDOWS,
     UNIX,
+    #[default]
     UNKNOWN,
 }
 
@@ -254,16 +553,21 @@ impl From<u64> for OsFlags {
         if i == 0 {
             return OsFlags::WINDOWS;
         }
-        if i == 0 {
+        if i == 1 {
             return OsFlags::UNIX;
         }
         OsFlags::UNKNOWN
     }
 }
 
-impl Default for OsFlags {
-    fn default() -> OsFlags {
-        OsFlags::UNKNOWN
+impl OsFlags {
+    /// Get the os from a RAR 1.5 - 4.x file header
+    pub fn from_rar4(i: u8) -> Self {
+        match i {
+            0 | 2 => OsFlags::WINDOWS,
+            3 => OsFlags::UNIX,
+            _ => OsFlags::UNKNOWN,
+        }
     }
 }
 
@@ -282,38 +586,25 @@ impl Compression {
         // get the vint
         let (inp, raw) = vint(inp)?;
 
-        // split it back to an u8 array
-        let clean = &split_u64(raw)[..];
-
-        // get the data from the compression
-        // !!!!!! THIS IS PROBABLY WRONG !!!!!!
-        let c = bits!(
-            clean,
-            do_parse!(
-                dictonary: take_bits!(u8, 4)
-                    >> flag: take_bits!(u8, 4)
-                    >> solid: take_bits!(u8, 1)
-                    >> version: take_bits!(u8, 6)
-                    >> (Compression {
-                        version,
-                        solid: to_bool(solid),
-                        flag: flag.into(),
-                        dictonary
-                    })
-            )
-        );
-
-        // change the error to an inp error and not and bit matchign error
-        let c =
-            c.map_err(|_| nom::Err::Error(nom::Context::Code(inp, nom::ErrorKind::Custom(0))))?;
+        // the compression information is a bit field with the
+        // version in the lowest 6 bits, followed by the solid flag,
+        // 3 bits for the compression method and 4 bits for the dictonary
+        let c = Compression {
+            version: (raw & 0x3f) as u8,
+            solid: get_bit_at(raw, 6),
+            flag: (((raw >> 7) & 0x07) as u8).into(),
+            dictonary: ((raw >> 10) & 0x0f) as u8,
+        };
 
         // return the compression
-        Ok((inp, c.1))
+        Ok((inp, c))
     }
 
     /// Return the dictonary in the right format
     pub fn get_directonary(&self) -> u32 {
-        128 * (self.dictonary as f32).exp2() as u32
+        // RAR 1.5 - 4.x starts with a dictonary of 64 KB
+        let base = if self.version >= 13 { 64 } else { 128 };
+        base * (self.dictonary as f32).exp2() as u32
     }
 }
 
@@ -331,6 +622,14 @@ fn test_get_compression() {
         Ok((&[0x00][..], c))
     );
     assert!(Compression::parse(&[0x80]).is_err());
+
+    let c = Compression {
+        version: 0,
+        solid: true,
+        flag: CompressionFlags::Normal,
+        dictonary: 5,
+    };
+    assert_eq!(Compression::parse(&[0xC0, 0x2B]), Ok((&[][..], c)));
 }
 #[test]
 fn test_get_directonary() {
@@ -348,10 +647,16 @@ fn test_get_directonary() {
     assert_eq!(data.get_directonary(), 131072);
     data.dictonary = 15;
     assert_eq!(data.get_directonary(), 4194304);
+
+    data.version = 29;
+    data.dictonary = 0;
+    assert_eq!(data.get_directonary(), 64);
+    data.dictonary = 6;
+    assert_eq!(data.get_directonary(), 4096);
 }
 
 /// Compression Flags
-#[derive(PartialEq, Debug, Clone)]
+#[derive(PartialEq, Debug, Clone, Default)]
 pub enum CompressionFlags {
     Save,
     Fastest,
@@ -359,6 +664,7 @@ pub enum CompressionFlags {
     Normal,
     Good,
     Best,
+    #[default]
     Unknown,
 }
 
@@ -385,9 +691,3 @@ impl From<u8> for CompressionFlags {
         CompressionFlags::Unknown
     }
 }
-
-impl Default for CompressionFlags {
-    fn default() -> CompressionFlags {
-        CompressionFlags::Unknown
-    }
-}
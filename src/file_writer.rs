@@ -1,41 +1,107 @@
+use aes_reader::FileKey;
+use blake2sp::Blake2sp;
+use error::RarError;
+use extra_block::FileHashBlock;
+use failure::Error;
 use file_block::FileBlock;
 use std::fs;
-use std::io::{BufWriter, Result, Write};
+use std::io::{copy, sink, BufWriter, ErrorKind, Read, Result, Sink, Write};
+use util::crc32;
 
-/// This FileWriter writes out the data into a new
-/// file underneath the given path
-pub struct FileWriter {
+/// This FileWriter writes out the data of a file into a writer
+/// and calculates the checksums of the data on the way.
+pub struct FileWriter<W: Write> {
     file: FileBlock,
-    writer: BufWriter<fs::File>,
+    writer: W,
     bytes_written: u64,
+    crc: u32,
+    hash: Option<Blake2sp>,
+    key: Option<FileKey>,
 }
 
-impl FileWriter {
-    /// Create a new FileWriter to write the data
-    pub fn new(file: FileBlock, path: &str) -> Result<FileWriter> {
-        // create the file and the path
-        fs::create_dir_all(path)?;
-
-        // create a file writer with a buffer
-        let writer = BufWriter::new(fs::File::create(format!("{}/{}", path, file.name))?);
+impl<W: Write> FileWriter<W> {
+    /// Create a new FileWriter to write the data into the writer.
+    ///
+    /// The key of an encrypted file is needed to verify its checksums.
+    pub fn new(file: FileBlock, writer: W, key: Option<FileKey>) -> FileWriter<W> {
+        // the hash is only calculated, when there is one to compare with
+        let hash = file.extra.file_hash.as_ref().map(|_| Blake2sp::new());
 
         // return the FileWriter
-        Ok(FileWriter {
+        FileWriter {
             file,
             writer,
             bytes_written: 0,
-        })
+            crc: 0,
+            hash,
+            key,
+        }
+    }
+
+    /// Compare the CRC32 of the written data with the one of the file header
+    pub fn check_crc(&self) -> ::std::result::Result<(), RarError> {
+        if !self.file.flags.crc {
+            return Ok(());
+        }
+
+        let calculated = match (self.tweaked(), &self.key) {
+            (false, _) => self.crc,
+            (true, Some(key)) => key.tweak_crc(self.crc),
+            // without the key the checksum can't be converted
+            (true, None) => return Ok(()),
+        };
+
+        if calculated != self.file.data_crc {
+            return Err(RarError::CrcMismatch {
+                name: self.file.name.clone(),
+                expected: self.file.data_crc,
+                calculated,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Compare the BLAKE2sp hash of the written data with the one of the extra area
+    pub fn check_hash(&self) -> ::std::result::Result<(), RarError> {
+        if let (Some(FileHashBlock::Blake2sp(expected)), Some(hash)) =
+            (&self.file.extra.file_hash, &self.hash)
+        {
+            let calculated = match (self.tweaked(), &self.key) {
+                (false, _) => hash.clone().finalize(),
+                (true, Some(key)) => key.tweak_hash(&hash.clone().finalize()),
+                // without the key the hash can't be converted
+                (true, None) => return Ok(()),
+            };
+
+            if calculated != *expected {
+                return Err(RarError::HashMismatch {
+                    name: self.file.name.clone(),
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The checksums of encrypted files can be converted with
+    /// the hash key, so they can't be compared directly
+    fn tweaked(&self) -> bool {
+        match self.file.extra.file_encryption {
+            Some(ref feb) => feb.flags.tweaked_crc,
+            None => false,
+        }
     }
 }
 
-impl Write for FileWriter {
+impl<W: Write> Write for FileWriter<W> {
     /// Write the data into the file
     fn write(&mut self, buf: &[u8]) -> Result<usize> {
         // calculate the length which still needs to be written
         let mut len = (self.file.unpacked_size - self.bytes_written) as usize;
 
         // when no data needs to be written anymore return 0
-        if len <= 0 {
+        if len == 0 {
             return Ok(0);
         }
 
@@ -46,6 +112,10 @@ impl Write for FileWriter {
 
         self.writer.write_all(&buf[..len])?;
         self.bytes_written += len as u64;
+        self.crc = crc32(self.crc, &buf[..len]);
+        if let Some(ref mut hash) = self.hash {
+            hash.update(&buf[..len]);
+        }
         Ok(len)
     }
 
@@ -54,12 +124,100 @@ impl Write for FileWriter {
     }
 }
 
+/// Reader over the data of a file, which verifies the checksums of the
+/// data, when its end is reached.
+///
+/// A damaged file is reported as an error of the last read.
+pub struct CheckedReader<R: Read> {
+    reader: R,
+    checksums: FileWriter<Sink>,
+    /// The end of the data was reached and the checksums are verified
+    done: bool,
+    /// The checksum, which didn't match
+    error: Option<RarError>,
+}
+
+impl<R: Read> CheckedReader<R> {
+    /// Create a new CheckedReader over the unpacked data of the file.
+    ///
+    /// The key of an encrypted file is needed to verify its checksums.
+    pub fn new(reader: R, file: FileBlock, key: Option<FileKey>) -> CheckedReader<R> {
+        CheckedReader {
+            reader,
+            checksums: FileWriter::new(file, sink(), key),
+            done: false,
+            error: None,
+        }
+    }
+
+    /// Read the data, which was not read yet, and return the
+    /// checksum which doesn't match with its own error type.
+    pub fn finish(mut self) -> ::std::result::Result<(), Error> {
+        let res = copy(&mut self, &mut sink());
+
+        // the checksums were verified at an earlier read as well
+        match (self.error.take(), res) {
+            (Some(error), _) => Err(error.into()),
+            (None, Err(e)) => Err(e.into()),
+            (None, Ok(_)) => Ok(()),
+        }
+    }
+}
+
+impl<R: Read> Read for CheckedReader<R> {
+    /// Read the data and verify the checksums at its end
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let read = self.reader.read(buf)?;
+        self.checksums.write_all(&buf[..read])?;
+
+        if read == 0 && !buf.is_empty() && !self.done {
+            self.done = true;
+            if let Err(e) = self
+                .checksums
+                .check_crc()
+                .and_then(|_| self.checksums.check_hash())
+            {
+                let err = ::std::io::Error::new(ErrorKind::InvalidData, e.to_string());
+                self.error = Some(e);
+                return Err(err);
+            }
+        }
+
+        Ok(read)
+    }
+}
+
+/// Create the file underneath the path with a buffer in front of it
+pub fn create_file(file: &FileBlock, path: &str) -> Result<BufWriter<fs::File>> {
+    // create the file and the path
+    fs::create_dir_all(path)?;
+
+    // create a file writer with a buffer
+    Ok(BufWriter::new(fs::File::create(format!(
+        "{}/{}",
+        path, file.name
+    ))?))
+}
+
 #[cfg(test)]
 mod tests {
+    use aes_reader::FileKey;
+    use error::RarError;
+    use extra_block::{FileEncryptionBlock, FileHashBlock};
     use file_block::FileBlock;
-    use file_writer::FileWriter;
+    use file_writer::{create_file, CheckedReader, FileWriter};
     use std::fs::{remove_dir_all, File};
-    use std::io::{ErrorKind, Read, Write};
+    use std::io::{BufWriter, ErrorKind, Read, Write};
+
+    // Small helper function to write a file underneath the path
+    fn file_writer(
+        file: FileBlock,
+        path: &str,
+        key: Option<FileKey>,
+    ) -> FileWriter<BufWriter<File>> {
+        let writer = create_file(&file, path).unwrap();
+        FileWriter::new(file, writer, key)
+    }
 
     // Small helper function to read a file
     fn read_file(path: &str) -> Vec<u8> {
@@ -71,9 +229,11 @@ mod tests {
 
     #[test]
     fn test_file_writer() {
-        let mut file = FileBlock::default();
-        file.unpacked_size = 10;
-        file.name = "test.txt".to_string();
+        let file = FileBlock {
+            unpacked_size: 10,
+            name: "test.txt".to_string(),
+            ..FileBlock::default()
+        };
 
         let data = [
             0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x10, 0x11, 0x12, 0x13,
@@ -81,7 +241,7 @@ mod tests {
         ];
 
         {
-            let mut fw = FileWriter::new(file, "target/rar-test/file_writer/").unwrap();
+            let mut fw = file_writer(file, "target/rar-test/file_writer/", None);
             assert_eq!(
                 fw.write_all(&data).map_err(|e| e.kind()),
                 Err(ErrorKind::WriteZero)
@@ -96,4 +256,169 @@ mod tests {
 
         remove_dir_all("target/rar-test/file_writer/").unwrap();
     }
+
+    #[test]
+    fn test_file_writer_memory() {
+        let mut file = FileBlock {
+            unpacked_size: 9,
+            ..FileBlock::default()
+        };
+        file.flags.crc = true;
+        file.data_crc = 0xCBF4_3926;
+
+        let mut data = vec![];
+        {
+            let mut fw = FileWriter::new(file, &mut data, None);
+            assert_eq!(
+                fw.write_all(b"1234567890").map_err(|e| e.kind()),
+                Err(ErrorKind::WriteZero)
+            );
+            assert_eq!(fw.check_crc(), Ok(()));
+        }
+
+        assert_eq!(data, b"123456789");
+    }
+
+    #[test]
+    fn test_checked_reader() {
+        let mut file = FileBlock {
+            unpacked_size: 9,
+            name: "crc.txt".to_string(),
+            ..FileBlock::default()
+        };
+        file.flags.crc = true;
+        file.data_crc = 0xCBF4_3926;
+
+        let mut reader = CheckedReader::new(&b"123456789"[..], file.clone(), None);
+        let mut data = vec![];
+        reader.read_to_end(&mut data).unwrap();
+        assert_eq!(data, b"123456789");
+        assert!(reader.finish().is_ok());
+
+        // the damage is reported at the end of the data
+        let mut reader = CheckedReader::new(&b"123456780"[..], file.clone(), None);
+        let mut data = [0u8; 9];
+        reader.read_exact(&mut data).unwrap();
+        assert_eq!(
+            reader.read(&mut data).map_err(|e| e.kind()),
+            Err(ErrorKind::InvalidData)
+        );
+        assert_eq!(
+            reader.finish().unwrap_err().downcast::<RarError>().unwrap(),
+            RarError::CrcMismatch {
+                name: "crc.txt".into(),
+                expected: 0xCBF4_3926,
+                calculated: 0xB228_8182,
+            }
+        );
+
+        // the rest of the data is verified as well
+        let reader = CheckedReader::new(&b"123456780"[..], file, None);
+        assert!(reader.finish().is_err());
+    }
+
+    #[test]
+    fn test_file_writer_crc() {
+        let mut file = FileBlock {
+            unpacked_size: 9,
+            name: "crc.txt".to_string(),
+            ..FileBlock::default()
+        };
+        file.flags.crc = true;
+        file.data_crc = 0xCBF4_3926;
+
+        {
+            let mut fw = file_writer(file.clone(), "target/rar-test/file_writer_crc/", None);
+            fw.write_all(b"123456789").unwrap();
+            assert_eq!(fw.check_crc(), Ok(()));
+        }
+
+        {
+            let mut fw = file_writer(file, "target/rar-test/file_writer_crc/", None);
+            fw.write_all(b"123456780").unwrap();
+            assert_eq!(
+                fw.check_crc(),
+                Err(RarError::CrcMismatch {
+                    name: "crc.txt".into(),
+                    expected: 0xCBF4_3926,
+                    calculated: 0xB228_8182,
+                })
+            );
+        }
+
+        remove_dir_all("target/rar-test/file_writer_crc/").unwrap();
+    }
+
+    #[test]
+    fn test_file_writer_hash() {
+        let mut file = FileBlock {
+            unpacked_size: 3,
+            name: "hash.txt".to_string(),
+            ..FileBlock::default()
+        };
+        file.extra.file_hash = Some(FileHashBlock::Blake2sp([
+            0x70, 0xF7, 0x5B, 0x58, 0xF1, 0xFE, 0xCA, 0xB8, 0x21, 0xDB, 0x43, 0xC8, 0x8A, 0xD8,
+            0x4E, 0xDD, 0xE5, 0xA5, 0x26, 0x00, 0x61, 0x6C, 0xD2, 0x25, 0x17, 0xB7, 0xBB, 0x14,
+            0xD4, 0x40, 0xA7, 0xD5,
+        ]));
+
+        {
+            let mut fw = file_writer(file.clone(), "target/rar-test/file_writer_hash/", None);
+            fw.write_all(b"abc").unwrap();
+            assert_eq!(fw.check_hash(), Ok(()));
+        }
+
+        {
+            let mut fw = file_writer(file, "target/rar-test/file_writer_hash/", None);
+            fw.write_all(b"abd").unwrap();
+            assert_eq!(
+                fw.check_hash(),
+                Err(RarError::HashMismatch {
+                    name: "hash.txt".into(),
+                })
+            );
+        }
+
+        remove_dir_all("target/rar-test/file_writer_hash/").unwrap();
+    }
+
+    #[test]
+    fn test_file_writer_tweaked_crc() {
+        let key = FileKey::Aes256 {
+            key: [0; 32],
+            hash_key: [0x0B; 32],
+        };
+
+        let mut feb = FileEncryptionBlock::default();
+        feb.flags.tweaked_crc = true;
+
+        let mut file = FileBlock {
+            unpacked_size: 9,
+            name: "tweaked.txt".to_string(),
+            ..FileBlock::default()
+        };
+        file.flags.crc = true;
+        file.data_crc = key.tweak_crc(0xCBF4_3926);
+        file.extra.file_encryption = Some(feb);
+
+        {
+            let mut fw = file_writer(
+                file.clone(),
+                "target/rar-test/file_writer_tweaked/",
+                Some(key.clone()),
+            );
+            fw.write_all(b"123456789").unwrap();
+            assert_eq!(fw.check_crc(), Ok(()));
+        }
+
+        {
+            // the plain CRC32 doesn't match the tweaked one
+            file.data_crc = 0xCBF4_3926;
+            let mut fw = file_writer(file, "target/rar-test/file_writer_tweaked/", Some(key));
+            fw.write_all(b"123456789").unwrap();
+            assert!(fw.check_crc().is_err());
+        }
+
+        remove_dir_all("target/rar-test/file_writer_tweaked/").unwrap();
+    }
 }
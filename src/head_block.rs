@@ -1,8 +1,21 @@
+use aes_reader::{decrypt_header, decrypt_header_rar3, derive_header_key, read_or_eof};
+use archive_block::ArchiveBlock;
+use encryption_block::EncryptionBlock;
+use error::RarError;
+use failure::Error;
 use nom;
-use nom::be_u32;
+use nom::{le_u16, le_u32, le_u8};
+use password::{aborted, Keyring, PasswordProvider, PasswordResponse};
+use rar_reader::RarReader;
+use sig_block::SignatureBlock;
 use std::convert::From;
-use util::get_bit_at;
+use std::io::{BufRead, Cursor, ErrorKind};
+use util::{crc32, get_bit_at};
 use vint::vint;
+use zeroize::Zeroizing;
+
+/// Upper limit of the size of a RAR5 header
+pub const MAX_HEADER_SIZE: u64 = 0x20_0000;
 
 /// general Header valid for all rar blocks
 #[derive(PartialEq, Debug, Clone, Default)]
@@ -13,6 +26,9 @@ pub struct HeadBlock {
     pub flags: Flags,
     pub extra_area_size: u64,
     pub data_area_size: u64,
+    /// Offset of the block in its .rar file, set
+    /// for the blocks which are returned by the archive
+    pub offset: u64,
 }
 
 impl HeadBlock {
@@ -25,6 +41,7 @@ impl HeadBlock {
             flags,
             extra_area_size: 0,
             data_area_size: 0,
+            offset: 0,
         }
     }
 
@@ -49,6 +66,465 @@ impl HeadBlock {
 
         Ok((input, bh))
     }
+
+    /// Parse a RAR 1.5 - 4.x HeaderBlock from a byte slice.
+    ///
+    /// The meaning of the flags depends on the block type in this format,
+    /// so the raw flags are returned together with the HeaderBlock.
+    pub fn parse_rar4(input: &[u8]) -> nom::IResult<&[u8], (HeadBlock, u16)> {
+        let (mut input, (crc, typ, raw_flags, size)) = base_header_rar4(input)?;
+
+        let mut bh = HeadBlock::new(crc as u32, size as u64, Typ::from_rar4(typ), Flags::new());
+        bh.flags.skip = get_bit_at(raw_flags as u64, 14);
+
+        // check for a data area
+        if get_bit_at(raw_flags as u64, 15) {
+            let (i, s) = le_u32(input)?;
+            input = i;
+            bh.flags.data_area = true;
+            bh.data_area_size = s as u64;
+        }
+
+        Ok((input, (bh, raw_flags)))
+    }
+
+    /// Parse a block of an unknown type and skip its header.
+    /// The data area still needs to be skipped afterwards.
+    pub fn parse_unknown(inp: &[u8]) -> nom::IResult<&[u8], HeadBlock> {
+        let (_, head) = HeadBlock::parse(inp)?;
+
+        if head.typ != Typ::Unknown {
+            return Err(nom::Err::Error(error_position!(inp, nom::ErrorKind::IsNot)));
+        }
+
+        // the size counts all bytes behind the size field
+        let (input, _) = le_u32(inp)?;
+        let (input, size) = vint(input)?;
+        let (input, _) = take!(input, size)?;
+
+        Ok((input, head))
+    }
+
+    /// Parse a RAR 1.5 - 4.x block of an unknown type and skip its header.
+    /// The data area still needs to be skipped afterwards.
+    pub fn parse_unknown_rar4(inp: &[u8]) -> nom::IResult<&[u8], HeadBlock> {
+        let (input, (head, _)) = HeadBlock::parse_rar4(inp)?;
+
+        if head.typ != Typ::Unknown {
+            return Err(nom::Err::Error(error_position!(inp, nom::ErrorKind::IsNot)));
+        }
+
+        let (input, _) = rest_rar4(inp, input, &head)?;
+        Ok((input, head))
+    }
+
+    /// Returns the parser for blocks of an unknown type
+    /// for the given archive version
+    pub fn unknown_parser(version: &SignatureBlock) -> fn(&[u8]) -> nom::IResult<&[u8], HeadBlock> {
+        match version {
+            SignatureBlock::RAR5 => HeadBlock::parse_unknown,
+            SignatureBlock::RAR4 => HeadBlock::parse_unknown_rar4,
+        }
+    }
+
+    /// Compare the stored CRC32 of the header at the start of the input with
+    /// the CRC32 of all header bytes behind the CRC field.
+    ///
+    /// Input which is too short to read the header size is not checked,
+    /// because no block parser can read it anyway.
+    pub fn check_crc(input: &[u8]) -> Result<(), RarError> {
+        let (rest, expected) = match le_u32(input) {
+            Ok(r) => r,
+            Err(_) => return Ok(()),
+        };
+        let (after, size) = match vint(rest) {
+            Ok(r) => r,
+            Err(_) => return Ok(()),
+        };
+
+        // the size counts all bytes behind the size field,
+        // damaged or crafted sizes must not overflow
+        if size > MAX_HEADER_SIZE {
+            return Err(RarError::HeaderTooLarge { size });
+        }
+        let len = ((rest.len() - after.len()) as u64)
+            .checked_add(size)
+            .ok_or(RarError::HeaderTooLarge { size })?;
+        compare_crc(expected, rest, len, 0xFFFF_FFFF)
+    }
+
+    /// Compare the stored CRC of the RAR 1.5 - 4.x header at the start of the
+    /// input with the lower 16 bits of the CRC32 of the header bytes behind it.
+    pub fn check_crc_rar4(input: &[u8]) -> Result<(), RarError> {
+        let (crc, typ, size) = match base_header_rar4(input) {
+            Ok((_, (crc, typ, _, size))) => (crc, typ, size),
+            Err(_) => return Ok(()),
+        };
+
+        // old authenticity verification and signature blocks
+        // don't store a valid CRC
+        if typ == 0x76 || typ == 0x79 {
+            return Ok(());
+        }
+
+        compare_crc(
+            crc as u32,
+            &input[2..],
+            (size as u64).saturating_sub(2),
+            0xFFFF,
+        )
+    }
+
+    /// Returns the header CRC check for the given archive version
+    pub fn crc_checker(version: &SignatureBlock) -> fn(&[u8]) -> Result<(), RarError> {
+        match version {
+            SignatureBlock::RAR5 => HeadBlock::check_crc,
+            SignatureBlock::RAR4 => HeadBlock::check_crc_rar4,
+        }
+    }
+}
+
+/// Compare the expected CRC with the masked CRC32 of the first `len` bytes of the data
+fn compare_crc(expected: u32, data: &[u8], len: u64, mask: u32) -> Result<(), RarError> {
+    if (data.len() as u64) < len {
+        return Err(RarError::TruncatedHeader { size: len });
+    }
+
+    let calculated = crc32(0, &data[..len as usize]) & mask;
+    if calculated != expected {
+        return Err(RarError::HeaderCrcMismatch {
+            expected,
+            calculated,
+        });
+    }
+
+    Ok(())
+}
+#[test]
+fn test_check_crc() {
+    // main archive header of a RAR5 archive
+    let mut data = vec![
+        0xF3, 0xE1, 0x82, 0xEB, 0x0B, 0x01, 0x05, 0x07, 0x00, 0x06, 0x01, 0x01, 0x80, 0x80, 0x80,
+        0x00,
+    ];
+    assert_eq!(HeadBlock::check_crc(&data), Ok(()));
+
+    // a flipped bit in the flags
+    data[7] ^= 0x01;
+    assert_eq!(
+        HeadBlock::check_crc(&data),
+        Err(RarError::HeaderCrcMismatch {
+            expected: 0xEB82_E1F3,
+            calculated: crc32(0, &data[4..16])
+        })
+    );
+
+    // a flipped bit in the size points behind the available data
+    data[7] ^= 0x01;
+    data[4] ^= 0x40;
+    assert_eq!(
+        HeadBlock::check_crc(&data),
+        Err(RarError::TruncatedHeader { size: 76 })
+    );
+
+    // not enough data to read the size
+    assert_eq!(HeadBlock::check_crc(&data[..3]), Ok(()));
+
+    // a size close to u64::MAX is rejected without overflowing
+    let mut data = vec![0, 0, 0, 0];
+    data.extend_from_slice(&[0xFF; 9]);
+    data.push(0x01);
+    assert_eq!(
+        HeadBlock::check_crc(&data),
+        Err(RarError::HeaderTooLarge { size: u64::MAX })
+    );
+}
+#[test]
+fn test_check_crc_rar4() {
+    // end of archive header
+    let mut data = [0xC4, 0x3D, 0x7B, 0x00, 0x40, 0x07, 0x00];
+    assert_eq!(HeadBlock::check_crc_rar4(&data), Ok(()));
+
+    data[3] = 0x01;
+    assert_eq!(
+        HeadBlock::check_crc_rar4(&data),
+        Err(RarError::HeaderCrcMismatch {
+            expected: 0x3DC4,
+            calculated: crc32(0, &data[2..]) & 0xFFFF
+        })
+    );
+}
+
+/// Defines how a block header with a wrong CRC32 is handled
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum CrcMode {
+    /// Stop reading the archive with an error
+    Strict,
+    /// Record a warning and continue with the damaged header
+    Lenient,
+}
+
+/// Prepares the headers in front of a reader for the parsers.
+///
+/// Encrypted headers are decrypted and every header is checked against
+/// its CRC. The warnings of the lenient mode are collected.
+pub struct HeaderReader<'a> {
+    pub mode: CrcMode,
+    pub warnings: Vec<RarError>,
+    /// Provider of the passwords with the derived keys
+    keyring: Keyring<'a>,
+    /// Password of the encrypted headers, once it is known
+    password: Option<Zeroizing<String>>,
+    /// A RAR 1.5 - 4.x header was decrypted with the password, afterwards
+    /// a wrong CRC is a damaged header instead of a wrong password
+    verified: bool,
+    /// Name of the current volume
+    name: String,
+    /// Key of the encrypted headers in the current volume
+    key: Option<HeaderKey>,
+}
+
+/// Key of the encrypted headers in a volume
+enum HeaderKey {
+    /// RAR5 key of all headers
+    Aes256(Zeroizing<[u8; 32]>),
+    /// RAR 3.x key, which is derived from the salt in front of each header
+    Aes128,
+}
+
+impl<'a> HeaderReader<'a> {
+    /// Create a new HeaderReader
+    pub fn new(mode: CrcMode, passwords: &'a mut dyn PasswordProvider) -> Self {
+        HeaderReader {
+            mode,
+            warnings: vec![],
+            keyring: Keyring::new(passwords),
+            password: None,
+            verified: false,
+            name: String::new(),
+            key: None,
+        }
+    }
+
+    /// Prepare the first header behind the signature of a volume.
+    ///
+    /// When the headers of the volume are encrypted, the encryption block
+    /// is returned and the key for the following headers is derived.
+    pub fn begin_volume(
+        &mut self,
+        reader: &mut RarReader,
+        version: &SignatureBlock,
+        name: &str,
+    ) -> Result<Option<EncryptionBlock>, Error> {
+        self.key = None;
+        self.name = name.into();
+        self.next(reader, version)?;
+
+        if *version != SignatureBlock::RAR5 {
+            return Ok(None);
+        }
+
+        let eb = match reader.exec_nom_parser(EncryptionBlock::parse) {
+            Ok(eb) => eb,
+            // a broken encryption block doesn't mean the headers are not encrypted
+            Err(_)
+                if HeadBlock::parse(reader.fill_buf()?)
+                    .map(|(_, head)| head.typ == Typ::Encryption)
+                    .unwrap_or(false) =>
+            {
+                return Err(format_err!("Invalid encryption header in {}", name))
+            }
+            Err(_) => return Ok(None),
+        };
+
+        let key = self.header_key(&eb)?;
+        self.key = Some(HeaderKey::Aes256(key));
+
+        self.next(reader, version)?;
+        Ok(Some(eb))
+    }
+
+    /// Continue behind the archive block of a volume.
+    ///
+    /// The headers of a RAR 1.5 - 4.x archive are encrypted behind
+    /// the archive block, when the block is flagged for it.
+    pub fn archive_block(&mut self, archive: &ArchiveBlock) -> Result<(), Error> {
+        if !archive.flags.encrypted_headers {
+            return Ok(());
+        }
+
+        // the password is asked for with the first header
+        self.key = Some(HeaderKey::Aes128);
+        Ok(())
+    }
+
+    /// Returns the password provider with the derived keys for the encrypted files
+    pub fn keyring(&mut self) -> &mut Keyring<'a> {
+        &mut self.keyring
+    }
+
+    /// Derive the key of the RAR5 headers, the password is asked
+    /// again as long as it doesn't match the check value.
+    fn header_key(&mut self, eb: &EncryptionBlock) -> Result<Zeroizing<[u8; 32]>, Error> {
+        let mut retry = 0;
+        loop {
+            let pwd = match self.password.take() {
+                Some(pwd) => pwd,
+                None => self.ask_password(retry)?,
+            };
+
+            match derive_header_key(eb, &pwd, &self.name, &mut self.keyring.keys) {
+                Ok(key) => {
+                    self.password = Some(pwd);
+                    return Ok(key);
+                }
+                Err(e) => match e.downcast::<RarError>() {
+                    Ok(RarError::WrongPassword { .. }) => retry += 1,
+                    Ok(e) => return Err(e.into()),
+                    Err(e) => return Err(e),
+                },
+            }
+        }
+    }
+
+    /// Ask the password provider for the password of the headers
+    fn ask_password(&mut self, retry: u32) -> Result<Zeroizing<String>, Error> {
+        match self.keyring.passwords.archive_password(&self.name, retry) {
+            PasswordResponse::Password(pwd) => Ok(pwd),
+            PasswordResponse::Skip | PasswordResponse::Abort => {
+                Err(aborted(&self.name, retry).into())
+            }
+        }
+    }
+
+    /// Prepare the header at the current position of the reader.
+    ///
+    /// An encrypted header is decrypted and placed in front of the reader,
+    /// afterwards the CRC of the header is checked without consuming anything.
+    pub fn next(&mut self, reader: &mut RarReader, version: &SignatureBlock) -> Result<(), Error> {
+        match self.key {
+            Some(HeaderKey::Aes256(ref key)) => {
+                let header = decrypt_header(reader, key)?;
+                reader.push_front(header);
+            }
+            Some(HeaderKey::Aes128) => return self.next_rar3(reader),
+            None => {}
+        }
+
+        // the whole header must be buffered to check it
+        let check = HeadBlock::crc_checker(version);
+        let mut res = check(reader.fill_buf()?);
+        while let Err(RarError::TruncatedHeader { .. }) = res {
+            if !reader.fill_more()? {
+                break;
+            }
+            res = check(reader.fill_buf()?);
+        }
+
+        match (res, self.mode) {
+            (Ok(()), _) => Ok(()),
+            (Err(e), CrcMode::Strict) => Err(e.into()),
+            (Err(e), CrcMode::Lenient) => {
+                self.warnings.push(e);
+                Ok(())
+            }
+        }
+    }
+
+    /// Prepare the next RAR 1.5 - 4.x header, which is encrypted
+    /// with the key derived from the salt in front of it.
+    ///
+    /// RAR 1.5 - 4.x has no check value, like unrar a broken first header
+    /// is reported as a wrong password, because both can't be told apart.
+    /// The password is asked again in that case. Behind the first header
+    /// a wrong CRC is handled like the one of a plain header.
+    fn next_rar3(&mut self, reader: &mut RarReader) -> Result<(), Error> {
+        let mut salt = [0u8; 8];
+        if !read_or_eof(reader, &mut salt)? {
+            return Ok(());
+        }
+
+        let mut retry = 0;
+        loop {
+            let pwd = match self.password.take() {
+                Some(pwd) => pwd,
+                None => self.ask_password(retry)?,
+            };
+            let keys = self.keyring.keys.generate_key_rar3(&pwd, Some(&salt));
+
+            let (header, len) = match decrypt_buffered_rar3(reader, &keys.0, &keys.1)? {
+                Some(decrypted) => decrypted,
+                None if !self.verified => {
+                    retry += 1;
+                    continue;
+                }
+                None => {
+                    return Err(RarError::WrongPassword {
+                        name: self.name.clone(),
+                    }
+                    .into())
+                }
+            };
+
+            if let Err(e) = HeadBlock::check_crc_rar4(&header) {
+                match (self.verified, self.mode) {
+                    (false, _) => {
+                        retry += 1;
+                        continue;
+                    }
+                    (true, CrcMode::Strict) => return Err(e.into()),
+                    (true, CrcMode::Lenient) => self.warnings.push(e),
+                }
+            }
+
+            self.password = Some(pwd);
+            self.verified = true;
+            reader.consume(len);
+            reader.push_front(header);
+            return Ok(());
+        }
+    }
+}
+
+/// Decrypt the RAR 1.5 - 4.x header at the start of the reader without
+/// consuming it, so it can be decrypted again with another password.
+///
+/// Returns the plain header with the size of the encrypted one,
+/// or nothing when the header can't be decrypted.
+fn decrypt_buffered_rar3(
+    reader: &mut RarReader,
+    key: &[u8; 16],
+    iv: &[u8; 16],
+) -> Result<Option<(Vec<u8>, usize)>, Error> {
+    loop {
+        let mut buffer = Cursor::new(reader.fill_buf()?);
+        match decrypt_header_rar3(&mut buffer, key, iv) {
+            Ok(header) => return Ok(Some((header, buffer.position() as usize))),
+            // the header is longer than the buffered data
+            Err(ref e) if e.kind() == ErrorKind::UnexpectedEof && reader.fill_more()? => continue,
+            Err(_) => return Ok(None),
+        }
+    }
+}
+
+/// Take the rest of a RAR 1.5 - 4.x header, which wasn't parsed yet.
+///
+/// The `inp` is the start of the header and `rest` the not consumed part of it.
+pub fn rest_rar4<'a>(
+    inp: &'a [u8],
+    rest: &'a [u8],
+    head: &HeadBlock,
+) -> nom::IResult<&'a [u8], &'a [u8]> {
+    let consumed = (inp.len() - rest.len()) as u64;
+
+    if head.size < consumed {
+        return Err(nom::Err::Error(error_position!(
+            inp,
+            nom::ErrorKind::Custom(0)
+        )));
+    }
+
+    take!(rest, head.size - consumed)
 }
 
 #[test]
@@ -58,22 +534,36 @@ fn test_header() {
     let mut flags = Flags::new();
     flags.extra_area = true;
     flags.skip = true;
-    let mut h = HeadBlock::new(4091642603, 11, Typ::MainArchive, flags);
+    let mut h = HeadBlock::new(0xEB82_E1F3, 11, Typ::MainArchive, flags);
     h.extra_area_size = 7;
     assert_eq!(HeadBlock::parse(&data), Ok((&[0x00][..], h)));
 }
 
 /// Definition of the header block typ
-#[derive(PartialEq, Debug, Clone)]
+#[derive(PartialEq, Debug, Clone, Default)]
 pub enum Typ {
     MainArchive,
     File,
     Service,
     Encryption,
     EndArchive,
+    #[default]
     Unknown,
 }
 
+impl Typ {
+    /// Get the block type of a RAR 1.5 - 4.x block
+    pub fn from_rar4(i: u8) -> Self {
+        match i {
+            0x73 => Typ::MainArchive,
+            0x74 => Typ::File,
+            0x7a => Typ::Service,
+            0x7b => Typ::EndArchive,
+            _ => Typ::Unknown,
+        }
+    }
+}
+
 impl From<u64> for Typ {
     fn from(i: u64) -> Self {
         match i {
@@ -87,12 +577,6 @@ impl From<u64> for Typ {
     }
 }
 
-impl Default for Typ {
-    fn default() -> Typ {
-        Typ::Unknown
-    }
-}
-
 /// Flags for a header block
 #[derive(PartialEq, Debug, Clone, Default)]
 pub struct Flags {
@@ -151,7 +635,7 @@ impl From<u64> for Flags {
 
 named_attr!(#[doc = "Get a base header"], base_header(&[u8]) -> HeadBlock,
     do_parse!(
-        crc: be_u32 >>
+        crc: le_u32 >>
         size: vint >>
         typ: vint >>
         flags: vint >>
@@ -165,6 +649,42 @@ fn test_base_header() {
     let mut flags = Flags::new();
     flags.extra_area = true;
     flags.skip = true;
-    let header = HeadBlock::new(4091642603, 11, Typ::MainArchive, flags);
+    let header = HeadBlock::new(0xEB82_E1F3, 11, Typ::MainArchive, flags);
     assert_eq!(base_header(&data), Ok((&[0x07][..], header)));
 }
+
+named_attr!(#[doc = "Get a RAR 1.5 - 4.x base header"], base_header_rar4(&[u8]) -> (u16, u8, u16, u16),
+    tuple!(le_u16, le_u8, le_u16, le_u16)
+);
+#[test]
+fn test_header_rar4() {
+    // main archive header
+    let data = [
+        0xCF, 0x90, 0x73, 0x00, 0x00, 0x0D, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    ];
+    let h = HeadBlock::new(0x90CF, 13, Typ::MainArchive, Flags::new());
+    assert_eq!(HeadBlock::parse_rar4(&data), Ok((&data[7..], (h, 0x0000))));
+
+    // file header with a data area
+    let data = [
+        0x0B, 0x2E, 0x74, 0x20, 0x90, 0x2D, 0x00, 0x46, 0x08, 0x00, 0x00, 0x46,
+    ];
+    let mut flags = Flags::new();
+    flags.data_area = true;
+    let mut h = HeadBlock::new(0x2E0B, 45, Typ::File, flags);
+    h.data_area_size = 2118;
+    assert_eq!(HeadBlock::parse_rar4(&data), Ok((&data[11..], (h, 0x9020))));
+}
+#[test]
+fn test_unknown_header_rar4() {
+    // old style comment header with some comment data
+    let data = [
+        0x00, 0x00, 0x75, 0x00, 0x00, 0x0A, 0x00, 0x41, 0x42, 0x43, 0xFF,
+    ];
+    let h = HeadBlock::new(0, 10, Typ::Unknown, Flags::new());
+    assert_eq!(HeadBlock::parse_unknown_rar4(&data), Ok((&[0xFF][..], h)));
+
+    // end of archive is not unknown
+    let data = [0xC4, 0x3D, 0x7B, 0x00, 0x40, 0x07, 0x00];
+    assert!(HeadBlock::parse_unknown_rar4(&data).is_err());
+}
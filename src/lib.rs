@@ -4,6 +4,7 @@ extern crate failure;
 extern crate nom;
 extern crate chrono;
 extern crate crypto;
+extern crate zeroize;
 
 #[cfg(test)]
 #[macro_use]
@@ -11,121 +12,375 @@ extern crate lazy_static;
 
 mod aes_reader;
 mod archive_block;
+mod archive_reader;
+mod blake2sp;
+mod encryption_block;
 mod end_block;
+mod entry_reader;
+mod error;
 mod extra_block;
 mod extractor;
 mod file_block;
 mod file_writer;
 mod head_block;
+mod password;
+mod rar15_reader;
 mod rar_reader;
+mod sha1;
 mod sig_block;
+mod unpack;
 mod util;
 mod vint;
+mod volume;
 
 const BUFFER_SIZE: usize = 8192;
 
+pub use archive_block::ArchiveFlags;
+use archive_reader::ArchiveReader;
+pub use entry_reader::EntryReader;
+pub use error::RarError;
 use failure::Error;
+pub use file_block::FileBlock;
+pub use head_block::CrcMode;
+pub use password::{PasswordProvider, PasswordResponse};
 use rar_reader::RarReader;
 use std::fs::File;
-use std::io::Read;
+use std::io;
+use std::io::{Read, Seek, Write};
+use unpack::UnpackState;
+pub use volume::{volume_name, FileVolumes, ReadSeek, SingleVolume, VolumeProvider};
+pub use zeroize::Zeroizing;
 
 /// The rar archive representation
 #[derive(PartialEq, Debug)]
 pub struct Archive {
     pub version: sig_block::SignatureBlock,
     pub details: archive_block::ArchiveBlock,
-    pub files: Vec<file_block::FileBlock>,
-    pub quick_open: Option<file_block::FileBlock>,
-    pub end: end_block::EndBlock,
+    pub files: Vec<FileBlock>,
+    pub quick_open: Option<FileBlock>,
+    /// The end block, which is missing when the archive was not read
+    /// up to its end, e.g. by `extract_file_to`. The files behind the
+    /// last read one are missing then as well.
+    pub end: Option<end_block::EndBlock>,
+    pub encryption: Option<encryption_block::EncryptionBlock>,
+    pub warnings: Vec<RarError>,
 }
 
 impl Archive {
     /// This function extracts the .rar archive and returns the parsed
     /// structure as additional information
     pub fn extract_all(file_name: &str, path: &str, password: &str) -> Result<Archive, Error> {
-        // Open a file reader
-        let reader = File::open(&file_name)?;
-        // initilize the buffer
-        let mut reader = RarReader::new_from_file(reader);
-
-        // try to parse the signature
-        let version = reader
-            .exec_nom_parser(sig_block::SignatureBlock::parse)
-            .map_err(|_| format_err!("Can't read RAR signature"))?;
-        // try to parse the archive information
-        let details = reader
-            .exec_nom_parser(archive_block::ArchiveBlock::parse)
-            .map_err(|_| format_err!("Can't read RAR archive block"))?;
+        Archive::extract_all_with_mode(file_name, path, password, CrcMode::Strict)
+    }
+
+    /// This function extracts the .rar archive like `extract_all`, but asks
+    /// the password provider for the passwords, when encrypted files or
+    /// headers are reached. Files can be skipped by the provider.
+    pub fn extract_all_with_passwords(
+        file_name: &str,
+        path: &str,
+        passwords: &mut dyn PasswordProvider,
+    ) -> Result<Archive, Error> {
+        Archive::extract_filtered(file_name, path, passwords, CrcMode::Strict, false, |_| true)
+    }
+
+    /// This function extracts the .rar archive like `extract_all`, but lets
+    /// the caller decide how block headers with a wrong CRC32 are handled.
+    ///
+    /// In the lenient mode the damaged headers are collected in the warnings
+    /// of the returned archive.
+    pub fn extract_all_with_mode(
+        file_name: &str,
+        path: &str,
+        password: &str,
+        mode: CrcMode,
+    ) -> Result<Archive, Error> {
+        Archive::extract_filtered(file_name, path, &mut { password }, mode, false, |_| true)
+    }
+
+    /// This function extracts the files whose names match the glob pattern
+    /// out of the .rar archive and skips all others.
+    ///
+    /// A `?` matches one character and a `*` any number of characters within
+    /// a directory, a `**` matches across directories as well.
+    ///
+    /// A pattern without them matches only one file, so like with
+    /// `extract_file_to` the archive isn't read behind this file.
+    pub fn extract_matching(
+        file_name: &str,
+        path: &str,
+        pattern: &str,
+        password: &str,
+    ) -> Result<Archive, Error> {
+        Archive::extract_filtered(
+            file_name,
+            path,
+            &mut { password },
+            CrcMode::Strict,
+            util::glob_is_literal(pattern),
+            |f| util::glob_match(pattern, &f.name),
+        )
+    }
+
+    /// This function extracts the files which are selected by the predicate
+    /// out of the .rar archive and skips all others.
+    pub fn extract_where<F>(
+        file_name: &str,
+        path: &str,
+        password: &str,
+        predicate: F,
+    ) -> Result<Archive, Error>
+    where
+        F: Fn(&FileBlock) -> bool,
+    {
+        Archive::extract_filtered(
+            file_name,
+            path,
+            &mut { password },
+            CrcMode::Strict,
+            false,
+            predicate,
+        )
+    }
+
+    /// This function lists the files of the .rar archive without extracting them.
+    ///
+    /// The data areas are skipped, nothing is unpacked or decrypted. The password
+    /// is only needed for archives with encrypted headers.
+    pub fn list(file_name: &str, password: &str) -> Result<Archive, Error> {
+        Archive::list_from(&mut FileVolumes::new(file_name), password)
+    }
+
+    /// This function lists the files of the archive like `list`, but the
+    /// volumes are read from the volume provider instead of the disk.
+    pub fn list_from(volumes: &mut dyn VolumeProvider, password: &str) -> Result<Archive, Error> {
+        let mut passwords = password;
+        let mut archive = ArchiveReader::open(volumes, &mut passwords, CrcMode::Strict)?;
 
         let mut files = vec![];
-        let mut quick_open = None;
-        let mut file_number = 1;
-        // loop over the packages and define how to handle them
-        loop {
-            // Check if the next is a file
-            match reader.exec_nom_parser(file_block::FileBlock::parse) {
-                Ok(mut f) => {
-                    // quick open file?
-                    if f.name == "QO" {
-                        reader.r_seek(f.head.data_area_size)?;
-                        quick_open = Some(f);
-                        break;
-                    }
+        while let Some((f, data_area_size)) = archive.next_file()? {
+            archive.reader.r_seek(data_area_size)?;
+            files.push(f);
+        }
 
-                    // limit the reader, because the rest of the file is not important,
-                    // when we have multiple files
-                    if f.head.flags.data_next {
-                        reader = RarReader::new(reader.take(f.head.data_area_size));
-                    }
+        archive.finish(files)
+    }
 
-                    // create a new reader which chains the different data areas
-                    // between the different .rar files to extract the right one
-                    let mut data_area_size = f.head.data_area_size;
-                    while f.head.flags.data_next {
-                        reader = extractor::continue_data_next_file(
-                            reader,
-                            &mut f,
-                            &file_name,
-                            &mut file_number,
-                            &mut data_area_size,
-                        )?;
-                    }
+    /// This function opens the stored file with the given name out of the .rar
+    /// archive for random access, e.g. to read the index at the end of a video.
+    ///
+    /// Encrypted files are decrypted while they are read, but their checksums
+    /// can't be verified. Packed files and files which are split over
+    /// volumes can't be opened.
+    pub fn open_entry(
+        file_name: &str,
+        name: &str,
+        password: &str,
+    ) -> Result<EntryReader<File>, Error> {
+        let file = File::open(file_name)?;
+        Archive::open_entry_in(file, &mut FileVolumes::new(file_name), name, password)
+    }
 
-                    // extract all the data
-                    extractor::extract(&f, path, &mut reader, data_area_size, password)?;
+    /// This function opens the stored file like `open_entry`, but the
+    /// volumes are read from the volume provider instead of the disk.
+    pub fn open_entry_from(
+        volumes: &mut dyn VolumeProvider,
+        name: &str,
+        password: &str,
+    ) -> Result<EntryReader<Box<dyn ReadSeek>>, Error> {
+        let data = volumes.volume(0)?;
+        Archive::open_entry_in(data, volumes, name, password)
+    }
 
-                    // add the file to the array
-                    files.push(f);
-                }
-                Err(_) => {
-                    break;
+    /// This function opens the stored file out of the first volume,
+    /// the volume provider is asked for the following ones.
+    fn open_entry_in<R: Read + Seek>(
+        mut data: R,
+        volumes: &mut dyn VolumeProvider,
+        name: &str,
+        password: &str,
+    ) -> Result<EntryReader<R>, Error> {
+        let (f, offset, key) = {
+            // the headers are parsed with a buffer over the same data
+            let reader = RarReader::new(&mut data);
+            let mut passwords = password;
+            let mut archive = ArchiveReader::new(reader, volumes, &mut passwords, CrcMode::Strict)?;
+
+            // loop over the files until the requested one is found
+            loop {
+                let (f, data_area_size) = match archive.next_file()? {
+                    Some(next) => next,
+                    None => return Err(format_err!("Can't find {} in the archive", name)),
+                };
+
+                if f.name != name {
+                    archive.reader.r_seek(data_area_size)?;
+                    continue;
                 }
+
+                // the data area follows directly after the file header
+                let key = match aes_reader::request_key(&f, archive.headers.keyring())? {
+                    aes_reader::KeyRequest::Key(key) => key,
+                    aes_reader::KeyRequest::Skip => {
+                        return Err(RarError::PasswordRequired { name: f.name }.into())
+                    }
+                };
+                break (f, archive.reader.position(), key);
             }
-        }
+        };
+
+        EntryReader::new(data, &f, offset, key)
+    }
+
+    /// This function extracts only the file with the given name out of the .rar
+    /// archive into the writer, e.g. into memory, instead of a file on the disk.
+    ///
+    /// A damaged file is reported after its data was written. The archive
+    /// isn't read behind the file, so the volumes behind it are not opened
+    /// and the returned structure doesn't contain the files behind it.
+    pub fn extract_file_to<W: Write>(
+        file_name: &str,
+        name: &str,
+        password: &str,
+        mut writer: W,
+    ) -> Result<Archive, Error> {
+        Archive::extract_entries(
+            &mut FileVolumes::new(file_name),
+            &mut { password },
+            CrcMode::Strict,
+            true,
+            |f| f.name == name,
+            |_, data| {
+                io::copy(data, &mut writer)?;
+                writer.flush()?;
+                Ok(())
+            },
+        )
+    }
 
-        // Get the end block
-        let end = reader
-            .exec_nom_parser(end_block::EndBlock::parse)
-            .map_err(|_| format_err!("Can't read RAR end"))?;
+    /// This function extracts all files of the .rar archive which are selected
+    /// by the filter and passes the reader of their data to the visitor.
+    ///
+    /// The reader returns an error at the end of the data, when the file is
+    /// damaged. The data which was not read by the visitor is skipped.
+    /// The visitor isn't called for encrypted files, which are skipped by
+    /// the password provider.
+    pub fn extract_with<F, V>(
+        file_name: &str,
+        passwords: &mut dyn PasswordProvider,
+        filter: F,
+        visit: V,
+    ) -> Result<Archive, Error>
+    where
+        F: Fn(&FileBlock) -> bool,
+        V: FnMut(&FileBlock, &mut dyn Read) -> Result<(), Error>,
+    {
+        Archive::extract_from(&mut FileVolumes::new(file_name), passwords, filter, visit)
+    }
+
+    /// This function extracts the files of the archive like `extract_with`, but
+    /// the volumes are read from the volume provider instead of the disk.
+    pub fn extract_from<F, V>(
+        volumes: &mut dyn VolumeProvider,
+        passwords: &mut dyn PasswordProvider,
+        filter: F,
+        visit: V,
+    ) -> Result<Archive, Error>
+    where
+        F: Fn(&FileBlock) -> bool,
+        V: FnMut(&FileBlock, &mut dyn Read) -> Result<(), Error>,
+    {
+        Archive::extract_entries(volumes, passwords, CrcMode::Strict, false, filter, visit)
+    }
 
-        // return the archive information
-        Ok(Archive {
-            version,
-            details,
-            files,
-            quick_open,
-            end,
+    /// This function extracts all files of the .rar archive which are selected
+    /// by the filter into files underneath the path and skips all others.
+    fn extract_filtered<F>(
+        file_name: &str,
+        path: &str,
+        passwords: &mut dyn PasswordProvider,
+        mode: CrcMode,
+        single: bool,
+        filter: F,
+    ) -> Result<Archive, Error>
+    where
+        F: Fn(&FileBlock) -> bool,
+    {
+        let volumes = &mut FileVolumes::new(file_name);
+        Archive::extract_entries(volumes, passwords, mode, single, filter, |f, data| {
+            let mut writer = file_writer::create_file(f, path)?;
+            io::copy(data, &mut writer)?;
+            writer.flush()?;
+            Ok(())
         })
     }
+
+    /// This function extracts all files of the .rar archive which are selected
+    /// by the filter with the visitor and skips all others.
+    ///
+    /// With `single` the archive is only read up to the first selected file.
+    fn extract_entries<F, V>(
+        volumes: &mut dyn VolumeProvider,
+        passwords: &mut dyn PasswordProvider,
+        mode: CrcMode,
+        single: bool,
+        filter: F,
+        mut visit: V,
+    ) -> Result<Archive, Error>
+    where
+        F: Fn(&FileBlock) -> bool,
+        V: FnMut(&FileBlock, &mut dyn Read) -> Result<(), Error>,
+    {
+        let mut archive = ArchiveReader::open(volumes, passwords, mode)?;
+
+        let mut files = vec![];
+        // the decoder state which is shared in solid archives
+        let mut state = UnpackState::new(archive.details.flags.solid);
+        // loop over the files of all volumes
+        while let Some((f, data_area_size)) = archive.next_file()? {
+            let reader = &mut archive.reader;
+            let keyring = archive.headers.keyring();
+
+            // extract the data or skip it, when the file is not selected
+            let selected = filter(&f);
+            if selected {
+                extractor::extract(&f, reader, data_area_size, keyring, &mut state, &mut visit)?;
+            } else {
+                extractor::skip(&f, reader, data_area_size, keyring, &mut state)?;
+            }
+
+            // add the file to the array
+            files.push(f);
+
+            // the following files and volumes are not needed
+            if selected && single {
+                return Ok(archive.stop(files));
+            }
+        }
+
+        archive.finish(files)
+    }
 }
 
 /********************** All .rar file test **********************/
 #[cfg(test)]
 mod tests {
+    use file_block::CompressionFlags;
     use sig_block::SignatureBlock;
-    use std::fs::{remove_dir_all, File};
-    use std::io::Read;
+    use std::collections::HashMap;
+    use std::fs::{create_dir_all, remove_dir_all, write, File};
+    use std::io;
+    use std::io::{Cursor, Read};
     use Archive;
+    use CrcMode;
+    use FileBlock;
+    use PasswordProvider;
+    use PasswordResponse;
+    use RarError;
+    use ReadSeek;
+    use SingleVolume;
+    use VolumeProvider;
+    use Zeroizing;
 
     // Small helper function to read a file
     fn read_file(path: &str) -> Vec<u8> {
@@ -145,6 +400,31 @@ mod tests {
         static ref TEXT: Vec<u8> = read_file("assets/text.txt");
     }
 
+    // Password provider which skips the photo and gives a wrong
+    // password first, all requests are recorded
+    #[derive(Default)]
+    struct Passwords {
+        asked: Vec<(String, u32)>,
+    }
+
+    impl PasswordProvider for Passwords {
+        fn password(&mut self, file: &FileBlock, retry: u32) -> PasswordResponse {
+            if file.name == "photo.jpg" {
+                self.asked.push((file.name.clone(), retry));
+                return PasswordResponse::Skip;
+            }
+            self.archive_password(&file.name, retry)
+        }
+
+        fn archive_password(&mut self, archive: &str, retry: u32) -> PasswordResponse {
+            self.asked.push((archive.into(), retry));
+            match retry {
+                0 => PasswordResponse::Password(Zeroizing::new("wrong".into())),
+                _ => PasswordResponse::Password(Zeroizing::new("test".into())),
+            }
+        }
+    }
+
     #[test]
     fn test_rar5_save_32mb_txt() {
         let rar = "rar5-save-32mb-txt";
@@ -164,7 +444,7 @@ mod tests {
             read_file(&format!("target/rar-test/{}/text.txt", rar))
         );
 
-        remove_dir_all(&format!("target/rar-test/{}", rar)).unwrap();
+        remove_dir_all(format!("target/rar-test/{}", rar)).unwrap();
     }
 
     #[test]
@@ -221,6 +501,138 @@ mod tests {
         remove_dir_all("target/rar-test/rar5-save-32mb-txt-png-pw-test/").unwrap();
     }
 
+    #[test]
+    fn test_rar5_normal_32mb_txt_png_pwf_test() {
+        let archive = Archive::extract_all(
+            "assets/rar5-normal-32mb-txt-png-pwf-test.rar",
+            "target/rar-test/rar5-normal-32mb-txt-png-pwf-test/",
+            "test",
+        )
+        .unwrap();
+
+        assert_eq!(archive.version, SignatureBlock::RAR5);
+        assert!(archive.encryption.is_some());
+        assert_eq!(archive.files[0].name, "photo.jpg");
+        assert_eq!(archive.files[0].unpacked_size, 2149083);
+        assert_eq!(archive.files[1].name, "text.txt");
+        assert_eq!(archive.files[1].unpacked_size, 2118);
+        assert_eq!(
+            *TEXT,
+            read_file("target/rar-test/rar5-normal-32mb-txt-png-pwf-test/text.txt")
+        );
+        assert_eq!(
+            *PHOTO,
+            read_file("target/rar-test/rar5-normal-32mb-txt-png-pwf-test/photo.jpg")
+        );
+
+        remove_dir_all("target/rar-test/rar5-normal-32mb-txt-png-pwf-test/").unwrap();
+    }
+
+    #[test]
+    fn test_rar5_save_32mb_txt_png_pw_test_password_provider() {
+        let rar = "rar5-save-32mb-txt-png-pw-test";
+        let mut passwords = Passwords::default();
+
+        let archive = Archive::extract_all_with_passwords(
+            &format!("assets/{}.rar", rar),
+            &format!("target/rar-test/{}/", rar),
+            &mut passwords,
+        )
+        .unwrap();
+
+        assert_eq!(archive.files.len(), 2);
+        assert_eq!(
+            passwords.asked,
+            [
+                ("photo.jpg".into(), 0),
+                ("text.txt".into(), 0),
+                ("text.txt".into(), 1)
+            ]
+        );
+        assert_eq!(
+            *TEXT,
+            read_file(&format!("target/rar-test/{}/text.txt", rar))
+        );
+        assert!(File::open(format!("target/rar-test/{}/photo.jpg", rar)).is_err());
+
+        remove_dir_all(format!("target/rar-test/{}", rar)).unwrap();
+    }
+
+    #[test]
+    fn test_rar5_normal_32mb_txt_png_pwf_test_password_provider() {
+        let rar = "rar5-normal-32mb-txt-png-pwf-test";
+        let mut passwords = Passwords::default();
+
+        Archive::extract_all_with_passwords(
+            &format!("assets/{}.rar", rar),
+            &format!("target/rar-test/{}-provider/", rar),
+            &mut passwords,
+        )
+        .unwrap();
+
+        // the password of the headers is asked once for the whole archive
+        let name = format!("assets/{}.rar", rar);
+        assert_eq!(passwords.asked[..2], [(name.clone(), 0), (name, 1)]);
+        assert_eq!(
+            *TEXT,
+            read_file(&format!("target/rar-test/{}-provider/text.txt", rar))
+        );
+
+        remove_dir_all(format!("target/rar-test/{}-provider", rar)).unwrap();
+    }
+
+    #[test]
+    fn test_rar5_normal_32mb_txt_png_pwf_test_wrong_password() {
+        let err = Archive::extract_all(
+            "assets/rar5-normal-32mb-txt-png-pwf-test.rar",
+            "target/rar-test/rar5-normal-32mb-txt-png-pwf-test-wrong/",
+            "wrong",
+        )
+        .unwrap_err();
+
+        assert_eq!(
+            err.downcast::<RarError>().unwrap(),
+            RarError::WrongPassword {
+                name: "assets/rar5-normal-32mb-txt-png-pwf-test.rar".into()
+            }
+        );
+    }
+
+    #[test]
+    fn test_rar5_save_32mb_txt_png_pw_test_wrong_password() {
+        let err = Archive::extract_all(
+            "assets/rar5-save-32mb-txt-png-pw-test.rar",
+            "target/rar-test/rar5-save-32mb-txt-png-pw-test-wrong/",
+            "wrong",
+        )
+        .unwrap_err();
+
+        assert_eq!(
+            err.downcast::<RarError>().unwrap(),
+            RarError::WrongPassword {
+                name: "photo.jpg".into()
+            }
+        );
+        // no file is created with a wrong password
+        assert!(
+            File::open("target/rar-test/rar5-save-32mb-txt-png-pw-test-wrong/photo.jpg").is_err()
+        );
+
+        let err = Archive::extract_all(
+            "assets/rar5-save-32mb-txt-png-pw-test.rar",
+            "target/rar-test/rar5-save-32mb-txt-png-pw-test-wrong/",
+            "",
+        )
+        .unwrap_err();
+
+        assert_eq!(
+            err.downcast::<RarError>().unwrap(),
+            RarError::PasswordRequired {
+                name: "photo.jpg".into()
+            }
+        );
+    }
+
     #[test]
     fn test_rar5_save_32mb_txt_png_512kb_multi_test() {
         let archive = Archive::extract_all(
@@ -247,4 +659,1014 @@ mod tests {
 
         remove_dir_all("target/rar-test/rar5-save-32mb-txt-png-512kb/").unwrap();
     }
+
+    #[test]
+    fn test_rar5_compression_32mb_txt() {
+        for rar in &["fastest", "fast", "normal", "good", "best"] {
+            let rar = format!("rar5-{}-32mb-txt", rar);
+
+            let archive = Archive::extract_all(
+                &format!("assets/{}.rar", rar),
+                &format!("target/rar-test/{}/", rar),
+                "",
+            )
+            .unwrap();
+
+            assert_eq!(archive.version, SignatureBlock::RAR5);
+            assert_eq!(archive.files[0].name, "text.txt");
+            assert_eq!(archive.files[0].unpacked_size, 2118);
+            assert_eq!(
+                *TEXT,
+                read_file(&format!("target/rar-test/{}/text.txt", rar))
+            );
+
+            remove_dir_all(format!("target/rar-test/{}", rar)).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_rar5_normal_32mb_txt_png_solid() {
+        let archive = Archive::extract_all(
+            "assets/rar5-normal-32mb-txt-png-solid.rar",
+            "target/rar-test/rar5-normal-32mb-txt-png-solid/",
+            "",
+        )
+        .unwrap();
+
+        assert_eq!(archive.version, SignatureBlock::RAR5);
+        assert!(archive.details.flags.solid);
+        assert_eq!(archive.files[0].name, "text.txt");
+        assert_eq!(archive.files[0].unpacked_size, 2118);
+        assert_eq!(archive.files[1].name, "photo.jpg");
+        assert_eq!(archive.files[1].unpacked_size, 2149083);
+        assert!(archive.files[1].compression.solid);
+        assert_eq!(
+            *TEXT,
+            read_file("target/rar-test/rar5-normal-32mb-txt-png-solid/text.txt")
+        );
+        assert_eq!(
+            *PHOTO,
+            read_file("target/rar-test/rar5-normal-32mb-txt-png-solid/photo.jpg")
+        );
+
+        remove_dir_all("target/rar-test/rar5-normal-32mb-txt-png-solid/").unwrap();
+    }
+
+    #[test]
+    fn test_rar5_normal_32mb_txt_png_solid_single_file() {
+        let archive = Archive::extract_matching(
+            "assets/rar5-normal-32mb-txt-png-solid.rar",
+            "target/rar-test/rar5-normal-32mb-txt-png-solid-single/",
+            "photo.jpg",
+            "",
+        )
+        .unwrap();
+
+        assert_eq!(archive.files.len(), 2);
+        assert_eq!(
+            *PHOTO,
+            read_file("target/rar-test/rar5-normal-32mb-txt-png-solid-single/photo.jpg")
+        );
+        assert!(
+            File::open("target/rar-test/rar5-normal-32mb-txt-png-solid-single/text.txt").is_err()
+        );
+
+        remove_dir_all("target/rar-test/rar5-normal-32mb-txt-png-solid-single/").unwrap();
+    }
+
+    #[test]
+    fn test_rar5_normal_32mb_txt_png_single_file() {
+        let archive = Archive::extract_matching(
+            "assets/rar5-normal-32mb-txt-png.rar",
+            "target/rar-test/rar5-normal-32mb-txt-png-single/",
+            "text.txt",
+            "",
+        )
+        .unwrap();
+
+        // the archive isn't read behind the only matching file
+        assert_eq!(archive.files.len(), 2);
+        assert_eq!(archive.end, None);
+        assert_eq!(
+            *TEXT,
+            read_file("target/rar-test/rar5-normal-32mb-txt-png-single/text.txt")
+        );
+        assert!(File::open("target/rar-test/rar5-normal-32mb-txt-png-single/photo.jpg").is_err());
+
+        remove_dir_all("target/rar-test/rar5-normal-32mb-txt-png-single/").unwrap();
+    }
+
+    #[test]
+    fn test_rar5_normal_32mb_txt_png_recovery() {
+        let archive = Archive::extract_all(
+            "assets/rar5-normal-32mb-txt-png-recovery.rar",
+            "target/rar-test/rar5-normal-32mb-txt-png-recovery/",
+            "",
+        )
+        .unwrap();
+
+        assert_eq!(archive.version, SignatureBlock::RAR5);
+        assert_eq!(archive.files.len(), 2);
+        assert_eq!(archive.quick_open.unwrap().name, "QO");
+        assert_eq!(
+            *TEXT,
+            read_file("target/rar-test/rar5-normal-32mb-txt-png-recovery/text.txt")
+        );
+        assert_eq!(
+            *PHOTO,
+            read_file("target/rar-test/rar5-normal-32mb-txt-png-recovery/photo.jpg")
+        );
+
+        remove_dir_all("target/rar-test/rar5-normal-32mb-txt-png-recovery/").unwrap();
+    }
+
+    #[test]
+    fn test_rar4_save_4096kb_txt() {
+        let rar = "rar4-save-4096kb-txt";
+
+        let archive = Archive::extract_all(
+            &format!("assets/{}.rar", rar),
+            &format!("target/rar-test/{}/", rar),
+            "",
+        )
+        .unwrap();
+
+        assert_eq!(archive.version, SignatureBlock::RAR4);
+        assert_eq!(archive.files[0].name, "text.txt");
+        assert_eq!(archive.files[0].unpacked_size, 2118);
+        assert_eq!(archive.files[0].compression.version, 20);
+        assert!(archive.end.unwrap().last_volume);
+        assert_eq!(
+            *TEXT,
+            read_file(&format!("target/rar-test/{}/text.txt", rar))
+        );
+
+        remove_dir_all(format!("target/rar-test/{}", rar)).unwrap();
+    }
+
+    #[test]
+    fn test_rar4_save_4096kb_txt_pw_test() {
+        let rar = "rar4-save-4096kb-txt-pw-test";
+
+        let archive = Archive::extract_all(
+            &format!("assets/{}.rar", rar),
+            &format!("target/rar-test/{}/", rar),
+            "test",
+        )
+        .unwrap();
+
+        assert_eq!(archive.files[0].name, "text.txt");
+        assert_eq!(
+            archive.files[0]
+                .extra
+                .file_encryption
+                .as_ref()
+                .unwrap()
+                .version,
+            ::extra_block::FileEncryptionVersion::Rar30(Some([
+                0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88
+            ]))
+        );
+        assert_eq!(
+            *TEXT,
+            read_file(&format!("target/rar-test/{}/text.txt", rar))
+        );
+
+        remove_dir_all(format!("target/rar-test/{}", rar)).unwrap();
+    }
+
+    #[test]
+    fn test_rar4_save_4096kb_txt_pw15_test() {
+        let rar = "rar4-save-4096kb-txt-pw15-test";
+
+        let archive = Archive::extract_all(
+            &format!("assets/{}.rar", rar),
+            &format!("target/rar-test/{}/", rar),
+            "test",
+        )
+        .unwrap();
+
+        assert_eq!(archive.files[0].compression.version, 15);
+        assert_eq!(
+            archive.files[0]
+                .extra
+                .file_encryption
+                .as_ref()
+                .unwrap()
+                .version,
+            ::extra_block::FileEncryptionVersion::Rar15
+        );
+        assert_eq!(
+            *TEXT,
+            read_file(&format!("target/rar-test/{}/text.txt", rar))
+        );
+
+        remove_dir_all(format!("target/rar-test/{}", rar)).unwrap();
+    }
+
+    #[test]
+    fn test_rar4_save_4096kb_txt_pwf_test() {
+        let rar = "rar4-save-4096kb-txt-pwf-test";
+
+        let archive = Archive::extract_all(
+            &format!("assets/{}.rar", rar),
+            &format!("target/rar-test/{}/", rar),
+            "test",
+        )
+        .unwrap();
+
+        assert!(archive.details.flags.encrypted_headers);
+        assert_eq!(archive.files[0].name, "text.txt");
+        assert!(archive.end.unwrap().last_volume);
+        assert_eq!(
+            *TEXT,
+            read_file(&format!("target/rar-test/{}/text.txt", rar))
+        );
+
+        remove_dir_all(format!("target/rar-test/{}", rar)).unwrap();
+    }
+
+    #[test]
+    fn test_rar4_save_4096kb_txt_pwf_test_wrong_password() {
+        let err = Archive::extract_all(
+            "assets/rar4-save-4096kb-txt-pwf-test.rar",
+            "target/rar-test/rar4-save-4096kb-txt-pwf-test-wrong/",
+            "wrong",
+        )
+        .unwrap_err();
+
+        assert_eq!(
+            err.downcast::<RarError>().unwrap(),
+            RarError::WrongPassword {
+                name: "assets/rar4-save-4096kb-txt-pwf-test.rar".into()
+            }
+        );
+
+        let err = Archive::extract_all(
+            "assets/rar4-save-4096kb-txt-pwf-test.rar",
+            "target/rar-test/rar4-save-4096kb-txt-pwf-test-wrong/",
+            "",
+        )
+        .unwrap_err();
+
+        assert_eq!(
+            err.downcast::<RarError>().unwrap(),
+            RarError::PasswordRequired {
+                name: "assets/rar4-save-4096kb-txt-pwf-test.rar".into()
+            }
+        );
+    }
+
+    #[test]
+    fn test_rar4_save_4096kb_txt_pwf_test_retry() {
+        // Password provider with a wrong password for the headers first
+        struct Retry(Vec<u32>);
+
+        impl PasswordProvider for Retry {
+            fn password(&mut self, _: &FileBlock, _: u32) -> PasswordResponse {
+                PasswordResponse::Password(Zeroizing::new("test".into()))
+            }
+
+            fn archive_password(&mut self, _: &str, retry: u32) -> PasswordResponse {
+                self.0.push(retry);
+                match retry {
+                    0 => PasswordResponse::Password(Zeroizing::new("wrong".into())),
+                    _ => PasswordResponse::Password(Zeroizing::new("test".into())),
+                }
+            }
+        }
+
+        let path = "target/rar-test/rar4-save-4096kb-txt-pwf-test-retry/";
+        let mut passwords = Retry(vec![]);
+        let archive = Archive::extract_all_with_passwords(
+            "assets/rar4-save-4096kb-txt-pwf-test.rar",
+            path,
+            &mut passwords,
+        )
+        .unwrap();
+
+        assert_eq!(archive.files[0].name, "text.txt");
+        assert_eq!(passwords.0, [0, 1]);
+        assert_eq!(*TEXT, read_file(&format!("{}text.txt", path)));
+
+        remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn test_rar4_save_4096kb_txt_pwf_test_damaged_header() {
+        use crypto::aessafe::AesSafe128Encryptor;
+        use crypto::symmetriccipher::BlockEncryptor;
+
+        // the CRC of the encrypted end block is damaged
+        let mut data = read_file("assets/rar4-save-4096kb-txt-pwf-test.rar");
+        let pos = data.len() - 24;
+        let mut salt = [0u8; 8];
+        salt.copy_from_slice(&data[pos..pos + 8]);
+        let keys = ::aes_reader::KeyCache::default().generate_key_rar3("test", Some(&salt));
+
+        let mut end =
+            ::aes_reader::decrypt_header_rar3(&mut &data[pos + 8..], &keys.0, &keys.1).unwrap();
+        end[0] ^= 0x01;
+        end.resize(16, 0);
+        for (e, iv) in end.iter_mut().zip(keys.1.iter()) {
+            *e ^= iv;
+        }
+        AesSafe128Encryptor::new(&keys.0).encrypt_block(&end, &mut data[pos + 8..]);
+
+        let path = "target/rar-test/rar4-save-4096kb-txt-pwf-test-damaged/";
+        create_dir_all(path).unwrap();
+        let file_name = format!("{}a.rar", path);
+        write(&file_name, &data).unwrap();
+
+        let err =
+            Archive::extract_all_with_mode(&file_name, path, "test", CrcMode::Strict).unwrap_err();
+        match err.downcast::<RarError>().unwrap() {
+            RarError::HeaderCrcMismatch { .. } => {}
+            e => panic!("unexpected error {:?}", e),
+        }
+
+        let archive =
+            Archive::extract_all_with_mode(&file_name, path, "test", CrcMode::Lenient).unwrap();
+        assert_eq!(archive.warnings.len(), 1);
+        assert_eq!(*TEXT, read_file(&format!("{}text.txt", path)));
+
+        remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn test_rar4_save_4096kb_txt_crc_mismatch() {
+        // damage one byte of the stored text
+        let mut data = read_file("assets/rar4-save-4096kb-txt.rar");
+        let pos = data.windows(16).position(|w| w == &TEXT[..16]).unwrap();
+        data[pos] ^= 0xFF;
+        create_dir_all("target/rar-test/rar4-save-4096kb-txt-damaged/").unwrap();
+        write(
+            "target/rar-test/rar4-save-4096kb-txt-damaged/damaged.rar",
+            &data,
+        )
+        .unwrap();
+
+        let err = Archive::extract_all(
+            "target/rar-test/rar4-save-4096kb-txt-damaged/damaged.rar",
+            "target/rar-test/rar4-save-4096kb-txt-damaged/out/",
+            "",
+        )
+        .unwrap_err();
+
+        assert_eq!(
+            err.downcast::<RarError>().unwrap(),
+            RarError::CrcMismatch {
+                name: "text.txt".into(),
+                expected: 0xCB9A_F293,
+                calculated: ::util::crc32(0, &data[pos..pos + 2118]),
+            }
+        );
+
+        // the data which isn't read by the visitor is verified as well
+        let err = Archive::extract_with(
+            "target/rar-test/rar4-save-4096kb-txt-damaged/damaged.rar",
+            &mut "",
+            |_| true,
+            |_, data| {
+                data.read_exact(&mut [0u8; 10])?;
+                Ok(())
+            },
+        )
+        .unwrap_err();
+        assert!(err.downcast::<RarError>().is_ok());
+
+        remove_dir_all("target/rar-test/rar4-save-4096kb-txt-damaged/").unwrap();
+    }
+
+    #[test]
+    fn test_rar5_save_32mb_txt_header_crc_mismatch() {
+        // damage the stored CRC of the end of archive header
+        let mut data = read_file("assets/rar5-save-32mb-txt.rar");
+        let pos = data.len() - 8;
+        data[pos] ^= 0xFF;
+        create_dir_all("target/rar-test/rar5-save-32mb-txt-damaged/").unwrap();
+        write(
+            "target/rar-test/rar5-save-32mb-txt-damaged/damaged.rar",
+            &data,
+        )
+        .unwrap();
+
+        // the strict mode stops at the damaged header
+        let err = Archive::extract_all(
+            "target/rar-test/rar5-save-32mb-txt-damaged/damaged.rar",
+            "target/rar-test/rar5-save-32mb-txt-damaged/out/",
+            "",
+        )
+        .unwrap_err();
+        let expected = RarError::HeaderCrcMismatch {
+            expected: 0x5156_77E2,
+            calculated: 0x5156_771D,
+        };
+        assert_eq!(err.downcast::<RarError>().unwrap(), expected);
+
+        // the lenient mode continues and records a warning
+        let archive = Archive::extract_all_with_mode(
+            "target/rar-test/rar5-save-32mb-txt-damaged/damaged.rar",
+            "target/rar-test/rar5-save-32mb-txt-damaged/out/",
+            "",
+            CrcMode::Lenient,
+        )
+        .unwrap();
+        assert_eq!(archive.warnings, vec![expected]);
+        assert_eq!(archive.files.len(), 1);
+
+        let mut data = Vec::new();
+        File::open("target/rar-test/rar5-save-32mb-txt-damaged/out/text.txt")
+            .unwrap()
+            .read_to_end(&mut data)
+            .unwrap();
+        assert_eq!(data, *TEXT);
+
+        remove_dir_all("target/rar-test/rar5-save-32mb-txt-damaged/").unwrap();
+    }
+
+    #[test]
+    fn test_rar5_save_32mb_txt_blake2() {
+        let rar = "rar5-save-32mb-txt-blake2";
+
+        let archive = Archive::extract_all(
+            &format!("assets/{}.rar", rar),
+            &format!("target/rar-test/{}/", rar),
+            "",
+        )
+        .unwrap();
+
+        assert_eq!(archive.files[0].name, "text.txt");
+        assert!(!archive.files[0].flags.crc);
+        assert!(archive.files[0].extra.file_hash.is_some());
+        assert_eq!(
+            *TEXT,
+            read_file(&format!("target/rar-test/{}/text.txt", rar))
+        );
+
+        remove_dir_all(format!("target/rar-test/{}", rar)).unwrap();
+    }
+
+    #[test]
+    fn test_rar5_save_32mb_txt_blake2_mismatch() {
+        // damage one byte of the stored text
+        let mut data = read_file("assets/rar5-save-32mb-txt-blake2.rar");
+        let pos = data.windows(16).position(|w| w == &TEXT[..16]).unwrap();
+        data[pos] ^= 0xFF;
+        create_dir_all("target/rar-test/rar5-save-32mb-txt-blake2-damaged/").unwrap();
+        write(
+            "target/rar-test/rar5-save-32mb-txt-blake2-damaged/damaged.rar",
+            &data,
+        )
+        .unwrap();
+
+        let err = Archive::extract_all(
+            "target/rar-test/rar5-save-32mb-txt-blake2-damaged/damaged.rar",
+            "target/rar-test/rar5-save-32mb-txt-blake2-damaged/out/",
+            "",
+        )
+        .unwrap_err();
+
+        assert_eq!(
+            err.downcast::<RarError>().unwrap(),
+            RarError::HashMismatch {
+                name: "text.txt".into(),
+            }
+        );
+
+        remove_dir_all("target/rar-test/rar5-save-32mb-txt-blake2-damaged/").unwrap();
+    }
+
+    #[test]
+    fn test_rar4_list_4096kb_txt() {
+        for rar in &["fastest", "fast", "normal", "good", "best", "ppmd"] {
+            let archive =
+                Archive::list(&format!("assets/rar4-{}-4096kb-txt.rar", rar), "").unwrap();
+
+            assert_eq!(archive.version, SignatureBlock::RAR4);
+            assert_eq!(archive.files.len(), 1);
+            assert_eq!(archive.files[0].name, "text.txt");
+            assert_eq!(archive.files[0].unpacked_size, 2118);
+            assert_eq!(archive.files[0].data_crc, 0xCB9A_F293);
+            assert_eq!(archive.files[0].compression.version, 29);
+        }
+    }
+
+    #[test]
+    fn test_rar4_compression_4096kb_txt() {
+        for rar in &["fastest", "fast", "normal", "good", "best", "ppmd"] {
+            let rar = format!("rar4-{}-4096kb-txt", rar);
+
+            let archive = Archive::extract_all(
+                &format!("assets/{}.rar", rar),
+                &format!("target/rar-test/{}/", rar),
+                "",
+            )
+            .unwrap();
+
+            assert_eq!(archive.version, SignatureBlock::RAR4);
+            assert_eq!(archive.files[0].name, "text.txt");
+            assert_eq!(
+                *TEXT,
+                read_file(&format!("target/rar-test/{}/text.txt", rar))
+            );
+
+            remove_dir_all(format!("target/rar-test/{}", rar)).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_rar4_e8_128kb_exe() {
+        // x86 like code, whose calls are packed with the standard E8 filter.
+        // The archive was built for the test and bsdtar (libarchive 3.8)
+        // extracts the same data out of it.
+        let mut data = vec![];
+        let archive =
+            Archive::extract_file_to("assets/rar4-e8-128kb-exe.rar", "code.exe", "", &mut data)
+                .unwrap();
+
+        assert_eq!(archive.files[0].compression.version, 29);
+        assert_eq!(archive.files[0].unpacked_size, 16384);
+        assert_eq!(data.len(), 16384);
+        assert_eq!(::util::crc32(0, &data), 0x76C6_8112);
+        // the first call to 0x38E0 is translated back to a relative address
+        assert_eq!(data[14..19], [0xE8, 0xD1, 0x38, 0x00, 0x00]);
+    }
+
+    // generated archive, which wasn't checked with another decoder,
+    // see assets/gen/README.md
+    #[test]
+    fn test_rar15_normal_64kb_txt() {
+        let rar = "rar15-normal-64kb-txt";
+
+        let archive = Archive::extract_all(
+            &format!("assets/{}.rar", rar),
+            &format!("target/rar-test/{}/", rar),
+            "",
+        )
+        .unwrap();
+
+        assert_eq!(archive.version, SignatureBlock::RAR4);
+        assert_eq!(archive.files[0].name, "text.txt");
+        assert_eq!(archive.files[0].compression.version, 15);
+        assert_eq!(
+            *TEXT,
+            read_file(&format!("target/rar-test/{}/text.txt", rar))
+        );
+
+        remove_dir_all(format!("target/rar-test/{}", rar)).unwrap();
+    }
+
+    // generated archives, which weren't checked with another decoder,
+    // see assets/gen/README.md
+    #[test]
+    fn test_rar20_compression_1024kb_txt() {
+        for rar in &["normal", "audio"] {
+            let rar = format!("rar20-{}-1024kb-txt", rar);
+
+            let archive = Archive::extract_all(
+                &format!("assets/{}.rar", rar),
+                &format!("target/rar-test/{}/", rar),
+                "",
+            )
+            .unwrap();
+
+            assert_eq!(archive.version, SignatureBlock::RAR4);
+            assert_eq!(archive.files[0].name, "text.txt");
+            assert_eq!(archive.files[0].compression.version, 20);
+            assert_eq!(
+                *TEXT,
+                read_file(&format!("target/rar-test/{}/text.txt", rar))
+            );
+
+            remove_dir_all(format!("target/rar-test/{}", rar)).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_rar4_list_normal_4096kb_txt_png_1mb_multi() {
+        let archive = Archive::list("assets/rar4-normal-4096kb-txt-png-1mb.part1.rar", "").unwrap();
+
+        assert_eq!(archive.version, SignatureBlock::RAR4);
+        assert!(archive.details.flags.multivolume);
+        assert_eq!(archive.files[0].name, "photo.jpg");
+        assert_eq!(archive.files[0].unpacked_size, 2149083);
+        assert_eq!(archive.files[0].compression.flag, CompressionFlags::Normal);
+        assert_eq!(archive.files[1].name, "text.txt");
+        assert_eq!(archive.files[1].unpacked_size, 2118);
+        assert!(archive.end.unwrap().last_volume);
+    }
+
+    #[test]
+    fn test_list_rar5_normal_32mb_txt_png() {
+        let archive = Archive::list("assets/rar5-normal-32mb-txt-png.rar", "").unwrap();
+
+        assert_eq!(archive.version, SignatureBlock::RAR5);
+        assert_eq!(archive.files.len(), 2);
+        assert_eq!(archive.files[0].name, "photo.jpg");
+        assert_eq!(archive.files[0].unpacked_size, 2149083);
+        assert!(!archive.files[0].is_encrypted());
+        assert_eq!(archive.files[0].volumes, 0..1);
+        assert_eq!(archive.files[1].name, "text.txt");
+        assert_eq!(archive.files[1].unpacked_size, 2118);
+        assert_eq!(archive.files[1].compression.flag, CompressionFlags::Normal);
+        assert!(archive.files[1].extra.file_time.is_some());
+        assert!(archive.end.unwrap().last_volume);
+    }
+
+    #[test]
+    fn test_list_offsets() {
+        let archive = Archive::list("assets/rar5-save-32mb-txt-png.rar", "").unwrap();
+        assert_eq!(archive.details.head.offset, 8);
+        assert_eq!(archive.files[0].head.offset, 25);
+        assert_eq!(archive.files[1].head.offset, 2149156);
+
+        // the offset in the first volume of the file
+        let archive = Archive::list("assets/rar5-save-32mb-txt-png-512kb.part1.rar", "").unwrap();
+        assert_eq!(archive.files[0].head.offset, 25);
+        assert_eq!(archive.files[1].head.offset, 52672);
+    }
+
+    #[test]
+    fn test_list_encrypted() {
+        // the data of the files is not decrypted
+        let archive = Archive::list("assets/rar5-save-32mb-txt-png-pw-test.rar", "").unwrap();
+        assert_eq!(archive.files.len(), 2);
+        assert!(archive.files.iter().all(FileBlock::is_encrypted));
+
+        let archive = Archive::list("assets/rar4-save-4096kb-txt-pw-test.rar", "").unwrap();
+        assert_eq!(archive.files[0].name, "text.txt");
+        assert!(archive.files[0].is_encrypted());
+
+        // but the headers need the password
+        let archive =
+            Archive::list("assets/rar5-normal-32mb-txt-png-pwf-test.rar", "test").unwrap();
+        assert_eq!(archive.files.len(), 2);
+        assert_eq!(archive.files[1].name, "text.txt");
+
+        let err = Archive::list("assets/rar4-save-4096kb-txt-pwf-test.rar", "").unwrap_err();
+        assert_eq!(
+            err.downcast::<RarError>().unwrap(),
+            RarError::PasswordRequired {
+                name: "assets/rar4-save-4096kb-txt-pwf-test.rar".into()
+            }
+        );
+    }
+
+    #[test]
+    fn test_list_multi_volume() {
+        let archive = Archive::list("assets/rar5-save-32mb-txt-png-512kb.part1.rar", "").unwrap();
+
+        assert!(archive.details.flags.multivolume);
+        assert_eq!(archive.files.len(), 2);
+        assert_eq!(archive.files[0].name, "photo.jpg");
+        assert_eq!(archive.files[0].unpacked_size, 2149083);
+        assert_eq!(archive.files[0].volumes, 0..5);
+        assert_eq!(archive.files[1].name, "text.txt");
+        assert_eq!(archive.files[1].volumes, 4..5);
+
+        let archive = Archive::list("assets/rar4-normal-4096kb-txt-png-1mb.part1.rar", "").unwrap();
+        assert_eq!(archive.files[0].name, "photo.jpg");
+        assert_eq!(archive.files[0].volumes, 0..3);
+        assert_eq!(archive.files[1].name, "text.txt");
+        assert_eq!(archive.files[1].volumes, 2..3);
+    }
+
+    #[test]
+    fn test_extract_old_volume_names() {
+        // the volumes named like with `rar -vn`
+        let path = "target/rar-test/old-volume-names/";
+        create_dir_all(path).unwrap();
+        for (part, ext) in ["part1.rar", "part2.rar", "part3.rar"]
+            .iter()
+            .zip(["rar", "r00", "r01"].iter())
+        {
+            ::std::fs::copy(
+                format!("assets/rar4-normal-4096kb-txt-png-1mb.{}", part),
+                format!("{}archive.{}", path, ext),
+            )
+            .unwrap();
+        }
+
+        let archive = Archive::extract_all(
+            &format!("{}archive.rar", path),
+            &format!("{}out/", path),
+            "",
+        )
+        .unwrap();
+
+        assert_eq!(archive.files[0].volumes, 0..3);
+        assert_eq!(*TEXT, read_file(&format!("{}out/text.txt", path)));
+        assert_eq!(*PHOTO, read_file(&format!("{}out/photo.jpg", path)));
+
+        remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn test_extract_matching() {
+        let path = "target/rar-test/rar5-normal-32mb-txt-png-matching/";
+        let archive =
+            Archive::extract_matching("assets/rar5-normal-32mb-txt-png.rar", path, "*.txt", "")
+                .unwrap();
+
+        assert_eq!(archive.files.len(), 2);
+        assert_eq!(*TEXT, read_file(&format!("{}text.txt", path)));
+        assert!(File::open(format!("{}photo.jpg", path)).is_err());
+
+        remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn test_extract_where() {
+        let path = "target/rar-test/rar5-normal-32mb-txt-png-where/";
+        let archive =
+            Archive::extract_where("assets/rar5-normal-32mb-txt-png-solid.rar", path, "", |f| {
+                f.unpacked_size > 1_000_000
+            })
+            .unwrap();
+
+        assert_eq!(archive.files.len(), 2);
+        assert_eq!(*PHOTO, read_file(&format!("{}photo.jpg", path)));
+        assert!(File::open(format!("{}text.txt", path)).is_err());
+
+        remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn test_extract_single_multi_volume_stops() {
+        // the volumes behind the photo are damaged
+        let path = "target/rar-test/rar5-save-32mb-txt-png-512kb-stop/";
+        create_dir_all(path).unwrap();
+        for i in 1..6 {
+            let mut data = read_file(&format!(
+                "assets/rar5-save-32mb-txt-png-512kb.part{}.rar",
+                i
+            ));
+            if let Some(pos) = data.windows(16).position(|w| w == &TEXT[..16]) {
+                data.truncate(pos);
+            }
+            write(format!("{}a.part{}.rar", path, i), &data).unwrap();
+        }
+
+        let file_name = format!("{}a.part1.rar", path);
+        assert!(Archive::list(&file_name, "").is_err());
+
+        let mut data = vec![];
+        let archive = Archive::extract_file_to(&file_name, "photo.jpg", "", &mut data).unwrap();
+        assert_eq!(archive.files.len(), 1);
+        assert_eq!(archive.files[0].volumes, 0..5);
+        assert_eq!(archive.end, None);
+        assert_eq!(*PHOTO, data);
+
+        // the same for a pattern, which matches only the photo
+        let out = format!("{}out/", path);
+        let archive = Archive::extract_matching(&file_name, &out, "photo.jpg", "").unwrap();
+        assert_eq!(archive.files.len(), 1);
+        assert_eq!(archive.files[0].volumes, 0..5);
+        assert_eq!(*PHOTO, read_file(&format!("{}photo.jpg", out)));
+
+        remove_dir_all(path).unwrap();
+    }
+
+    // Volume provider which keeps all volumes in memory
+    struct MemoryVolumes(Vec<Vec<u8>>);
+
+    impl VolumeProvider for MemoryVolumes {
+        fn volume(&mut self, number: usize) -> io::Result<Box<dyn ReadSeek>> {
+            Ok(Box::new(Cursor::new(self.0[number].clone())))
+        }
+
+        fn name(&self, number: usize) -> String {
+            format!("memory {}", number)
+        }
+    }
+
+    #[test]
+    fn test_list_invalid_kdf_count() {
+        // the encryption header asks for too many rounds
+        let mut data = read_file("assets/rar5-normal-32mb-txt-png-pwf-test.rar");
+        data[17] = 25;
+        let crc = ::util::crc32(0, &data[12..46]);
+        data[8..12].copy_from_slice(&crc.to_le_bytes());
+
+        let err = Archive::list_from(&mut SingleVolume::new(Cursor::new(data)), "test")
+            .unwrap_err()
+            .to_string();
+        assert_eq!(err, "Invalid encryption header in volume 1");
+    }
+
+    #[test]
+    fn test_list_header_size_overflow() {
+        // the size of the main archive header is close to u64::MAX
+        let mut data = read_file("assets/rar5-normal-32mb-txt-png.rar");
+        data.truncate(12);
+        data.extend_from_slice(&[0xFF; 9]);
+        data.push(0x01);
+        data.extend_from_slice(&[0; 16]);
+
+        let err = Archive::list_from(&mut SingleVolume::new(Cursor::new(data)), "")
+            .unwrap_err()
+            .to_string();
+        assert_eq!(
+            err,
+            "Header size of 18446744073709551615 bytes exceeds the maximum header size"
+        );
+    }
+
+    #[test]
+    fn test_list_from_memory() {
+        let data = read_file("assets/rar5-normal-32mb-txt-png.rar");
+        let archive = Archive::list_from(&mut SingleVolume::new(Cursor::new(data)), "").unwrap();
+        assert_eq!(
+            archive,
+            Archive::list("assets/rar5-normal-32mb-txt-png.rar", "").unwrap()
+        );
+
+        // the following volumes are missing
+        let data = read_file("assets/rar5-save-32mb-txt-png-512kb.part1.rar");
+        assert!(Archive::list_from(&mut SingleVolume::new(Cursor::new(data)), "").is_err());
+    }
+
+    #[test]
+    fn test_extract_from_memory_volumes() {
+        let mut volumes = MemoryVolumes(
+            (1..6)
+                .map(|i| {
+                    read_file(&format!(
+                        "assets/rar5-save-32mb-txt-png-512kb.part{}.rar",
+                        i
+                    ))
+                })
+                .collect(),
+        );
+
+        let mut files = HashMap::new();
+        let archive = Archive::extract_from(
+            &mut volumes,
+            &mut "",
+            |_| true,
+            |f, data| {
+                let mut buf = vec![];
+                data.read_to_end(&mut buf)?;
+                files.insert(f.name.clone(), buf);
+                Ok(())
+            },
+        )
+        .unwrap();
+
+        assert_eq!(archive.files[0].volumes, 0..5);
+        assert_eq!(files["photo.jpg"], *PHOTO);
+        assert_eq!(files["text.txt"], *TEXT);
+    }
+
+    #[test]
+    fn test_open_entry_from_memory() {
+        use std::io::{Seek, SeekFrom};
+
+        let data = read_file("assets/rar5-save-32mb-txt-png-pw-test.rar");
+        let mut entry = Archive::open_entry_from(
+            &mut SingleVolume::new(Cursor::new(data)),
+            "photo.jpg",
+            "test",
+        )
+        .unwrap();
+
+        entry.seek(SeekFrom::Start(123_457)).unwrap();
+        let mut buf = [0u8; 100];
+        entry.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf[..], &PHOTO[123_457..123_557]);
+    }
+
+    #[test]
+    fn test_extract_file_to_memory() {
+        let mut data = vec![];
+        let archive = Archive::extract_file_to(
+            "assets/rar5-normal-32mb-txt-png.rar",
+            "photo.jpg",
+            "",
+            &mut data,
+        )
+        .unwrap();
+
+        // the text behind the photo and the end block are not read
+        assert_eq!(archive.files.len(), 1);
+        assert!(archive.end.is_none());
+        assert_eq!(data, *PHOTO);
+    }
+
+    #[test]
+    fn test_extract_with_visitor() {
+        let mut passwords = Passwords::default();
+        let mut files = HashMap::new();
+        let archive = Archive::extract_with(
+            "assets/rar5-save-32mb-txt-png-pw-test.rar",
+            &mut passwords,
+            |_| true,
+            |f, data| {
+                let mut buf = vec![];
+                data.read_to_end(&mut buf)?;
+                files.insert(f.name.clone(), buf);
+                Ok(())
+            },
+        )
+        .unwrap();
+
+        // the photo is skipped by the password provider
+        assert_eq!(archive.files.len(), 2);
+        assert_eq!(files.len(), 1);
+        assert_eq!(files["text.txt"], *TEXT);
+    }
+
+    #[test]
+    fn test_open_entry_rar5_save_pw() {
+        use std::io::{Seek, SeekFrom};
+
+        let mut entry = Archive::open_entry(
+            "assets/rar5-save-32mb-txt-png-pw-test.rar",
+            "photo.jpg",
+            "test",
+        )
+        .unwrap();
+        assert_eq!(entry.size(), PHOTO.len() as u64);
+
+        // read from unaligned positions in any order
+        let mut buf = [0u8; 1000];
+        for &pos in &[1_000_003u64, 0, 17, 2_000_000, 65_535] {
+            assert_eq!(entry.seek(SeekFrom::Start(pos)).unwrap(), pos);
+            entry.read_exact(&mut buf).unwrap();
+            assert_eq!(&buf[..], &PHOTO[pos as usize..pos as usize + 1000]);
+        }
+
+        // the end is the end of the photo, without the padding
+        let pos = entry.seek(SeekFrom::End(-100)).unwrap() as usize;
+        let mut data = vec![];
+        entry.read_to_end(&mut data).unwrap();
+        assert_eq!(data, &PHOTO[pos..]);
+
+        entry.seek(SeekFrom::Start(0)).unwrap();
+        let mut data = vec![];
+        entry.read_to_end(&mut data).unwrap();
+        assert_eq!(data, *PHOTO);
+
+        // the file behind the photo
+        let mut entry = Archive::open_entry(
+            "assets/rar5-save-32mb-txt-png-pw-test.rar",
+            "text.txt",
+            "test",
+        )
+        .unwrap();
+        entry.seek(SeekFrom::Current(100)).unwrap();
+        let mut data = vec![];
+        entry.read_to_end(&mut data).unwrap();
+        assert_eq!(data, &TEXT[100..]);
+    }
+
+    #[test]
+    fn test_open_entry_rar4_save() {
+        use std::io::{Seek, SeekFrom};
+
+        for rar in &["txt", "txt-pw-test", "txt-pwf-test"] {
+            let mut entry = Archive::open_entry(
+                &format!("assets/rar4-save-4096kb-{}.rar", rar),
+                "text.txt",
+                "test",
+            )
+            .unwrap();
+
+            entry.seek(SeekFrom::End(-1000)).unwrap();
+            let mut data = vec![];
+            entry.read_to_end(&mut data).unwrap();
+            assert_eq!(data, &TEXT[TEXT.len() - 1000..]);
+        }
+    }
+
+    #[test]
+    fn test_open_entry_errors() {
+        // packed files can't be seeked
+        assert!(Archive::open_entry("assets/rar5-normal-32mb-txt.rar", "text.txt", "").is_err());
+        assert!(Archive::open_entry("assets/rar5-save-32mb-txt.rar", "missing.txt", "").is_err());
+
+        let err = Archive::open_entry("assets/rar5-save-32mb-txt-png-pw-test.rar", "text.txt", "")
+            .err()
+            .unwrap();
+        assert_eq!(
+            err.downcast::<RarError>().unwrap(),
+            RarError::PasswordRequired {
+                name: "text.txt".into()
+            }
+        );
+        let err = Archive::open_entry(
+            "assets/rar5-save-32mb-txt-png-pw-test.rar",
+            "text.txt",
+            "wrong",
+        )
+        .err()
+        .unwrap();
+        assert_eq!(
+            err.downcast::<RarError>().unwrap(),
+            RarError::WrongPassword {
+                name: "text.txt".into()
+            }
+        );
+    }
 }
@@ -0,0 +1,130 @@
+use aes_reader::KeyCache;
+use error::RarError;
+use file_block::FileBlock;
+use std::fmt;
+use zeroize::Zeroizing;
+
+/// Answer of a `PasswordProvider` for an encrypted file or archive
+#[derive(PartialEq, Clone)]
+pub enum PasswordResponse {
+    /// Try to decrypt with this password, it is wiped after use
+    Password(Zeroizing<String>),
+    /// Don't extract the file and continue with the next one
+    Skip,
+    /// Stop the extraction of the archive
+    Abort,
+}
+
+/// The password is never printed
+impl fmt::Debug for PasswordResponse {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PasswordResponse::Password(_) => write!(f, "Password(<redacted>)"),
+            PasswordResponse::Skip => write!(f, "Skip"),
+            PasswordResponse::Abort => write!(f, "Abort"),
+        }
+    }
+}
+
+/// Provides the passwords of an archive, when they are needed.
+///
+/// The provider is only asked when an encrypted file or encrypted
+/// headers are reached. When the archive can tell that a password
+/// is wrong, the provider is asked again with a higher retry count.
+pub trait PasswordProvider {
+    /// Returns the password for the encrypted file.
+    ///
+    /// The `retry` counts the wrong passwords, which were given for this file before.
+    fn password(&mut self, file: &FileBlock, retry: u32) -> PasswordResponse;
+
+    /// Returns the password for the encrypted headers of an archive.
+    ///
+    /// The headers can't be skipped, so `Skip` aborts the extraction as well.
+    fn archive_password(&mut self, archive: &str, retry: u32) -> PasswordResponse {
+        let _ = (archive, retry);
+        PasswordResponse::Abort
+    }
+}
+
+/// A single password for everything, which is given only once
+impl PasswordProvider for &str {
+    fn password(&mut self, _: &FileBlock, retry: u32) -> PasswordResponse {
+        self.archive_password("", retry)
+    }
+
+    fn archive_password(&mut self, _: &str, retry: u32) -> PasswordResponse {
+        if self.is_empty() || retry > 0 {
+            PasswordResponse::Abort
+        } else {
+            PasswordResponse::Password(Zeroizing::new(self.to_string()))
+        }
+    }
+}
+
+/// The password provider of an archive together with the
+/// keys, which were derived from its passwords.
+pub struct Keyring<'a> {
+    pub passwords: &'a mut dyn PasswordProvider,
+    pub keys: KeyCache,
+}
+
+impl<'a> Keyring<'a> {
+    /// Create a new Keyring with an empty cache
+    pub fn new(passwords: &'a mut dyn PasswordProvider) -> Self {
+        Keyring {
+            passwords,
+            keys: KeyCache::default(),
+        }
+    }
+}
+
+/// Returns the error for a file or archive, whose password was not given.
+///
+/// Without any try the password is missing, otherwise the last one was wrong.
+pub fn aborted(name: &str, retry: u32) -> RarError {
+    if retry == 0 {
+        RarError::PasswordRequired { name: name.into() }
+    } else {
+        RarError::WrongPassword { name: name.into() }
+    }
+}
+
+#[test]
+fn test_str_password_provider() {
+    let file = FileBlock::default();
+
+    let mut passwords = "test";
+    assert_eq!(
+        passwords.password(&file, 0),
+        PasswordResponse::Password(Zeroizing::new("test".into()))
+    );
+    assert_eq!(passwords.password(&file, 1), PasswordResponse::Abort);
+    assert_eq!(
+        format!("{:?}", passwords.password(&file, 0)),
+        "Password(<redacted>)"
+    );
+    assert_eq!(
+        passwords.archive_password("a.rar", 0),
+        PasswordResponse::Password(Zeroizing::new("test".into()))
+    );
+
+    let mut passwords = "";
+    assert_eq!(passwords.password(&file, 0), PasswordResponse::Abort);
+    assert_eq!(
+        passwords.archive_password("a.rar", 0),
+        PasswordResponse::Abort
+    );
+
+    assert_eq!(
+        aborted("a.rar", 0),
+        RarError::PasswordRequired {
+            name: "a.rar".into()
+        }
+    );
+    assert_eq!(
+        aborted("a.rar", 2),
+        RarError::WrongPassword {
+            name: "a.rar".into()
+        }
+    );
+}
@@ -0,0 +1,103 @@
+use std::io::{Read, Result};
+use util::{crc32, CRC32_TABLE};
+use zeroize::Zeroize;
+
+/// RAR 1.5 decryption reader to decrypt files of old .rar archives.
+///
+/// The cipher is a stream of bytes, which are XORed onto the data.
+/// The key stream is generated out of the password with the CRC32 table.
+pub struct Rar15Reader<R: Read> {
+    /// Reader to read encrypted data from
+    reader: R,
+    /// Current state of the key stream
+    key: [u16; 4],
+}
+
+impl<R: Read> Rar15Reader<R> {
+    /// Create a new decryption reader with the key of `key_rar15`
+    pub fn new(reader: R, key: [u16; 4]) -> Rar15Reader<R> {
+        Rar15Reader { reader, key }
+    }
+
+    /// Decrypt the data in place and advance the key stream
+    fn decrypt(&mut self, data: &mut [u8]) {
+        let k = &mut self.key;
+
+        for d in data.iter_mut() {
+            k[0] = k[0].wrapping_add(0x1234);
+            let t = CRC32_TABLE[((k[0] & 0x1FE) >> 1) as usize];
+            k[1] ^= t as u16;
+            k[2] = k[2].wrapping_sub((t >> 16) as u16);
+            k[0] ^= k[2];
+            k[3] = k[3].rotate_right(1) ^ k[1];
+            k[3] = k[3].rotate_right(1);
+            k[0] ^= k[3];
+            *d ^= (k[0] >> 8) as u8;
+        }
+    }
+}
+
+impl<R: Read> Drop for Rar15Reader<R> {
+    fn drop(&mut self) {
+        self.key.zeroize();
+    }
+}
+
+impl<R: Read> Read for Rar15Reader<R> {
+    /// Reads encrypted data from the underlying reader and decrypts it into the passed buffer
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let read = self.reader.read(buf)?;
+        self.decrypt(&mut buf[..read]);
+        Ok(read)
+    }
+}
+
+/// Generate the RAR 1.5 key out of the password.
+///
+/// The key starts with the CRC32 register of the password,
+/// the other half is mixed from the password bytes and their CRC32 table entries.
+pub fn key_rar15(pwd: &str) -> [u16; 4] {
+    // the CRC32 register without the final inversion
+    let mut crc = !crc32(0, pwd.as_bytes());
+    let mut key = [crc as u16, (crc >> 16) as u16, 0, 0];
+    crc.zeroize();
+
+    for p in pwd.bytes() {
+        let t = CRC32_TABLE[p as usize];
+        key[2] ^= (u32::from(p) ^ t) as u16;
+        key[3] = key[3].wrapping_add((u32::from(p) + (t >> 16)) as u16);
+    }
+
+    key
+}
+#[test]
+fn test_key_rar15() {
+    assert_eq!(key_rar15("test"), [0x81F3, 0x2780, 0xB547, 0xB774]);
+    assert_eq!(key_rar15(""), [0xFFFF, 0xFFFF, 0, 0]);
+}
+#[test]
+fn test_rar15_reader() {
+    // the key stream of the password
+    let mut reader = Rar15Reader::new(&[0u8; 32][..], key_rar15("test"));
+    let mut data = vec![];
+    reader.read_to_end(&mut data).unwrap();
+    assert_eq!(
+        data,
+        [
+            0xBA, 0xA4, 0x5A, 0x25, 0x8E, 0x6F, 0x99, 0xCF, 0xEF, 0x40, 0x32, 0x9E, 0xE8, 0x6C,
+            0x5A, 0x03, 0x45, 0xD1, 0xB9, 0x1A, 0x54, 0x6D, 0x83, 0xDE, 0x46, 0x9E, 0x2D, 0x43,
+            0xA4, 0x07, 0x7D, 0x2F
+        ]
+    );
+
+    // the key stream continues over the reads
+    let data = [
+        0xEE, 0xCC, 0x3F, 0x05, 0xFF, 0x1A, 0xF0, 0xAC, 0x84, 0x60, 0x50, 0xEC, 0x87, 0x1B, 0x34,
+        0x23, 0x23, 0xBE, 0xC1,
+    ];
+    let mut reader = Rar15Reader::new(&data[..], key_rar15("test"));
+    let mut plain = [0u8; 19];
+    reader.read_exact(&mut plain[..7]).unwrap();
+    reader.read_exact(&mut plain[7..]).unwrap();
+    assert_eq!(&plain, b"The quick brown fox");
+}
@@ -1,111 +1,302 @@
 use failure;
 use nom;
+use std::collections::VecDeque;
 use std::io;
-use std::io::{BufRead, BufReader, Read};
+use std::io::{BufRead, Cursor, ErrorKind, Read, Seek, SeekFrom};
+use volume::ReadSeek;
 
-/// The RarReader is abstracting an BufRead trait to
-/// easily share, change and chain the different reader.
+/// Headers are at most 2 MB big, the buffer is not grown
+/// behind this size to parse a header.
+const MAX_HEADER_SIZE: usize = 0x20_0000 + ::BUFFER_SIZE;
+
+/// The RarReader is a buffered reader over the .rar files, which
+/// can seek over data areas without reading them.
 ///
-/// In addition it provides and r_seek and nom parser functionality.
+/// The data areas of a file which continues in the next volumes are
+/// queued behind each other, when the end of one is reached the reader
+/// continues with the next volume. In addition it provides the nom parser
+/// functionality on top of the buffer.
 pub struct RarReader<'a> {
-    inner: Box<dyn BufRead + 'a>,
+    /// The volumes which are read, the first one is the current one
+    volumes: VecDeque<Volume<'a>>,
+    /// Data which is read in front of the current volume
+    front: Cursor<Vec<u8>>,
 }
 
-impl<'a> RarReader<'a> {
-    /// Create a new RarReader based upon an anlready existing
-    /// BufRead implementation
-    pub fn new<R: BufRead + 'a>(r: R) -> RarReader<'a> {
-        RarReader { inner: Box::new(r) }
+/// A .rar file, which is read by the RarReader
+struct Volume<'a> {
+    inner: Box<dyn ReadSeek + 'a>,
+    /// Buffered data of the inner reader
+    buffer: Vec<u8>,
+    /// Position of the next byte in the buffer
+    pos: usize,
+    /// Offset of the buffer in the inner reader
+    offset: u64,
+    /// Offset where the data of the volume ends for the reader,
+    /// the end of the inner reader when not set
+    end: Option<u64>,
+}
+
+impl<'a> Volume<'a> {
+    /// Offset of the next byte, which is read
+    fn position(&self) -> u64 {
+        self.offset + self.pos as u64
     }
 
-    /// Create a new RarReader implementation over an file.
-    /// Internally we creating an BufReader to accelerrate the performance.
-    pub fn new_from_file(file: ::std::fs::File) -> RarReader<'a> {
+    /// Returns the not yet read data of the buffer
+    fn remaining(&self) -> &[u8] {
+        &self.buffer[self.pos..]
+    }
+
+    /// Read more data behind the buffered one into the buffer and return
+    /// the number of read bytes. The already read data is dropped.
+    fn fill_more(&mut self) -> io::Result<usize> {
+        self.buffer.drain(..self.pos);
+        self.offset += self.pos as u64;
+        self.pos = 0;
+
+        let read_end = self.offset + self.buffer.len() as u64;
+        let mut len = ::BUFFER_SIZE as u64;
+        if let Some(end) = self.end {
+            len = len.min(end.saturating_sub(read_end));
+        }
+
+        let start = self.buffer.len();
+        self.buffer.resize(start + len as usize, 0);
+        let read = match self.inner.read(&mut self.buffer[start..]) {
+            Ok(read) => read,
+            Err(e) => {
+                self.buffer.truncate(start);
+                return Err(e);
+            }
+        };
+        self.buffer.truncate(start + read);
+
+        // the data area ends in this volume, but the data is missing
+        if read == 0 && len > 0 && self.end.is_some() {
+            return Err(io::Error::new(
+                ErrorKind::UnexpectedEof,
+                "The data area of the volume is truncated",
+            ));
+        }
+
+        Ok(read)
+    }
+
+    /// Seek forward, the data in between is not read when it's not buffered
+    fn skip(&mut self, amt: u64) -> io::Result<()> {
+        let buffered = self.remaining().len() as u64;
+        if amt <= buffered {
+            self.pos += amt as usize;
+            return Ok(());
+        }
+
+        let jump = amt - buffered;
+        if jump > i64::MAX as u64 {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                "Can't seek that far",
+            ));
+        }
+
+        self.inner.seek(SeekFrom::Current(jump as i64))?;
+        self.offset = self.position() + amt;
+        self.buffer.clear();
+        self.pos = 0;
+        Ok(())
+    }
+}
+
+impl<'a> RarReader<'a> {
+    /// Create a new RarReader over a .rar file.
+    ///
+    /// The offsets are counted from the position of the reader.
+    pub fn new<R: Read + Seek + 'a>(r: R) -> RarReader<'a> {
+        let mut volumes = VecDeque::new();
+        volumes.push_back(Volume {
+            inner: Box::new(r),
+            buffer: Vec::with_capacity(::BUFFER_SIZE),
+            pos: 0,
+            offset: 0,
+            end: None,
+        });
+
         RarReader {
-            inner: Box::new(BufReader::new(file)),
+            volumes,
+            front: Cursor::new(vec![]),
         }
     }
 
-    /// Seeks the reader forward -> right now it's leveraging the read function
-    /// which is not really performant....
+    /// Seeks the reader forward without reading the data in between.
     ///
-    /// Unless the Chain struct is not supporting the Seek trait, we need to live with this.
+    /// When the end of the current volume is reached,
+    /// the seek continues in the following volumes.
     pub fn r_seek(&mut self, amt: u64) -> Result<(), io::Error> {
         let mut amt = amt;
-        let mut buf = [0u8; 8000];
 
-        // continue as long as we haven't seeked enough
-        while amt > 0 {
-            let mut len = buf.len();
+        // the data in front is skipped first
+        let front = (self.front_remaining().len() as u64).min(amt);
+        self.front.consume(front as usize);
+        amt -= front;
+
+        loop {
+            let last = self.volumes.len() == 1;
+            let volume = &mut self.volumes[0];
+            let left = match volume.end {
+                Some(end) => end.saturating_sub(volume.position()),
+                None => u64::MAX,
+            };
+
+            // a seek to the end of the data stays in the last volume
+            if amt < left || (last && amt == left) {
+                return volume.skip(amt);
+            }
 
-            // when the buffer is bigger than the need to seek
-            // we shrink the buffer. Otherwiese we would seek to much.
-            if buf.len() > amt as usize {
-                len = amt as usize;
+            if last {
+                return Err(io::Error::new(
+                    ErrorKind::UnexpectedEof,
+                    "Can't seek behind the end of the volume",
+                ));
             }
 
-            // read the data to pusht he curser foreward
-            self.inner.read_exact(&mut buf[..len])?;
-            // subtract the readed data
-            amt -= len as u64;
+            amt -= left;
+            self.volumes.pop_front();
         }
+    }
 
-        Ok(())
+    /// The data of the current volume ends after the given size,
+    /// afterwards the reader continues with the next volume.
+    pub fn limit(&mut self, size: u64) {
+        let volume = &mut self.volumes[0];
+        volume.end = Some(volume.position() + size);
+    }
+
+    /// Continue with the data of the other reader,
+    /// when the end of the volumes of this one is reached.
+    pub fn append(&mut self, other: RarReader<'a>) {
+        self.volumes.extend(other.volumes);
+    }
+
+    /// Read more data into the buffer of the current volume, without
+    /// dropping the not yet read data. Returns false when nothing
+    /// more can be read.
+    ///
+    /// This is used when a header is bigger than the buffered data.
+    pub fn fill_more(&mut self) -> Result<bool, io::Error> {
+        if !self.front_remaining().is_empty() {
+            return Ok(false);
+        }
+
+        let volume = &mut self.volumes[0];
+        if volume.remaining().len() >= MAX_HEADER_SIZE {
+            return Ok(false);
+        }
+
+        Ok(volume.fill_more()? > 0)
+    }
+
+    /// Place the data in front of the not yet read data.
+    ///
+    /// This is used to parse decrypted headers, which are
+    /// placed between the encrypted data.
+    pub fn push_front(&mut self, data: Vec<u8>) {
+        let mut front = data;
+        front.extend_from_slice(self.front_remaining());
+        self.front = Cursor::new(front);
+    }
+
+    /// Returns the offset in the current volume.
+    ///
+    /// For a reader over a whole .rar file this is the offset of a block
+    /// before its header is read, or of the data area when its file header
+    /// was parsed. Decrypted headers in front of the volume don't count,
+    /// their encrypted data was read already.
+    pub fn position(&self) -> u64 {
+        self.volumes[0].position()
+    }
+
+    /// Returns the not yet read data in front of the current volume
+    fn front_remaining(&self) -> &[u8] {
+        &self.front.get_ref()[self.front.position() as usize..]
     }
 
     /// This function executes a nom parser against the data of the buffer.
+    ///
+    /// When the parser needs more data, the buffer is filled up
+    /// and the parser is executed again.
     pub fn exec_nom_parser<F, D>(&mut self, func: F) -> Result<D, failure::Error>
     where
         F: Fn(&[u8]) -> nom::IResult<&[u8], D>,
     {
         // Local enum for collecting the stati and avoid locks between
-        // using the inner bufreader
+        // using the inner buffer
         enum Stati<D> {
             Error,
+            Incomplete,
             Success(usize, D),
         }
 
-        // execute the nom command against the buffer content
-        // and match the outcome to the local stati enum
-        let res;
-        let buf_len;
-        {
-            let buf = self.fill_buf()?;
-            buf_len = buf.len();
-            match func(buf) {
-                Ok((bl, d)) => {
-                    res = Stati::Success(bl.len(), d);
+        loop {
+            // execute the nom command against the buffer content
+            // and match the outcome to the local stati enum
+            let res = {
+                let buf = self.fill_buf()?;
+                match func(buf) {
+                    Ok((bl, d)) => Stati::Success(buf.len() - bl.len(), d),
+                    Err(nom::Err::Incomplete(_)) => Stati::Incomplete,
+                    Err(_) => Stati::Error,
                 }
-                Err(_) => {
-                    res = Stati::Error;
-                }
-            }
-        }
+            };
 
-        // take the outcome and perform the required changes
-        match res {
-            // on error return an error
-            Stati::Error => Err(format_err!("Can't execute nom parser")),
-            // on sucess resize the buffer and return the result
-            Stati::Success(bl, d) => {
-                self.consume(buf_len - bl);
-                Ok(d)
+            // take the outcome and perform the required changes
+            match res {
+                // retry with more data, as long as there is some
+                Stati::Incomplete if self.fill_more()? => continue,
+                // on error return an error
+                Stati::Error | Stati::Incomplete => {
+                    return Err(format_err!("Can't execute nom parser"))
+                }
+                // on sucess resize the buffer and return the result
+                Stati::Success(len, d) => {
+                    self.consume(len);
+                    return Ok(d);
+                }
             }
         }
     }
 }
 
 impl<'a> Read for RarReader<'a> {
-    /// Read from the internal reader.
+    /// Read from the buffer and the volumes.
     fn read(&mut self, buf: &mut [u8]) -> Result<usize, io::Error> {
-        self.inner.read(buf)
+        let read = {
+            let data = self.fill_buf()?;
+            let len = data.len().min(buf.len());
+            buf[..len].copy_from_slice(&data[..len]);
+            len
+        };
+
+        self.consume(read);
+        Ok(read)
     }
 }
 
 impl<'a> BufRead for RarReader<'a> {
     /// Fills the buffer and returns the content
     fn fill_buf(&mut self) -> Result<&[u8], io::Error> {
-        self.inner.fill_buf()
+        if !self.front_remaining().is_empty() {
+            return self.front.fill_buf();
+        }
+
+        // continue with the next volume at the end of the current one
+        while self.volumes[0].remaining().is_empty()
+            && self.volumes[0].fill_more()? == 0
+            && self.volumes.len() > 1
+        {
+            self.volumes.pop_front();
+        }
+
+        Ok(self.volumes[0].remaining())
     }
 
     /// Tells this buffer that amt bytes have been consumed from the buffer,
@@ -113,10 +304,20 @@ impl<'a> BufRead for RarReader<'a> {
     ///
     /// Only the buffer is effected, can't push more foreward than the buffer
     fn consume(&mut self, amt: usize) {
-        self.inner.consume(amt)
+        if !self.front_remaining().is_empty() {
+            return self.front.consume(amt);
+        }
+
+        let volume = &mut self.volumes[0];
+        volume.pos = (volume.pos + amt).min(volume.buffer.len());
+        let done = volume.end == Some(volume.position());
+
+        // the next volume is current, as soon as the data of this one is read
+        if done && self.volumes.len() > 1 {
+            self.volumes.pop_front();
+        }
     }
 }
-
 #[test]
 fn test_exec_nom_parser() {
     let data = [
@@ -138,8 +339,10 @@ fn test_consume() {
 
     let mut db = RarReader::new(::std::io::Cursor::new(data));
 
+    db.fill_buf().unwrap();
     db.consume(8);
     assert_eq!(db.fill_buf().unwrap(), &data[8..]);
+    assert_eq!(db.position(), 8);
 }
 #[test]
 fn test_seek() {
@@ -149,6 +352,95 @@ fn test_seek() {
 
     let mut db = RarReader::new(::std::io::Cursor::new(data));
 
-    db.consume(8);
+    db.r_seek(8).unwrap();
     assert_eq!(db.fill_buf().unwrap(), &data[8..]);
+    assert_eq!(db.position(), 8);
+
+    // behind the end of the data
+    db.limit(3);
+    assert!(db.r_seek(4).is_err());
+}
+#[test]
+fn test_push_front() {
+    let data = [0x01, 0x02, 0x03];
+
+    let mut db = RarReader::new(::std::io::Cursor::new(data));
+
+    db.push_front(vec![0xAA, 0xBB]);
+    assert_eq!(db.fill_buf().unwrap(), &[0xAA, 0xBB]);
+    db.consume(1);
+
+    let mut buf = vec![];
+    db.read_to_end(&mut buf).unwrap();
+    assert_eq!(buf, [0xBB, 0x01, 0x02, 0x03]);
+    // only the data of the inner reader counts
+    assert_eq!(db.position(), 3);
+}
+#[test]
+fn test_seek_without_reading() {
+    /// Reader which fails on every read
+    struct Unreadable(u64);
+
+    impl Read for Unreadable {
+        fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+            Err(io::Error::other("Data is read"))
+        }
+    }
+
+    impl Seek for Unreadable {
+        fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+            if let SeekFrom::Current(amt) = pos {
+                self.0 += amt as u64;
+            }
+            Ok(self.0)
+        }
+    }
+
+    let mut db = RarReader::new(Unreadable(0));
+    db.r_seek(4 * 1024 * 1024 * 1024).unwrap();
+    assert_eq!(db.position(), 4 * 1024 * 1024 * 1024);
+}
+#[test]
+fn test_append() {
+    let mut db = RarReader::new(Cursor::new(vec![1u8, 2, 3, 4]));
+    db.limit(2);
+
+    let mut next = RarReader::new(Cursor::new(vec![5u8, 6, 7, 8]));
+    next.r_seek(1).unwrap();
+    next.limit(2);
+    db.append(next);
+    db.append(RarReader::new(Cursor::new(vec![9u8, 10])));
+
+    let mut buf = [0u8; 3];
+    db.read_exact(&mut buf).unwrap();
+    assert_eq!(buf, [1, 2, 6]);
+
+    // the seek continues in the last volume
+    db.r_seek(2).unwrap();
+    assert_eq!(db.position(), 1);
+
+    let mut buf = vec![];
+    db.read_to_end(&mut buf).unwrap();
+    assert_eq!(buf, [10]);
+}
+#[test]
+fn test_exec_nom_parser_incomplete() {
+    // a header behind the end of the first buffer
+    let mut data = vec![0u8; ::BUFFER_SIZE - 4];
+    data.extend_from_slice(&[0x52, 0x61, 0x72, 0x21, 0x1A, 0x07, 0x01, 0x00]);
+
+    let mut db = RarReader::new(Cursor::new(data));
+    db.fill_buf().unwrap();
+    db.consume(::BUFFER_SIZE - 4);
+    assert_eq!(db.fill_buf().unwrap().len(), 4);
+
+    assert!(db
+        .exec_nom_parser(::sig_block::SignatureBlock::parse)
+        .is_ok());
+    assert_eq!(db.position(), ::BUFFER_SIZE as u64 + 4);
+
+    // there is no more data
+    assert!(db
+        .exec_nom_parser(::sig_block::SignatureBlock::parse)
+        .is_err());
 }
@@ -0,0 +1,206 @@
+use zeroize::Zeroize;
+
+/// SHA-1 hasher, which is used by the RAR 3.x key derivation.
+///
+/// Besides the normal hashing it emulates the implementation of RAR 2.9,
+/// which overwrites the hashed data with the message schedule.
+/// The hashed password stays in the state, so it is wiped on drop.
+#[derive(Clone)]
+pub struct Sha1 {
+    state: [u32; 5],
+    count: u64,
+    buffer: [u8; 64],
+}
+
+impl Sha1 {
+    /// Create a new SHA-1 hasher
+    pub fn new() -> Sha1 {
+        Sha1 {
+            state: [
+                0x6745_2301,
+                0xEFCD_AB89,
+                0x98BA_DCFE,
+                0x1032_5476,
+                0xC3D2_E1F0,
+            ],
+            count: 0,
+            buffer: [0; 64],
+        }
+    }
+
+    /// Hash more data
+    pub fn update(&mut self, data: &[u8]) {
+        self.process(data);
+    }
+
+    /// Hash more data like RAR 2.9 did it.
+    ///
+    /// Full blocks, which are hashed directly out of the data,
+    /// are overwritten with the last 16 words of the message schedule.
+    pub fn update_rar29(&mut self, data: &mut [u8]) {
+        // every block is overwritten after it was hashed,
+        // so the data can be changed afterwards
+        let mut direct = self.process(data);
+        for &(i, ref workspace) in direct.iter() {
+            for (d, w) in data[i..i + 64].chunks_mut(4).zip(workspace.iter()) {
+                d.copy_from_slice(&w.to_le_bytes());
+            }
+        }
+
+        for (_, workspace) in direct.iter_mut() {
+            workspace.zeroize();
+        }
+    }
+
+    /// Returns the digest of all hashed data, without consuming the hasher
+    pub fn digest(&self) -> [u8; 20] {
+        let mut sha = self.clone();
+        let bits = sha.count << 3;
+
+        sha.update(&[0x80]);
+        while sha.count & 63 != 56 {
+            sha.update(&[0]);
+        }
+        sha.update(&bits.to_be_bytes());
+
+        let mut out = [0u8; 20];
+        for (o, s) in out.chunks_mut(4).zip(sha.state.iter()) {
+            o.copy_from_slice(&s.to_be_bytes());
+        }
+        out
+    }
+
+    /// Split the data into blocks and hash them.
+    ///
+    /// Returns the position and the workspace of the blocks,
+    /// which were hashed directly out of the data.
+    fn process(&mut self, data: &[u8]) -> Vec<(usize, [u32; 16])> {
+        let mut direct = vec![];
+        let mut j = (self.count & 63) as usize;
+        self.count += data.len() as u64;
+
+        let mut i = 0;
+        if j + data.len() > 63 {
+            i = 64 - j;
+            self.buffer[j..].copy_from_slice(&data[..i]);
+            let block = self.buffer;
+            self.transform(&block);
+
+            while i + 63 < data.len() {
+                let mut block = [0u8; 64];
+                block.copy_from_slice(&data[i..i + 64]);
+                direct.push((i, self.transform(&block)));
+                i += 64;
+            }
+            j = 0;
+        }
+
+        self.buffer[j..j + data.len() - i].copy_from_slice(&data[i..]);
+        direct
+    }
+
+    /// The SHA-1 compression function, which returns its workspace
+    fn transform(&mut self, block: &[u8; 64]) -> [u32; 16] {
+        let mut w = [0u32; 16];
+        for (w, b) in w.iter_mut().zip(block.chunks(4)) {
+            *w = u32::from_be_bytes([b[0], b[1], b[2], b[3]]);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e] = self.state;
+        for i in 0..80 {
+            if i >= 16 {
+                w[i & 15] = (w[(i + 13) & 15] ^ w[(i + 8) & 15] ^ w[(i + 2) & 15] ^ w[i & 15])
+                    .rotate_left(1);
+            }
+
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5A82_7999),
+                20..=39 => (b ^ c ^ d, 0x6ED9_EBA1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8F1B_BCDC),
+                _ => (b ^ c ^ d, 0xCA62_C1D6),
+            };
+
+            let t = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(w[i & 15]);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = t;
+        }
+
+        for (s, v) in self.state.iter_mut().zip([a, b, c, d, e].iter()) {
+            *s = s.wrapping_add(*v);
+        }
+
+        w
+    }
+}
+
+impl Drop for Sha1 {
+    fn drop(&mut self) {
+        self.state.zeroize();
+        self.count.zeroize();
+        self.buffer.zeroize();
+    }
+}
+
+impl Default for Sha1 {
+    fn default() -> Self {
+        Sha1::new()
+    }
+}
+
+#[test]
+fn test_sha1() {
+    let mut sha = Sha1::new();
+    assert_eq!(
+        sha.digest(),
+        [
+            0xDA, 0x39, 0xA3, 0xEE, 0x5E, 0x6B, 0x4B, 0x0D, 0x32, 0x55, 0xBF, 0xEF, 0x95, 0x60,
+            0x18, 0x90, 0xAF, 0xD8, 0x07, 0x09
+        ]
+    );
+
+    sha.update(b"abc");
+    assert_eq!(
+        sha.digest(),
+        [
+            0xA9, 0x99, 0x3E, 0x36, 0x47, 0x06, 0x81, 0x6A, 0xBA, 0x3E, 0x25, 0x71, 0x78, 0x50,
+            0xC2, 0x6C, 0x9C, 0xD0, 0xD8, 0x9D
+        ]
+    );
+}
+#[test]
+fn test_sha1_rar29() {
+    let mut sha = Sha1::new();
+    sha.update_rar29(&mut [b'x'; 10]);
+
+    // only the block, which is hashed directly out of the data, is overwritten
+    let mut data: Vec<u8> = (0..150).map(|i| i as u8).collect();
+    sha.update_rar29(&mut data);
+
+    let workspace = [
+        0xBA, 0x0F, 0xAB, 0x1E, 0x0B, 0xDA, 0xAB, 0x7A, 0xC7, 0xCE, 0xD3, 0xDA, 0x82, 0xB7, 0x3F,
+        0x0A, 0xE6, 0x76, 0x56, 0xC6, 0x7B, 0xB2, 0xB1, 0x78, 0x13, 0xA4, 0x55, 0xE2, 0x9D, 0x51,
+        0x3D, 0xF1, 0x52, 0xEC, 0xA9, 0x17, 0xED, 0xAB, 0x76, 0x30, 0x59, 0xAE, 0x76, 0x81, 0xCA,
+        0x31, 0xE0, 0x1B, 0x95, 0xE2, 0x4B, 0x3C, 0x4E, 0x51, 0x3C, 0x23, 0xB1, 0x66, 0x69, 0xBE,
+        0x09, 0xF4, 0x91, 0x6C,
+    ];
+    assert_eq!(data[..54], (0..54).collect::<Vec<u8>>()[..]);
+    assert_eq!(data[54..118], workspace[..]);
+    assert_eq!(data[118..], (118..150).collect::<Vec<u8>>()[..]);
+
+    // the digest itself is not changed
+    assert_eq!(
+        sha.digest(),
+        [
+            0xA8, 0x0A, 0x6D, 0xC4, 0x04, 0x34, 0x5A, 0xEF, 0x19, 0xE6, 0xE3, 0x84, 0xD5, 0x25,
+            0xFE, 0xEE, 0x4E, 0xBB, 0x4D, 0x2C
+        ]
+    );
+}
@@ -0,0 +1,152 @@
+use std::io::{Read, Result};
+
+/// Bit level reader over the packed data stream.
+///
+/// The reader keeps a small buffer of the underlying stream
+/// and allows to peek and consume single bits in big endian
+/// order, like the RAR decoders need it.
+pub struct BitReader<'a> {
+    /// Reader to read the packed data from
+    inner: Box<dyn Read + 'a>,
+    /// Buffered data of the reader
+    buf: Vec<u8>,
+    /// Current byte position within the buffer
+    pos: usize,
+    /// Current bit position within the byte
+    bit: u32,
+    /// Number of bytes which were dropped from the buffer already
+    offset: u64,
+    /// Indicates wheather eof of the underlying reader was reached
+    eof: bool,
+}
+
+impl<'a> BitReader<'a> {
+    /// Create a new BitReader over a reader
+    pub fn new<R: Read + 'a>(r: R) -> BitReader<'a> {
+        BitReader {
+            inner: Box::new(r),
+            buf: Vec::with_capacity(::BUFFER_SIZE),
+            pos: 0,
+            bit: 0,
+            offset: 0,
+            eof: false,
+        }
+    }
+
+    /// Make sure enough data is buffered for the next bit operations.
+    ///
+    /// The decoders never need more than a few bytes at once, so
+    /// we only refill when less than 16 bytes are left.
+    pub fn fill(&mut self) -> Result<()> {
+        if self.eof || self.buf.len() >= self.pos + 16 {
+            return Ok(());
+        }
+
+        // drop the already consumed data
+        let consumed = self.pos.min(self.buf.len());
+        self.buf.drain(..consumed);
+        self.offset += consumed as u64;
+        self.pos -= consumed;
+
+        // read until the buffer is full or the reader is empty
+        let mut chunk = [0u8; ::BUFFER_SIZE];
+        while self.buf.len() < ::BUFFER_SIZE {
+            let read = self
+                .inner
+                .read(&mut chunk[..::BUFFER_SIZE - self.buf.len()])?;
+            if read == 0 {
+                self.eof = true;
+                break;
+            }
+            self.buf.extend_from_slice(&chunk[..read]);
+        }
+
+        Ok(())
+    }
+
+    /// Returns the byte at the given distance from the current position.
+    /// Data behind the end of the stream is read as zero.
+    fn byte(&self, n: usize) -> u32 {
+        self.buf.get(self.pos + n).map(|b| *b as u32).unwrap_or(0)
+    }
+
+    /// Peek the next 16 bits
+    pub fn getbits(&self) -> u32 {
+        let field = (self.byte(0) << 16) | (self.byte(1) << 8) | self.byte(2);
+        (field >> (8 - self.bit)) & 0xffff
+    }
+
+    /// Peek the next 32 bits
+    pub fn getbits32(&self) -> u32 {
+        let field =
+            (self.byte(0) << 24) | (self.byte(1) << 16) | (self.byte(2) << 8) | self.byte(3);
+        let field = field << self.bit;
+        field | ((self.byte(4) << 8) >> (16 - self.bit))
+    }
+
+    /// Consume the given number of bits
+    pub fn addbits(&mut self, bits: u32) {
+        let bits = bits + self.bit;
+        self.pos += (bits >> 3) as usize;
+        self.bit = bits & 7;
+    }
+
+    /// Move forward to the next byte boundary
+    pub fn align(&mut self) {
+        if self.bit > 0 {
+            self.addbits(8 - self.bit);
+        }
+    }
+
+    /// Read the next byte from a byte boundary
+    pub fn get_byte(&mut self) -> u8 {
+        let b = (self.getbits() >> 8) as u8;
+        self.addbits(8);
+        b
+    }
+
+    /// Returns the absolute byte position within the packed stream
+    pub fn byte_pos(&self) -> u64 {
+        self.offset + self.pos as u64
+    }
+
+    /// Returns the bit position within the current byte
+    pub fn bit_pos(&self) -> u32 {
+        self.bit
+    }
+
+    /// Returns the number of buffered bytes which are not consumed yet.
+    /// After a fill these are at least 16 bytes, unless the stream ends.
+    pub fn remaining(&self) -> usize {
+        self.buf.len().saturating_sub(self.pos)
+    }
+
+    /// Returns true when more data was consumed than the stream provides
+    pub fn overrun(&self) -> bool {
+        self.eof && self.pos > self.buf.len()
+    }
+}
+
+#[test]
+fn test_bit_reader() {
+    let data = [0b1010_1100, 0b0101_0011, 0xFF, 0x00, 0x81];
+    let mut r = BitReader::new(&data[..]);
+    r.fill().unwrap();
+
+    assert_eq!(r.getbits(), 0b1010_1100_0101_0011);
+    r.addbits(3);
+    assert_eq!(r.getbits(), 0b0110_0010_1001_1111);
+    assert_eq!(r.getbits32(), 0b0110_0010_1001_1111_1111_1000_0000_0100);
+    assert_eq!(r.byte_pos(), 0);
+    assert_eq!(r.bit_pos(), 3);
+
+    r.align();
+    assert_eq!(r.byte_pos(), 1);
+    assert_eq!(r.get_byte(), 0b0101_0011);
+    assert_eq!(r.get_byte(), 0xFF);
+    assert!(!r.overrun());
+
+    r.addbits(24);
+    assert_eq!(r.getbits(), 0);
+    assert!(r.overrun());
+}
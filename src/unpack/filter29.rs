@@ -0,0 +1,664 @@
+use std::io::Result;
+use unpack::bit_reader::BitReader;
+use unpack::data_error;
+use unpack::filter50::delta;
+use util::crc32;
+
+/// Maximum number of filters which can be defined or wait to be applied
+const MAX_UNPACK_FILTERS: usize = 8192;
+/// Size of the global memory area of the RarVM
+const VM_GLOBALSIZE: u32 = 0x2000;
+/// Size of the fixed part of the global memory area
+const VM_FIXEDGLOBALSIZE: u32 = 0x40;
+/// Size of the memory of the RarVM, which limits the size of a filter block
+const VM_MEMSIZE: usize = 0x40000;
+
+/// Read a number in the variable length format of the RarVM
+fn read_data(inp: &mut BitReader) -> u32 {
+    let data = inp.getbits();
+    match data & 0xc000 {
+        0 => {
+            inp.addbits(6);
+            (data >> 10) & 0xf
+        }
+        0x4000 => {
+            if data & 0x3c00 == 0 {
+                inp.addbits(14);
+                0xffff_ff00 | ((data >> 2) & 0xff)
+            } else {
+                inp.addbits(10);
+                (data >> 6) & 0xff
+            }
+        }
+        0x8000 => {
+            inp.addbits(2);
+            let data = inp.getbits();
+            inp.addbits(16);
+            data
+        }
+        _ => {
+            inp.addbits(2);
+            let high = inp.getbits() << 16;
+            inp.addbits(16);
+            let low = inp.getbits();
+            inp.addbits(16);
+            high | low
+        }
+    }
+}
+
+#[test]
+fn test_read_data() {
+    let data = [
+        0x29, 0x13, 0x40, 0x06, 0x48, 0xD3, 0xC4, 0x8D, 0x15, 0x9E, 0x00,
+    ];
+    let mut inp = BitReader::new(&data[..]);
+    inp.fill().unwrap();
+
+    // 4 bit value
+    assert_eq!(read_data(&mut inp), 0x0A);
+    // 8 bit value
+    assert_eq!(read_data(&mut inp), 0x13);
+    // negative 8 bit value
+    assert_eq!(read_data(&mut inp), 0xffff_ff01);
+    // 16 bit value
+    assert_eq!(read_data(&mut inp), 0x48D3);
+    // 32 bit value
+    assert_eq!(read_data(&mut inp), 0x1234_5678);
+    assert_eq!(inp.byte_pos(), 10);
+}
+
+/// The standard filter programs of the RarVM.
+///
+/// The RAR versions 3.x and 4.x only write these programs, so they are
+/// recognized by their code and executed natively instead of running
+/// the byte code.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum FilterType {
+    /// x86 CALL instructions
+    E8,
+    /// x86 CALL and JMP instructions
+    E8E9,
+    /// IA-64 branch instructions
+    Itanium,
+    /// Byte wise delta coding
+    Delta,
+    /// 24 bit RGB image data
+    Rgb,
+    /// Audio data
+    Audio,
+}
+
+impl FilterType {
+    /// Recognize a standard filter by the length and CRC32 of its code
+    fn from_code(code: &[u8]) -> Option<FilterType> {
+        // the first byte is a checksum over the rest of the code
+        let xor_sum = code[1..].iter().fold(0, |sum, b| sum ^ b);
+        if xor_sum != code[0] {
+            return None;
+        }
+
+        match (code.len(), crc32(0, code)) {
+            (53, 0xAD57_6887) => Some(FilterType::E8),
+            (57, 0x3CD7_E57E) => Some(FilterType::E8E9),
+            (120, 0x3769_893F) => Some(FilterType::Itanium),
+            (29, 0x0E06_077D) => Some(FilterType::Delta),
+            (149, 0x1C2C_5DC8) => Some(FilterType::Rgb),
+            (216, 0xBC85_E701) => Some(FilterType::Audio),
+            _ => None,
+        }
+    }
+}
+
+/// Filter which waits until its data block is unpacked
+pub struct PendingFilter {
+    /// The filter program to execute
+    typ: FilterType,
+    /// Initial values of the registers R0 - R6
+    registers: [u32; 7],
+    /// Block start within the window
+    pub block_start: usize,
+    /// Length of the data block
+    pub block_length: usize,
+    /// The block start belongs to the next round of the window
+    pub next_window: bool,
+}
+
+/// The filters of a RAR 2.9 data stream.
+///
+/// The filter programs are defined within the packed data and can be
+/// used by several filters later on, also for the following files of
+/// a solid archive.
+#[derive(Default)]
+pub struct Filters {
+    /// Filter programs which were defined so far
+    programs: Vec<FilterType>,
+    /// Last used block length of every filter program
+    old_lengths: Vec<usize>,
+    /// Index of the last used filter program
+    last_filter: usize,
+    /// Filters which are not applied yet
+    pub stack: Vec<Option<PendingFilter>>,
+}
+
+impl Filters {
+    /// Remove all filters. Without the solid flag also the
+    /// filter programs are removed.
+    pub fn reset(&mut self, solid: bool) {
+        if !solid {
+            self.programs.clear();
+            self.old_lengths.clear();
+            self.last_filter = 0;
+        }
+        self.stack.clear();
+    }
+
+    /// Add a new filter from the code which was read from the packed data.
+    ///
+    /// The block start is relative to the current unpack position
+    /// within the window.
+    pub fn add(
+        &mut self,
+        first_byte: u8,
+        code: &[u8],
+        unp_ptr: usize,
+        wr_ptr: usize,
+        mask: usize,
+    ) -> Result<()> {
+        let mut inp = BitReader::new(code);
+        inp.fill()?;
+
+        let mut filt_pos = self.last_filter;
+        if first_byte & 0x80 != 0 {
+            filt_pos = read_data(&mut inp) as usize;
+            if filt_pos == 0 {
+                self.reset(false);
+            } else {
+                filt_pos -= 1;
+            }
+        }
+
+        if filt_pos > self.programs.len() || filt_pos > self.old_lengths.len() {
+            return Err(data_error("Invalid filter number"));
+        }
+        self.last_filter = filt_pos;
+
+        let new_filter = filt_pos == self.programs.len();
+        if new_filter {
+            if filt_pos > MAX_UNPACK_FILTERS {
+                return Err(data_error("Too many filters"));
+            }
+            self.old_lengths.push(0);
+        }
+
+        // remove the already applied filters from the stack
+        self.stack.retain(Option::is_some);
+        if self.stack.len() > MAX_UNPACK_FILTERS {
+            return Err(data_error("Too many filters"));
+        }
+
+        let mut block_start = read_data(&mut inp) as usize;
+        if first_byte & 0x40 != 0 {
+            block_start += 258;
+        }
+
+        let block_length = if first_byte & 0x20 != 0 {
+            let length = read_data(&mut inp) as usize;
+            self.old_lengths[filt_pos] = length;
+            length
+        } else {
+            self.old_lengths[filt_pos]
+        };
+
+        let next_window = wr_ptr != unp_ptr && (wr_ptr.wrapping_sub(unp_ptr) & mask) <= block_start;
+
+        // the block length is always passed in R4, the
+        // file offset in R6 is set when the filter is applied
+        let mut registers = [0; 7];
+        registers[4] = block_length as u32;
+
+        // optional initial values of the registers
+        if first_byte & 0x10 != 0 {
+            let init_mask = inp.getbits() >> 9;
+            inp.addbits(7);
+            for (i, register) in registers.iter_mut().enumerate() {
+                if init_mask & (1 << i) != 0 {
+                    *register = read_data(&mut inp);
+                }
+            }
+        }
+
+        if new_filter {
+            let code_size = read_data(&mut inp) as usize;
+            if code_size >= 0x10000
+                || code_size == 0
+                || inp.byte_pos() as usize + code_size > code.len()
+            {
+                return Err(data_error("Invalid filter code"));
+            }
+            let mut program = Vec::with_capacity(code_size);
+            for _ in 0..code_size {
+                program.push((inp.getbits() >> 8) as u8);
+                inp.addbits(8);
+            }
+
+            // only the standard filters are supported, because
+            // the RarVM itself isn't implemented
+            match FilterType::from_code(&program) {
+                Some(typ) => self.programs.push(typ),
+                None => {
+                    return Err(data_error(&format!(
+                        "Unknown RarVM filter with {} bytes of code",
+                        code_size
+                    )))
+                }
+            }
+        }
+
+        // optional data for the global memory, which is not used
+        // by any of the filters
+        if first_byte & 0x08 != 0 {
+            let data_size = read_data(&mut inp);
+            if data_size > VM_GLOBALSIZE - VM_FIXEDGLOBALSIZE {
+                return Err(data_error("Invalid filter data"));
+            }
+            inp.addbits(data_size * 8);
+        }
+
+        self.stack.push(Some(PendingFilter {
+            typ: self.programs[filt_pos],
+            registers,
+            block_start: (block_start + unp_ptr) & mask,
+            block_length,
+            next_window,
+        }));
+
+        Ok(())
+    }
+
+    /// Apply the filter on the data block.
+    ///
+    /// The file offset is the position of the data within the unpacked file.
+    pub fn apply(&self, filter: &PendingFilter, mut data: Vec<u8>, offset: u64) -> Result<Vec<u8>> {
+        let mut r = filter.registers;
+        r[6] = offset as u32;
+
+        let size = r[4] as usize;
+        if size > data.len() || size > VM_MEMSIZE {
+            return Err(data_error("Invalid filter block length"));
+        }
+        data.truncate(size);
+
+        let data = match filter.typ {
+            FilterType::E8 => e8e9(data, r[6], false),
+            FilterType::E8E9 => e8e9(data, r[6], true),
+            FilterType::Itanium => itanium(data, r[6]),
+            FilterType::Delta if size <= VM_MEMSIZE / 2 && r[0] > 0 && r[0] <= 1024 => {
+                delta(&data, r[0] as usize)
+            }
+            FilterType::Rgb
+                if (3..=VM_MEMSIZE / 2).contains(&size)
+                    && r[0].wrapping_sub(3) as usize <= size
+                    && r[1] <= 2 =>
+            {
+                rgb(&data, r[0] as usize - 3, r[1] as usize)
+            }
+            FilterType::Audio if size <= VM_MEMSIZE / 2 && r[0] > 0 && r[0] <= 128 => {
+                audio(&data, r[0] as usize)
+            }
+            typ => {
+                return Err(data_error(&format!(
+                    "Invalid parameters for the {:?} filter",
+                    typ
+                )))
+            }
+        };
+
+        Ok(data)
+    }
+}
+
+/// Translate the absolute addresses of x86 CALL (0xE8) and
+/// optionally JMP (0xE9) instructions back to relative ones.
+fn e8e9(mut data: Vec<u8>, file_offset: u32, e9: bool) -> Vec<u8> {
+    const FILE_SIZE: u32 = 0x0100_0000;
+    let cmp_byte2 = if e9 { 0xe9 } else { 0xe8 };
+
+    let mut pos = 0;
+    while pos + 4 < data.len() {
+        let byte = data[pos];
+        pos += 1;
+        if byte != 0xe8 && byte != cmp_byte2 {
+            continue;
+        }
+
+        let offset = (pos as u32).wrapping_add(file_offset);
+        let addr = get_u32(&data, pos);
+
+        if addr & 0x8000_0000 != 0 {
+            // negative address, which gets positive with the offset
+            if addr.wrapping_add(offset) & 0x8000_0000 == 0 {
+                set_u32(&mut data, pos, addr.wrapping_add(FILE_SIZE));
+            }
+        } else if addr.wrapping_sub(FILE_SIZE) & 0x8000_0000 != 0 {
+            // positive address within the file size
+            set_u32(&mut data, pos, addr.wrapping_sub(offset));
+        }
+        pos += 4;
+    }
+
+    data
+}
+
+/// Translate the absolute addresses of IA-64 branch instructions back to relative ones.
+///
+/// The instructions are grouped in bundles of 16 bytes with up to 3 instructions.
+fn itanium(mut data: Vec<u8>, file_offset: u32) -> Vec<u8> {
+    // instruction slots which can contain a branch for every bundle template
+    static MASKS: [u8; 16] = [4, 4, 6, 6, 0, 0, 7, 7, 4, 4, 0, 0, 4, 4, 0, 0];
+
+    let mut file_offset = file_offset >> 4;
+    let mut pos = 0;
+    while pos + 21 < data.len() {
+        let template = data[pos] & 0x1f;
+        if template >= 0x10 {
+            let cmd_mask = MASKS[(template - 0x10) as usize];
+            for i in 0..3 {
+                if cmd_mask & (1 << i) == 0 {
+                    continue;
+                }
+
+                let start_pos = pos * 8 + i * 41 + 5;
+                let op_type = get_bits(&data, start_pos + 37, 4);
+                if op_type == 5 {
+                    let offset = get_bits(&data, start_pos + 13, 20);
+                    let offset = offset.wrapping_sub(file_offset) & 0xfffff;
+                    set_bits(&mut data, offset, start_pos + 13, 20);
+                }
+            }
+        }
+        pos += 16;
+        file_offset = file_offset.wrapping_add(1);
+    }
+
+    data
+}
+
+/// Restore 24 bit RGB image data with the given line width in bytes.
+///
+/// Every byte is predicted by the left, upper and upper left byte of the
+/// same color and the green color was subtracted from red and blue.
+fn rgb(data: &[u8], width: usize, pos_r: usize) -> Vec<u8> {
+    let mut out = vec![0u8; data.len()];
+    let mut src = data.iter();
+
+    for channel in 0..3 {
+        let mut prev = 0u8;
+        for i in (channel..data.len()).step_by(3) {
+            let predicted = if i >= width + 3 {
+                let upper = i32::from(out[i - width]);
+                let upper_left = i32::from(out[i - width - 3]);
+                let left = i32::from(prev);
+
+                // paeth predictor
+                let predicted = left + upper - upper_left;
+                let pa = (predicted - left).abs();
+                let pb = (predicted - upper).abs();
+                let pc = (predicted - upper_left).abs();
+                if pa <= pb && pa <= pc {
+                    prev
+                } else if pb <= pc {
+                    upper as u8
+                } else {
+                    upper_left as u8
+                }
+            } else {
+                prev
+            };
+
+            prev = predicted.wrapping_sub(*src.next().unwrap_or(&0));
+            out[i] = prev;
+        }
+    }
+
+    let mut i = pos_r;
+    while i + 2 < data.len() {
+        let green = out[i + 1];
+        out[i] = out[i].wrapping_add(green);
+        out[i + 2] = out[i + 2].wrapping_add(green);
+        i += 3;
+    }
+
+    out
+}
+
+/// Restore audio data with the given number of channels.
+///
+/// Every sample is predicted from the previous ones with weights,
+/// which adapt to the data.
+fn audio(data: &[u8], channels: usize) -> Vec<u8> {
+    let mut out = vec![0u8; data.len()];
+    let mut src = data.iter();
+
+    for channel in 0..channels {
+        let mut prev_byte = 0u8;
+        let mut prev_delta = 0i32;
+        let mut dif = [0u32; 7];
+        let (mut d1, mut d2) = (0i32, 0i32);
+        let (mut k1, mut k2, mut k3) = (0i32, 0i32, 0i32);
+
+        for (byte_count, i) in (channel..data.len()).step_by(channels).enumerate() {
+            let d3 = d2;
+            d2 = prev_delta.wrapping_sub(d1);
+            d1 = prev_delta;
+
+            let predicted = (8 * i32::from(prev_byte))
+                .wrapping_add(k1.wrapping_mul(d1))
+                .wrapping_add(k2.wrapping_mul(d2))
+                .wrapping_add(k3.wrapping_mul(d3));
+            let predicted = ((predicted as u32 >> 3) & 0xff) as u8;
+
+            let cur_byte = *src.next().unwrap_or(&0);
+            let value = predicted.wrapping_sub(cur_byte);
+            out[i] = value;
+            prev_delta = i32::from(value.wrapping_sub(prev_byte) as i8);
+            prev_byte = value;
+
+            let d = i32::from(cur_byte as i8) << 3;
+            for (j, diff) in [0, d1, -d1, d2, -d2, d3, -d3].iter().enumerate() {
+                dif[j] = dif[j].wrapping_add((d - diff).unsigned_abs());
+            }
+
+            // adapt the weights to the predictor with the least error
+            if byte_count & 0x1f == 0 {
+                let mut min_dif = dif[0];
+                let mut num_min_dif = 0;
+                dif[0] = 0;
+                for (j, dif) in dif.iter_mut().enumerate().skip(1) {
+                    if *dif < min_dif {
+                        min_dif = *dif;
+                        num_min_dif = j;
+                    }
+                    *dif = 0;
+                }
+
+                match num_min_dif {
+                    1 if k1 >= -16 => k1 -= 1,
+                    2 if k1 < 16 => k1 += 1,
+                    3 if k2 >= -16 => k2 -= 1,
+                    4 if k2 < 16 => k2 += 1,
+                    5 if k3 >= -16 => k3 -= 1,
+                    6 if k3 < 16 => k3 += 1,
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    out
+}
+
+/// Read a little endian u32 at the given position
+fn get_u32(data: &[u8], pos: usize) -> u32 {
+    u32::from(data[pos])
+        | u32::from(data[pos + 1]) << 8
+        | u32::from(data[pos + 2]) << 16
+        | u32::from(data[pos + 3]) << 24
+}
+
+/// Write a little endian u32 at the given position
+fn set_u32(data: &mut [u8], pos: usize, value: u32) {
+    data[pos..pos + 4].copy_from_slice(&[
+        value as u8,
+        (value >> 8) as u8,
+        (value >> 16) as u8,
+        (value >> 24) as u8,
+    ]);
+}
+
+/// Read up to 24 bits at the given bit position
+fn get_bits(data: &[u8], bit_pos: usize, bit_count: u32) -> u32 {
+    (get_u32(data, bit_pos / 8) >> (bit_pos & 7)) & (0xffff_ffff >> (32 - bit_count))
+}
+
+/// Write up to 24 bits at the given bit position
+fn set_bits(data: &mut [u8], value: u32, bit_pos: usize, bit_count: u32) {
+    let pos = bit_pos / 8;
+    let shift = bit_pos & 7;
+    let mask = !((0xffff_ffff >> (32 - bit_count)) << shift);
+    let value = (get_u32(data, pos) & mask) | (value << shift);
+    set_u32(data, pos, value);
+}
+
+#[test]
+fn test_filter_type_from_code() {
+    // the first byte doesn't match the checksum
+    assert_eq!(FilterType::from_code(&[0x00, 0x01, 0x02]), None);
+    // valid checksum, but no standard filter
+    assert_eq!(FilterType::from_code(&[0x03, 0x01, 0x02]), None);
+}
+
+#[test]
+fn test_filter_apply() {
+    let mut filters = Filters::default();
+    let mut filter = PendingFilter {
+        typ: FilterType::Delta,
+        registers: [2, 0, 0, 0, 6, 0, 0],
+        block_start: 0,
+        block_length: 6,
+        next_window: false,
+    };
+
+    // two channels with the values 1, 2, 3 and 10, 20, 30 stored as negative deltas
+    let data = vec![0xFF, 0xFF, 0xFF, 0xF6, 0xF6, 0xF6];
+    assert_eq!(
+        filters.apply(&filter, data.clone(), 0).unwrap(),
+        vec![1, 10, 2, 20, 3, 30]
+    );
+
+    // the number of channels must be given
+    filter.registers[0] = 0;
+    assert!(filters.apply(&filter, data.clone(), 0).is_err());
+
+    // the block can't be bigger than the data
+    filter.registers = [2, 0, 0, 0, 7, 0, 0];
+    assert!(filters.apply(&filter, data, 0).is_err());
+
+    // unknown filters are rejected when they are defined,
+    // here the block start 0 and the 3 bytes of code 0x03, 0x01, 0x02
+    let code = [0x00, 0x30, 0x30, 0x10, 0x20];
+    let err = filters.add(0x00, &code, 0, 0, 0xffff).unwrap_err();
+    assert_eq!(err.to_string(), "Unknown RarVM filter with 3 bytes of code");
+}
+
+#[test]
+fn test_filter_e8() {
+    // a call at file offset 0x1000 to the absolute address 0x1105
+    let data = vec![
+        0x90, 0xE8, 0x05, 0x11, 0x00, 0x00, 0x90, 0xE9, 0x05, 0x11, 0x00, 0x00,
+    ];
+    assert_eq!(
+        e8e9(data.clone(), 0x1000, false),
+        vec![0x90, 0xE8, 0x03, 0x01, 0x00, 0x00, 0x90, 0xE9, 0x05, 0x11, 0x00, 0x00]
+    );
+
+    // the jump is only translated with the E8E9 filter
+    assert_eq!(
+        e8e9(data, 0x1000, true),
+        vec![0x90, 0xE8, 0x03, 0x01, 0x00, 0x00, 0x90, 0xE9, 0xFD, 0x00, 0x00, 0x00]
+    );
+}
+
+#[test]
+fn test_filter_itanium() {
+    // bundle with a branch in the third slot to the absolute bundle 0x123
+    let mut data = vec![0u8; 32];
+    data[0] = 0x10;
+    data[12] = 0x30;
+    data[13] = 0x12;
+    data[15] = 0x50;
+
+    // the bundle is at the file offset 0x100, which is the bundle 0x10
+    let data = itanium(data, 0x100);
+    assert_eq!(data[12], 0x30);
+    assert_eq!(data[13], 0x11);
+    assert_eq!(data[15], 0x50);
+}
+
+#[test]
+fn test_filter_rgb() {
+    // 4 lines with one pixel, the bytes of every color are stored together
+    let data = [
+        0xF6, 0xF6, 0xF6, 0xFB, 0xFB, 0xFB, 0xF6, 0xF6, 0xF6, 0xFE, 0xFE, 0xFE,
+    ];
+    assert_eq!(
+        rgb(&data, 3, 0),
+        vec![15, 5, 15, 30, 10, 22, 50, 20, 34, 65, 30, 46]
+    );
+}
+
+#[test]
+fn test_filter_audio() {
+    // the weights start with 0, so the first samples are simple deltas
+    let data = [0xFF, 0xFF, 0xFE, 0xF6, 0xF6, 0xEC];
+    assert_eq!(audio(&data, 2), vec![1, 10, 2, 20, 4, 40]);
+}
+
+#[test]
+fn test_filter_add_standard() {
+    // a program with the fingerprint of the standard E8 filter: 53 bytes with the
+    // CRC32 0xAD576887 and the XOR of the code in front. The WinRAR byte code isn't
+    // needed, its last bytes are chosen to give the same length, CRC32 and XOR.
+    let program = b"%E8 fingerprint, not the WinRAR byte code E8 fin\x02\x1E\xF2\xFC\x00";
+    assert_eq!(FilterType::from_code(program), Some(FilterType::E8));
+
+    // the block start 0 and length 12, followed by the 53 bytes of code
+    let code = [
+        0x00, 0xC4, 0xD4, 0x95, 0x14, 0xE0, 0x81, 0x99, 0xA5, 0xB9, 0x9D, 0x95, 0xC9, 0xC1, 0xC9,
+        0xA5, 0xB9, 0xD0, 0xB0, 0x81, 0xB9, 0xBD, 0xD0, 0x81, 0xD1, 0xA1, 0x94, 0x81, 0x5D, 0xA5,
+        0xB9, 0x49, 0x05, 0x48, 0x81, 0x89, 0xE5, 0xD1, 0x94, 0x81, 0x8D, 0xBD, 0x91, 0x94, 0x81,
+        0x14, 0xE0, 0x81, 0x99, 0xA5, 0xB8, 0x08, 0x7B, 0xCB, 0xF0, 0x00,
+    ];
+    let mut filters = Filters::default();
+    filters.add(0x20, &code, 0, 0, 0xffff).unwrap();
+    let filter = filters.stack[0].take().unwrap();
+    assert_eq!(filter.typ, FilterType::E8);
+    assert_eq!(filter.block_length, 12);
+
+    // the call is translated like in test_filter_e8, the jump isn't
+    let data = vec![
+        0x90, 0xE8, 0x05, 0x11, 0x00, 0x00, 0x90, 0xE9, 0x05, 0x11, 0x00, 0x00,
+    ];
+    assert_eq!(
+        filters.apply(&filter, data, 0x1000).unwrap(),
+        vec![0x90, 0xE8, 0x03, 0x01, 0x00, 0x00, 0x90, 0xE9, 0x05, 0x11, 0x00, 0x00]
+    );
+
+    // the program is used again by its number 1, with the block start 16 and length 24
+    filters
+        .add(0xA0, &[0x05, 0x10, 0x46, 0x00], 0, 0, 0xffff)
+        .unwrap();
+    let filter = filters.stack.pop().unwrap().unwrap();
+    assert_eq!(filter.typ, FilterType::E8);
+    assert_eq!((filter.block_start, filter.block_length), (16, 24));
+}
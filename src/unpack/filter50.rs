@@ -0,0 +1,283 @@
+use std::io::Result;
+use unpack::bit_reader::BitReader;
+use unpack::data_error;
+
+/// Maximum size of the data a single filter can process
+const MAX_FILTER_BLOCK_SIZE: usize = 0x0040_0000;
+
+/// Types of the RAR5 post-processing filters
+#[derive(PartialEq, Debug, Clone)]
+pub enum FilterType {
+    /// Byte wise delta coding with the given number of channels
+    Delta(usize),
+    /// x86 CALL instructions
+    E8,
+    /// x86 CALL and JMP instructions
+    E8E9,
+    /// ARM BL instructions
+    Arm,
+}
+
+/// A filter which needs to be applied on a block of the unpacked data
+#[derive(PartialEq, Debug, Clone)]
+pub struct Filter {
+    pub typ: FilterType,
+    /// Start of the filtered block, relative to the position
+    /// of the filter within the unpacked data
+    pub block_start: usize,
+    /// Length of the filtered block
+    pub block_length: usize,
+}
+
+impl Filter {
+    /// Read a filter definition from the packed data,
+    /// an unknown filter type is a data error
+    pub fn read(inp: &mut BitReader) -> Result<Filter> {
+        let block_start = read_filter_data(inp) as usize;
+        let mut block_length = read_filter_data(inp) as usize;
+        if block_length > MAX_FILTER_BLOCK_SIZE {
+            block_length = 0;
+        }
+
+        let typ = inp.getbits() >> 13;
+        inp.addbits(3);
+
+        let typ = match typ {
+            0 => {
+                let channels = (inp.getbits() >> 11) + 1;
+                inp.addbits(5);
+                FilterType::Delta(channels as usize)
+            }
+            1 => FilterType::E8,
+            2 => FilterType::E8E9,
+            3 => FilterType::Arm,
+            _ => return Err(data_error("Unknown filter type")),
+        };
+
+        Ok(Filter {
+            typ,
+            block_start,
+            block_length,
+        })
+    }
+
+    /// Apply the filter on the data.
+    ///
+    /// The file offset is the position of the data within the unpacked file,
+    /// which is needed to translate the relative addresses back.
+    pub fn apply(&self, data: Vec<u8>, file_offset: u64) -> Vec<u8> {
+        match self.typ {
+            FilterType::Delta(channels) => delta(&data, channels),
+            FilterType::E8 => e8e9(data, file_offset, false),
+            FilterType::E8E9 => e8e9(data, file_offset, true),
+            FilterType::Arm => arm(data, file_offset),
+        }
+    }
+}
+
+/// Read a number with a variable byte count from the filter definition
+fn read_filter_data(inp: &mut BitReader) -> u32 {
+    let byte_count = (inp.getbits() >> 14) + 1;
+    inp.addbits(2);
+
+    let mut data = 0;
+    for i in 0..byte_count {
+        data += (inp.getbits() >> 8) << (i * 8);
+        inp.addbits(8);
+    }
+    data
+}
+
+/// Restore the delta coded data.
+///
+/// The bytes of every channel are stored grouped together, so they
+/// need to be placed back to their interleaving positions.
+pub fn delta(data: &[u8], channels: usize) -> Vec<u8> {
+    let mut out = vec![0u8; data.len()];
+    let mut src = data.iter();
+
+    for channel in 0..channels {
+        let mut prev = 0u8;
+        for dest in out.iter_mut().skip(channel).step_by(channels) {
+            prev = prev.wrapping_sub(*src.next().unwrap_or(&0));
+            *dest = prev;
+        }
+    }
+
+    out
+}
+
+/// Translate the absolute addresses of x86 CALL (0xE8) and
+/// optionally JMP (0xE9) instructions back to relative ones.
+fn e8e9(mut data: Vec<u8>, file_offset: u64, e9: bool) -> Vec<u8> {
+    const FILE_SIZE: u32 = 0x0100_0000;
+    let cmp_byte2 = if e9 { 0xe9 } else { 0xe8 };
+
+    let mut pos = 0;
+    while pos + 4 < data.len() {
+        let byte = data[pos];
+        pos += 1;
+        if byte != 0xe8 && byte != cmp_byte2 {
+            continue;
+        }
+
+        let offset = ((pos as u64 + file_offset) % FILE_SIZE as u64) as u32;
+        let addr = u32::from(data[pos])
+            | u32::from(data[pos + 1]) << 8
+            | u32::from(data[pos + 2]) << 16
+            | u32::from(data[pos + 3]) << 24;
+
+        let new_addr = if addr & 0x8000_0000 != 0 {
+            // negative address, which gets positive with the offset
+            if addr.wrapping_add(offset) & 0x8000_0000 == 0 {
+                Some(addr.wrapping_add(FILE_SIZE))
+            } else {
+                None
+            }
+        } else if addr.wrapping_sub(FILE_SIZE) & 0x8000_0000 != 0 {
+            // positive address within the file size
+            Some(addr.wrapping_sub(offset))
+        } else {
+            None
+        };
+
+        if let Some(a) = new_addr {
+            data[pos..pos + 4].copy_from_slice(&[
+                a as u8,
+                (a >> 8) as u8,
+                (a >> 16) as u8,
+                (a >> 24) as u8,
+            ]);
+        }
+        pos += 4;
+    }
+
+    data
+}
+
+/// Translate the absolute addresses of ARM BL instructions
+/// back to relative ones.
+fn arm(mut data: Vec<u8>, file_offset: u64) -> Vec<u8> {
+    let mut pos = 0;
+    while pos + 3 < data.len() {
+        // BL command with the always condition
+        if data[pos + 3] == 0xeb {
+            let offset = u32::from(data[pos])
+                | u32::from(data[pos + 1]) << 8
+                | u32::from(data[pos + 2]) << 16;
+            let offset = offset.wrapping_sub(((file_offset + pos as u64) / 4) as u32);
+            data[pos] = offset as u8;
+            data[pos + 1] = (offset >> 8) as u8;
+            data[pos + 2] = (offset >> 16) as u8;
+        }
+        pos += 4;
+    }
+
+    data
+}
+
+#[test]
+fn test_filter_read() {
+    // start 0x10 in 1 byte, length 0x0100 in 2 bytes, delta with 3 channels
+    let data = [
+        0b0000_0100,
+        0b0001_0000,
+        0b0000_0000,
+        0b0001_0000,
+        0b0010_0000,
+    ];
+    let mut inp = BitReader::new(&data[..]);
+    inp.fill().unwrap();
+
+    assert_eq!(
+        Filter::read(&mut inp).unwrap(),
+        Filter {
+            typ: FilterType::Delta(3),
+            block_start: 0x10,
+            block_length: 0x0100,
+        }
+    );
+    assert_eq!(inp.byte_pos(), 4);
+    assert_eq!(inp.bit_pos(), 4);
+
+    // start 0x00, length 0x08 and ARM
+    let data = [0b0000_0000, 0b0000_0000, 0b1000_0110];
+    let mut inp = BitReader::new(&data[..]);
+    inp.fill().unwrap();
+
+    assert_eq!(
+        Filter::read(&mut inp).unwrap(),
+        Filter {
+            typ: FilterType::Arm,
+            block_start: 0,
+            block_length: 8,
+        }
+    );
+
+    // an unknown filter type
+    let data = [0b0000_0000, 0b0000_0000, 0b1000_1110];
+    let mut inp = BitReader::new(&data[..]);
+    inp.fill().unwrap();
+    assert!(Filter::read(&mut inp).is_err());
+}
+
+#[test]
+fn test_filter_delta() {
+    // two channels with the values 1, 2, 3 and 10, 20, 30 stored as negative deltas
+    let data = vec![0xFF, 0xFF, 0xFF, 0xF6, 0xF6, 0xF6];
+    let filter = Filter {
+        typ: FilterType::Delta(2),
+        block_start: 0,
+        block_length: data.len(),
+    };
+
+    assert_eq!(filter.apply(data, 0), vec![1, 10, 2, 20, 3, 30]);
+}
+
+#[test]
+fn test_filter_e8() {
+    // a call at file offset 0x1000 to the absolute address 0x1105
+    let data = vec![
+        0x90, 0xE8, 0x05, 0x11, 0x00, 0x00, 0x90, 0xE9, 0x05, 0x11, 0x00, 0x00,
+    ];
+    let mut filter = Filter {
+        typ: FilterType::E8,
+        block_start: 0,
+        block_length: data.len(),
+    };
+
+    assert_eq!(
+        filter.apply(data.clone(), 0x1000),
+        vec![0x90, 0xE8, 0x03, 0x01, 0x00, 0x00, 0x90, 0xE9, 0x05, 0x11, 0x00, 0x00]
+    );
+
+    // the jump is only translated with the E8E9 filter
+    filter.typ = FilterType::E8E9;
+    assert_eq!(
+        filter.apply(data, 0x1000),
+        vec![0x90, 0xE8, 0x03, 0x01, 0x00, 0x00, 0x90, 0xE9, 0xFD, 0x00, 0x00, 0x00]
+    );
+
+    // a negative address which gets positive with the offset
+    let data = vec![0xE8, 0xFF, 0xFF, 0xFF, 0xFF, 0x00];
+    assert_eq!(
+        filter.apply(data, 0x10),
+        vec![0xE8, 0xFF, 0xFF, 0xFF, 0x00, 0x00]
+    );
+}
+
+#[test]
+fn test_filter_arm() {
+    // BL instruction at file offset 0x100 to the absolute word address 0x50
+    let data = vec![0x50, 0x00, 0x00, 0xEB, 0x50, 0x00, 0x00, 0xEA];
+    let filter = Filter {
+        typ: FilterType::Arm,
+        block_start: 0,
+        block_length: data.len(),
+    };
+
+    assert_eq!(
+        filter.apply(data, 0x100),
+        vec![0x10, 0x00, 0x00, 0xEB, 0x50, 0x00, 0x00, 0xEA]
+    );
+}
@@ -0,0 +1,91 @@
+use unpack::bit_reader::BitReader;
+
+/// Canonical Huffman decoding table as used by all RAR decoders.
+///
+/// The table is build from the bit length of every symbol. Codes are
+/// at most 15 bits long.
+#[derive(Clone, Default)]
+pub struct HuffmanTable {
+    /// Left aligned upper limit code for every bit length
+    decode_len: [u32; 16],
+    /// Start position in the code list for every bit length
+    decode_pos: [u32; 16],
+    /// Symbols sorted by their code
+    decode_num: Vec<u16>,
+}
+
+impl HuffmanTable {
+    /// Create a new decoding table from the bit length of every symbol
+    pub fn new(lengths: &[u8]) -> HuffmanTable {
+        // count how many symbols we have for every bit length
+        let mut length_count = [0u32; 16];
+        for l in lengths {
+            length_count[(*l & 0x0f) as usize] += 1;
+        }
+        // zero length codes don't exist
+        length_count[0] = 0;
+
+        let mut table = HuffmanTable {
+            decode_len: [0; 16],
+            decode_pos: [0; 16],
+            decode_num: vec![0; lengths.len()],
+        };
+
+        // calculate the upper limit and start position for every bit length
+        let mut upper_limit = 0;
+        for i in 1..16 {
+            upper_limit += length_count[i];
+            table.decode_len[i] = upper_limit << (16 - i);
+            upper_limit *= 2;
+            table.decode_pos[i] = table.decode_pos[i - 1] + length_count[i - 1];
+        }
+
+        // sort the symbols into the code list
+        let mut copy_pos = table.decode_pos;
+        for (i, l) in lengths.iter().enumerate() {
+            let l = (*l & 0x0f) as usize;
+            if l != 0 {
+                table.decode_num[copy_pos[l] as usize] = i as u16;
+                copy_pos[l] += 1;
+            }
+        }
+
+        table
+    }
+
+    /// Decode the next symbol from the bit reader
+    pub fn decode(&self, inp: &mut BitReader) -> u32 {
+        // left aligned 15 bit length raw bit field
+        let bit_field = inp.getbits() & 0xfffe;
+
+        // detect the real bit length of the current code
+        let bits = (1..15)
+            .find(|i| bit_field < self.decode_len[*i])
+            .unwrap_or(15);
+        inp.addbits(bits as u32);
+
+        // calculate the position of the code within the code list
+        let dist = bit_field.wrapping_sub(self.decode_len[bits - 1]) >> (16 - bits);
+        let pos = self.decode_pos[bits].wrapping_add(dist) as usize;
+
+        // out of bounds safety check required for damaged archives
+        self.decode_num.get(pos).map(|n| *n as u32).unwrap_or(0)
+    }
+}
+
+#[test]
+fn test_huffman_table() {
+    // A = 0, B = 10, C = 110, D = 111
+    let table = HuffmanTable::new(&[1, 2, 3, 3]);
+    let data = [0b0101_1011, 0b1000_0000];
+    let mut inp = BitReader::new(&data[..]);
+    inp.fill().unwrap();
+
+    assert_eq!(table.decode(&mut inp), 0);
+    assert_eq!(table.decode(&mut inp), 1);
+    assert_eq!(table.decode(&mut inp), 2);
+    assert_eq!(table.decode(&mut inp), 3);
+    assert_eq!(table.decode(&mut inp), 0);
+    assert_eq!(inp.byte_pos(), 1);
+    assert_eq!(inp.bit_pos(), 2);
+}
@@ -0,0 +1,210 @@
+use failure::Error;
+use file_block::{CompressionFlags, FileBlock};
+use std::io;
+use std::io::Read;
+
+mod bit_reader;
+mod filter29;
+mod filter50;
+mod huffman;
+mod ppm;
+mod v15;
+mod v20;
+mod v29;
+mod v50;
+
+pub use self::bit_reader::BitReader;
+
+/// Trait which needs to be implemented by every RAR decoder.
+///
+/// A decoder holds the complete state which is needed to unpack
+/// the data of a file, like the sliding window or the Huffman tables.
+pub trait Unpack {
+    /// Prepare the decoder for the next file with the given unpacked size.
+    /// Without the solid flag the decoder starts from a clean state.
+    ///
+    /// Only the formats without an end marker need to know the size.
+    fn init(&mut self, solid: bool, size: u64);
+
+    /// Decode the next chunk of data and append the unpacked bytes to `out`.
+    /// Returns true when the data of the file is completly unpacked.
+    fn decode(&mut self, inp: &mut BitReader, out: &mut Vec<u8>) -> io::Result<bool>;
+}
+
+/// The decoder state of an archive.
+///
+/// In solid archives the files are packed as one continuous stream,
+/// so the dictonary and the tables of the decoder need to be kept
+/// from one file to the next.
+pub struct UnpackState {
+    /// The archive is solid
+    solid: bool,
+    /// Decoder of the last unpacked file
+    unpacker: Option<Box<dyn Unpack>>,
+    /// Name of the packed file, whose data was skipped without unpacking
+    skipped: Option<String>,
+}
+
+impl UnpackState {
+    /// Create a new state for an archive
+    pub fn new(solid: bool) -> UnpackState {
+        UnpackState {
+            solid,
+            unpacker: None,
+            skipped: None,
+        }
+    }
+
+    /// Returns true when the archive is solid
+    pub fn is_solid(&self) -> bool {
+        self.solid
+    }
+
+    /// Returns the decoder for the given file, which is ready to unpack
+    /// the data. Files which are not solid always get a fresh decoder.
+    pub fn unpacker(&mut self, file: &FileBlock) -> Result<&mut dyn Unpack, Error> {
+        if file.compression.solid {
+            if let Some(ref skipped) = self.skipped {
+                return Err(format_err!(
+                    "Solid file {} can't be unpacked, because the data of {} was skipped",
+                    file.name,
+                    skipped
+                ));
+            }
+        }
+
+        let solid = file.compression.solid && self.unpacker.is_some();
+        if !solid {
+            self.skipped = None;
+            self.unpacker = Some(new_unpacker(file, self.solid)?);
+        }
+
+        let unpacker = self.unpacker.as_mut().unwrap();
+        unpacker.init(solid, file.unpacked_size);
+        Ok(&mut **unpacker)
+    }
+
+    /// Marks the data of the file as skipped without unpacking it.
+    /// In solid archives the decoder misses the data of this file,
+    /// so the following solid files can't be unpacked anymore.
+    pub fn skip(&mut self, file: &FileBlock) {
+        if self.solid && file.compression.flag != CompressionFlags::Save {
+            self.skipped = Some(file.name.clone());
+        }
+    }
+}
+
+/// Create the decoder which fits to the compression of the given file
+fn new_unpacker(file: &FileBlock, solid: bool) -> Result<Box<dyn Unpack>, Error> {
+    if file.compression.flag == CompressionFlags::Save
+        || file.compression.flag == CompressionFlags::Unknown
+    {
+        return Err(format_err!(
+            "Compression {:?} can't be unpacked",
+            file.compression.flag
+        ));
+    }
+
+    match file.compression.version {
+        0 => Ok(Box::new(v50::Unpack50::new(window_size(file, solid)))),
+        15 => Ok(Box::new(v15::Unpack15::new(window_size(file, solid)))),
+        20 | 26 => Ok(Box::new(v20::Unpack20::new(window_size(file, solid)))),
+        29 | 36 => Ok(Box::new(v29::Unpack29::new(window_size(file, solid)))),
+        v => Err(format_err!("Unsupported compression version {}", v)),
+    }
+}
+
+/// Calculate the sliding window size for the given file.
+///
+/// The dictonary size is the upper limit, but for files of non solid
+/// archives a window bigger than the file itself is never needed.
+fn window_size(file: &FileBlock, solid: bool) -> usize {
+    let dictonary = file.compression.get_directonary() as u64 * 1024;
+
+    if solid || file.compression.solid || file.flags.unknown_size {
+        return dictonary as usize;
+    }
+
+    // huge sizes of damaged headers must not overflow when rounded up
+    let needed = file.unpacked_size.min(dictonary).next_power_of_two();
+    dictonary.min(needed.max(0x40000)) as usize
+}
+
+/// Reader which unpacks the data of a file on the fly.
+pub struct UnpackReader<'a, 'b> {
+    /// Packed input data
+    input: BitReader<'a>,
+    /// Decoder to unpack the data with
+    unpacker: &'b mut dyn Unpack,
+    /// Unpacked data which wasn't read yet
+    buffer: Vec<u8>,
+    /// Read position within the buffer
+    pos: usize,
+    /// Indicates wheather the decoder has finished the file
+    finished: bool,
+}
+
+impl<'a, 'b> UnpackReader<'a, 'b> {
+    /// Create a new reader which unpacks the data from the reader
+    /// with the given decoder.
+    pub fn new<R: Read + 'a>(reader: R, unpacker: &'b mut dyn Unpack) -> UnpackReader<'a, 'b> {
+        UnpackReader {
+            input: BitReader::new(reader),
+            unpacker,
+            buffer: Vec::new(),
+            pos: 0,
+            finished: false,
+        }
+    }
+}
+
+impl<'a, 'b> Read for UnpackReader<'a, 'b> {
+    /// Reads unpacked data into the passed buffer
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        // decode new data, when everything was read already
+        while self.pos >= self.buffer.len() {
+            if self.finished {
+                return Ok(0);
+            }
+
+            self.buffer.clear();
+            self.pos = 0;
+            self.finished = self.unpacker.decode(&mut self.input, &mut self.buffer)?;
+        }
+
+        let len = buf.len().min(self.buffer.len() - self.pos);
+        buf[..len].copy_from_slice(&self.buffer[self.pos..self.pos + len]);
+        self.pos += len;
+        Ok(len)
+    }
+}
+
+/// Create an error for damaged or unsupported packed data
+fn data_error(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[test]
+fn test_window_size() {
+    use file_block::Compression;
+
+    let mut file = FileBlock {
+        compression: Compression {
+            version: 29,
+            dictonary: 6,
+            ..Compression::default()
+        },
+        ..FileBlock::default()
+    };
+
+    // small files get at least 256 KB
+    file.unpacked_size = 1000;
+    assert_eq!(window_size(&file, false), 0x40000);
+    file.unpacked_size = 0x30_0000;
+    assert_eq!(window_size(&file, false), 0x40_0000);
+
+    // the dictionary is the upper limit, also for sizes which can't be rounded up
+    file.unpacked_size = u64::MAX;
+    assert_eq!(window_size(&file, false), 0x40_0000);
+    assert_eq!(window_size(&file, true), 0x40_0000);
+}
@@ -0,0 +1,1254 @@
+use std::io::Result;
+use unpack::bit_reader::BitReader;
+use unpack::data_error;
+
+/// Maximum order of the PPM model
+const MAX_O: usize = 64;
+/// Number of bits for the integer part of the binary probabilities
+const INT_BITS: u32 = 7;
+/// Number of bits for the adaption period
+const PERIOD_BITS: u32 = 7;
+/// Total scale of the binary probabilities
+const BIN_SCALE: u32 = 1 << (INT_BITS + PERIOD_BITS);
+/// Maximum frequency of a symbol before the context gets rescaled
+const MAX_FREQ: u32 = 124;
+/// Size of one allocation unit of the model memory
+const UNIT_SIZE: u32 = 12;
+/// Number of free lists of the memory allocator
+const N_INDEXES: usize = 38;
+/// Stamp which marks free memory blocks while they are glued
+const FREE_STAMP: u32 = 0xFFFF;
+
+/// Upper border for the range decoder normalization
+const TOP: u32 = 1 << 24;
+/// Lower border for the range decoder normalization
+const BOT: u32 = 1 << 15;
+
+static EXP_ESCAPE: [u8; 16] = [25, 14, 9, 7, 5, 5, 4, 4, 4, 3, 3, 3, 2, 2, 2, 2];
+static INIT_BIN_ESC: [u16; 8] = [
+    0x3CDD, 0x1F3F, 0x59BF, 0x48F3, 0x64A1, 0x5ABC, 0x6632, 0x6051,
+];
+
+/// Read the next byte of the packed data
+fn read_byte(inp: &mut BitReader) -> Result<u8> {
+    inp.fill()?;
+    Ok(inp.get_byte())
+}
+
+/// Carryless range decoder as used by the RAR PPM blocks.
+///
+/// The bytes are taken from the same bit reader as the LZ blocks,
+/// so both block types can follow each other within one file.
+#[derive(Default)]
+struct RangeDecoder {
+    low: u32,
+    code: u32,
+    range: u32,
+}
+
+impl RangeDecoder {
+    /// Start decoding at the current position of the reader
+    fn init(&mut self, inp: &mut BitReader) -> Result<()> {
+        self.low = 0;
+        self.code = 0;
+        self.range = 0xFFFF_FFFF;
+        for _ in 0..4 {
+            self.code = (self.code << 8) | read_byte(inp)? as u32;
+        }
+        Ok(())
+    }
+
+    /// Shift in new bytes until the range is big enough again
+    fn normalize(&mut self, inp: &mut BitReader) -> Result<()> {
+        loop {
+            if (self.low ^ self.low.wrapping_add(self.range)) >= TOP {
+                if self.range >= BOT {
+                    break;
+                }
+                self.range = self.low.wrapping_neg() & (BOT - 1);
+            }
+            self.code = (self.code << 8) | read_byte(inp)? as u32;
+            self.range <<= 8;
+            self.low <<= 8;
+        }
+        Ok(())
+    }
+
+    /// Returns the current count for the given total frequency
+    fn get_threshold(&mut self, total: u32) -> u32 {
+        // damaged data could shrink the range to zero
+        self.range = (self.range / total).max(1);
+        self.code / self.range
+    }
+
+    /// Remove the decoded interval from the range
+    fn decode(&mut self, start: u32, size: u32) {
+        let start = start.wrapping_mul(self.range);
+        self.low = self.low.wrapping_add(start);
+        self.code = self.code.wrapping_sub(start);
+        self.range = self.range.wrapping_mul(size);
+    }
+
+    /// Remove the lower part of a binary probability from the range
+    fn decode_bit_0(&mut self, size: u32, inp: &mut BitReader) -> Result<()> {
+        self.range = size;
+        self.normalize(inp)
+    }
+
+    /// Remove the upper part of a binary probability from the range
+    fn decode_bit_1(&mut self, size: u32) {
+        self.low = self.low.wrapping_add(size);
+        self.code = self.code.wrapping_sub(size);
+        self.range = (self.range & !(BIN_SCALE - 1)).wrapping_sub(size);
+    }
+}
+
+/// Adaptive estimation of the escape frequency
+#[derive(Clone, Copy, Default)]
+struct See {
+    summ: u16,
+    shift: u8,
+    count: u8,
+}
+
+impl See {
+    /// Initialize the context with the given start value
+    fn init(&mut self, value: u32) {
+        self.shift = (PERIOD_BITS - 4) as u8;
+        self.summ = (value << self.shift) as u16;
+        self.count = 4;
+    }
+
+    /// Returns the mean escape frequency and adapts the sum
+    fn mean(&mut self) -> u32 {
+        let r = self.summ as u32 >> self.shift;
+        self.summ = self.summ.wrapping_sub(r as u16);
+        r + (r == 0) as u32
+    }
+
+    /// Adapt the context after a symbol was found
+    fn update(&mut self) {
+        if (self.shift as u32) < PERIOD_BITS {
+            self.count = self.count.wrapping_sub(1);
+            if self.count == 0 {
+                self.summ = self.summ.wrapping_add(self.summ);
+                self.count = (3u32 << self.shift) as u8;
+                self.shift += 1;
+            }
+        }
+    }
+}
+
+/// PPMd variant H model, which decodes the PPM blocks of RAR 2.9 data.
+///
+/// All contexts and states live within one memory area and reference each
+/// other by their offset, like the original implementation does it with
+/// pointers. The layout of the records is:
+///
+/// * State (6 bytes): symbol, freq, successor (u32)
+/// * Context (12 bytes): num_stats (u16), summ_freq (u16) or the symbol
+///   and freq of the only state, stats or the successor of the only
+///   state (u32), suffix (u32)
+/// * Free block (12 bytes): stamp (u16), number of units (u16), next (u32)
+pub struct ModelPpm {
+    /// Memory of the model
+    heap: Vec<u8>,
+    /// Start of the unused raw text area
+    text: u32,
+    /// Start of the units area
+    units_start: u32,
+    /// Lower end of the unused units area
+    lo_unit: u32,
+    /// Upper end of the unused units area
+    hi_unit: u32,
+    /// Countdown until the free blocks are glued again
+    glue_count: u32,
+    /// Free lists of the memory blocks
+    free_list: [u32; N_INDEXES],
+    index2units: [u8; N_INDEXES],
+    units2index: [u8; 128],
+    ns2index: [u8; 256],
+    ns2bs_index: [u8; 256],
+    min_context: u32,
+    max_context: u32,
+    found_state: u32,
+    order_fall: u32,
+    init_esc: u32,
+    prev_success: u32,
+    max_order: u32,
+    hi_bits_flag: u32,
+    run_length: i32,
+    init_rl: i32,
+    see: [[See; 16]; 25],
+    dummy_see: See,
+    bin_summ: [[u16; 64]; 128],
+    rc: RangeDecoder,
+}
+
+impl ModelPpm {
+    /// Create a new model without any memory
+    pub fn new() -> ModelPpm {
+        let mut index2units = [0u8; N_INDEXES];
+        let mut units2index = [0u8; 128];
+        let mut k = 0;
+        for (i, units) in index2units.iter_mut().enumerate() {
+            let step = if i >= 12 { 4 } else { (i >> 2) + 1 };
+            for _ in 0..step {
+                units2index[k] = i as u8;
+                k += 1;
+            }
+            *units = k as u8;
+        }
+
+        let mut ns2bs_index = [0u8; 256];
+        ns2bs_index[1] = 2;
+        for v in ns2bs_index[2..11].iter_mut() {
+            *v = 4;
+        }
+        for v in ns2bs_index[11..].iter_mut() {
+            *v = 6;
+        }
+
+        let mut ns2index = [0u8; 256];
+        let (mut m, mut k, mut step) = (3, 1, 1);
+        for (i, v) in ns2index.iter_mut().enumerate() {
+            if i < 3 {
+                *v = i as u8;
+                continue;
+            }
+            *v = m;
+            k -= 1;
+            if k == 0 {
+                step += 1;
+                k = step;
+                m += 1;
+            }
+        }
+
+        ModelPpm {
+            heap: Vec::new(),
+            text: 0,
+            units_start: 0,
+            lo_unit: 0,
+            hi_unit: 0,
+            glue_count: 0,
+            free_list: [0; N_INDEXES],
+            index2units,
+            units2index,
+            ns2index,
+            ns2bs_index,
+            min_context: 0,
+            max_context: 0,
+            found_state: 0,
+            order_fall: 0,
+            init_esc: 0,
+            prev_success: 0,
+            max_order: 0,
+            hi_bits_flag: 0,
+            run_length: 0,
+            init_rl: 0,
+            see: [[See::default(); 16]; 25],
+            dummy_see: See::default(),
+            bin_summ: [[0; 64]; 128],
+            rc: RangeDecoder::default(),
+        }
+    }
+
+    /// Read the parameters of a PPM block and prepare the model.
+    ///
+    /// The escape char is changed, when the block defines a new one.
+    pub fn init(&mut self, inp: &mut BitReader, esc_char: &mut u8) -> Result<()> {
+        let flags = read_byte(inp)?;
+        let reset = flags & 0x20 != 0;
+
+        let max_mb = if reset {
+            read_byte(inp)? as usize
+        } else if self.heap.is_empty() {
+            return Err(data_error("PPM block without a model"));
+        } else {
+            0
+        };
+
+        if flags & 0x40 != 0 {
+            *esc_char = read_byte(inp)?;
+        }
+        self.rc.init(inp)?;
+
+        if reset {
+            let mut order = (flags as u32 & 0x1f) + 1;
+            if order > 16 {
+                order = 16 + (order - 16) * 3;
+            }
+            if order == 1 {
+                self.heap = Vec::new();
+                return Err(data_error("Invalid PPM model order"));
+            }
+
+            let size = (max_mb + 1) << 20;
+            if self.heap.len() != size {
+                self.heap = vec![0; size];
+            }
+            self.max_order = order;
+            self.restart_model();
+        }
+
+        Ok(())
+    }
+}
+
+/// Access to the records within the model memory
+impl ModelPpm {
+    fn u16_at(&self, p: u32) -> u32 {
+        let p = p as usize;
+        self.heap[p] as u32 | (self.heap[p + 1] as u32) << 8
+    }
+
+    fn set_u16(&mut self, p: u32, v: u32) {
+        let p = p as usize;
+        self.heap[p] = v as u8;
+        self.heap[p + 1] = (v >> 8) as u8;
+    }
+
+    fn u32_at(&self, p: u32) -> u32 {
+        self.u16_at(p) | self.u16_at(p + 2) << 16
+    }
+
+    fn set_u32(&mut self, p: u32, v: u32) {
+        self.set_u16(p, v);
+        self.set_u16(p + 2, v >> 16);
+    }
+
+    fn symbol(&self, s: u32) -> u32 {
+        self.heap[s as usize] as u32
+    }
+
+    fn set_symbol(&mut self, s: u32, v: u32) {
+        self.heap[s as usize] = v as u8;
+    }
+
+    fn freq(&self, s: u32) -> u32 {
+        self.heap[s as usize + 1] as u32
+    }
+
+    fn set_freq(&mut self, s: u32, v: u32) {
+        self.heap[s as usize + 1] = v as u8;
+    }
+
+    fn successor(&self, s: u32) -> u32 {
+        self.u32_at(s + 2)
+    }
+
+    fn set_successor(&mut self, s: u32, v: u32) {
+        self.set_u32(s + 2, v)
+    }
+
+    /// Copy a state record
+    fn copy_state(&mut self, dst: u32, src: u32) {
+        let src = src as usize;
+        self.heap.copy_within(src..src + 6, dst as usize);
+    }
+
+    /// Read a complete state record
+    fn get_state(&self, s: u32) -> [u8; 6] {
+        let mut state = [0u8; 6];
+        state.copy_from_slice(&self.heap[s as usize..s as usize + 6]);
+        state
+    }
+
+    /// Write a complete state record
+    fn put_state(&mut self, s: u32, state: [u8; 6]) {
+        self.heap[s as usize..s as usize + 6].copy_from_slice(&state);
+    }
+
+    /// Swap a state with the state in front of it
+    fn swap_states(&mut self, s: u32) {
+        let tmp = self.get_state(s);
+        self.copy_state(s, s - 6);
+        self.put_state(s - 6, tmp);
+    }
+
+    fn num_stats(&self, c: u32) -> u32 {
+        self.u16_at(c)
+    }
+
+    fn set_num_stats(&mut self, c: u32, v: u32) {
+        self.set_u16(c, v)
+    }
+
+    fn summ_freq(&self, c: u32) -> u32 {
+        self.u16_at(c + 2)
+    }
+
+    fn set_summ_freq(&mut self, c: u32, v: u32) {
+        self.set_u16(c + 2, v)
+    }
+
+    fn stats(&self, c: u32) -> u32 {
+        self.u32_at(c + 4)
+    }
+
+    fn set_stats(&mut self, c: u32, v: u32) {
+        self.set_u32(c + 4, v)
+    }
+
+    fn suffix(&self, c: u32) -> u32 {
+        self.u32_at(c + 8)
+    }
+
+    fn set_suffix(&mut self, c: u32, v: u32) {
+        self.set_u32(c + 8, v)
+    }
+
+    /// Returns the only state of a context with one symbol
+    fn one_state(c: u32) -> u32 {
+        c + 2
+    }
+}
+
+/// Memory allocator of the model
+impl ModelPpm {
+    fn units_to_bytes(&self, index: usize) -> u32 {
+        self.index2units[index] as u32 * UNIT_SIZE
+    }
+
+    fn insert_node(&mut self, p: u32, index: usize) {
+        let next = self.free_list[index];
+        self.set_u32(p, next);
+        self.free_list[index] = p;
+    }
+
+    fn remove_node(&mut self, index: usize) -> u32 {
+        let p = self.free_list[index];
+        self.free_list[index] = self.u32_at(p);
+        p
+    }
+
+    /// Put the unused rest of a block back into the free lists
+    fn split_block(&mut self, p: u32, old_index: usize, new_index: usize) {
+        let mut diff = self.index2units[old_index] as u32 - self.index2units[new_index] as u32;
+        let mut p = p + self.units_to_bytes(new_index);
+
+        let mut i = self.units2index[diff as usize - 1] as usize;
+        if self.index2units[i] as u32 != diff {
+            i -= 1;
+            self.insert_node(p, i);
+            p += self.units_to_bytes(i);
+            diff -= self.index2units[i] as u32;
+        }
+        let i = self.units2index[diff as usize - 1] as usize;
+        self.insert_node(p, i);
+    }
+
+    /// Merge neighbouring free blocks to bigger ones
+    fn glue_free_blocks(&mut self) {
+        if self.lo_unit != self.hi_unit {
+            self.heap[self.lo_unit as usize] = 0;
+        }
+
+        // every block is inserted at the head of the list
+        let mut list = Vec::new();
+        for i in 0..N_INDEXES {
+            while self.free_list[i] != 0 {
+                let p = self.remove_node(i);
+                self.set_u16(p, FREE_STAMP);
+                self.set_u16(p + 2, self.index2units[i] as u32);
+                list.push(p);
+            }
+        }
+        list.reverse();
+
+        for p in list.iter().cloned() {
+            if self.u16_at(p) != FREE_STAMP {
+                // the block was merged into another one already
+                continue;
+            }
+            loop {
+                let nu = self.u16_at(p + 2);
+                let p1 = p + nu * UNIT_SIZE;
+                let nu1 = self.u16_at(p1 + 2);
+                if self.u16_at(p1) != FREE_STAMP || nu + nu1 >= 0x10000 {
+                    break;
+                }
+                self.set_u16(p1, 0);
+                self.set_u16(p + 2, nu + nu1);
+            }
+        }
+
+        for mut p in list {
+            if self.u16_at(p) != FREE_STAMP {
+                continue;
+            }
+            let mut size = self.u16_at(p + 2);
+            while size > 128 {
+                self.insert_node(p, N_INDEXES - 1);
+                size -= 128;
+                p += 128 * UNIT_SIZE;
+            }
+            let mut i = self.units2index[size as usize - 1] as usize;
+            if self.index2units[i] as u32 != size {
+                i -= 1;
+                let k = size - self.index2units[i] as u32;
+                self.insert_node(p + (size - k) * UNIT_SIZE, k as usize - 1);
+            }
+            self.insert_node(p, i);
+        }
+    }
+
+    fn alloc_units_rare(&mut self, index: usize) -> Option<u32> {
+        if self.glue_count == 0 {
+            self.glue_count = 255;
+            self.glue_free_blocks();
+            if self.free_list[index] != 0 {
+                return Some(self.remove_node(index));
+            }
+        }
+
+        let mut i = index;
+        loop {
+            i += 1;
+            if i == N_INDEXES {
+                self.glue_count -= 1;
+                let bytes = self.units_to_bytes(index);
+                if self.units_start - self.text > bytes {
+                    self.units_start -= bytes;
+                    return Some(self.units_start);
+                }
+                return None;
+            }
+            if self.free_list[i] != 0 {
+                break;
+            }
+        }
+
+        let p = self.remove_node(i);
+        self.split_block(p, i, index);
+        Some(p)
+    }
+
+    fn alloc_units(&mut self, index: usize) -> Option<u32> {
+        if self.free_list[index] != 0 {
+            return Some(self.remove_node(index));
+        }
+        let bytes = self.units_to_bytes(index);
+        if self.hi_unit - self.lo_unit >= bytes {
+            let p = self.lo_unit;
+            self.lo_unit += bytes;
+            return Some(p);
+        }
+        self.alloc_units_rare(index)
+    }
+
+    fn alloc_context(&mut self) -> Option<u32> {
+        if self.hi_unit != self.lo_unit {
+            self.hi_unit -= UNIT_SIZE;
+            Some(self.hi_unit)
+        } else if self.free_list[0] != 0 {
+            Some(self.remove_node(0))
+        } else {
+            self.alloc_units_rare(0)
+        }
+    }
+}
+
+/// Update of the model
+impl ModelPpm {
+    /// Reset the model to the initial state
+    fn restart_model(&mut self) {
+        let size = self.heap.len() as u32;
+        self.free_list = [0; N_INDEXES];
+
+        self.text = 0;
+        self.hi_unit = size;
+        self.units_start = self.hi_unit - size / 8 / UNIT_SIZE * 7 * UNIT_SIZE;
+        self.lo_unit = self.units_start;
+        self.glue_count = 0;
+
+        self.order_fall = self.max_order;
+        self.init_rl = -(self.max_order.min(12) as i32) - 1;
+        self.run_length = self.init_rl;
+        self.prev_success = 0;
+
+        // the order 0 context with all 256 symbols
+        self.hi_unit -= UNIT_SIZE;
+        let mc = self.hi_unit;
+        let stats = self.lo_unit;
+        self.lo_unit += 256 / 2 * UNIT_SIZE;
+        self.min_context = mc;
+        self.max_context = mc;
+        self.found_state = stats;
+
+        self.set_num_stats(mc, 256);
+        self.set_summ_freq(mc, 256 + 1);
+        self.set_stats(mc, stats);
+        self.set_suffix(mc, 0);
+        for i in 0..256 {
+            let s = stats + i * 6;
+            self.set_symbol(s, i);
+            self.set_freq(s, 1);
+            self.set_successor(s, 0);
+        }
+
+        for (i, summ) in self.bin_summ.iter_mut().enumerate() {
+            for (k, esc) in INIT_BIN_ESC.iter().enumerate() {
+                let value = (BIN_SCALE - *esc as u32 / (i as u32 + 2)) as u16;
+                for m in (0..64).step_by(8) {
+                    summ[k + m] = value;
+                }
+            }
+        }
+
+        for (i, see) in self.see.iter_mut().enumerate() {
+            for s in see.iter_mut() {
+                s.init(5 * i as u32 + 10);
+            }
+        }
+
+        self.dummy_see.summ = 0;
+        self.dummy_see.shift = PERIOD_BITS as u8;
+        self.dummy_see.count = 64;
+    }
+
+    /// Create the contexts for the raw successor of the found state.
+    ///
+    /// Returns None when the memory is exhausted.
+    fn create_successors(&mut self) -> Option<u32> {
+        let mut c = self.min_context;
+        let up_branch = self.successor(self.found_state);
+        let fs_symbol = self.symbol(self.found_state);
+        let mut ps = [0u32; MAX_O + 1];
+        let mut num_ps = 0;
+
+        if self.order_fall != 0 {
+            ps[num_ps] = self.found_state;
+            num_ps += 1;
+        }
+
+        while self.suffix(c) != 0 {
+            c = self.suffix(c);
+            let s = if self.num_stats(c) != 1 {
+                let mut s = self.stats(c);
+                while self.symbol(s) != fs_symbol {
+                    s += 6;
+                }
+                s
+            } else {
+                ModelPpm::one_state(c)
+            };
+
+            let successor = self.successor(s);
+            if successor != up_branch {
+                c = successor;
+                if num_ps == 0 {
+                    return Some(c);
+                }
+                break;
+            }
+            if num_ps > MAX_O {
+                return None;
+            }
+            ps[num_ps] = s;
+            num_ps += 1;
+        }
+
+        // all new contexts get the next symbol of the raw text
+        let new_symbol = self.symbol(up_branch);
+        let up_branch = up_branch + 1;
+
+        let new_freq = if self.num_stats(c) == 1 {
+            self.freq(ModelPpm::one_state(c))
+        } else {
+            let mut s = self.stats(c);
+            while self.symbol(s) != new_symbol {
+                s += 6;
+            }
+            let cf = self.freq(s) - 1;
+            let s0 = self.summ_freq(c) - self.num_stats(c) - cf;
+            1 + if 2 * cf <= s0 {
+                (5 * cf > s0) as u32
+            } else {
+                (2 * cf + 3 * s0 - 1) / (2 * s0)
+            }
+        };
+
+        while num_ps > 0 {
+            let c1 = self.alloc_context()?;
+            self.set_num_stats(c1, 1);
+            let s = ModelPpm::one_state(c1);
+            self.set_symbol(s, new_symbol);
+            self.set_freq(s, new_freq);
+            self.set_successor(s, up_branch);
+            self.set_suffix(c1, c);
+
+            num_ps -= 1;
+            self.set_successor(ps[num_ps], c1);
+            c = c1;
+        }
+
+        Some(c)
+    }
+
+    /// Update the model after a symbol was decoded
+    fn update_model(&mut self) {
+        if self.try_update_model().is_none() {
+            self.restart_model();
+        }
+    }
+
+    /// Update the model, returns None when the memory is exhausted
+    fn try_update_model(&mut self) -> Option<()> {
+        let fs = self.found_state;
+        let fs_symbol = self.symbol(fs);
+        let fs_freq = self.freq(fs);
+        let mc = self.min_context;
+
+        // update the frequency in the suffix context
+        if fs_freq < MAX_FREQ / 4 && self.suffix(mc) != 0 {
+            let c = self.suffix(mc);
+            if self.num_stats(c) == 1 {
+                let s = ModelPpm::one_state(c);
+                if self.freq(s) < 32 {
+                    let freq = self.freq(s) + 1;
+                    self.set_freq(s, freq);
+                }
+            } else {
+                let mut s = self.stats(c);
+                if self.symbol(s) != fs_symbol {
+                    while self.symbol(s) != fs_symbol {
+                        s += 6;
+                    }
+                    if self.freq(s) >= self.freq(s - 6) {
+                        self.swap_states(s);
+                        s -= 6;
+                    }
+                }
+                if self.freq(s) < MAX_FREQ - 9 {
+                    let freq = self.freq(s) + 2;
+                    self.set_freq(s, freq);
+                    let summ = self.summ_freq(c) + 2;
+                    self.set_summ_freq(c, summ);
+                }
+            }
+        }
+
+        if self.order_fall == 0 {
+            let c = self.create_successors()?;
+            self.min_context = c;
+            self.max_context = c;
+            self.set_successor(fs, c);
+            return Some(());
+        }
+
+        self.set_symbol(self.text, fs_symbol);
+        self.text += 1;
+        if self.text >= self.units_start {
+            return None;
+        }
+
+        let mut max_successor = self.text;
+        let mut min_successor = self.successor(fs);
+        if min_successor == 0 {
+            self.set_successor(fs, max_successor);
+            min_successor = self.min_context;
+        } else {
+            if min_successor <= max_successor {
+                // the successor is raw text, so real contexts are created
+                min_successor = self.create_successors()?;
+            }
+            self.order_fall -= 1;
+            if self.order_fall == 0 {
+                max_successor = min_successor;
+                if self.max_context != self.min_context {
+                    self.text -= 1;
+                }
+            }
+        }
+
+        let mut c = self.max_context;
+        self.min_context = min_successor;
+        self.max_context = min_successor;
+
+        let ns = self.num_stats(mc);
+        let s0 = self.summ_freq(mc) - ns - (fs_freq - 1);
+
+        while c != mc {
+            let ns1 = self.num_stats(c);
+            let mut sum;
+            if ns1 != 1 {
+                if ns1 & 1 == 0 {
+                    // the stats are full and need one more unit
+                    let old_nu = ns1 as usize >> 1;
+                    let i = self.units2index[old_nu - 1] as usize;
+                    if i != self.units2index[old_nu] as usize {
+                        let p = self.alloc_units(i + 1)?;
+                        let old = self.stats(c) as usize;
+                        self.heap
+                            .copy_within(old..old + old_nu * UNIT_SIZE as usize, p as usize);
+                        self.insert_node(old as u32, i);
+                        self.set_stats(c, p);
+                    }
+                }
+                sum = self.summ_freq(c);
+                sum += ((2 * ns1 < ns) as u32)
+                    + 2 * (((4 * ns1 <= ns) as u32) & ((sum <= 8 * ns1) as u32));
+            } else {
+                // turn the single state context into one with stats
+                let s = self.alloc_units(0)?;
+                let one = ModelPpm::one_state(c);
+                self.copy_state(s, one);
+                self.set_stats(c, s);
+
+                let mut freq = self.freq(s);
+                if freq < MAX_FREQ / 4 - 1 {
+                    freq += freq;
+                } else {
+                    freq = MAX_FREQ - 4;
+                }
+                self.set_freq(s, freq);
+                sum = freq + self.init_esc + (ns > 3) as u32;
+            }
+
+            let mut cf = 2 * fs_freq * (sum + 6);
+            let sf = s0 + sum;
+            if cf < 6 * sf {
+                cf = 1 + (cf > sf) as u32 + (cf >= 4 * sf) as u32;
+                sum += 3;
+            } else {
+                cf = 4 + (cf >= 9 * sf) as u32 + (cf >= 12 * sf) as u32 + (cf >= 15 * sf) as u32;
+                sum += cf;
+            }
+
+            let s = self.stats(c) + ns1 * 6;
+            self.set_successor(s, max_successor);
+            self.set_symbol(s, fs_symbol);
+            self.set_freq(s, cf);
+            self.set_summ_freq(c, sum);
+            self.set_num_stats(c, ns1 + 1);
+
+            c = self.suffix(c);
+        }
+
+        Some(())
+    }
+
+    /// Halve the frequencies of the current context
+    fn rescale(&mut self) {
+        let mc = self.min_context;
+        let stats = self.stats(mc);
+        let old_ns = self.num_stats(mc);
+
+        // move the found state to the front
+        let mut s = self.found_state;
+        while s != stats {
+            self.swap_states(s);
+            s -= 6;
+        }
+
+        let first = self.freq(s) + 4;
+        let mut esc_freq = self.summ_freq(mc) + 4 - first;
+        let adder = (self.order_fall != 0) as u32;
+        let mut sum_freq = (first + adder) >> 1;
+        self.set_freq(s, sum_freq);
+
+        for _ in 1..old_ns {
+            s += 6;
+            let freq = self.freq(s);
+            esc_freq = esc_freq.wrapping_sub(freq);
+            let freq = (freq + adder) >> 1;
+            sum_freq += freq;
+            self.set_freq(s, freq);
+
+            if freq > self.freq(s - 6) {
+                // keep the states sorted by their frequency
+                let tmp = self.get_state(s);
+                let mut s1 = s;
+                loop {
+                    self.copy_state(s1, s1 - 6);
+                    s1 -= 6;
+                    if s1 == stats || freq <= self.freq(s1 - 6) {
+                        break;
+                    }
+                }
+                self.put_state(s1, tmp);
+            }
+        }
+
+        if self.freq(s) == 0 {
+            // remove the states which have no frequency anymore
+            let mut i = 0;
+            while self.freq(s) == 0 {
+                i += 1;
+                s -= 6;
+            }
+            esc_freq = esc_freq.wrapping_add(i);
+            let ns = old_ns - i;
+            self.set_num_stats(mc, ns);
+
+            if ns == 1 {
+                let mut tmp = self.get_state(stats);
+                loop {
+                    tmp[1] -= tmp[1] >> 1;
+                    esc_freq >>= 1;
+                    if esc_freq <= 1 {
+                        break;
+                    }
+                }
+                let index = self.units2index[((old_ns + 1) >> 1) as usize - 1] as usize;
+                self.insert_node(stats, index);
+                let one = ModelPpm::one_state(mc);
+                self.put_state(one, tmp);
+                self.found_state = one;
+                return;
+            }
+        }
+
+        esc_freq = esc_freq.wrapping_sub(esc_freq >> 1);
+        self.set_summ_freq(mc, sum_freq.wrapping_add(esc_freq));
+
+        // shrink the stats when they need less units now
+        let n0 = ((old_ns + 1) >> 1) as usize;
+        let n1 = ((self.num_stats(mc) + 1) >> 1) as usize;
+        if n0 != n1 {
+            let i0 = self.units2index[n0 - 1] as usize;
+            let i1 = self.units2index[n1 - 1] as usize;
+            if i0 != i1 {
+                if self.free_list[i1] != 0 {
+                    let p = self.remove_node(i1);
+                    let old = stats as usize;
+                    self.heap
+                        .copy_within(old..old + n1 * UNIT_SIZE as usize, p as usize);
+                    self.insert_node(stats, i0);
+                    self.set_stats(mc, p);
+                } else {
+                    self.split_block(stats, i0, i1);
+                }
+            }
+        }
+        self.found_state = self.stats(mc);
+    }
+}
+
+/// Decoding of the symbols
+impl ModelPpm {
+    /// Decode the next byte of a PPM block
+    pub fn decode_char(&mut self, inp: &mut BitReader) -> Result<u8> {
+        self.decode_symbol(inp)?
+            .ok_or_else(|| data_error("Invalid PPM data"))
+    }
+
+    /// Continue with the successor of the found state
+    fn next_context(&mut self) {
+        let successor = self.successor(self.found_state);
+        if self.order_fall == 0 && successor >= self.units_start {
+            self.min_context = successor;
+            self.max_context = successor;
+        } else {
+            self.update_model();
+        }
+    }
+
+    /// The first state of a context with several states was found
+    fn update1_0(&mut self) {
+        let s = self.found_state;
+        let mc = self.min_context;
+        let freq = self.freq(s);
+        let summ = self.summ_freq(mc);
+        self.prev_success = (2 * freq > summ) as u32;
+        self.run_length += self.prev_success as i32;
+        self.set_summ_freq(mc, summ + 4);
+        self.set_freq(s, freq + 4);
+        if freq + 4 > MAX_FREQ {
+            self.rescale();
+        }
+        self.next_context();
+    }
+
+    /// Another state of a context with several states was found
+    fn update1(&mut self) {
+        let s = self.found_state;
+        let mc = self.min_context;
+        let freq = self.freq(s) + 4;
+        self.set_freq(s, freq);
+        let summ = self.summ_freq(mc) + 4;
+        self.set_summ_freq(mc, summ);
+        if freq > self.freq(s - 6) {
+            self.swap_states(s);
+            self.found_state = s - 6;
+            if freq > MAX_FREQ {
+                self.rescale();
+            }
+        }
+        self.next_context();
+    }
+
+    /// A state was found after an escape
+    fn update2(&mut self) {
+        let s = self.found_state;
+        let mc = self.min_context;
+        let freq = self.freq(s) + 4;
+        self.run_length = self.init_rl;
+        let summ = self.summ_freq(mc) + 4;
+        self.set_summ_freq(mc, summ);
+        self.set_freq(s, freq);
+        if freq > MAX_FREQ {
+            self.rescale();
+        }
+        self.update_model();
+    }
+
+    /// Returns the flag for symbols with high bits
+    fn hi_bits_flag(symbol: u32) -> u32 {
+        if symbol >= 0x40 {
+            8
+        } else {
+            0
+        }
+    }
+
+    /// Calculate the escape frequency of the current context.
+    /// Returns the used SEE context, None for the dummy context.
+    fn make_esc_freq(&mut self, num_masked: u32) -> (Option<(usize, usize)>, u32) {
+        let mc = self.min_context;
+        let num_stats = self.num_stats(mc);
+        if num_stats == 256 {
+            return (None, 1);
+        }
+
+        let diff = num_stats - num_masked;
+        let suffix_stats = self.num_stats(self.suffix(mc));
+        let i = self.ns2index[diff as usize - 1] as usize;
+        let k = (diff < suffix_stats.wrapping_sub(num_stats)) as usize
+            + 2 * (self.summ_freq(mc) < 11 * num_stats) as usize
+            + 4 * (num_masked > diff) as usize
+            + self.hi_bits_flag as usize;
+
+        let esc = self.see[i][k].mean();
+        (Some((i, k)), esc)
+    }
+
+    /// Returns the SEE context with the given position
+    fn see(&mut self, pos: Option<(usize, usize)>) -> &mut See {
+        match pos {
+            Some((i, k)) => &mut self.see[i][k],
+            None => &mut self.dummy_see,
+        }
+    }
+
+    /// Decode the next symbol, returns None for damaged data
+    fn decode_symbol(&mut self, inp: &mut BitReader) -> Result<Option<u8>> {
+        let mut char_mask = [true; 256];
+        let mc = self.min_context;
+
+        if self.num_stats(mc) != 1 {
+            let summ = self.summ_freq(mc);
+            let count = self.rc.get_threshold(summ);
+            if count >= summ {
+                return Ok(None);
+            }
+
+            let mut s = self.stats(mc);
+            let mut hi_cnt = 0;
+            for i in 0..self.num_stats(mc) {
+                let freq = self.freq(s);
+                if count < hi_cnt + freq {
+                    self.rc.decode(hi_cnt, freq);
+                    self.rc.normalize(inp)?;
+                    let symbol = self.symbol(s) as u8;
+                    self.found_state = s;
+                    if i == 0 {
+                        self.update1_0();
+                    } else {
+                        self.prev_success = 0;
+                        self.update1();
+                    }
+                    return Ok(Some(symbol));
+                }
+                hi_cnt += freq;
+                s += 6;
+            }
+
+            // escape, none of the symbols of the context matched
+            self.prev_success = 0;
+            self.hi_bits_flag = ModelPpm::hi_bits_flag(self.symbol(self.found_state));
+            self.rc.decode(hi_cnt, summ - hi_cnt);
+            let stats = self.stats(mc);
+            for i in 0..self.num_stats(mc) {
+                char_mask[self.symbol(stats + i * 6) as usize] = false;
+            }
+        } else {
+            // binary context with a single symbol
+            let s = ModelPpm::one_state(mc);
+            self.hi_bits_flag = ModelPpm::hi_bits_flag(self.symbol(self.found_state));
+            let freq = self.freq(s);
+            let suffix_stats = self.num_stats(self.suffix(mc));
+            let index = self.prev_success
+                + self.ns2bs_index[suffix_stats as usize - 1] as u32
+                + self.hi_bits_flag
+                + 2 * ModelPpm::hi_bits_flag(self.symbol(s))
+                + ((self.run_length >> 26) & 0x20) as u32;
+            let prob = self.bin_summ[freq as usize - 1][index as usize] as u32;
+
+            let size = (self.rc.range >> 14) * prob;
+            let prob = prob - ((prob + (1 << (PERIOD_BITS - 2))) >> PERIOD_BITS);
+
+            if self.rc.code < size {
+                self.bin_summ[freq as usize - 1][index as usize] = (prob + (1 << INT_BITS)) as u16;
+                self.rc.decode_bit_0(size, inp)?;
+
+                let symbol = self.symbol(s) as u8;
+                self.found_state = s;
+                self.prev_success = 1;
+                self.run_length += 1;
+                self.set_freq(s, freq + (freq < 128) as u32);
+                self.next_context();
+                return Ok(Some(symbol));
+            }
+
+            self.bin_summ[freq as usize - 1][index as usize] = prob as u16;
+            self.init_esc = EXP_ESCAPE[(prob >> 10) as usize] as u32;
+            self.rc.decode_bit_1(size);
+            char_mask[self.symbol(s) as usize] = false;
+            self.prev_success = 0;
+        }
+
+        loop {
+            self.rc.normalize(inp)?;
+
+            // go down to the next context with unmasked symbols
+            let mut mc = self.min_context;
+            let num_masked = self.num_stats(mc);
+            while self.num_stats(mc) == num_masked {
+                self.order_fall += 1;
+                mc = self.suffix(mc);
+                if mc == 0 {
+                    return Ok(None);
+                }
+            }
+            self.min_context = mc;
+
+            let stats = self.stats(mc);
+            let num_stats = self.num_stats(mc);
+            let mut hi_cnt = 0;
+            for i in 0..num_stats {
+                let s = stats + i * 6;
+                if char_mask[self.symbol(s) as usize] {
+                    hi_cnt += self.freq(s);
+                }
+            }
+
+            let (see, esc_freq) = self.make_esc_freq(num_masked);
+            let freq_sum = esc_freq + hi_cnt;
+
+            let count = self.rc.get_threshold(freq_sum);
+            if count >= freq_sum {
+                return Ok(None);
+            }
+
+            if count < hi_cnt {
+                let mut low = 0;
+                let mut s = stats;
+                loop {
+                    if char_mask[self.symbol(s) as usize] {
+                        let freq = self.freq(s);
+                        if count < low + freq {
+                            break;
+                        }
+                        low += freq;
+                    }
+                    s += 6;
+                }
+
+                let freq = self.freq(s);
+                self.rc.decode(low, freq);
+                self.rc.normalize(inp)?;
+                self.see(see).update();
+
+                let symbol = self.symbol(s) as u8;
+                self.found_state = s;
+                self.update2();
+                return Ok(Some(symbol));
+            }
+
+            // escape again, mask all symbols of this context
+            self.rc.decode(hi_cnt, freq_sum - hi_cnt);
+            let see = self.see(see);
+            see.summ = see.summ.wrapping_add(freq_sum as u16);
+            for i in 0..num_stats {
+                char_mask[self.symbol(stats + i * 6) as usize] = false;
+            }
+        }
+    }
+}
+
+#[test]
+fn test_range_decoder() {
+    let data = [0x12, 0x34, 0x56, 0x78, 0x9A, 0xBC];
+    let mut inp = BitReader::new(&data[..]);
+    let mut rc = RangeDecoder::default();
+    rc.init(&mut inp).unwrap();
+    assert_eq!((rc.low, rc.code, rc.range), (0, 0x1234_5678, 0xFFFF_FFFF));
+
+    // the count falls into the second of 16 equal intervals
+    assert_eq!(rc.get_threshold(16), 1);
+    rc.decode(1, 2);
+    rc.normalize(&mut inp).unwrap();
+    assert_eq!(
+        (rc.low, rc.code, rc.range),
+        (0x0FFF_FFFF, 0x0234_5679, 0x1FFF_FFFE)
+    );
+
+    // a small interval close to a carry is cut down to the carry,
+    // which shifts in both of the remaining bytes
+    rc.decode_bit_0(0x100, &mut inp).unwrap();
+    assert_eq!(
+        (rc.low, rc.code, rc.range),
+        (0xFFFF_0000, 0x5679_9ABC, 0x0001_0000)
+    );
+}
+
+#[test]
+fn test_ppm_init() {
+    let mut model = ModelPpm::new();
+    let mut esc_char = 2;
+
+    // the first block must create the model
+    let mut inp = BitReader::new(&[0x03, 0, 0, 0, 0][..]);
+    assert!(model.init(&mut inp, &mut esc_char).is_err());
+
+    // an order of 1 is invalid
+    let mut inp = BitReader::new(&[0x20, 0x00, 0, 0, 0, 0][..]);
+    assert!(model.init(&mut inp, &mut esc_char).is_err());
+
+    // order 4 with 1 MB and a new escape char
+    let mut inp = BitReader::new(&[0x63, 0x00, b'#', 0, 0, 0, 0][..]);
+    model.init(&mut inp, &mut esc_char).unwrap();
+    assert_eq!(esc_char, b'#');
+    assert_eq!(model.max_order, 4);
+    assert_eq!(model.heap.len(), 1 << 20);
+
+    // orders above 16 grow in steps of 3
+    let mut inp = BitReader::new(&[0x31, 0x00, 0, 0, 0, 0][..]);
+    model.init(&mut inp, &mut esc_char).unwrap();
+    assert_eq!(model.max_order, 22);
+}
+
+#[test]
+fn test_ppm_decode() {
+    // order 4 and 1 MB, encoded by an independent PPMd var. H encoder
+    let data = [
+        0x23, 0x00, 0x61, 0x03, 0x7C, 0x12, 0x0C, 0xE5, 0x94, 0xFD, 0xCB, 0x3B, 0xF2, 0x95, 0x5C,
+        0x00,
+    ];
+    let text = b"abracadabra abracadabra abracadabra";
+
+    let mut model = ModelPpm::new();
+    let mut esc_char = 2;
+    let mut inp = BitReader::new(&data[..]);
+    model.init(&mut inp, &mut esc_char).unwrap();
+    assert_eq!(esc_char, 2);
+
+    let mut decoded = vec![];
+    for _ in 0..text.len() {
+        decoded.push(model.decode_char(&mut inp).unwrap());
+    }
+    assert_eq!(&decoded[..], &text[..]);
+}
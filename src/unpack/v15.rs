@@ -0,0 +1,686 @@
+use std::io::Result;
+use unpack::bit_reader::BitReader;
+use unpack::{data_error, Unpack};
+
+/// Maximum number of bytes which can be added to the window by one symbol
+const MAX_INC_LZ_MATCH: usize = 0x200;
+/// Maximum number of bytes which are written out at once
+const UNPACK_MAX_WRITE: usize = 0x0040_0000;
+
+/// Fixed code tables of the RAR 1.5 format. Every table consists of the
+/// start bit length, the upper limits of the codes for every bit length
+/// and the start positions of the bit lengths in the symbol list.
+type CodeTable = (u32, &'static [u32], &'static [u32]);
+
+static L1: CodeTable = (
+    2,
+    &[
+        0x8000, 0xa000, 0xc000, 0xd000, 0xe000, 0xea00, 0xee00, 0xf000, 0xf200, 0xf200, 0xffff,
+    ],
+    &[0, 0, 0, 2, 3, 5, 7, 11, 16, 20, 24, 32, 32],
+);
+static L2: CodeTable = (
+    3,
+    &[
+        0xa000, 0xc000, 0xd000, 0xe000, 0xea00, 0xee00, 0xf000, 0xf200, 0xf240, 0xffff,
+    ],
+    &[0, 0, 0, 0, 5, 7, 9, 13, 18, 22, 26, 34, 36],
+);
+static HF0: CodeTable = (
+    4,
+    &[
+        0x8000, 0xc000, 0xe000, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xffff,
+    ],
+    &[0, 0, 0, 0, 0, 8, 16, 24, 33, 33, 33, 33, 33],
+);
+static HF1: CodeTable = (
+    5,
+    &[
+        0x2000, 0xc000, 0xe000, 0xf000, 0xf200, 0xf200, 0xf7e0, 0xffff,
+    ],
+    &[0, 0, 0, 0, 0, 0, 4, 44, 60, 76, 80, 80, 127],
+);
+static HF2: CodeTable = (
+    5,
+    &[
+        0x1000, 0x2400, 0x8000, 0xc000, 0xfa00, 0xffff, 0xffff, 0xffff,
+    ],
+    &[0, 0, 0, 0, 0, 0, 2, 7, 53, 117, 233, 0, 0],
+);
+static HF3: CodeTable = (
+    6,
+    &[0x800, 0x2400, 0xee00, 0xfe80, 0xffff, 0xffff, 0xffff],
+    &[0, 0, 0, 0, 0, 0, 0, 2, 16, 218, 251, 0, 0],
+);
+static HF4: CodeTable = (
+    8,
+    &[0xff00, 0xffff, 0xffff, 0xffff, 0xffff, 0xffff],
+    &[0, 0, 0, 0, 0, 0, 0, 0, 0, 255, 0, 0, 0],
+);
+
+/// Code lengths and prefixes of the short matches
+static SHORT_LEN1: [u32; 16] = [1, 3, 4, 4, 5, 6, 7, 8, 8, 4, 4, 5, 6, 6, 4, 0];
+static SHORT_XOR1: [u32; 15] = [
+    0, 0xa0, 0xd0, 0xe0, 0xf0, 0xf8, 0xfc, 0xfe, 0xff, 0xc0, 0x80, 0x90, 0x98, 0x9c, 0xb0,
+];
+static SHORT_LEN2: [u32; 16] = [2, 3, 3, 3, 4, 4, 5, 6, 6, 4, 4, 5, 6, 6, 4, 0];
+static SHORT_XOR2: [u32; 15] = [
+    0, 0x40, 0x60, 0xa0, 0xd0, 0xe0, 0xf0, 0xf8, 0xfc, 0xc0, 0x80, 0x90, 0x98, 0x9c, 0xb0,
+];
+
+/// Decode a number with one of the fixed code tables
+fn decode_num(inp: &mut BitReader, table: &CodeTable) -> u32 {
+    let (mut start_pos, dec_tab, pos_tab) = *table;
+    let num = inp.getbits() & 0xfff0;
+
+    let mut i = 0;
+    while dec_tab[i] <= num {
+        start_pos += 1;
+        i += 1;
+    }
+    inp.addbits(start_pos);
+
+    let base = if i > 0 { dec_tab[i - 1] } else { 0 };
+    ((num - base) >> (16 - start_pos)) + pos_tab[start_pos as usize]
+}
+
+/// Adaptive list of symbols, which are sorted by their frequency
+struct CharSet {
+    /// Symbol in the upper and a frequency class in the lower byte
+    set: [u32; 256],
+    /// Next position for every frequency class
+    num_to_place: [u8; 256],
+}
+
+impl CharSet {
+    /// Create a new list with the given symbols
+    fn new<F: Fn(u32) -> u32>(f: F) -> CharSet {
+        let mut set = [0; 256];
+        for (i, s) in set.iter_mut().enumerate() {
+            *s = f(i as u32);
+        }
+        CharSet {
+            set,
+            num_to_place: [0; 256],
+        }
+    }
+
+    /// Reset the frequency classes when a counter overflows
+    fn correct(&mut self) {
+        for (i, s) in self.set.iter_mut().enumerate() {
+            *s = (*s & !0xff) | (7 - i as u32 / 32);
+        }
+        self.num_to_place = [0; 256];
+        for i in 0..7 {
+            self.num_to_place[i] = ((7 - i) * 32) as u8;
+        }
+    }
+
+    /// Move the symbol at the given place forward, the `limit` is
+    /// the highest frequency class before the classes are reset.
+    /// Returns the value at the place before it was moved.
+    fn update(&mut self, place: usize, limit: u32) -> u32 {
+        loop {
+            let value = self.set[place];
+            let class = (value & 0xff) as usize;
+            let new_place = self.num_to_place[class] as usize;
+            self.num_to_place[class] = self.num_to_place[class].wrapping_add(1);
+
+            let value = value + 1;
+            if value & 0xff > limit || value & 0xff == 0 {
+                self.correct();
+                continue;
+            }
+
+            self.set[place] = self.set[new_place];
+            self.set[new_place] = value;
+            return value - 1;
+        }
+    }
+}
+
+/// Decoder for the RAR 1.5 compression format.
+///
+/// The format mixes LZ matches with adaptive Huffman coded literals
+/// and uses fixed code tables together with lists of symbols, which
+/// are reordered by their frequency.
+pub struct Unpack15 {
+    /// Sliding window which holds the unpacked data
+    window: Vec<u8>,
+    /// Mask to wrap positions around the window
+    mask: usize,
+    /// Current write position in the window
+    unp_ptr: usize,
+    /// Position up to which the window was written out
+    wr_ptr: usize,
+    /// Amount of data after which the window is written out
+    write_limit: usize,
+    /// The last four used distances
+    old_dist: [usize; 4],
+    /// Position of the next distance in the list of the old distances
+    old_dist_ptr: usize,
+    /// The last used distance
+    last_dist: usize,
+    /// The last used length
+    last_length: usize,
+    /// Literals
+    ch_set: CharSet,
+    /// Short distances
+    ch_set_a: [u32; 256],
+    /// Long distances
+    ch_set_b: CharSet,
+    /// Flag bytes
+    ch_set_c: CharSet,
+    /// Average place of the literals
+    avr_plc: u32,
+    /// Average place of the long distances
+    avr_plc_b: u32,
+    /// Average length of the short matches
+    avr_ln1: u32,
+    /// Average length of the long matches
+    avr_ln2: u32,
+    /// Average of the long matches with a short length
+    avr_ln3: u32,
+    /// Number of literals in a row
+    num_huf: u32,
+    /// Weight of the literals
+    nhfb: u32,
+    /// Weight of the long matches
+    nlzb: u32,
+    /// Distance from which matches get longer
+    max_dist3: usize,
+    /// Changes the code of the short match length
+    buf60: u32,
+    /// Flags, which select the type of the next symbols
+    flag_buf: u32,
+    /// Number of flags left in the flag buffer
+    flags_cnt: i32,
+    /// Only literals are following
+    st_mode: bool,
+    /// Number of repeated last matches
+    l_count: u32,
+    /// Indicates whether the beginning of the file was read
+    started: bool,
+    /// Unpacked size of the current file
+    size: u64,
+    /// Number of bytes unpacked for the current file
+    unpacked: u64,
+    /// Number of bytes written out for the current file
+    written: u64,
+}
+
+impl Unpack15 {
+    /// Create a new decoder with the given window size
+    pub fn new(window_size: usize) -> Unpack15 {
+        let mut unp = Unpack15 {
+            window: vec![0; window_size],
+            mask: window_size - 1,
+            unp_ptr: 0,
+            wr_ptr: 0,
+            write_limit: (window_size - MAX_INC_LZ_MATCH).min(UNPACK_MAX_WRITE),
+            old_dist: [0; 4],
+            old_dist_ptr: 0,
+            last_dist: 0,
+            last_length: 0,
+            ch_set: CharSet::new(|i| i << 8),
+            ch_set_a: [0; 256],
+            ch_set_b: CharSet::new(|i| i << 8),
+            ch_set_c: CharSet::new(|i| (i.wrapping_neg() & 0xff) << 8),
+            avr_plc: 0,
+            avr_plc_b: 0,
+            avr_ln1: 0,
+            avr_ln2: 0,
+            avr_ln3: 0,
+            num_huf: 0,
+            nhfb: 0,
+            nlzb: 0,
+            max_dist3: 0,
+            buf60: 0,
+            flag_buf: 0,
+            flags_cnt: 0,
+            st_mode: false,
+            l_count: 0,
+            started: false,
+            size: 0,
+            unpacked: 0,
+            written: 0,
+        };
+        unp.init(false, 0);
+        unp
+    }
+
+    /// Reset the adaptive symbol lists
+    fn init_huff(&mut self) {
+        self.ch_set = CharSet::new(|i| i << 8);
+        self.ch_set_b = CharSet::new(|i| i << 8);
+        self.ch_set_c = CharSet::new(|i| (i.wrapping_neg() & 0xff) << 8);
+        for (i, s) in self.ch_set_a.iter_mut().enumerate() {
+            *s = i as u32;
+        }
+        self.ch_set_b.correct();
+    }
+
+    /// Read the next flag byte
+    fn get_flags_buf(&mut self, inp: &mut BitReader) {
+        let place = decode_num(inp, &HF2) as usize;
+
+        // only damaged data can contain this place
+        if place >= 256 {
+            return;
+        }
+
+        self.flag_buf = self.ch_set_c.update(place, 0xff) >> 8;
+    }
+
+    /// Take the next flag from the flag buffer
+    fn next_flag(&mut self, inp: &mut BitReader) -> bool {
+        self.flags_cnt -= 1;
+        if self.flags_cnt < 0 {
+            self.get_flags_buf(inp);
+            self.flags_cnt = 7;
+        }
+
+        let flag = self.flag_buf & 0x80 != 0;
+        self.flag_buf <<= 1;
+        flag
+    }
+
+    /// Decode a match with a short length
+    fn short_lz(&mut self, inp: &mut BitReader) {
+        self.num_huf = 0;
+
+        let mut bit_field = inp.getbits();
+        if self.l_count == 2 {
+            inp.addbits(1);
+            if bit_field >= 0x8000 {
+                let (distance, length) = (self.last_dist, self.last_length);
+                self.copy_string(distance, length);
+                return;
+            }
+            bit_field <<= 1;
+            self.l_count = 0;
+        }
+        bit_field >>= 8;
+
+        // the length of the code for the length 1 or 3 depends on buf60
+        let (short_len, short_xor, variable) = if self.avr_ln1 < 37 {
+            (&SHORT_LEN1, &SHORT_XOR1, 1)
+        } else {
+            (&SHORT_LEN2, &SHORT_XOR2, 3)
+        };
+        let code_len = |i: usize| {
+            if i == variable {
+                self.buf60 + 3
+            } else {
+                short_len[i]
+            }
+        };
+
+        let mut length = 0;
+        while length < short_xor.len()
+            && (bit_field ^ short_xor[length]) & !(0xff >> code_len(length)) != 0
+        {
+            length += 1;
+        }
+        inp.addbits(code_len(length));
+
+        if length >= 9 {
+            // repeat the last match
+            if length == 9 {
+                self.l_count += 1;
+                let (distance, length) = (self.last_dist, self.last_length);
+                self.copy_string(distance, length);
+                return;
+            }
+
+            // match with a 15 bit distance
+            if length == 14 {
+                self.l_count = 0;
+                let length = decode_num(inp, &L2) as usize + 5;
+                let distance = ((inp.getbits() >> 1) | 0x8000) as usize;
+                inp.addbits(15);
+                self.last_length = length;
+                self.last_dist = distance;
+                self.copy_string(distance, length);
+                return;
+            }
+
+            // match with one of the last four distances
+            self.l_count = 0;
+            let save_length = length;
+            let distance = self.old_dist[self.old_dist_ptr.wrapping_sub(length - 9) & 3];
+            let mut length = decode_num(inp, &L1) as usize + 2;
+            if length == 0x101 && save_length == 10 {
+                self.buf60 ^= 1;
+                return;
+            }
+            if distance > 256 {
+                length += 1;
+            }
+            if distance >= self.max_dist3 {
+                length += 1;
+            }
+
+            self.insert_old_dist(distance);
+            self.last_length = length;
+            self.last_dist = distance;
+            self.copy_string(distance, length);
+            return;
+        }
+
+        self.l_count = 0;
+        self.avr_ln1 += length as u32;
+        self.avr_ln1 -= self.avr_ln1 >> 4;
+
+        let place = (decode_num(inp, &HF2) & 0xff) as usize;
+        let distance = self.ch_set_a[place];
+        if place > 0 {
+            self.ch_set_a[place] = self.ch_set_a[place - 1];
+            self.ch_set_a[place - 1] = distance;
+        }
+
+        let length = length + 2;
+        let distance = distance as usize + 1;
+        self.insert_old_dist(distance);
+        self.last_length = length;
+        self.last_dist = distance;
+        self.copy_string(distance, length);
+    }
+
+    /// Decode a match with a long length
+    fn long_lz(&mut self, inp: &mut BitReader) {
+        self.num_huf = 0;
+        self.nlzb += 16;
+        if self.nlzb > 0xff {
+            self.nlzb = 0x90;
+            self.nhfb >>= 1;
+        }
+        let old_avr2 = self.avr_ln2;
+
+        let bit_field = inp.getbits();
+        let mut length = if self.avr_ln2 >= 122 {
+            decode_num(inp, &L2)
+        } else if self.avr_ln2 >= 64 {
+            decode_num(inp, &L1)
+        } else if bit_field < 0x100 {
+            inp.addbits(16);
+            bit_field
+        } else {
+            let length = bit_field.leading_zeros() - 16;
+            inp.addbits(length + 1);
+            length
+        };
+
+        self.avr_ln2 += length;
+        self.avr_ln2 -= self.avr_ln2 >> 5;
+
+        let place = if self.avr_plc_b > 0x28ff {
+            decode_num(inp, &HF2)
+        } else if self.avr_plc_b > 0x6ff {
+            decode_num(inp, &HF1)
+        } else {
+            decode_num(inp, &HF0)
+        };
+
+        self.avr_plc_b += place;
+        self.avr_plc_b -= self.avr_plc_b >> 8;
+
+        let distance = self.ch_set_b.update((place & 0xff) as usize, 0xff) + 1;
+        let distance = (((distance & 0xff00) | (inp.getbits() >> 8)) >> 1) as usize;
+        inp.addbits(7);
+
+        let old_avr3 = self.avr_ln3;
+        if length != 1 && length != 4 {
+            if length == 0 && distance <= self.max_dist3 {
+                self.avr_ln3 += 1;
+                self.avr_ln3 -= self.avr_ln3 >> 8;
+            } else if self.avr_ln3 > 0 {
+                self.avr_ln3 -= 1;
+            }
+        }
+
+        length += 3;
+        if distance >= self.max_dist3 {
+            length += 1;
+        }
+        if distance <= 256 {
+            length += 8;
+        }
+
+        self.max_dist3 = if old_avr3 > 0xb0 || (self.avr_plc >= 0x2a00 && old_avr2 < 0x40) {
+            0x7f00
+        } else {
+            0x2001
+        };
+
+        let length = length as usize;
+        self.insert_old_dist(distance);
+        self.last_length = length;
+        self.last_dist = distance;
+        self.copy_string(distance, length);
+    }
+
+    /// Decode a literal
+    fn huff_decode(&mut self, inp: &mut BitReader) {
+        let bit_field = inp.getbits();
+
+        let place = if self.avr_plc > 0x75ff {
+            decode_num(inp, &HF4)
+        } else if self.avr_plc > 0x5dff {
+            decode_num(inp, &HF3)
+        } else if self.avr_plc > 0x35ff {
+            decode_num(inp, &HF2)
+        } else if self.avr_plc > 0x0dff {
+            decode_num(inp, &HF1)
+        } else {
+            decode_num(inp, &HF0)
+        };
+        let mut place = place & 0xff;
+
+        if self.st_mode {
+            if place == 0 && bit_field > 0xfff {
+                place = 0x100;
+            }
+
+            // the place 0 is an escape code
+            if place == 0 {
+                let bit_field = inp.getbits();
+                inp.addbits(1);
+                if bit_field & 0x8000 != 0 {
+                    self.num_huf = 0;
+                    self.st_mode = false;
+                    return;
+                }
+
+                let length = if bit_field & 0x4000 != 0 { 4 } else { 3 };
+                inp.addbits(1);
+                let distance = decode_num(inp, &HF2);
+                let distance = (distance << 5) | (inp.getbits() >> 11);
+                inp.addbits(5);
+                self.copy_string(distance as usize, length);
+                return;
+            }
+            place -= 1;
+        } else {
+            if self.num_huf >= 16 && self.flags_cnt == 0 {
+                self.st_mode = true;
+            }
+            self.num_huf += 1;
+        }
+
+        self.avr_plc += place;
+        self.avr_plc -= self.avr_plc >> 8;
+        self.nhfb += 16;
+        if self.nhfb > 0xff {
+            self.nhfb = 0x90;
+            self.nlzb >>= 1;
+        }
+
+        let value = self.ch_set.update(place as usize, 0xa1);
+        self.window[self.unp_ptr] = (value >> 8) as u8;
+        self.unp_ptr = (self.unp_ptr + 1) & self.mask;
+        self.unpacked += 1;
+    }
+
+    /// Add a distance to the list of the last used distances
+    fn insert_old_dist(&mut self, distance: usize) {
+        self.old_dist[self.old_dist_ptr & 3] = distance;
+        self.old_dist_ptr = (self.old_dist_ptr + 1) & 3;
+    }
+
+    /// Copy a string of the given length from the given distance
+    /// behind the current position
+    fn copy_string(&mut self, distance: usize, length: usize) {
+        self.unpacked += length as u64;
+
+        let mut src = self.unp_ptr.wrapping_sub(distance);
+        for _ in 0..length {
+            self.window[self.unp_ptr] = self.window[src & self.mask];
+            src = src.wrapping_add(1);
+            self.unp_ptr = (self.unp_ptr + 1) & self.mask;
+        }
+    }
+
+    /// Write out the window data which is not written yet.
+    ///
+    /// A match can reach behind the end of the file, so the
+    /// data is cut at the unpacked size.
+    fn write_buf(&mut self, out: &mut Vec<u8>) {
+        let (start, end) = (self.wr_ptr, self.unp_ptr);
+        let len = (end.wrapping_sub(start) & self.mask) as u64;
+        let len = len.min(self.size.saturating_sub(self.written)) as usize;
+
+        if start + len > self.window.len() {
+            out.extend_from_slice(&self.window[start..]);
+            out.extend_from_slice(&self.window[..start + len - self.window.len()]);
+        } else {
+            out.extend_from_slice(&self.window[start..start + len]);
+        }
+
+        self.written += len as u64;
+        self.wr_ptr = end;
+    }
+}
+
+impl Unpack for Unpack15 {
+    fn init(&mut self, solid: bool, size: u64) {
+        if !solid {
+            self.old_dist = [0; 4];
+            self.old_dist_ptr = 0;
+            self.last_dist = 0;
+            self.last_length = 0;
+            self.unp_ptr = 0;
+            self.wr_ptr = 0;
+
+            self.avr_plc_b = 0;
+            self.avr_ln1 = 0;
+            self.avr_ln2 = 0;
+            self.avr_ln3 = 0;
+            self.num_huf = 0;
+            self.buf60 = 0;
+            self.avr_plc = 0x3500;
+            self.max_dist3 = 0x2001;
+            self.nhfb = 0x80;
+            self.nlzb = 0x80;
+            self.init_huff();
+        }
+
+        self.flags_cnt = 0;
+        self.flag_buf = 0;
+        self.st_mode = false;
+        self.l_count = 0;
+
+        self.size = size;
+        self.unpacked = 0;
+        self.written = 0;
+        self.started = false;
+    }
+
+    fn decode(&mut self, inp: &mut BitReader, out: &mut Vec<u8>) -> Result<bool> {
+        if !self.started {
+            inp.fill()?;
+            if self.size > 0 {
+                self.get_flags_buf(inp);
+                self.flags_cnt = 8;
+            }
+            self.started = true;
+        }
+
+        // the format has no end marker, so we stop at the unpacked size
+        while self.unpacked < self.size {
+            inp.fill()?;
+            if inp.overrun() {
+                return Err(data_error("Unexpected end of packed data"));
+            }
+
+            // write out the data when enough is unpacked
+            if (self.unp_ptr.wrapping_sub(self.wr_ptr) & self.mask) >= self.write_limit {
+                self.write_buf(out);
+                return Ok(false);
+            }
+
+            if self.st_mode {
+                self.huff_decode(inp);
+                continue;
+            }
+
+            // the flags select the type of the next symbol, the more
+            // frequent one of literals and long matches gets the short code
+            if self.next_flag(inp) {
+                if self.nlzb > self.nhfb {
+                    self.long_lz(inp);
+                } else {
+                    self.huff_decode(inp);
+                }
+            } else if self.next_flag(inp) {
+                if self.nlzb > self.nhfb {
+                    self.huff_decode(inp);
+                } else {
+                    self.long_lz(inp);
+                }
+            } else {
+                self.short_lz(inp);
+            }
+        }
+
+        self.write_buf(out);
+        Ok(true)
+    }
+}
+
+#[test]
+fn test_decode_num() {
+    // 0x0800 is the second code with 5 bits of the HF2 table
+    let data = [0x08, 0x00, 0xff, 0xff];
+    let mut inp = BitReader::new(&data[..]);
+    inp.fill().unwrap();
+    assert_eq!(decode_num(&mut inp, &HF2), 1);
+    assert_eq!(inp.bit_pos(), 5);
+
+    // all bits set is the last code of the L1 table
+    let mut inp = BitReader::new(&data[2..]);
+    inp.fill().unwrap();
+    assert_eq!(decode_num(&mut inp, &L1), 255);
+}
+
+#[test]
+fn test_unpack15_literals() {
+    // the flag byte 0xff followed by the literal places 2, 0 and 2,
+    // which change their symbols after every literal
+    let data = [0b0000_1000, 0b1000_0000, 0b0010_0000, 0x00];
+    let mut unp = Unpack15::new(0x10000);
+    unp.init(false, 3);
+    let mut inp = BitReader::new(&data[..]);
+    let mut out = vec![];
+    assert!(unp.decode(&mut inp, &mut out).unwrap());
+    assert_eq!(out, vec![2, 2, 0]);
+
+    // a stream of zero bits contains only short matches with the distance 1
+    let data = [0u8; 16];
+    let mut unp = Unpack15::new(0x10000);
+    unp.init(false, 10);
+    let mut inp = BitReader::new(&data[..]);
+    let mut out = vec![];
+    assert!(unp.decode(&mut inp, &mut out).unwrap());
+    assert_eq!(out, vec![0; 10]);
+}
@@ -0,0 +1,643 @@
+use std::io::Result;
+use unpack::bit_reader::BitReader;
+use unpack::huffman::HuffmanTable;
+use unpack::{data_error, Unpack};
+
+/// Number of symbols in the main table
+const NC: usize = 298;
+/// Number of symbols in the distance table
+const DC: usize = 48;
+/// Number of symbols in the repeat length table
+const RC: usize = 28;
+/// Number of symbols in the bit length table
+const BC: usize = 19;
+/// Number of symbols in the table of an audio channel
+const MC: usize = 257;
+/// Maximum number of audio channels
+const MAX_CHANNELS: usize = 4;
+
+/// Maximum number of bytes which can be added to the window by one symbol
+const MAX_INC_LZ_MATCH: usize = 0x200;
+/// Maximum number of bytes which are written out at once
+const UNPACK_MAX_WRITE: usize = 0x0040_0000;
+
+/// Base values of the length slots
+static LDECODE: [u8; 28] = [
+    0, 1, 2, 3, 4, 5, 6, 7, 8, 10, 12, 14, 16, 20, 24, 28, 32, 40, 48, 56, 64, 80, 96, 112, 128,
+    160, 192, 224,
+];
+/// Number of extra bits of the length slots
+static LBITS: [u8; 28] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5,
+];
+/// Base values of the distance slots
+static DDECODE: [u32; DC] = [
+    0, 1, 2, 3, 4, 6, 8, 12, 16, 24, 32, 48, 64, 96, 128, 192, 256, 384, 512, 768, 1024, 1536,
+    2048, 3072, 4096, 6144, 8192, 12288, 16384, 24576, 32768, 49152, 65536, 98304, 131_072,
+    196_608, 262_144, 327_680, 393_216, 458_752, 524_288, 589_824, 655_360, 720_896, 786_432,
+    851_968, 917_504, 983_040,
+];
+/// Number of extra bits of the distance slots
+static DBITS: [u8; DC] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13, 14, 14, 15, 15, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16,
+];
+/// Base values of the short distance slots
+static SDDECODE: [u8; 8] = [0, 4, 8, 16, 32, 64, 128, 192];
+/// Number of extra bits of the short distance slots
+static SDBITS: [u8; 8] = [2, 2, 3, 4, 5, 6, 6, 6];
+
+/// Huffman tables which are used to decode a LZ block
+#[derive(Default)]
+struct BlockTables {
+    /// Literals, lengths and special codes
+    ld: HuffmanTable,
+    /// Distances
+    dd: HuffmanTable,
+    /// Lengths of repeated distances
+    rd: HuffmanTable,
+}
+
+/// State of the predictor of an audio channel
+#[derive(Clone, Copy, Default)]
+struct AudioVariables {
+    /// Weights of the last deltas and the delta of the previous channel
+    k: [i32; 5],
+    /// The last four deltas
+    d: [i32; 4],
+    /// The last delta of the channel
+    last_delta: i32,
+    /// Summed up errors of the possible weight changes
+    dif: [u32; 11],
+    /// Number of decoded bytes of the channel
+    byte_count: u32,
+    /// The last byte of the channel
+    last_char: i32,
+}
+
+/// Decoder for the RAR 2.0 compression format.
+///
+/// Besides the LZ blocks the format knows audio blocks, where every
+/// byte is predicted from the previous bytes of its channel.
+pub struct Unpack20 {
+    /// Sliding window which holds the unpacked data
+    window: Vec<u8>,
+    /// Mask to wrap positions around the window
+    mask: usize,
+    /// Current write position in the window
+    unp_ptr: usize,
+    /// Position up to which the window was written out
+    wr_ptr: usize,
+    /// Amount of data after which the window is written out
+    write_limit: usize,
+    /// The last four used distances
+    old_dist: [usize; 4],
+    /// Position of the next distance in the list of the old distances
+    old_dist_ptr: usize,
+    /// The last used distance
+    last_dist: usize,
+    /// The last used length
+    last_length: usize,
+    /// Current Huffman tables for LZ blocks
+    tables: BlockTables,
+    /// Current Huffman tables for audio blocks, one per channel
+    md: [HuffmanTable; MAX_CHANNELS],
+    /// Bit lengths of the last tables, new tables can be based on them
+    old_table: [u8; MC * MAX_CHANNELS],
+    /// Indicates whether the tables are valid for the next file
+    tables_read: bool,
+    /// The current block is an audio block
+    audio_block: bool,
+    /// Number of audio channels
+    channels: usize,
+    /// Channel of the next audio byte
+    cur_channel: usize,
+    /// The last delta of any channel
+    channel_delta: i32,
+    /// Predictor state of every audio channel
+    aud_v: [AudioVariables; MAX_CHANNELS],
+    /// Indicates whether the beginning of the file was read
+    started: bool,
+    /// Unpacked size of the current file
+    size: u64,
+    /// Number of bytes unpacked for the current file
+    unpacked: u64,
+    /// Number of bytes written out for the current file
+    written: u64,
+}
+
+impl Unpack20 {
+    /// Create a new decoder with the given window size
+    pub fn new(window_size: usize) -> Unpack20 {
+        let mut unp = Unpack20 {
+            window: vec![0; window_size],
+            mask: window_size - 1,
+            unp_ptr: 0,
+            wr_ptr: 0,
+            write_limit: (window_size - MAX_INC_LZ_MATCH).min(UNPACK_MAX_WRITE),
+            old_dist: [0; 4],
+            old_dist_ptr: 0,
+            last_dist: 0,
+            last_length: 0,
+            tables: BlockTables::default(),
+            md: Default::default(),
+            old_table: [0; MC * MAX_CHANNELS],
+            tables_read: false,
+            audio_block: false,
+            channels: 1,
+            cur_channel: 0,
+            channel_delta: 0,
+            aud_v: [AudioVariables::default(); MAX_CHANNELS],
+            started: false,
+            size: 0,
+            unpacked: 0,
+            written: 0,
+        };
+        unp.init(false, 0);
+        unp
+    }
+
+    /// Read the tables of the next block
+    fn read_tables(&mut self, inp: &mut BitReader) -> Result<()> {
+        inp.fill()?;
+
+        let bit_field = inp.getbits();
+        self.audio_block = bit_field & 0x8000 != 0;
+
+        // the new tables are based on the old ones when this flag is set
+        if bit_field & 0x4000 == 0 {
+            self.old_table = [0; MC * MAX_CHANNELS];
+        }
+        inp.addbits(2);
+
+        let table_size = if self.audio_block {
+            self.channels = ((bit_field >> 12) & 3) as usize + 1;
+            if self.cur_channel >= self.channels {
+                self.cur_channel = 0;
+            }
+            inp.addbits(2);
+            MC * self.channels
+        } else {
+            NC + DC + RC
+        };
+
+        // read the bit lengths of the bit length table
+        let mut bit_length = [0u8; BC];
+        for length in bit_length.iter_mut() {
+            *length = (inp.getbits() >> 12) as u8;
+            inp.addbits(4);
+        }
+        let bd = HuffmanTable::new(&bit_length);
+
+        // read the bit lengths of all other tables
+        let mut table = [0u8; MC * MAX_CHANNELS];
+        let mut i = 0;
+        while i < table_size {
+            inp.fill()?;
+            let number = bd.decode(inp);
+            if number < 16 {
+                table[i] = (number as u8 + self.old_table[i]) & 0xf;
+                i += 1;
+            } else if number == 16 {
+                // repeat the previous length
+                let n = (inp.getbits() >> 14) as usize + 3;
+                inp.addbits(2);
+                if i == 0 {
+                    return Err(data_error("Invalid Huffman table"));
+                }
+                let end = table_size.min(i + n);
+                while i < end {
+                    table[i] = table[i - 1];
+                    i += 1;
+                }
+            } else {
+                // a sequence of zero bit lengths
+                let n = if number == 17 {
+                    let n = (inp.getbits() >> 13) + 3;
+                    inp.addbits(3);
+                    n
+                } else {
+                    let n = (inp.getbits() >> 9) + 11;
+                    inp.addbits(7);
+                    n
+                } as usize;
+                let end = table_size.min(i + n);
+                while i < end {
+                    table[i] = 0;
+                    i += 1;
+                }
+            }
+        }
+
+        if inp.overrun() {
+            return Err(data_error("Unexpected end of packed data"));
+        }
+
+        if self.audio_block {
+            for (md, lengths) in self.md.iter_mut().zip(table.chunks(MC)) {
+                *md = HuffmanTable::new(lengths);
+            }
+        } else {
+            self.tables = BlockTables {
+                ld: HuffmanTable::new(&table[..NC]),
+                dd: HuffmanTable::new(&table[NC..NC + DC]),
+                rd: HuffmanTable::new(&table[NC + DC..NC + DC + RC]),
+            };
+        }
+        self.old_table[..table_size].copy_from_slice(&table[..table_size]);
+        self.tables_read = true;
+
+        Ok(())
+    }
+
+    /// The tables for the next file of a solid archive can be stored
+    /// at the end of the current file, so we need to check for them.
+    fn read_last_tables(&mut self, inp: &mut BitReader) -> Result<()> {
+        inp.fill()?;
+        if inp.remaining() < 5 {
+            return Ok(());
+        }
+
+        let new_tables = if self.audio_block {
+            self.md[self.cur_channel].decode(inp) == 256
+        } else {
+            self.tables.ld.decode(inp) == 269
+        };
+        if new_tables {
+            self.read_tables(inp)?;
+        }
+        Ok(())
+    }
+
+    /// Decode the next symbol of an audio block
+    fn decode_audio_block(&mut self, inp: &mut BitReader) -> Result<()> {
+        let number = self.md[self.cur_channel].decode(inp);
+        if number == 256 {
+            return self.read_tables(inp);
+        }
+
+        self.window[self.unp_ptr] = self.decode_audio(number as i32);
+        self.unp_ptr = (self.unp_ptr + 1) & self.mask;
+        self.unpacked += 1;
+
+        self.cur_channel += 1;
+        if self.cur_channel == self.channels {
+            self.cur_channel = 0;
+        }
+        Ok(())
+    }
+
+    /// Restore the next byte of the current audio channel from the delta
+    fn decode_audio(&mut self, delta: i32) -> u8 {
+        let channel_delta = self.channel_delta;
+        let v = &mut self.aud_v[self.cur_channel];
+
+        v.byte_count = v.byte_count.wrapping_add(1);
+        v.d[3] = v.d[2];
+        v.d[2] = v.d[1];
+        v.d[1] = v.last_delta - v.d[0];
+        v.d[0] = v.last_delta;
+
+        let predicted = 8 * v.last_char
+            + v.k[0] * v.d[0]
+            + v.k[1] * v.d[1]
+            + v.k[2] * v.d[2]
+            + v.k[3] * v.d[3]
+            + v.k[4] * channel_delta;
+        let predicted = (predicted >> 3) & 0xff;
+
+        let ch = predicted.wrapping_sub(delta);
+
+        let d = i32::from(delta as u8 as i8) << 3;
+        let diffs = [
+            0,
+            v.d[0],
+            -v.d[0],
+            v.d[1],
+            -v.d[1],
+            v.d[2],
+            -v.d[2],
+            v.d[3],
+            -v.d[3],
+            channel_delta,
+            -channel_delta,
+        ];
+        for (dif, diff) in v.dif.iter_mut().zip(diffs.iter()) {
+            *dif = dif.wrapping_add((d - diff).unsigned_abs());
+        }
+
+        v.last_delta = i32::from(ch.wrapping_sub(v.last_char) as i8);
+        v.last_char = ch & 0xff;
+        self.channel_delta = v.last_delta;
+
+        // adapt the weights to the predictor with the least error
+        if v.byte_count & 0x1f == 0 {
+            let mut min_dif = v.dif[0];
+            let mut num_min_dif = 0;
+            v.dif[0] = 0;
+            for (i, dif) in v.dif.iter_mut().enumerate().skip(1) {
+                if *dif < min_dif {
+                    min_dif = *dif;
+                    num_min_dif = i;
+                }
+                *dif = 0;
+            }
+
+            if num_min_dif > 0 {
+                let k = &mut v.k[(num_min_dif - 1) / 2];
+                if num_min_dif % 2 == 1 {
+                    if *k >= -16 {
+                        *k -= 1;
+                    }
+                } else if *k < 16 {
+                    *k += 1;
+                }
+            }
+        }
+
+        ch as u8
+    }
+
+    /// Decode the next symbol of a LZ block
+    fn decode_lz(&mut self, inp: &mut BitReader) -> Result<()> {
+        let number = self.tables.ld.decode(inp) as usize;
+
+        // literal
+        if number < 256 {
+            self.window[self.unp_ptr] = number as u8;
+            self.unp_ptr = (self.unp_ptr + 1) & self.mask;
+            self.unpacked += 1;
+            return Ok(());
+        }
+
+        // match with a new distance
+        if number > 269 {
+            let mut length = read_length(inp, number - 270) + 3;
+
+            let dist_number = self.tables.dd.decode(inp) as usize;
+            let mut distance = DDECODE[dist_number] as usize + 1;
+            let bits = DBITS[dist_number] as u32;
+            if bits > 0 {
+                distance += (inp.getbits() >> (16 - bits)) as usize;
+                inp.addbits(bits);
+            }
+
+            if distance >= 0x2000 {
+                length += 1;
+                if distance >= 0x40000 {
+                    length += 1;
+                }
+            }
+
+            self.copy_string(length, distance);
+            return Ok(());
+        }
+
+        match number {
+            // new tables
+            269 => self.read_tables(inp)?,
+            // repeat the last match
+            256 => {
+                let (length, distance) = (self.last_length, self.last_dist);
+                self.copy_string(length, distance);
+            }
+            // match with one of the last four distances
+            257..=260 => {
+                let distance = self.old_dist[self.old_dist_ptr.wrapping_sub(number - 256) & 3];
+
+                let length_number = self.tables.rd.decode(inp) as usize;
+                let mut length = read_length(inp, length_number) + 2;
+                if distance >= 0x101 {
+                    length += 1;
+                    if distance >= 0x2000 {
+                        length += 1;
+                        if distance >= 0x40000 {
+                            length += 1;
+                        }
+                    }
+                }
+
+                self.copy_string(length, distance);
+            }
+            // match with a short distance and a length of 2
+            _ => {
+                let slot = number - 261;
+                let mut distance = SDDECODE[slot] as usize + 1;
+                let bits = SDBITS[slot] as u32;
+                distance += (inp.getbits() >> (16 - bits)) as usize;
+                inp.addbits(bits);
+
+                self.copy_string(2, distance);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Copy a string of the given length from the given distance
+    /// behind the current position and remember the match
+    fn copy_string(&mut self, length: usize, distance: usize) {
+        self.old_dist[self.old_dist_ptr & 3] = distance;
+        self.old_dist_ptr = self.old_dist_ptr.wrapping_add(1);
+        self.last_dist = distance;
+        self.last_length = length;
+        self.unpacked += length as u64;
+
+        let mut src = self.unp_ptr.wrapping_sub(distance);
+        for _ in 0..length {
+            self.window[self.unp_ptr] = self.window[src & self.mask];
+            src = src.wrapping_add(1);
+            self.unp_ptr = (self.unp_ptr + 1) & self.mask;
+        }
+    }
+
+    /// Write out the window data which is not written yet.
+    ///
+    /// A match can reach behind the end of the file, so the
+    /// data is cut at the unpacked size.
+    fn write_buf(&mut self, out: &mut Vec<u8>) {
+        let (start, end) = (self.wr_ptr, self.unp_ptr);
+        let len = (end.wrapping_sub(start) & self.mask) as u64;
+        let len = len.min(self.size.saturating_sub(self.written)) as usize;
+
+        if start + len > self.window.len() {
+            out.extend_from_slice(&self.window[start..]);
+            out.extend_from_slice(&self.window[..start + len - self.window.len()]);
+        } else {
+            out.extend_from_slice(&self.window[start..start + len]);
+        }
+
+        self.written += len as u64;
+        self.wr_ptr = end;
+    }
+}
+
+/// Decode the length from a length slot
+fn read_length(inp: &mut BitReader, slot: usize) -> usize {
+    let mut length = LDECODE[slot] as usize;
+    let bits = LBITS[slot] as u32;
+    if bits > 0 {
+        length += (inp.getbits() >> (16 - bits)) as usize;
+        inp.addbits(bits);
+    }
+    length
+}
+
+impl Unpack for Unpack20 {
+    fn init(&mut self, solid: bool, size: u64) {
+        if !solid {
+            self.old_dist = [0; 4];
+            self.old_dist_ptr = 0;
+            self.last_dist = 0;
+            self.last_length = 0;
+            self.unp_ptr = 0;
+            self.wr_ptr = 0;
+            self.tables_read = false;
+            self.audio_block = false;
+            self.channel_delta = 0;
+            self.cur_channel = 0;
+            self.channels = 1;
+            self.aud_v = [AudioVariables::default(); MAX_CHANNELS];
+            self.old_table = [0; MC * MAX_CHANNELS];
+        }
+
+        self.size = size;
+        self.unpacked = 0;
+        self.written = 0;
+        self.started = false;
+    }
+
+    fn decode(&mut self, inp: &mut BitReader, out: &mut Vec<u8>) -> Result<bool> {
+        // read the tables at the beginning of the file, when
+        // they aren't known from the previous file
+        if !self.started {
+            if !self.tables_read {
+                self.read_tables(inp)?;
+            }
+            self.started = true;
+        }
+
+        // the format has no end marker, so we stop at the unpacked size
+        while self.unpacked < self.size {
+            inp.fill()?;
+            if inp.overrun() {
+                return Err(data_error("Unexpected end of packed data"));
+            }
+
+            // write out the data when enough is unpacked
+            if (self.unp_ptr.wrapping_sub(self.wr_ptr) & self.mask) >= self.write_limit {
+                self.write_buf(out);
+                return Ok(false);
+            }
+
+            if self.audio_block {
+                self.decode_audio_block(inp)?;
+            } else {
+                self.decode_lz(inp)?;
+            }
+        }
+
+        self.read_last_tables(inp)?;
+        self.write_buf(out);
+        Ok(true)
+    }
+}
+
+/// Small helper to build packed test data bit by bit
+#[cfg(test)]
+struct BitWriter {
+    data: Vec<u8>,
+    bits: usize,
+}
+
+#[cfg(test)]
+impl BitWriter {
+    fn put(&mut self, value: u32, n: u32) {
+        for i in (0..n).rev() {
+            if self.bits.is_multiple_of(8) {
+                self.data.push(0);
+            }
+            if (value >> i) & 1 == 1 {
+                *self.data.last_mut().unwrap() |= 0x80 >> (self.bits % 8);
+            }
+            self.bits += 1;
+        }
+    }
+}
+
+#[test]
+fn test_unpack20_lz() {
+    let mut block = BitWriter {
+        data: vec![],
+        bits: 0,
+    };
+
+    // LZ block with new tables
+    block.put(0, 2);
+    // bit length table where every symbol has a 5 bit code
+    for _ in 0..BC {
+        block.put(5, 4);
+    }
+    // main table with 9 bit codes, the other tables with 6 and 5 bit codes
+    for _ in 0..NC {
+        block.put(9, 5);
+    }
+    for _ in 0..DC {
+        block.put(6, 5);
+    }
+    for _ in 0..RC {
+        block.put(5, 5);
+    }
+
+    // the literals "abc"
+    for c in b"abc" {
+        block.put(u32::from(*c), 9);
+    }
+    // short distance 1 + 2 with a length of 2
+    block.put(261, 9);
+    block.put(2, 2);
+    // length 3 with the distance 3
+    block.put(270, 9);
+    block.put(2, 6);
+    // repeat the last match
+    block.put(256, 9);
+    block.put(0, 16);
+
+    let mut unp = Unpack20::new(0x10000);
+    unp.init(false, 11);
+    let mut inp = BitReader::new(&block.data[..]);
+    let mut out = vec![];
+    assert!(unp.decode(&mut inp, &mut out).unwrap());
+    assert_eq!(out, b"abcabcabcab");
+}
+
+#[test]
+fn test_unpack20_audio() {
+    let mut block = BitWriter {
+        data: vec![],
+        bits: 0,
+    };
+
+    // audio block with 2 channels and new tables
+    block.put(0b10, 2);
+    block.put(1, 2);
+    for _ in 0..BC {
+        block.put(5, 4);
+    }
+    for _ in 0..MC * 2 {
+        block.put(9, 5);
+    }
+
+    // the weights start with 0, so the first bytes are simple deltas
+    for delta in &[0xFF, 0xF6, 0xFF, 0xF6, 0xFE, 0xEC] {
+        block.put(*delta, 9);
+    }
+    block.put(0, 16);
+
+    let mut unp = Unpack20::new(0x10000);
+    unp.init(false, 6);
+    let mut inp = BitReader::new(&block.data[..]);
+    let mut out = vec![];
+    assert!(unp.decode(&mut inp, &mut out).unwrap());
+    assert_eq!(out, vec![1, 10, 2, 20, 4, 40]);
+}
@@ -0,0 +1,720 @@
+use std::io::Result;
+use unpack::bit_reader::BitReader;
+use unpack::filter29::Filters;
+use unpack::huffman::HuffmanTable;
+use unpack::ppm::ModelPpm;
+use unpack::{data_error, Unpack};
+
+/// Number of symbols in the main table
+const NC: usize = 299;
+/// Number of symbols in the distance table
+const DC: usize = 60;
+/// Number of symbols in the low distance table
+const LDC: usize = 17;
+/// Number of symbols in the repeat length table
+const RC: usize = 28;
+/// Number of symbols in the bit length table
+const BC: usize = 20;
+/// Size of all tables together
+const HUFF_TABLE_SIZE: usize = NC + DC + LDC + RC;
+
+/// Number of times the last low distance is repeated
+const LOW_DIST_REP_COUNT: u32 = 16;
+/// Maximum number of bytes which can be added to the window by one symbol
+const MAX_INC_LZ_MATCH: usize = 0x200;
+/// Maximum number of bytes which are written out at once
+const UNPACK_MAX_WRITE: usize = 0x0040_0000;
+
+/// Base values of the length slots
+static LDECODE: [u8; 28] = [
+    0, 1, 2, 3, 4, 5, 6, 7, 8, 10, 12, 14, 16, 20, 24, 28, 32, 40, 48, 56, 64, 80, 96, 112, 128,
+    160, 192, 224,
+];
+/// Number of extra bits of the length slots
+static LBITS: [u8; 28] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5,
+];
+/// Base values of the short distance slots
+static SDDECODE: [u8; 8] = [0, 4, 8, 16, 32, 64, 128, 192];
+/// Number of extra bits of the short distance slots
+static SDBITS: [u8; 8] = [2, 2, 3, 4, 5, 6, 6, 6];
+/// Number of distance slots for every number of extra bits
+static DBIT_LENGTH_COUNTS: [usize; 19] =
+    [4, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 14, 0, 12];
+
+/// Huffman tables which are used to decode a LZ block
+#[derive(Default)]
+struct BlockTables {
+    /// Literals, lengths and special codes
+    ld: HuffmanTable,
+    /// Distances
+    dd: HuffmanTable,
+    /// Lowest 4 bits of big distances
+    ldd: HuffmanTable,
+    /// Lengths of repeated distances
+    rd: HuffmanTable,
+}
+
+/// Decoder for the RAR 2.9 compression format, which is used
+/// by RAR 3.x and 4.x archives.
+///
+/// The data consists of LZ blocks and PPM blocks, which can follow
+/// each other in any order.
+pub struct Unpack29 {
+    /// Sliding window which holds the unpacked data
+    window: Vec<u8>,
+    /// Mask to wrap positions around the window
+    mask: usize,
+    /// Current write position in the window
+    unp_ptr: usize,
+    /// Position up to which the window was written out
+    wr_ptr: usize,
+    /// Amount of data after which the window is written out
+    write_limit: usize,
+    /// The last four used distances
+    old_dist: [usize; 4],
+    /// The last used length
+    last_length: usize,
+    /// The last used low distance
+    prev_low_dist: usize,
+    /// Number of times the last low distance still gets repeated
+    low_dist_rep_count: u32,
+    /// Base values of the distance slots
+    ddecode: [usize; DC],
+    /// Number of extra bits of the distance slots
+    dbits: [u8; DC],
+    /// Current Huffman tables
+    tables: BlockTables,
+    /// Bit lengths of the last tables, new tables can be based on them
+    old_table: [u8; HUFF_TABLE_SIZE],
+    /// Indicates whether the LZ tables are valid for the next file
+    tables_read: bool,
+    /// The current block is a PPM block
+    ppm_block: bool,
+    /// Model to decode PPM blocks
+    ppm: ModelPpm,
+    /// Escape char to mark special codes within PPM blocks
+    esc_char: u8,
+    /// Filters of the data
+    filters: Filters,
+    /// Indicates whether the beginning of the file was read
+    started: bool,
+    /// Number of bytes written out for the current file
+    written: u64,
+}
+
+impl Unpack29 {
+    /// Create a new decoder with the given window size
+    pub fn new(window_size: usize) -> Unpack29 {
+        let mut ddecode = [0; DC];
+        let mut dbits = [0; DC];
+        let mut slot = 0;
+        let mut dist = 0;
+        for (bits, count) in DBIT_LENGTH_COUNTS.iter().enumerate() {
+            for _ in 0..*count {
+                ddecode[slot] = dist;
+                dbits[slot] = bits as u8;
+                slot += 1;
+                dist += 1 << bits;
+            }
+        }
+
+        let mut unp = Unpack29 {
+            window: vec![0; window_size],
+            mask: window_size - 1,
+            unp_ptr: 0,
+            wr_ptr: 0,
+            write_limit: (window_size - MAX_INC_LZ_MATCH).min(UNPACK_MAX_WRITE),
+            old_dist: [0; 4],
+            last_length: 0,
+            prev_low_dist: 0,
+            low_dist_rep_count: 0,
+            ddecode,
+            dbits,
+            tables: BlockTables::default(),
+            old_table: [0; HUFF_TABLE_SIZE],
+            tables_read: false,
+            ppm_block: false,
+            ppm: ModelPpm::new(),
+            esc_char: 2,
+            filters: Filters::default(),
+            started: false,
+            written: 0,
+        };
+        unp.init(false, 0);
+        unp
+    }
+
+    /// Read the tables of a LZ block or the parameters of a PPM block
+    fn read_tables(&mut self, inp: &mut BitReader) -> Result<()> {
+        inp.fill()?;
+        inp.align();
+
+        let bit_field = inp.getbits();
+        if bit_field & 0x8000 != 0 {
+            self.ppm_block = true;
+            return self.ppm.init(inp, &mut self.esc_char);
+        }
+
+        self.ppm_block = false;
+        self.prev_low_dist = 0;
+        self.low_dist_rep_count = 0;
+
+        // the new tables are based on the old ones when this flag is set
+        if bit_field & 0x4000 == 0 {
+            self.old_table = [0; HUFF_TABLE_SIZE];
+        }
+        inp.addbits(2);
+
+        // read the bit lengths of the bit length table
+        let mut bit_length = [0u8; BC];
+        let mut i = 0;
+        while i < BC {
+            let length = (inp.getbits() >> 12) as u8;
+            inp.addbits(4);
+            if length == 15 {
+                let zero_count = (inp.getbits() >> 12) as usize;
+                inp.addbits(4);
+                if zero_count == 0 {
+                    bit_length[i] = 15;
+                    i += 1;
+                } else {
+                    // a sequence of zero bit lengths
+                    let end = BC.min(i + zero_count + 2);
+                    while i < end {
+                        bit_length[i] = 0;
+                        i += 1;
+                    }
+                }
+            } else {
+                bit_length[i] = length;
+                i += 1;
+            }
+        }
+        let bd = HuffmanTable::new(&bit_length);
+
+        // read the bit lengths of all other tables
+        let mut table = [0u8; HUFF_TABLE_SIZE];
+        let mut i = 0;
+        while i < HUFF_TABLE_SIZE {
+            inp.fill()?;
+            let number = bd.decode(inp);
+            if number < 16 {
+                table[i] = (number as u8 + self.old_table[i]) & 0xf;
+                i += 1;
+                continue;
+            }
+
+            // get the repeat count
+            let n = if number == 16 || number == 18 {
+                let n = (inp.getbits() >> 13) + 3;
+                inp.addbits(3);
+                n
+            } else {
+                let n = (inp.getbits() >> 9) + 11;
+                inp.addbits(7);
+                n
+            } as usize;
+            let end = HUFF_TABLE_SIZE.min(i + n);
+
+            if number < 18 {
+                // repeat the previous length
+                if i == 0 {
+                    return Err(data_error("Invalid Huffman table"));
+                }
+                while i < end {
+                    table[i] = table[i - 1];
+                    i += 1;
+                }
+            } else {
+                // a sequence of zero bit lengths
+                while i < end {
+                    table[i] = 0;
+                    i += 1;
+                }
+            }
+        }
+
+        if inp.overrun() {
+            return Err(data_error("Unexpected end of packed data"));
+        }
+
+        self.tables = BlockTables {
+            ld: HuffmanTable::new(&table[..NC]),
+            dd: HuffmanTable::new(&table[NC..NC + DC]),
+            ldd: HuffmanTable::new(&table[NC + DC..NC + DC + LDC]),
+            rd: HuffmanTable::new(&table[NC + DC + LDC..]),
+        };
+        self.old_table = table;
+        self.tables_read = true;
+
+        Ok(())
+    }
+
+    /// Read the marker at the end of a LZ block.
+    /// Returns true when the file is complete.
+    fn read_end_of_block(&mut self, inp: &mut BitReader) -> Result<bool> {
+        let bit_field = inp.getbits();
+
+        // "1"  - no new file, new table just here
+        // "00" - new file, no new table
+        // "01" - new file, new table at the beginning of the next file
+        let (new_table, new_file) = if bit_field & 0x8000 != 0 {
+            inp.addbits(1);
+            (true, false)
+        } else {
+            inp.addbits(2);
+            (bit_field & 0x4000 != 0, true)
+        };
+        self.tables_read = !new_table;
+
+        if !new_file {
+            self.read_tables(inp)?;
+        }
+        Ok(new_file)
+    }
+
+    /// Read the code of a filter from a LZ block
+    fn read_filter(&mut self, inp: &mut BitReader) -> Result<()> {
+        let first_byte = (inp.getbits() >> 8) as u8;
+        inp.addbits(8);
+
+        let mut length = (first_byte & 7) as usize + 1;
+        if length == 7 {
+            length = (inp.getbits() >> 8) as usize + 7;
+            inp.addbits(8);
+        } else if length == 8 {
+            length = inp.getbits() as usize;
+            inp.addbits(16);
+        }
+        if length == 0 {
+            return Err(data_error("Invalid filter"));
+        }
+
+        let mut code = Vec::with_capacity(length);
+        for _ in 0..length {
+            inp.fill()?;
+            code.push((inp.getbits() >> 8) as u8);
+            inp.addbits(8);
+        }
+
+        self.filters
+            .add(first_byte, &code, self.unp_ptr, self.wr_ptr, self.mask)
+    }
+
+    /// Read the code of a filter from a PPM block
+    fn read_filter_ppm(&mut self, inp: &mut BitReader) -> Result<()> {
+        let first_byte = self.ppm.decode_char(inp)?;
+
+        let mut length = (first_byte & 7) as usize + 1;
+        if length == 7 {
+            length = self.ppm.decode_char(inp)? as usize + 7;
+        } else if length == 8 {
+            length = (self.ppm.decode_char(inp)? as usize) << 8;
+            length += self.ppm.decode_char(inp)? as usize;
+        }
+        if length == 0 {
+            return Err(data_error("Invalid filter"));
+        }
+
+        let mut code = Vec::with_capacity(length);
+        for _ in 0..length {
+            code.push(self.ppm.decode_char(inp)?);
+        }
+
+        self.filters
+            .add(first_byte, &code, self.unp_ptr, self.wr_ptr, self.mask)
+    }
+
+    /// Decode the next symbol of a PPM block.
+    /// Returns true when the file is complete.
+    fn decode_ppm(&mut self, inp: &mut BitReader) -> Result<bool> {
+        let ch = self.ppm.decode_char(inp)?;
+        if ch == self.esc_char {
+            match self.ppm.decode_char(inp)? {
+                // end of the PPM block
+                0 => {
+                    self.read_tables(inp)?;
+                    return Ok(false);
+                }
+                // end of the file
+                2 => return Ok(true),
+                // filter code
+                3 => {
+                    self.read_filter_ppm(inp)?;
+                    return Ok(false);
+                }
+                // match with a distance of up to 24 bits
+                4 => {
+                    let mut distance = 0;
+                    for _ in 0..3 {
+                        distance = (distance << 8) + self.ppm.decode_char(inp)? as usize;
+                    }
+                    let length = self.ppm.decode_char(inp)? as usize;
+                    self.copy_string(length + 32, distance + 2);
+                    return Ok(false);
+                }
+                // repeat the last byte
+                5 => {
+                    let length = self.ppm.decode_char(inp)? as usize;
+                    self.copy_string(length + 4, 1);
+                    return Ok(false);
+                }
+                // the escape char itself
+                _ => {}
+            }
+        }
+
+        self.window[self.unp_ptr] = ch;
+        self.unp_ptr = (self.unp_ptr + 1) & self.mask;
+        Ok(false)
+    }
+
+    /// Decode the next symbol of a LZ block.
+    /// Returns true when the file is complete.
+    fn decode_lz(&mut self, inp: &mut BitReader) -> Result<bool> {
+        let number = self.tables.ld.decode(inp) as usize;
+
+        // literal
+        if number < 256 {
+            self.window[self.unp_ptr] = number as u8;
+            self.unp_ptr = (self.unp_ptr + 1) & self.mask;
+            return Ok(false);
+        }
+
+        // match with a new distance
+        if number >= 271 {
+            let mut length = read_length(inp, number - 271) + 3;
+
+            let dist_number = self.tables.dd.decode(inp) as usize;
+            let mut distance = self.ddecode[dist_number] + 1;
+            let bits = self.dbits[dist_number] as u32;
+            if bits > 0 {
+                if dist_number > 9 {
+                    if bits > 4 {
+                        distance += ((inp.getbits() >> (20 - bits)) as usize) << 4;
+                        inp.addbits(bits - 4);
+                    }
+                    if self.low_dist_rep_count > 0 {
+                        self.low_dist_rep_count -= 1;
+                        distance += self.prev_low_dist;
+                    } else {
+                        let low_dist = self.tables.ldd.decode(inp) as usize;
+                        if low_dist == 16 {
+                            self.low_dist_rep_count = LOW_DIST_REP_COUNT - 1;
+                            distance += self.prev_low_dist;
+                        } else {
+                            distance += low_dist;
+                            self.prev_low_dist = low_dist;
+                        }
+                    }
+                } else {
+                    distance += (inp.getbits() >> (16 - bits)) as usize;
+                    inp.addbits(bits);
+                }
+            }
+
+            if distance >= 0x2000 {
+                length += 1;
+                if distance >= 0x40000 {
+                    length += 1;
+                }
+            }
+
+            self.insert_old_dist(distance);
+            self.last_length = length;
+            self.copy_string(length, distance);
+            return Ok(false);
+        }
+
+        match number {
+            // end of the block
+            256 => self.read_end_of_block(inp),
+            // filter
+            257 => {
+                self.read_filter(inp)?;
+                Ok(false)
+            }
+            // repeat the last match
+            258 => {
+                if self.last_length != 0 {
+                    let (length, distance) = (self.last_length, self.old_dist[0]);
+                    self.copy_string(length, distance);
+                }
+                Ok(false)
+            }
+            // match with one of the last four distances
+            259..=262 => {
+                let dist_num = number - 259;
+                let distance = self.old_dist[dist_num];
+                for i in (1..=dist_num).rev() {
+                    self.old_dist[i] = self.old_dist[i - 1];
+                }
+                self.old_dist[0] = distance;
+
+                let length_number = self.tables.rd.decode(inp) as usize;
+                let length = read_length(inp, length_number) + 2;
+                self.last_length = length;
+                self.copy_string(length, distance);
+                Ok(false)
+            }
+            // match with a short distance and a length of 2
+            _ => {
+                let slot = number - 263;
+                let mut distance = SDDECODE[slot] as usize + 1;
+                let bits = SDBITS[slot] as u32;
+                distance += (inp.getbits() >> (16 - bits)) as usize;
+                inp.addbits(bits);
+
+                self.insert_old_dist(distance);
+                self.last_length = 2;
+                self.copy_string(2, distance);
+                Ok(false)
+            }
+        }
+    }
+
+    /// Copy a string of the given length from the given distance
+    /// behind the current position
+    fn copy_string(&mut self, length: usize, distance: usize) {
+        let mut src = self.unp_ptr.wrapping_sub(distance);
+        for _ in 0..length {
+            self.window[self.unp_ptr] = self.window[src & self.mask];
+            src = src.wrapping_add(1);
+            self.unp_ptr = (self.unp_ptr + 1) & self.mask;
+        }
+    }
+
+    /// Add a distance to the list of the last used distances
+    fn insert_old_dist(&mut self, distance: usize) {
+        self.old_dist[3] = self.old_dist[2];
+        self.old_dist[2] = self.old_dist[1];
+        self.old_dist[1] = self.old_dist[0];
+        self.old_dist[0] = distance;
+    }
+
+    /// Write out the window data which is not written yet and
+    /// apply the filters on it.
+    fn write_buf(&mut self, out: &mut Vec<u8>) -> Result<()> {
+        let mask = self.mask;
+        let mut written_border = self.wr_ptr;
+        let mut write_size = self.unp_ptr.wrapping_sub(written_border) & mask;
+
+        let mut i = 0;
+        while i < self.filters.stack.len() {
+            let (block_start, block_length) = match self.filters.stack[i] {
+                Some(ref mut f) if f.next_window => {
+                    f.next_window = false;
+                    i += 1;
+                    continue;
+                }
+                Some(ref f) => (f.block_start, f.block_length),
+                None => {
+                    i += 1;
+                    continue;
+                }
+            };
+
+            if (block_start.wrapping_sub(written_border) & mask) < write_size {
+                // write the data in front of the filter block
+                if written_border != block_start {
+                    self.write_area(written_border, block_start, out);
+                    written_border = block_start;
+                    write_size = self.unp_ptr.wrapping_sub(written_border) & mask;
+                }
+
+                if block_length > write_size {
+                    // the filter block intersects the write border, so we stop
+                    // here and process the filter next time
+                    for f in self.filters.stack[i..].iter_mut().flatten() {
+                        f.next_window = false;
+                    }
+                    self.wr_ptr = written_border;
+                    return Ok(());
+                }
+
+                // the filter is applied on a copy, because the original
+                // data is still needed in the window for future matches
+                let block_end = (block_start + block_length) & mask;
+                let mut data = Vec::with_capacity(block_length);
+                if block_start < block_end || block_end == 0 {
+                    data.extend_from_slice(&self.window[block_start..block_start + block_length]);
+                } else {
+                    data.extend_from_slice(&self.window[block_start..]);
+                    data.extend_from_slice(&self.window[..block_end]);
+                }
+
+                let filter = self.filters.stack[i].take().unwrap();
+                data = self.filters.apply(&filter, data, self.written)?;
+
+                // apply the following filters on the same data block
+                while i + 1 < self.filters.stack.len() {
+                    match self.filters.stack[i + 1] {
+                        Some(ref next)
+                            if next.block_start == block_start
+                                && next.block_length == data.len()
+                                && !next.next_window => {}
+                        _ => break,
+                    }
+                    i += 1;
+                    let filter = self.filters.stack[i].take().unwrap();
+                    data = self.filters.apply(&filter, data, self.written)?;
+                }
+
+                self.written += data.len() as u64;
+                out.extend(data);
+                written_border = block_end;
+                write_size = self.unp_ptr.wrapping_sub(written_border) & mask;
+            }
+            i += 1;
+        }
+
+        let end = self.unp_ptr;
+        self.write_area(written_border, end, out);
+        self.wr_ptr = end;
+        Ok(())
+    }
+
+    /// Write the data between the start and end position of the window
+    fn write_area(&mut self, start: usize, end: usize, out: &mut Vec<u8>) {
+        if end < start {
+            out.extend_from_slice(&self.window[start..]);
+            out.extend_from_slice(&self.window[..end]);
+        } else {
+            out.extend_from_slice(&self.window[start..end]);
+        }
+        self.written += (end.wrapping_sub(start) & self.mask) as u64;
+    }
+}
+
+/// Decode the length from a length slot
+fn read_length(inp: &mut BitReader, slot: usize) -> usize {
+    let mut length = LDECODE[slot] as usize;
+    let bits = LBITS[slot] as u32;
+    if bits > 0 {
+        length += (inp.getbits() >> (16 - bits)) as usize;
+        inp.addbits(bits);
+    }
+    length
+}
+
+impl Unpack for Unpack29 {
+    fn init(&mut self, solid: bool, _size: u64) {
+        if !solid {
+            self.old_dist = [0; 4];
+            self.last_length = 0;
+            self.prev_low_dist = 0;
+            self.low_dist_rep_count = 0;
+            self.unp_ptr = 0;
+            self.wr_ptr = 0;
+            self.tables_read = false;
+            self.old_table = [0; HUFF_TABLE_SIZE];
+            self.ppm_block = false;
+            self.esc_char = 2;
+        }
+
+        // filters never span over several files
+        self.filters.reset(solid);
+        self.written = 0;
+        self.started = false;
+    }
+
+    fn decode(&mut self, inp: &mut BitReader, out: &mut Vec<u8>) -> Result<bool> {
+        // read the tables at the beginning of the file, when
+        // they aren't known from the previous file
+        if !self.started {
+            if !self.tables_read {
+                self.read_tables(inp)?;
+            }
+            self.started = true;
+        }
+
+        loop {
+            inp.fill()?;
+
+            // the data of the file is completely consumed
+            if inp.overrun() {
+                break;
+            }
+
+            // write out the data when enough is unpacked
+            if (self.unp_ptr.wrapping_sub(self.wr_ptr) & self.mask) >= self.write_limit {
+                let len = out.len();
+                self.write_buf(out)?;
+                if out.len() > len {
+                    return Ok(false);
+                }
+            }
+
+            let file_done = if self.ppm_block {
+                self.decode_ppm(inp)?
+            } else {
+                self.decode_lz(inp)?
+            };
+            if file_done {
+                break;
+            }
+        }
+
+        self.write_buf(out)?;
+        Ok(true)
+    }
+}
+
+#[test]
+fn test_read_tables() {
+    // a LZ block with new tables: the bit length table gives 2 bits to the
+    // lengths 2, the repeats 16 and the zero runs 18 and 19, the main table
+    // then gives 2 bits to 'a' to 'd', followed by the literals "abcdd"
+    let data = [
+        0x00, 0x0B, 0xEC, 0x80, 0x8B, 0xAC, 0x23, 0xFF, 0xFF, 0xC8, 0x0D, 0xE0, 0x00, 0x00,
+    ];
+    let mut inp = BitReader::new(&data[..]);
+    let mut unp = Unpack29::new(0x10000);
+    unp.read_tables(&mut inp).unwrap();
+
+    assert!(!unp.ppm_block);
+    assert!(unp.tables_read);
+    assert_eq!(unp.old_table[..97], [0; 97][..]);
+    assert_eq!(unp.old_table[97..101], [2, 2, 2, 2]);
+    assert_eq!(unp.old_table[101..], [0; HUFF_TABLE_SIZE - 101][..]);
+
+    let mut literals = vec![];
+    for _ in 0..5 {
+        inp.fill().unwrap();
+        literals.push(unp.tables.ld.decode(&mut inp) as u8);
+    }
+    assert_eq!(literals, b"abcdd");
+}
+
+#[test]
+fn test_read_tables_invalid() {
+    // the bit length table gives 1 bit to the repeats 16 and 17,
+    // so the first length is a repeat without a previous length
+    let data = [0x3F, 0x84, 0x40, 0x00, 0x00, 0x00];
+    let mut inp = BitReader::new(&data[..]);
+    let mut unp = Unpack29::new(0x10000);
+    assert_eq!(
+        unp.read_tables(&mut inp).unwrap_err().to_string(),
+        "Invalid Huffman table"
+    );
+}
+
+#[test]
+fn test_decode_ppm() {
+    // order 4 and 1 MB with "ab", 7 repeats of the last byte, a match of 33
+    // bytes at the distance 9, the escape char itself and the end of the file,
+    // encoded by an independent PPMd var. H encoder
+    let data = [
+        0xA3, 0x00, 0x61, 0x03, 0x0A, 0x7E, 0x59, 0x60, 0x17, 0x51, 0x7D, 0xC8, 0x86, 0x49, 0x04,
+        0x4A, 0x3F, 0x00,
+    ];
+    let mut inp = BitReader::new(&data[..]);
+    let mut unp = Unpack29::new(0x10000);
+    let mut out = vec![];
+    assert!(unp.decode(&mut inp, &mut out).unwrap());
+
+    let mut expected = b"abbbbbbbb".repeat(4);
+    expected.extend_from_slice(b"abbbbb\x02");
+    assert_eq!(out, expected);
+}
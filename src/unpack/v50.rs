@@ -0,0 +1,638 @@
+use std::io::Result;
+use unpack::bit_reader::BitReader;
+use unpack::filter50::Filter;
+use unpack::huffman::HuffmanTable;
+use unpack::{data_error, Unpack};
+
+/// Number of symbols in the main table
+const NC: usize = 306;
+/// Number of symbols in the distance table
+const DC: usize = 64;
+/// Number of symbols in the low distance table
+const LDC: usize = 16;
+/// Number of symbols in the repeat length table
+const RC: usize = 44;
+/// Number of symbols in the bit length table
+const BC: usize = 20;
+/// Size of all tables together
+const HUFF_TABLE_SIZE: usize = NC + DC + LDC + RC;
+
+/// Maximum number of bytes which can be added to the window by one symbol
+const MAX_INC_LZ_MATCH: usize = 0x1001 + 3;
+/// Maximum number of bytes which are written out at once
+const UNPACK_MAX_WRITE: usize = 0x0040_0000;
+/// Maximum number of filters which are waiting to be applied
+const MAX_UNPACK_FILTERS: usize = 8192;
+
+/// Header in front of every compressed block
+#[derive(Default)]
+struct BlockHeader {
+    /// Position of the first byte of the block data
+    block_start: u64,
+    /// Size of the block data in bytes
+    block_size: u64,
+    /// Number of valid bits in the last byte of the block
+    block_bit_size: u32,
+    /// This is the last block of the file
+    last_block: bool,
+    /// The block starts with new Huffman tables
+    table_present: bool,
+}
+
+/// Huffman tables which are used to decode a block
+#[derive(Default)]
+struct BlockTables {
+    /// Literals, lengths and special codes
+    ld: HuffmanTable,
+    /// Distances
+    dd: HuffmanTable,
+    /// Lowest 4 bits of big distances
+    ldd: HuffmanTable,
+    /// Lengths of repeated distances
+    rd: HuffmanTable,
+}
+
+/// Filter which waits until its data block is unpacked
+struct PendingFilter {
+    /// Filter with the absolute block start within the window
+    filter: Filter,
+    /// The block start belongs to the next round of the window
+    next_window: bool,
+    /// The filter was applied already
+    done: bool,
+}
+
+/// Decoder for the RAR 5.0 compression format
+pub struct Unpack50 {
+    /// Sliding window which holds the unpacked data
+    window: Vec<u8>,
+    /// Mask to wrap positions around the window
+    mask: usize,
+    /// Current write position in the window
+    unp_ptr: usize,
+    /// Position up to which the window was written out
+    wr_ptr: usize,
+    /// Position at which the window needs to be written out
+    write_border: usize,
+    /// The last four used distances
+    old_dist: [usize; 4],
+    /// The last used length
+    last_length: usize,
+    /// Current block header
+    header: BlockHeader,
+    /// Current Huffman tables
+    tables: BlockTables,
+    /// Indicates whether the tables were read at least once
+    tables_read: bool,
+    /// Indicates whether the first block of the file was read
+    started: bool,
+    /// Filters which are not applied yet
+    filters: Vec<PendingFilter>,
+    /// Number of bytes written out for the current file
+    written: u64,
+}
+
+impl Unpack50 {
+    /// Create a new decoder with the given window size
+    pub fn new(window_size: usize) -> Unpack50 {
+        let mut unp = Unpack50 {
+            window: vec![0; window_size],
+            mask: window_size - 1,
+            unp_ptr: 0,
+            wr_ptr: 0,
+            write_border: 0,
+            old_dist: [0; 4],
+            last_length: 0,
+            header: BlockHeader::default(),
+            tables: BlockTables::default(),
+            tables_read: false,
+            started: false,
+            filters: Vec::new(),
+            written: 0,
+        };
+        unp.init(false, 0);
+        unp
+    }
+
+    /// Read the header of the next block
+    fn read_block_header(&mut self, inp: &mut BitReader) -> Result<()> {
+        inp.fill()?;
+        inp.align();
+
+        let flags = inp.get_byte();
+        let byte_count = ((flags >> 3) & 3) + 1;
+        if byte_count == 4 {
+            return Err(data_error("Invalid block header"));
+        }
+
+        let checksum = inp.get_byte();
+        let mut block_size = 0u32;
+        for i in 0..byte_count {
+            block_size += (inp.get_byte() as u32) << (i * 8);
+        }
+
+        // validate the header checksum
+        let check =
+            0x5a ^ flags ^ block_size as u8 ^ (block_size >> 8) as u8 ^ (block_size >> 16) as u8;
+        if check != checksum {
+            return Err(data_error("Block header checksum mismatch"));
+        }
+
+        self.header = BlockHeader {
+            block_start: inp.byte_pos(),
+            block_size: block_size as u64,
+            block_bit_size: ((flags & 7) + 1) as u32,
+            last_block: flags & 0x40 != 0,
+            table_present: flags & 0x80 != 0,
+        };
+
+        Ok(())
+    }
+
+    /// Read the Huffman tables when they are present for the current block
+    fn read_tables(&mut self, inp: &mut BitReader) -> Result<()> {
+        if !self.header.table_present {
+            return Ok(());
+        }
+
+        // read the bit lengths of the bit length table
+        let mut bit_length = [0u8; BC];
+        let mut i = 0;
+        while i < BC {
+            inp.fill()?;
+            let length = (inp.getbits() >> 12) as u8;
+            inp.addbits(4);
+            if length == 15 {
+                let zero_count = (inp.getbits() >> 12) as usize;
+                inp.addbits(4);
+                if zero_count == 0 {
+                    bit_length[i] = 15;
+                    i += 1;
+                } else {
+                    // a sequence of zero bit lengths
+                    let end = BC.min(i + zero_count + 2);
+                    while i < end {
+                        bit_length[i] = 0;
+                        i += 1;
+                    }
+                }
+            } else {
+                bit_length[i] = length;
+                i += 1;
+            }
+        }
+        let bd = HuffmanTable::new(&bit_length);
+
+        // read the bit lengths of all other tables
+        let mut table = [0u8; HUFF_TABLE_SIZE];
+        let mut i = 0;
+        while i < HUFF_TABLE_SIZE {
+            inp.fill()?;
+            let number = bd.decode(inp);
+            if number < 16 {
+                table[i] = number as u8;
+                i += 1;
+                continue;
+            }
+
+            // get the repeat count
+            let n = if number == 16 || number == 18 {
+                let n = (inp.getbits() >> 13) + 3;
+                inp.addbits(3);
+                n
+            } else {
+                let n = (inp.getbits() >> 9) + 11;
+                inp.addbits(7);
+                n
+            } as usize;
+            let end = HUFF_TABLE_SIZE.min(i + n);
+
+            if number < 18 {
+                // repeat the previous length
+                if i == 0 {
+                    return Err(data_error("Invalid Huffman table"));
+                }
+                while i < end {
+                    table[i] = table[i - 1];
+                    i += 1;
+                }
+            } else {
+                // a sequence of zero bit lengths
+                while i < end {
+                    table[i] = 0;
+                    i += 1;
+                }
+            }
+        }
+
+        if inp.overrun() {
+            return Err(data_error("Unexpected end of packed data"));
+        }
+
+        self.tables = BlockTables {
+            ld: HuffmanTable::new(&table[..NC]),
+            dd: HuffmanTable::new(&table[NC..NC + DC]),
+            ldd: HuffmanTable::new(&table[NC + DC..NC + DC + LDC]),
+            rd: HuffmanTable::new(&table[NC + DC + LDC..]),
+        };
+        self.tables_read = true;
+
+        Ok(())
+    }
+
+    /// Returns true when all data of the current block is consumed
+    fn block_end(&self, inp: &BitReader) -> bool {
+        let last = self.header.block_start + self.header.block_size;
+        let pos = inp.byte_pos() + 1;
+        pos > last || (pos == last && inp.bit_pos() >= self.header.block_bit_size)
+    }
+
+    /// Add a new filter which is applied when its data gets written out
+    fn add_filter(&mut self, mut filter: Filter, out: &mut Vec<u8>) {
+        if self.filters.len() >= MAX_UNPACK_FILTERS {
+            // write the data to apply and flush the filters
+            self.write_buf(out);
+            if self.filters.len() >= MAX_UNPACK_FILTERS {
+                // still too many filters, prevent excessive memory use
+                self.filters.clear();
+            }
+        }
+
+        // when the distance to the filter start is that large, that it points
+        // to old not written data due to the circular window, the filter is
+        // only processed after that older data
+        let next_window = self.wr_ptr != self.unp_ptr
+            && (self.wr_ptr.wrapping_sub(self.unp_ptr) & self.mask) <= filter.block_start;
+
+        filter.block_start = (filter.block_start + self.unp_ptr) & self.mask;
+        self.filters.push(PendingFilter {
+            filter,
+            next_window,
+            done: false,
+        });
+    }
+
+    /// Copy a string of the given length from the given distance
+    /// behind the current position
+    fn copy_string(&mut self, length: usize, distance: usize) {
+        let mut src = self.unp_ptr.wrapping_sub(distance);
+        for _ in 0..length {
+            self.window[self.unp_ptr] = self.window[src & self.mask];
+            src = src.wrapping_add(1);
+            self.unp_ptr = (self.unp_ptr + 1) & self.mask;
+        }
+    }
+
+    /// Add a distance to the list of the last used distances
+    fn insert_old_dist(&mut self, distance: usize) {
+        self.old_dist[3] = self.old_dist[2];
+        self.old_dist[2] = self.old_dist[1];
+        self.old_dist[1] = self.old_dist[0];
+        self.old_dist[0] = distance;
+    }
+
+    /// Write out the window data which is not written yet and
+    /// apply the filters on it.
+    fn write_buf(&mut self, out: &mut Vec<u8>) {
+        let mask = self.mask;
+        let mut written_border = self.wr_ptr;
+        let full_write_size = self.unp_ptr.wrapping_sub(written_border) & mask;
+        let mut write_size_left = full_write_size;
+        let mut all_filters_processed = true;
+
+        for i in 0..self.filters.len() {
+            if self.filters[i].done {
+                continue;
+            }
+
+            let block_start = self.filters[i].filter.block_start;
+            let block_length = self.filters[i].filter.block_length;
+
+            // skip filters which belong to the next round of the window,
+            // until their block start is covered by the written data
+            if self.filters[i].next_window {
+                if (block_start.wrapping_sub(self.wr_ptr) & mask) <= full_write_size {
+                    self.filters[i].next_window = false;
+                }
+                continue;
+            }
+
+            if (block_start.wrapping_sub(written_border) & mask) >= write_size_left {
+                continue;
+            }
+
+            // write the data in front of the filter block
+            if written_border != block_start {
+                self.write_area(written_border, block_start, out);
+                written_border = block_start;
+                write_size_left = self.unp_ptr.wrapping_sub(written_border) & mask;
+            }
+
+            if block_length <= write_size_left {
+                if block_length > 0 {
+                    // the filter is applied on a copy, because the original
+                    // data is still needed in the window for future matches
+                    let block_end = (block_start + block_length) & mask;
+                    let mut data = Vec::with_capacity(block_length);
+                    if block_start < block_end || block_end == 0 {
+                        data.extend_from_slice(
+                            &self.window[block_start..block_start + block_length],
+                        );
+                    } else {
+                        data.extend_from_slice(&self.window[block_start..]);
+                        data.extend_from_slice(&self.window[..block_end]);
+                    }
+
+                    out.extend(self.filters[i].filter.apply(data, self.written));
+                    self.written += block_length as u64;
+                    written_border = block_end;
+                    write_size_left = self.unp_ptr.wrapping_sub(written_border) & mask;
+                }
+                self.filters[i].done = true;
+            } else {
+                // the filter block intersects the write border, so we stop here
+                // and process the filter next time
+                self.wr_ptr = written_border;
+                for f in self.filters[i..].iter_mut() {
+                    f.next_window = false;
+                }
+                all_filters_processed = false;
+                break;
+            }
+        }
+
+        // remove the processed filters
+        self.filters.retain(|f| !f.done);
+
+        // write the data after the last filter
+        if all_filters_processed {
+            let end = self.unp_ptr;
+            self.write_area(written_border, end, out);
+            self.wr_ptr = self.unp_ptr;
+        }
+
+        // we prefer to write data in blocks not exceeding UNPACK_MAX_WRITE
+        let window_size = mask + 1;
+        self.write_border = (self.unp_ptr + window_size.min(UNPACK_MAX_WRITE)) & mask;
+
+        // choose the nearest among the write border and the actual written border
+        if self.write_border == self.unp_ptr
+            || (self.wr_ptr != self.unp_ptr
+                && (self.wr_ptr.wrapping_sub(self.unp_ptr) & mask)
+                    < (self.write_border.wrapping_sub(self.unp_ptr) & mask))
+        {
+            self.write_border = self.wr_ptr;
+        }
+    }
+
+    /// Write the data between the start and end position of the window
+    fn write_area(&mut self, start: usize, end: usize, out: &mut Vec<u8>) {
+        if end < start {
+            out.extend_from_slice(&self.window[start..]);
+            out.extend_from_slice(&self.window[..end]);
+        } else {
+            out.extend_from_slice(&self.window[start..end]);
+        }
+        self.written += (end.wrapping_sub(start) & self.mask) as u64;
+    }
+}
+
+/// Decode the length from a length slot
+fn slot_to_length(inp: &mut BitReader, slot: u32) -> usize {
+    let (bits, mut length) = if slot < 8 {
+        (0, 2 + slot)
+    } else {
+        let bits = slot / 4 - 1;
+        (bits, 2 + ((4 | (slot & 3)) << bits))
+    };
+
+    if bits > 0 {
+        length += inp.getbits() >> (16 - bits);
+        inp.addbits(bits);
+    }
+
+    length as usize
+}
+
+impl Unpack for Unpack50 {
+    fn init(&mut self, solid: bool, _size: u64) {
+        if !solid {
+            self.old_dist = [0; 4];
+            self.last_length = 0;
+            self.unp_ptr = 0;
+            self.wr_ptr = 0;
+            self.write_border = self.window.len().min(UNPACK_MAX_WRITE) & self.mask;
+            self.tables_read = false;
+        }
+
+        // filters never span over several files
+        self.filters.clear();
+        self.written = 0;
+
+        self.header = BlockHeader::default();
+        self.started = false;
+    }
+
+    fn decode(&mut self, inp: &mut BitReader, out: &mut Vec<u8>) -> Result<bool> {
+        // read the first block of the file
+        if !self.started {
+            self.read_block_header(inp)?;
+            self.read_tables(inp)?;
+            if !self.tables_read {
+                return Err(data_error("No Huffman tables present"));
+            }
+            self.started = true;
+        }
+
+        loop {
+            inp.fill()?;
+
+            // check if the next block needs to be read
+            let mut file_done = false;
+            while self.block_end(inp) {
+                if self.header.last_block {
+                    file_done = true;
+                    break;
+                }
+                self.read_block_header(inp)?;
+                self.read_tables(inp)?;
+            }
+            if file_done {
+                break;
+            }
+            if inp.overrun() {
+                return Err(data_error("Unexpected end of packed data"));
+            }
+
+            // write out the data when the write border is reached
+            if ((self.write_border.wrapping_sub(self.unp_ptr)) & self.mask) < MAX_INC_LZ_MATCH
+                && self.write_border != self.unp_ptr
+            {
+                self.write_buf(out);
+                return Ok(false);
+            }
+
+            let main_slot = self.tables.ld.decode(inp);
+
+            // literal
+            if main_slot < 256 {
+                self.window[self.unp_ptr] = main_slot as u8;
+                self.unp_ptr = (self.unp_ptr + 1) & self.mask;
+                continue;
+            }
+
+            // match with a new distance
+            if main_slot >= 262 {
+                let mut length = slot_to_length(inp, main_slot - 262);
+
+                let dist_slot = self.tables.dd.decode(inp) as usize;
+                let (bits, mut distance) = if dist_slot < 4 {
+                    (0, 1 + dist_slot)
+                } else {
+                    let bits = dist_slot / 2 - 1;
+                    (bits, 1 + ((2 | (dist_slot & 1)) << bits))
+                };
+
+                if bits >= 4 {
+                    if bits > 4 {
+                        distance += ((inp.getbits32() >> (36 - bits)) as usize) << 4;
+                        inp.addbits(bits as u32 - 4);
+                    }
+                    distance += self.tables.ldd.decode(inp) as usize;
+                } else if bits > 0 {
+                    distance += (inp.getbits32() >> (32 - bits)) as usize;
+                    inp.addbits(bits as u32);
+                }
+
+                if distance > 0x100 {
+                    length += 1;
+                    if distance > 0x2000 {
+                        length += 1;
+                        if distance > 0x40000 {
+                            length += 1;
+                        }
+                    }
+                }
+
+                self.insert_old_dist(distance);
+                self.last_length = length;
+                self.copy_string(length, distance);
+                continue;
+            }
+
+            // filter
+            if main_slot == 256 {
+                let filter = Filter::read(inp)?;
+                self.add_filter(filter, out);
+                continue;
+            }
+
+            // repeat the last match
+            if main_slot == 257 {
+                if self.last_length != 0 {
+                    let (length, distance) = (self.last_length, self.old_dist[0]);
+                    self.copy_string(length, distance);
+                }
+                continue;
+            }
+
+            // match with one of the last four distances
+            let dist_num = (main_slot - 258) as usize;
+            let distance = self.old_dist[dist_num];
+            for i in (1..=dist_num).rev() {
+                self.old_dist[i] = self.old_dist[i - 1];
+            }
+            self.old_dist[0] = distance;
+
+            let length_slot = self.tables.rd.decode(inp);
+            let length = slot_to_length(inp, length_slot);
+            self.last_length = length;
+            self.copy_string(length, distance);
+        }
+
+        self.write_buf(out);
+        Ok(true)
+    }
+}
+
+/// Small helper to build packed test data bit by bit
+#[cfg(test)]
+struct BitWriter {
+    data: Vec<u8>,
+    bits: usize,
+}
+
+#[cfg(test)]
+impl BitWriter {
+    fn put(&mut self, value: u32, n: u32) {
+        for i in (0..n).rev() {
+            if self.bits.is_multiple_of(8) {
+                self.data.push(0);
+            }
+            if (value >> i) & 1 == 1 {
+                *self.data.last_mut().unwrap() |= 0x80 >> (self.bits % 8);
+            }
+            self.bits += 1;
+        }
+    }
+}
+
+#[test]
+fn test_unpack50_filter() {
+    let mut block = BitWriter {
+        data: vec![],
+        bits: 0,
+    };
+
+    // bit length table where every symbol has a 5 bit code
+    for _ in 0..BC {
+        block.put(5, 4);
+    }
+    // main table with 9 bit codes, the other tables with 6 and 4 bit codes
+    for _ in 0..NC {
+        block.put(9, 5);
+    }
+    for _ in 0..DC {
+        block.put(6, 5);
+    }
+    for _ in 0..LDC {
+        block.put(4, 5);
+    }
+    for _ in 0..RC {
+        block.put(6, 5);
+    }
+
+    // E8 filter over the next 8 bytes
+    block.put(256, 9);
+    block.put(0, 2);
+    block.put(0, 8);
+    block.put(0, 2);
+    block.put(8, 8);
+    block.put(1, 3);
+
+    // literals with a call instruction
+    for b in &[0x90, 0xE8, 0x05, 0x11, 0x00, 0x00, 0x90, 0x90] {
+        block.put(*b, 9);
+    }
+
+    // match with length 2 and distance 1
+    block.put(262, 9);
+    block.put(0, 6);
+
+    // block header with tables, which is the last block of the file
+    let size = block.data.len();
+    let (low, high) = (size as u8, (size >> 8) as u8);
+    let flags = 0x80 | 0x40 | 0x08 | ((block.bits - 1) % 8) as u8;
+    let mut data = vec![flags, 0x5a ^ flags ^ low ^ high, low, high];
+    data.extend(block.data);
+
+    let mut unpacker = Unpack50::new(0x40000);
+    let mut inp = BitReader::new(&data[..]);
+    let mut out = vec![];
+    while !unpacker.decode(&mut inp, &mut out).unwrap() {}
+
+    assert_eq!(
+        out,
+        vec![0x90, 0xE8, 0x03, 0x11, 0x00, 0x00, 0x90, 0x90, 0x90, 0x90]
+    );
+}
@@ -8,21 +8,21 @@ pub fn get_bit_at(input: u64, n: u64) -> bool {
 }
 #[test]
 fn test_get_bit_at() {
-    assert_eq!(get_bit_at(0xFF, 1), true);
-    assert_eq!(get_bit_at(0x7F, 7), false);
-    assert_eq!(get_bit_at(0x7F, 6), true);
-    assert_eq!(get_bit_at(0x00, 0), false);
-    assert_eq!(get_bit_at(0x01, 0), true);
-    assert_eq!(get_bit_at(0x02, 0), false);
-    assert_eq!(get_bit_at(0x02, 1), true);
+    assert!(get_bit_at(0xFF, 1));
+    assert!(!get_bit_at(0x7F, 7));
+    assert!(get_bit_at(0x7F, 6));
+    assert!(!get_bit_at(0x00, 0));
+    assert!(get_bit_at(0x01, 0));
+    assert!(!get_bit_at(0x02, 0));
+    assert!(get_bit_at(0x02, 1));
 }
 
 /// Split an u64 back to an array of u8
 ///
 /// This is usefull if you need to have bytes again out of an
 /// vint
+#[allow(dead_code)]
 pub fn split_u64(x: u64) -> [u8; 8] {
-    let x = x.clone();
     let b1: u8 = ((x >> 56) & 0xff) as u8;
     let b2: u8 = ((x >> 48) & 0xff) as u8;
     let b3: u8 = ((x >> 40) & 0xff) as u8;
@@ -31,7 +31,7 @@ pub fn split_u64(x: u64) -> [u8; 8] {
     let b6: u8 = ((x >> 16) & 0xff) as u8;
     let b7: u8 = ((x >> 8) & 0xff) as u8;
     let b8: u8 = (x & 0xff) as u8;
-    return [b1, b2, b3, b4, b5, b6, b7, b8];
+    [b1, b2, b3, b4, b5, b6, b7, b8]
 }
 #[test]
 fn test_split_u64() {
@@ -71,7 +71,134 @@ pub fn to_bool(i: u8) -> bool {
 }
 #[test]
 fn test_to_bool() {
-    assert_eq!(to_bool(0), false);
-    assert_eq!(to_bool(1), true);
-    assert_eq!(to_bool(10), true);
+    assert!(!to_bool(0));
+    assert!(to_bool(1));
+    assert!(to_bool(10));
+}
+
+/// Lookup table for the CRC32 calculation
+pub static CRC32_TABLE: [u32; 256] = crc32_table();
+
+/// Build the lookup table for the CRC32 polynom 0xEDB88320
+const fn crc32_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut c = i as u32;
+        let mut j = 0;
+        while j < 8 {
+            c = if c & 1 != 0 {
+                0xEDB8_8320 ^ (c >> 1)
+            } else {
+                c >> 1
+            };
+            j += 1;
+        }
+        table[i] = c;
+        i += 1;
+    }
+    table
+}
+
+/// Update the CRC32 with the given data.
+///
+/// The calculation starts with a CRC of 0 and can be
+/// continued with the result for the next part of the data.
+pub fn crc32(crc: u32, data: &[u8]) -> u32 {
+    let mut crc = !crc;
+    for b in data {
+        crc = CRC32_TABLE[((crc ^ u32::from(*b)) & 0xff) as usize] ^ (crc >> 8);
+    }
+    !crc
+}
+#[test]
+fn test_crc32() {
+    assert_eq!(crc32(0, b""), 0);
+    assert_eq!(crc32(0, b"123456789"), 0xCBF4_3926);
+    assert_eq!(crc32(crc32(0, b"12345"), b"6789"), 0xCBF4_3926);
+}
+
+/// Overwrite the memory of a value with zeros, which can't be optimized away.
+///
+/// This wipes the key material out of types of other crates,
+/// which don't offer a way to do it.
+///
+/// # Safety
+///
+/// All zero bytes must be a valid value of the type,
+/// e.g. it must not contain any references.
+pub unsafe fn wipe<T: Copy>(value: &mut T) {
+    use std::{mem, slice};
+    use zeroize::Zeroize;
+
+    slice::from_raw_parts_mut(value as *mut T as *mut u8, mem::size_of::<T>()).zeroize();
+}
+#[test]
+fn test_wipe() {
+    let mut value = ([0xAAu8; 5], 0xBBBB_u16, 0xCCCC_CCCC_u32);
+    unsafe { wipe(&mut value) };
+    assert_eq!(value, ([0; 5], 0, 0));
+}
+
+/// Returns true, when the name matches the glob pattern.
+///
+/// A `?` matches one character and a `*` any number of characters,
+/// both don't match the separators of a path. A `**` matches across them.
+pub fn glob_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    glob_match_chars(&pattern, &name)
+}
+
+/// Returns true, when the pattern has no wildcards and
+/// matches only the name, which is equal to it.
+pub fn glob_is_literal(pattern: &str) -> bool {
+    !pattern.contains(['?', '*'])
+}
+
+/// Match the characters of the pattern against the ones of the name
+fn glob_match_chars(pattern: &[char], name: &[char]) -> bool {
+    let is_sep = |c: &char| *c == '/' || *c == '\\';
+
+    match pattern.first() {
+        None => name.is_empty(),
+        Some('*') if pattern.get(1) == Some(&'*') => {
+            (0..=name.len()).any(|i| glob_match_chars(&pattern[2..], &name[i..]))
+        }
+        Some('*') => {
+            // the name is only consumed up to the next separator
+            let len = name.iter().position(is_sep).unwrap_or(name.len());
+            (0..=len).any(|i| glob_match_chars(&pattern[1..], &name[i..]))
+        }
+        Some('?') => match name.first() {
+            Some(c) if !is_sep(c) => glob_match_chars(&pattern[1..], &name[1..]),
+            _ => false,
+        },
+        Some(p) => match name.first() {
+            // both separators are the same in the names of an archive
+            Some(c) if c == p || (is_sep(c) && is_sep(p)) => {
+                glob_match_chars(&pattern[1..], &name[1..])
+            }
+            _ => false,
+        },
+    }
+}
+#[test]
+fn test_glob_match() {
+    assert!(glob_match("text.txt", "text.txt"));
+    assert!(!glob_match("text.txt", "text.txt.bak"));
+    assert!(glob_match("*.txt", "text.txt"));
+    assert!(glob_match("*", ""));
+    assert!(glob_match("t??t.*", "text.txt"));
+    assert!(!glob_match("t?t.*", "text.txt"));
+    assert!(!glob_match("*.txt", "docs/text.txt"));
+    assert!(glob_match("docs/*.txt", "docs\\text.txt"));
+    assert!(glob_match("**.txt", "docs/text.txt"));
+    assert!(glob_match("**/config.*", "a/b/config.toml"));
+    assert!(!glob_match("**/config.*", "config.toml"));
+    assert!(glob_match("*/*", "docs/text.txt"));
+
+    assert!(glob_is_literal("docs/text.txt"));
+    assert!(!glob_is_literal("docs/*.txt"));
+    assert!(!glob_is_literal("t?xt.txt"));
 }
@@ -11,7 +11,7 @@ pub fn vint(input: &[u8]) -> nom::IResult<&[u8], u64> {
             // get just the data bit's from the collection
             let coll =
                 v.1.iter()
-                    .map(|x| split_vint(x.clone()).1 as u64)
+                    .map(|x| split_vint(*x).1 as u64)
                     .collect::<Vec<u64>>();
             let mut len = coll.len();
 
@@ -26,7 +26,7 @@ pub fn vint(input: &[u8]) -> nom::IResult<&[u8], u64> {
             // afterwards we are looping in a reversed order over the collection
             // to push all the remaining bits to our vint
             for i in coll.iter().rev() {
-                out = out << 7;
+                out <<= 7;
                 out += i;
             }
 
@@ -113,8 +113,8 @@ fn is_vint_bit(a: u8) -> bool {
 }
 #[test]
 fn test_is_vint_bit() {
-    assert_eq!(is_vint_bit(0xFF), true);
-    assert_eq!(is_vint_bit(0x7F), false);
-    assert_eq!(is_vint_bit(0x00), false);
-    assert_eq!(is_vint_bit(0x01), false);
+    assert!(is_vint_bit(0xFF));
+    assert!(!is_vint_bit(0x7F));
+    assert!(!is_vint_bit(0x00));
+    assert!(!is_vint_bit(0x01));
 }
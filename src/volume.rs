@@ -0,0 +1,204 @@
+use archive_block::ArchiveFlags;
+use std::fs::File;
+use std::io;
+use std::io::{ErrorKind, Read, Seek};
+
+/// Data of a volume, which can be read and seeked
+pub trait ReadSeek: Read + Seek {}
+
+impl<T: Read + Seek> ReadSeek for T {}
+
+/// Provides the volumes of an archive, when they are needed.
+///
+/// The volumes are numbered from 0 for the first one,
+/// every volume is requested only once while the archive is read.
+pub trait VolumeProvider {
+    /// Returns the data of the volume with the number
+    fn volume(&mut self, number: usize) -> io::Result<Box<dyn ReadSeek>>;
+
+    /// Returns the name of the volume, which is used in errors
+    /// and to ask for the password of encrypted headers
+    fn name(&self, number: usize) -> String;
+
+    /// Receives the flags of the archive block of the first volume,
+    /// before the following volumes are requested
+    fn archive_flags(&mut self, _flags: &ArchiveFlags) {}
+}
+
+/// The volumes of an archive on the disk, which are named after the first one
+pub struct FileVolumes {
+    file_name: String,
+    /// Volumes are named .partN.rar instead of .rar, .r00, .r01
+    new_numbering: bool,
+}
+
+impl FileVolumes {
+    /// Create the volumes of the archive with the name of its first .rar file
+    pub fn new(file_name: &str) -> FileVolumes {
+        FileVolumes {
+            file_name: file_name.into(),
+            new_numbering: true,
+        }
+    }
+}
+
+impl VolumeProvider for FileVolumes {
+    /// Open the .rar file of the volume
+    fn volume(&mut self, number: usize) -> io::Result<Box<dyn ReadSeek>> {
+        Ok(Box::new(File::open(self.name(number))?))
+    }
+
+    /// The name is computed out of the name of the first .rar file
+    fn name(&self, number: usize) -> String {
+        volume_name(&self.file_name, number, self.new_numbering)
+    }
+
+    /// The naming scheme of the volumes is taken from the flags
+    fn archive_flags(&mut self, flags: &ArchiveFlags) {
+        self.new_numbering = flags.new_numbering;
+    }
+}
+
+/// Returns the name of the volume with the number, counted from 0,
+/// out of the name of the first volume.
+///
+/// With the new numbering the part number in front of the .rar extension
+/// is counted up with the same number of digits, e.g. name.part01.rar is
+/// followed by name.part02.rar. Otherwise, or when the name has no part
+/// number, the old scheme name.rar, name.r00, name.r01, ... is used.
+pub fn volume_name(first: &str, number: usize, new_numbering: bool) -> String {
+    if number == 0 {
+        return first.into();
+    }
+
+    if new_numbering {
+        if let Some(name) = part_name(first, number) {
+            return name;
+        }
+    }
+
+    old_name(first, number)
+}
+
+/// Count up the part number in front of the .rar extension
+fn part_name(first: &str, number: usize) -> Option<String> {
+    let ext = first.len().checked_sub(4)?;
+    if !first.is_char_boundary(ext) || !first[ext..].eq_ignore_ascii_case(".rar") {
+        return None;
+    }
+
+    let stem = &first[..ext];
+    let start = stem.trim_end_matches(|c: char| c.is_ascii_digit()).len();
+    let width = ext - start;
+    if width == 0 {
+        return None;
+    }
+
+    let part = stem[start..].parse::<usize>().ok()?;
+    Some(format!(
+        "{}{:0width$}{}",
+        &first[..start],
+        part + number,
+        &first[ext..],
+        width = width
+    ))
+}
+
+/// Replace the extension with .r00 for the second volume, .r01 for the
+/// third one and so on. After .r99 the letter is counted up to .s00.
+fn old_name(first: &str, number: usize) -> String {
+    // the extension starts at the last dot of the file name
+    let stem = match first.rfind(['.', '/', '\\']) {
+        Some(dot) if first[dot..].starts_with('.') => &first[..dot],
+        _ => first,
+    };
+
+    let index = number - 1;
+    let letter = b'r'.saturating_add((index / 100).min(255) as u8) as char;
+    let ext = format!("{}{:02}", letter, index % 100);
+
+    // keep an upper case extension
+    if first[stem.len()..].starts_with(".R") {
+        format!("{}.{}", stem, ext.to_uppercase())
+    } else {
+        format!("{}.{}", stem, ext)
+    }
+}
+
+/// An archive with a single volume out of any reader, e.g. a buffer in memory
+pub struct SingleVolume<R: Read + Seek + 'static> {
+    reader: Option<R>,
+}
+
+impl<R: Read + Seek + 'static> SingleVolume<R> {
+    /// Create the archive out of the reader
+    pub fn new(reader: R) -> SingleVolume<R> {
+        SingleVolume {
+            reader: Some(reader),
+        }
+    }
+}
+
+impl<R: Read + Seek + 'static> VolumeProvider for SingleVolume<R> {
+    /// Returns the reader as the first volume, there are no others
+    fn volume(&mut self, number: usize) -> io::Result<Box<dyn ReadSeek>> {
+        let reader = if number == 0 {
+            self.reader.take()
+        } else {
+            None
+        };
+
+        match reader {
+            Some(reader) => Ok(Box::new(reader)),
+            None => Err(io::Error::new(
+                ErrorKind::NotFound,
+                format!("The {} is not available", self.name(number)),
+            )),
+        }
+    }
+
+    fn name(&self, number: usize) -> String {
+        format!("volume {}", number + 1)
+    }
+}
+
+#[test]
+fn test_file_volumes() {
+    let volumes = FileVolumes::new("assets/a.part1.rar");
+    assert_eq!(volumes.name(0), "assets/a.part1.rar");
+    assert_eq!(volumes.name(1), "assets/a.part2.rar");
+    assert_eq!(volumes.name(9), "assets/a.part10.rar");
+}
+#[test]
+fn test_volume_name() {
+    // the width of the part number is kept
+    assert_eq!(volume_name("a.part01.rar", 1, true), "a.part02.rar");
+    assert_eq!(volume_name("a.part01.rar", 9, true), "a.part10.rar");
+    assert_eq!(volume_name("a.part001.rar", 99, true), "a.part100.rar");
+    assert_eq!(volume_name("a.part9.rar", 1, true), "a.part10.rar");
+    assert_eq!(volume_name("A.PART1.RAR", 1, true), "A.PART2.RAR");
+
+    // the old scheme
+    assert_eq!(volume_name("dir.v1/a.rar", 0, false), "dir.v1/a.rar");
+    assert_eq!(volume_name("dir.v1/a.rar", 1, false), "dir.v1/a.r00");
+    assert_eq!(volume_name("dir.v1/a.rar", 11, false), "dir.v1/a.r10");
+    assert_eq!(volume_name("a.rar", 101, false), "a.s00");
+    assert_eq!(volume_name("A.RAR", 2, false), "A.R01");
+    assert_eq!(volume_name("dir.v1/a", 1, false), "dir.v1/a.r00");
+    assert_eq!(volume_name("a.part1.rar", 1, false), "a.part1.r00");
+
+    // without a part number the old scheme is used
+    assert_eq!(volume_name("a.rar", 1, true), "a.r00");
+}
+#[test]
+fn test_single_volume() {
+    let mut volumes = SingleVolume::new(io::Cursor::new(vec![1u8, 2, 3]));
+    assert!(volumes.volume(1).is_err());
+
+    let mut data = vec![];
+    volumes.volume(0).unwrap().read_to_end(&mut data).unwrap();
+    assert_eq!(data, [1, 2, 3]);
+
+    // the reader is given only once
+    assert!(volumes.volume(0).is_err());
+}